@@ -0,0 +1,416 @@
+//! A small HTTP server keeping a backup of a node's data, which the node mirrors every write to
+//! when started with `--backup=http://<host>:<port>`.
+//!
+//! Entries are stored in `<data_dir>/data` in the same layout as the node's own data directory, so
+//! restoring a node only takes copying them back. The latest version of every entry is kept in
+//! `<data_dir>/versions`, and writes or removals carrying an older version are rejected. Clients
+//! continue from the highest version of any entry, which is served at [`VERSION_PATH`].
+
+use ldk::http_backup::{KEYS_PATH, OBJECTS_PATH, VERSION_HEADER, VERSION_PATH};
+use ldk::sqlite_store::is_valid_kvstore_str;
+use lightning::util::persist::{KVStore, MigratableKVStore};
+use lightning_persister::fs_store::FilesystemStore;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::env;
+use std::path::Path;
+use std::process;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Requests with larger bodies are rejected. The largest entries a node writes are its network
+/// graph and scorer.
+const MAX_BODY_LEN: usize = 256 * 1024 * 1024;
+
+struct Request {
+	method: String,
+	path: String,
+	query: HashMap<String, String>,
+	headers: HashMap<String, String>,
+	body: Vec<u8>,
+}
+
+struct Response {
+	status: u16,
+	body: Vec<u8>,
+}
+
+impl Response {
+	fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+		Self { status, body: body.into() }
+	}
+}
+
+struct BackupServer {
+	data: FilesystemStore,
+	versions: FilesystemStore,
+	token: Option<String>,
+	/// The highest version of any entry. Held while checking an entry's version and updating it,
+	/// so concurrent requests for the same entry can't both pass the check.
+	latest_version: Mutex<u64>,
+}
+
+impl BackupServer {
+	fn new(data_dir: &Path, token: Option<String>) -> std::io::Result<Self> {
+		let versions = FilesystemStore::new(data_dir.join("versions"));
+		let mut latest_version = 0;
+		for (primary_namespace, secondary_namespace, key) in versions.list_all_keys()? {
+			let version = versions.read(&primary_namespace, &secondary_namespace, &key)?;
+			if let Ok(version) = version.try_into() {
+				latest_version = latest_version.max(u64::from_be_bytes(version));
+			}
+		}
+		Ok(Self {
+			data: FilesystemStore::new(data_dir.join("data")),
+			versions,
+			token,
+			latest_version: Mutex::new(latest_version),
+		})
+	}
+
+	fn handle(&self, request: &Request) -> Response {
+		if let Some(token) = &self.token {
+			let expected = format!("Bearer {}", token);
+			if request.headers.get("authorization") != Some(&expected) {
+				return Response::new(401, "Unauthorized");
+			}
+		}
+		if request.path == VERSION_PATH {
+			if request.method != "GET" {
+				return Response::new(405, "Method not allowed");
+			}
+			return Response::new(200, self.latest_version.lock().unwrap().to_string());
+		}
+
+		let param = |name: &str| match request.query.get(name) {
+			Some(value) if is_valid_kvstore_str(value) => Ok(value.as_str()),
+			_ => Err(Response::new(400, format!("Missing or invalid {}", name))),
+		};
+		let namespaces = param("primary_namespace")
+			.and_then(|primary_namespace| Ok((primary_namespace, param("secondary_namespace")?)));
+		let (primary_namespace, secondary_namespace) = match namespaces {
+			Ok(namespaces) => namespaces,
+			Err(response) => return response,
+		};
+
+		if request.path == KEYS_PATH {
+			if request.method != "GET" {
+				return Response::new(405, "Method not allowed");
+			}
+			return match self.data.list(primary_namespace, secondary_namespace) {
+				Ok(keys) => Response::new(200, keys.join("\n")),
+				Err(e) => Response::new(500, e.to_string()),
+			};
+		}
+		if request.path != OBJECTS_PATH {
+			return Response::new(404, "Not found");
+		}
+		let key = match param("key") {
+			Ok(key) => key,
+			Err(response) => return response,
+		};
+		match request.method.as_str() {
+			"GET" => match self.data.read(primary_namespace, secondary_namespace, key) {
+				Ok(value) => Response::new(200, value),
+				Err(e) if e.kind() == lightning::io::ErrorKind::NotFound => {
+					Response::new(404, "Not found")
+				},
+				Err(e) => Response::new(500, e.to_string()),
+			},
+			"PUT" | "DELETE" => {
+				let version = match request.headers.get(&VERSION_HEADER.to_lowercase()) {
+					Some(version) => match version.parse::<u64>() {
+						Ok(version) => version,
+						Err(_) => return Response::new(400, format!("Invalid {}", VERSION_HEADER)),
+					},
+					None => return Response::new(400, format!("Missing {}", VERSION_HEADER)),
+				};
+				let mut latest_version = self.latest_version.lock().unwrap();
+				let current_version = self
+					.versions
+					.read(primary_namespace, secondary_namespace, key)
+					.ok()
+					.and_then(|bytes| bytes.try_into().ok())
+					.map(u64::from_be_bytes)
+					.unwrap_or(0);
+				if version <= current_version {
+					return Response::new(
+						409,
+						format!("Version {} is not newer than {}", version, current_version),
+					);
+				}
+				let res = if request.method == "PUT" {
+					self.data.write(primary_namespace, secondary_namespace, key, &request.body)
+				} else {
+					match self.data.remove(primary_namespace, secondary_namespace, key, false) {
+						Err(e) if e.kind() == lightning::io::ErrorKind::NotFound => Ok(()),
+						res => res,
+					}
+				};
+				// The version of a removed entry is kept, so a delayed write can't bring it back.
+				let res = res.and_then(|()| {
+					self.versions.write(
+						primary_namespace,
+						secondary_namespace,
+						key,
+						&version.to_be_bytes(),
+					)
+				});
+				match res {
+					Ok(()) => {
+						*latest_version = version.max(*latest_version);
+						Response::new(200, "")
+					},
+					Err(e) => Response::new(500, e.to_string()),
+				}
+			},
+			_ => Response::new(405, "Method not allowed"),
+		}
+	}
+}
+
+async fn read_request(stream: &mut BufReader<TcpStream>) -> Result<Request, Response> {
+	let bad_request = |_| Response::new(400, "Bad request");
+	let mut request_line = String::new();
+	stream.read_line(&mut request_line).await.map_err(bad_request)?;
+	let mut parts = request_line.split_whitespace();
+	let method = parts.next().unwrap_or_default().to_string();
+	let target = parts.next().unwrap_or_default();
+	let (path, query) = target.split_once('?').unwrap_or((target, ""));
+	let query = query
+		.split('&')
+		.filter_map(|param| param.split_once('='))
+		.map(|(name, value)| (name.to_string(), value.to_string()))
+		.collect();
+
+	let mut headers = HashMap::new();
+	loop {
+		let mut line = String::new();
+		if stream.read_line(&mut line).await.map_err(bad_request)? == 0 {
+			break;
+		}
+		let line = line.trim_end();
+		if line.is_empty() {
+			break;
+		}
+		if let Some((name, value)) = line.split_once(':') {
+			headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+		}
+	}
+	let content_length = match headers.get("content-length") {
+		Some(len) => len.parse::<usize>().map_err(|_| Response::new(400, "Bad request"))?,
+		None => 0,
+	};
+	if content_length > MAX_BODY_LEN {
+		return Err(Response::new(413, "Payload too large"));
+	}
+	let mut body = vec![0; content_length];
+	stream.read_exact(&mut body).await.map_err(bad_request)?;
+	Ok(Request { method, path: path.to_string(), query, headers, body })
+}
+
+async fn serve_connection(stream: TcpStream, server: Arc<BackupServer>) -> std::io::Result<()> {
+	let mut stream = BufReader::new(stream);
+	let response = match read_request(&mut stream).await {
+		Ok(request) => tokio::task::spawn_blocking(move || server.handle(&request)).await.unwrap(),
+		Err(response) => response,
+	};
+	let reason = match response.status {
+		200 => "OK",
+		400 => "Bad Request",
+		401 => "Unauthorized",
+		404 => "Not Found",
+		405 => "Method Not Allowed",
+		409 => "Conflict",
+		413 => "Payload Too Large",
+		_ => "Internal Server Error",
+	};
+	let head = format!(
+		"HTTP/1.1 {} {}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+		response.status,
+		reason,
+		response.body.len()
+	);
+	let stream = stream.get_mut();
+	stream.write_all(head.as_bytes()).await?;
+	stream.write_all(&response.body).await?;
+	stream.shutdown().await
+}
+
+async fn serve(listener: TcpListener, server: Arc<BackupServer>) {
+	loop {
+		let (stream, _) = match listener.accept().await {
+			Ok(conn) => conn,
+			Err(e) => {
+				println!("ERROR: Failed to accept a connection: {}", e);
+				continue;
+			},
+		};
+		let server = Arc::clone(&server);
+		tokio::spawn(async move {
+			let _ = serve_connection(stream, server).await;
+		});
+	}
+}
+
+#[tokio::main]
+async fn main() {
+	let (options, args): (Vec<String>, Vec<String>) =
+		env::args().partition(|arg| arg.starts_with("--"));
+	if args.len() != 3 {
+		println!(
+			"Usage: `cargo run -p backup-server -- <listen-addr> <backup-data-dir> [--token=<token>]`"
+		);
+		process::exit(1);
+	}
+	let mut token = None;
+	for option in options {
+		match option.split_once('=') {
+			Some(("--token", value)) if !value.is_empty() => token = Some(value.to_string()),
+			_ => {
+				println!("ERROR: unknown option {}", option);
+				process::exit(1);
+			},
+		}
+	}
+
+	let listener = match TcpListener::bind(&args[1]).await {
+		Ok(listener) => listener,
+		Err(e) => {
+			println!("ERROR: Failed to listen on {}: {}", args[1], e);
+			process::exit(1);
+		},
+	};
+	let server = match BackupServer::new(Path::new(&args[2]), token) {
+		Ok(server) => Arc::new(server),
+		Err(e) => {
+			println!("ERROR: Failed to read the versions in {}: {}", args[2], e);
+			process::exit(1);
+		},
+	};
+	println!("Backing up node data to {}, listening on {}", args[2], args[1]);
+	serve(listener, server).await;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use ldk::http_backup::HttpBackupClient;
+	use lightning::io;
+	use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+	async fn start_server(name: &str, token: Option<&str>) -> (String, Arc<BackupServer>) {
+		let data_dir =
+			env::temp_dir().join(format!("backup-server-test-{}-{}", name, process::id()));
+		let _ = std::fs::remove_dir_all(&data_dir);
+		let server = Arc::new(BackupServer::new(&data_dir, token.map(String::from)).unwrap());
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let url = format!("http://{}", listener.local_addr().unwrap());
+		tokio::spawn(serve(listener, Arc::clone(&server)));
+		(url, server)
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_backup_round_trip() {
+		let (url, _) = start_server("round-trip", None).await;
+		tokio::task::spawn_blocking(move || {
+			let client = HttpBackupClient::new(&url, None).unwrap();
+			client.write("", "", "manager", &[1, 2, 3]).unwrap();
+			client.write("monitor_updates", "chan_a", "1", &[4]).unwrap();
+			client.write("monitor_updates", "chan_a", "2", &[5]).unwrap();
+			assert_eq!(client.read("", "", "manager").unwrap(), vec![1, 2, 3]);
+
+			let mut keys = client.list("monitor_updates", "chan_a").unwrap();
+			keys.sort();
+			assert_eq!(keys, vec!["1".to_string(), "2".to_string()]);
+
+			client.remove("monitor_updates", "chan_a", "1", true).unwrap();
+			assert_eq!(client.list("monitor_updates", "chan_a").unwrap(), vec!["2".to_string()]);
+			assert_eq!(
+				client.read("monitor_updates", "chan_a", "1").unwrap_err().kind(),
+				io::ErrorKind::NotFound
+			);
+		})
+		.await
+		.unwrap();
+	}
+
+	fn object_request(method: &str, version: u64, body: &[u8]) -> Request {
+		let query = [("primary_namespace", ""), ("secondary_namespace", ""), ("key", "manager")];
+		let headers = [(VERSION_HEADER.to_lowercase(), version.to_string())];
+		Request {
+			method: method.to_string(),
+			path: OBJECTS_PATH.to_string(),
+			query: query
+				.iter()
+				.map(|(name, value)| (name.to_string(), value.to_string()))
+				.collect(),
+			headers: headers.iter().cloned().collect(),
+			body: body.to_vec(),
+		}
+	}
+
+	#[test]
+	fn test_stale_writes_are_rejected() {
+		let data_dir = env::temp_dir().join(format!("backup-server-test-stale-{}", process::id()));
+		let _ = std::fs::remove_dir_all(&data_dir);
+		let server = BackupServer::new(&data_dir, None).unwrap();
+		assert_eq!(server.handle(&object_request("PUT", 2, &[2])).status, 200);
+		assert_eq!(server.handle(&object_request("PUT", 1, &[1])).status, 409);
+		assert_eq!(server.handle(&object_request("GET", 0, &[])).body, vec![2]);
+
+		// Neither can a delayed write bring back a removed entry.
+		assert_eq!(server.handle(&object_request("DELETE", 4, &[])).status, 200);
+		assert_eq!(server.handle(&object_request("PUT", 3, &[3])).status, 409);
+		assert_eq!(server.handle(&object_request("GET", 0, &[])).status, 404);
+		assert_eq!(server.handle(&object_request("PUT", 5, &[5])).status, 200);
+		assert_eq!(server.handle(&object_request("GET", 0, &[])).body, vec![5]);
+
+		// The highest version is found again after a restart.
+		let server = BackupServer::new(&data_dir, None).unwrap();
+		assert_eq!(*server.latest_version.lock().unwrap(), 5);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_versions_survive_the_clock_going_backwards() {
+		let (url, server) = start_server("clock", None).await;
+		// Before its clock was stepped back an hour, the node wrote versions which followed it.
+		let clock_ahead = SystemTime::now() + Duration::from_secs(60 * 60);
+		let version = clock_ahead.duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64;
+		assert_eq!(server.handle(&object_request("PUT", version, &[1])).status, 200);
+
+		tokio::task::spawn_blocking(move || {
+			let client = HttpBackupClient::new(&url, None).unwrap();
+			client.write("", "", "manager", &[2]).unwrap();
+			assert_eq!(client.read("", "", "manager").unwrap(), vec![2]);
+
+			// A second node writing to the same backup makes our versions stale, which must not
+			// be mistaken for a successful write.
+			let other_client = HttpBackupClient::new(&url, None).unwrap();
+			other_client.write("", "", "manager", &[3]).unwrap();
+			other_client.write("", "", "manager", &[4]).unwrap();
+			let err = client.write("", "", "manager", &[5]).unwrap_err();
+			assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+			assert_eq!(client.read("", "", "manager").unwrap(), vec![4]);
+		})
+		.await
+		.unwrap();
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_token_is_required() {
+		let (url, _) = start_server("token", Some("secret")).await;
+		tokio::task::spawn_blocking(move || {
+			let client = HttpBackupClient::new(&url, None).unwrap();
+			let err = client.write("", "", "manager", &[1]).unwrap_err();
+			assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+			let client = HttpBackupClient::new(&url, Some("secret".to_string())).unwrap();
+			client.write("", "", "manager", &[1]).unwrap();
+			assert_eq!(client.read("", "", "manager").unwrap(), vec![1]);
+		})
+		.await
+		.unwrap();
+	}
+}
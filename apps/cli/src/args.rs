@@ -1,33 +1,53 @@
-use crate::cli::LdkUserInfo;
+use crate::cli::{
+	BackupConfig, BitcoindRpcInfo, ChainSourceConfig, DataStoreConfig, EncryptionConfig,
+	LdkUserInfo,
+};
 use bitcoin::network::Network;
+use ldk::fee_policy::{EstimateMode, FeePolicy, FeeSource};
+use ldk::logging::{LogConfig, LogFormat, LogRotation};
+use ldk::scoring::ScoringParams;
+use ldk::webhooks::WebhookConfig;
+use lightning::chain::chaininterface::ConfirmationTarget;
 use lightning::ln::msgs::SocketAddress;
+use lightning::util::logger::Level;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::env;
 use std::fs;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
 pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
-	if env::args().len() < 3 {
-		println!("ldk-tutorial-node requires at least 2 arguments: `cargo run [<bitcoind-rpc-username>:<bitcoind-rpc-password>@]<bitcoind-rpc-host>:<bitcoind-rpc-port> ldk_storage_directory_path [<ldk-incoming-peer-listening-port>] [bitcoin-network] [announced-node-name announced-listen-addr*]`");
-		return Err(());
+	// Options of the form `--name=value` may appear anywhere, everything else is positional.
+	let (options, mut args): (Vec<String>, Vec<String>) =
+		env::args().partition(|arg| arg.starts_with("--"));
+
+	// The chain source decides the positional arguments: only bitcoind's needs its RPC details, as
+	// nodes syncing from Esplora or Electrum run without bitcoind.
+	let mut chain_source = ChainSourceConfig::Bitcoind;
+	for option in &options {
+		if let Some(value) = option.strip_prefix("--chain-source=") {
+			chain_source = parse_chain_source(value)?;
+		}
 	}
-	let bitcoind_rpc_info = env::args().skip(1).next().unwrap();
-	let bitcoind_rpc_info_parts: Vec<&str> = bitcoind_rpc_info.rsplitn(2, "@").collect();
-
-	// Parse rpc auth after getting network for default .cookie location
-	let bitcoind_rpc_path: Vec<&str> = bitcoind_rpc_info_parts[0].split(":").collect();
-	if bitcoind_rpc_path.len() != 2 {
-		println!("ERROR: bad bitcoind RPC path provided");
+	let min_args = match chain_source {
+		ChainSourceConfig::Bitcoind => 3,
+		ChainSourceConfig::Esplora(_) | ChainSourceConfig::Electrum(_) => 2,
+	};
+	if args.len() < min_args {
+		println!("ldk-tutorial-node requires at least 2 arguments, or only the storage directory with an Esplora or Electrum chain source: `cargo run [[<bitcoind-rpc-username>:<bitcoind-rpc-password>@]<bitcoind-rpc-host>:<bitcoind-rpc-port>] ldk_storage_directory_path [<ldk-incoming-peer-listening-port>] [bitcoin-network] [announced-node-name announced-listen-addr*] [--chain-source=<bitcoind|esplora:url|electrum:url>] [--zmq-block=<endpoint>] [--zmq-rawtx=<endpoint>] [--bitcoind-wallet=<name>] [--create-bitcoind-wallet] [--fee-policy=<path>] [--scoring-params=<path>] [--data-store=<filesystem|sqlite>] [--encrypt | --encryption-keyfile=<path>] [--restore-from-mnemonic] [--bip39-passphrase] [--mnemonic-export=<path>] [--backup=<dir|http://host:port>] [--backup-token=<token>] [--backup-max-wait=<seconds>] [--force-unlock] [--rgs=<url|path>] [--sweep-batch-blocks=<n>] [--sweep-fee-blocks=<n>] [--webhook=<url>]* [--webhook-secret=<secret>] [--event-stream=<host:port>] [--metrics=<host:port>] [--log-level=<level>] [--log-filter=<module>=<level>]* [--log-rotation=<never|daily|size:MiB>] [--log-retention=<files>] [--log-json]`");
 		return Err(());
 	}
-	let bitcoind_rpc_host = bitcoind_rpc_path[0].to_string();
-	let bitcoind_rpc_port = bitcoind_rpc_path[1].parse::<u16>().unwrap();
-
-	let ldk_storage_dir_path = env::args().skip(2).next().unwrap();
+	let bitcoind_rpc_info = match chain_source {
+		ChainSourceConfig::Bitcoind => Some(args.remove(1)),
+		ChainSourceConfig::Esplora(_) | ChainSourceConfig::Electrum(_) => None,
+	};
+	let ldk_storage_dir_path = args[1].clone();
 
 	let mut ldk_peer_port_set = true;
-	let ldk_peer_listening_port: u16 = match env::args().skip(3).next().map(|p| p.parse()) {
+	let ldk_peer_listening_port: u16 = match args.get(2).map(|p| p.parse()) {
 		Some(Ok(p)) => p,
 		Some(Err(_)) => {
 			ldk_peer_port_set = false;
@@ -40,10 +60,10 @@ pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
 	};
 
 	let mut arg_idx = match ldk_peer_port_set {
-		true => 4,
-		false => 3,
+		true => 3,
+		false => 2,
 	};
-	let network: Network = match env::args().skip(arg_idx).next().as_ref().map(String::as_str) {
+	let network: Network = match args.get(arg_idx).map(String::as_str) {
 		Some("testnet") => Network::Testnet,
 		Some("regtest") => Network::Regtest,
 		Some("signet") => Network::Signet,
@@ -53,23 +73,13 @@ pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
 		None => Network::Testnet,
 	};
 
-	let (bitcoind_rpc_username, bitcoind_rpc_password) = if bitcoind_rpc_info_parts.len() == 1 {
-		get_rpc_auth_from_env_vars()
-			.or(get_rpc_auth_from_env_file(None))
-			.or(get_rpc_auth_from_cookie(None, Some(network), None))
-			.or({
-				println!("ERROR: unable to get bitcoind RPC username and password");
-				print_rpc_auth_help();
-				Err(())
-			})?
-	} else if bitcoind_rpc_info_parts.len() == 2 {
-		parse_rpc_auth(bitcoind_rpc_info_parts[1])?
-	} else {
-		println!("ERROR: bad bitcoind RPC URL provided");
-		return Err(());
+	// Parse rpc auth after getting network for default .cookie location
+	let bitcoind_rpc = match bitcoind_rpc_info {
+		Some(info) => Some(parse_bitcoind_rpc(&info, network)?),
+		None => None,
 	};
 
-	let ldk_announced_node_name = match env::args().skip(arg_idx + 1).next().as_ref() {
+	let ldk_announced_node_name = match args.get(arg_idx + 1) {
 		Some(s) => {
 			if s.len() > 32 {
 				panic!("Node Alias can not be longer than 32 bytes");
@@ -84,7 +94,7 @@ pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
 
 	let mut ldk_announced_listen_addr = Vec::new();
 	loop {
-		match env::args().skip(arg_idx + 1).next().as_ref() {
+		match args.get(arg_idx + 1) {
 			Some(s) => match SocketAddress::from_str(s) {
 				Ok(sa) => {
 					ldk_announced_listen_addr.push(sa);
@@ -96,19 +106,347 @@ pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
 		}
 	}
 
+	let (mut zmq_block_endpoint, mut zmq_rawtx_endpoint) = (None, None);
+	let mut bitcoind_wallet_name = None;
+	let mut create_bitcoind_wallet = false;
+	let mut fee_policy = FeePolicy::default();
+	let mut scoring_params = ScoringParams::default();
+	let mut data_store = DataStoreConfig::Filesystem;
+	let mut encryption = EncryptionConfig::Disabled;
+	let mut restore_from_mnemonic = false;
+	let mut bip39_passphrase = false;
+	let mut mnemonic_export_path = None;
+	let (mut backup, mut backup_token, mut backup_max_wait) = (None, None, None);
+	let mut force_unlock = false;
+	let mut rgs_source = None;
+	let mut sweep_batch_blocks = 1;
+	let mut sweep_fee_blocks = None;
+	let mut webhooks = WebhookConfig::default();
+	let mut event_stream_addr = None;
+	let mut metrics_addr = None;
+	let mut logging = LogConfig::default();
+	for option in options {
+		let (name, value) = match option.split_once('=') {
+			Some(name_and_value) => name_and_value,
+			None if option == "--create-bitcoind-wallet" => {
+				create_bitcoind_wallet = true;
+				continue;
+			},
+			None if option == "--encrypt" => {
+				if let EncryptionConfig::Keyfile(_) = encryption {
+					println!("ERROR: --encrypt and --encryption-keyfile can't be used together");
+					return Err(());
+				}
+				encryption = EncryptionConfig::Passphrase;
+				continue;
+			},
+			None if option == "--restore-from-mnemonic" => {
+				restore_from_mnemonic = true;
+				continue;
+			},
+			None if option == "--bip39-passphrase" => {
+				bip39_passphrase = true;
+				continue;
+			},
+			None if option == "--force-unlock" => {
+				force_unlock = true;
+				continue;
+			},
+			None if option == "--log-json" => {
+				logging.format = LogFormat::JsonLines;
+				continue;
+			},
+			None => {
+				println!("ERROR: option {} requires a value: `{}=<value>`", option, option);
+				return Err(());
+			},
+		};
+		match name {
+			// Parsed up front, as it decides the positional arguments.
+			"--chain-source" => {},
+			"--zmq-block" => zmq_block_endpoint = Some(value.to_string()),
+			"--zmq-rawtx" => zmq_rawtx_endpoint = Some(value.to_string()),
+			"--bitcoind-wallet" => bitcoind_wallet_name = Some(value.to_string()),
+			"--fee-policy" => fee_policy = read_fee_policy(value)?,
+			"--scoring-params" => scoring_params = read_scoring_params(value)?,
+			"--data-store" => data_store = parse_data_store(value)?,
+			"--encryption-keyfile" => {
+				if let EncryptionConfig::Passphrase = encryption {
+					println!("ERROR: --encrypt and --encryption-keyfile can't be used together");
+					return Err(());
+				}
+				encryption = EncryptionConfig::Keyfile(value.to_string());
+			},
+			"--mnemonic-export" => mnemonic_export_path = Some(value.to_string()),
+			"--backup" if !value.is_empty() => backup = Some(value.to_string()),
+			"--backup-token" => backup_token = Some(value.to_string()),
+			"--backup-max-wait" => match value.parse::<u64>() {
+				Ok(secs) => backup_max_wait = Some(Duration::from_secs(secs)),
+				Err(_) => {
+					println!("ERROR: --backup-max-wait must be a number of seconds, got {}", value);
+					return Err(());
+				},
+			},
+			"--rgs" if !value.is_empty() => rgs_source = Some(value.to_string()),
+			"--sweep-batch-blocks" => sweep_batch_blocks = parse_blocks(name, value)?,
+			"--sweep-fee-blocks" => sweep_fee_blocks = Some(parse_blocks(name, value)?),
+			"--webhook" if value.starts_with("http://") || value.starts_with("https://") => {
+				webhooks.urls.push(value.to_string())
+			},
+			"--webhook" => {
+				println!("ERROR: webhook URLs must start with http:// or https://, got {}", value);
+				return Err(());
+			},
+			"--webhook-secret" if !value.is_empty() => webhooks.secret = Some(value.to_string()),
+			"--event-stream" => match value.parse::<SocketAddr>() {
+				Ok(addr) => event_stream_addr = Some(addr),
+				Err(_) => {
+					println!("ERROR: --event-stream must be a host:port to listen on, got {}", value);
+					return Err(());
+				},
+			},
+			"--metrics" => match value.parse::<SocketAddr>() {
+				Ok(addr) => metrics_addr = Some(addr),
+				Err(_) => {
+					println!("ERROR: --metrics must be a host:port to listen on, got {}", value);
+					return Err(());
+				},
+			},
+			"--log-level" => logging.min_level = parse_log_level(value)?,
+			"--log-filter" => match value.split_once('=') {
+				Some((module, level)) if !module.is_empty() => {
+					logging.module_levels.push((module.to_string(), parse_log_level(level)?))
+				},
+				_ => {
+					println!("ERROR: --log-filter must be <module>=<level>, got {}", value);
+					return Err(());
+				},
+			},
+			"--log-rotation" => logging.rotation = parse_log_rotation(value)?,
+			"--log-retention" => match value.parse::<usize>() {
+				Ok(files) => logging.max_rotated_files = files,
+				Err(_) => {
+					println!("ERROR: --log-retention must be a number of files, got {}", value);
+					return Err(());
+				},
+			},
+			_ => {
+				println!("ERROR: unknown option {}", name);
+				return Err(());
+			},
+		}
+	}
+
+	let zmq_configured = zmq_block_endpoint.is_some() || zmq_rawtx_endpoint.is_some();
+	if zmq_configured && !matches!(chain_source, ChainSourceConfig::Bitcoind) {
+		println!("ERROR: ZMQ notifications can only be used with the bitcoind chain source");
+		return Err(());
+	}
+
+	if bitcoind_wallet_name.is_some() && bitcoind_rpc.is_none() {
+		println!("ERROR: --bitcoind-wallet can only be used with the bitcoind chain source");
+		return Err(());
+	}
+
+	if create_bitcoind_wallet && bitcoind_wallet_name.is_none() {
+		println!("ERROR: --create-bitcoind-wallet requires a wallet name set with --bitcoind-wallet");
+		return Err(());
+	}
+
+	if restore_from_mnemonic && mnemonic_export_path.is_some() {
+		println!("ERROR: --mnemonic-export can't be used when restoring from a mnemonic");
+		return Err(());
+	}
+
+	// Sweeps use LDK's `OutputSpendingFee` target, so a sweep fee target overrides the fee
+	// policy's estimate for it, keeping whatever estimate mode the policy set.
+	if let Some(blocks) = sweep_fee_blocks {
+		let blocks = u16::try_from(blocks).map_err(|_| {
+			println!("ERROR: --sweep-fee-blocks must be at most {}", u16::MAX);
+		})?;
+		let target = fee_policy.target_mut(ConfirmationTarget::OutputSpendingFee);
+		let mode = match target.source {
+			FeeSource::Estimate { mode, .. } => mode,
+			FeeSource::MempoolMinimum => EstimateMode::Economical,
+		};
+		target.source = FeeSource::Estimate { blocks, mode };
+	}
+
+	if webhooks.secret.is_some() && webhooks.urls.is_empty() {
+		println!("ERROR: --webhook-secret requires a webhook URL set with --webhook");
+		return Err(());
+	}
+
+	if backup_max_wait.is_some() && backup.is_none() {
+		println!("ERROR: --backup-max-wait requires a backup set with --backup");
+		return Err(());
+	}
+
+	let backup = match backup {
+		Some(url) if url.starts_with("http://") => {
+			Some(BackupConfig::Http { url, token: backup_token })
+		},
+		_ if backup_token.is_some() => {
+			println!("ERROR: --backup-token requires an HTTP backup server set with --backup");
+			return Err(());
+		},
+		Some(dir) => Some(BackupConfig::Directory(dir)),
+		None => None,
+	};
+
 	Ok(LdkUserInfo {
-		bitcoind_rpc_username,
-		bitcoind_rpc_password,
-		bitcoind_rpc_host,
-		bitcoind_rpc_port,
+		bitcoind_rpc,
+		bitcoind_wallet_name,
+		create_bitcoind_wallet,
 		ldk_storage_dir_path,
 		ldk_peer_listening_port,
 		ldk_announced_listen_addr,
 		ldk_announced_node_name,
 		network,
+		chain_source,
+		zmq_block_endpoint,
+		zmq_rawtx_endpoint,
+		fee_policy,
+		scoring_params,
+		data_store,
+		encryption,
+		restore_from_mnemonic,
+		bip39_passphrase,
+		mnemonic_export_path,
+		backup,
+		backup_max_wait,
+		force_unlock,
+		rgs_source,
+		sweep_batch_blocks,
+		webhooks,
+		event_stream_addr,
+		metrics_addr,
+		logging,
+	})
+}
+
+fn parse_bitcoind_rpc(bitcoind_rpc_info: &str, network: Network) -> Result<BitcoindRpcInfo, ()> {
+	let bitcoind_rpc_info_parts: Vec<&str> = bitcoind_rpc_info.rsplitn(2, "@").collect();
+
+	let bitcoind_rpc_path: Vec<&str> = bitcoind_rpc_info_parts[0].split(":").collect();
+	if bitcoind_rpc_path.len() != 2 {
+		println!("ERROR: bad bitcoind RPC path provided");
+		return Err(());
+	}
+	let host = bitcoind_rpc_path[0].to_string();
+	let port = bitcoind_rpc_path[1].parse::<u16>().unwrap();
+
+	let (username, password) = if bitcoind_rpc_info_parts.len() == 1 {
+		get_rpc_auth_from_env_vars()
+			.or(get_rpc_auth_from_env_file(None))
+			.or(get_rpc_auth_from_cookie(None, Some(network), None))
+			.or({
+				println!("ERROR: unable to get bitcoind RPC username and password");
+				print_rpc_auth_help();
+				Err(())
+			})?
+	} else if bitcoind_rpc_info_parts.len() == 2 {
+		parse_rpc_auth(bitcoind_rpc_info_parts[1])?
+	} else {
+		println!("ERROR: bad bitcoind RPC URL provided");
+		return Err(());
+	};
+	Ok(BitcoindRpcInfo { username, password, host, port })
+}
+
+fn parse_blocks(name: &str, value: &str) -> Result<u32, ()> {
+	match value.parse::<u32>() {
+		Ok(blocks) if blocks > 0 => Ok(blocks),
+		_ => {
+			println!("ERROR: {} must be a positive number of blocks, got {}", name, value);
+			Err(())
+		},
+	}
+}
+
+fn read_fee_policy(path: &str) -> Result<FeePolicy, ()> {
+	let json = fs::read_to_string(path).map_err(|e| {
+		println!("ERROR: unable to read fee policy file {}: {}", path, e);
+	})?;
+	FeePolicy::from_json(&json).map_err(|e| {
+		println!("ERROR: invalid fee policy in {}: {}", path, e);
 	})
 }
 
+fn read_scoring_params(path: &str) -> Result<ScoringParams, ()> {
+	let json = fs::read_to_string(path).map_err(|e| {
+		println!("ERROR: unable to read scoring parameters file {}: {}", path, e);
+	})?;
+	ScoringParams::from_json(&json).map_err(|e| {
+		println!("ERROR: invalid scoring parameters in {}: {}", path, e);
+	})
+}
+
+fn parse_chain_source(chain_source: &str) -> Result<ChainSourceConfig, ()> {
+	match chain_source.split_once(':') {
+		_ if chain_source == "bitcoind" => Ok(ChainSourceConfig::Bitcoind),
+		Some(("esplora", url)) if !url.is_empty() => Ok(ChainSourceConfig::Esplora(url.to_string())),
+		Some(("electrum", url)) if !url.is_empty() => {
+			Ok(ChainSourceConfig::Electrum(url.to_string()))
+		},
+		_ => {
+			println!(
+				"ERROR: bad chain source provided. Options are: `bitcoind`, `esplora:<url>` and `electrum:<url>`. Got {}",
+				chain_source
+			);
+			Err(())
+		},
+	}
+}
+
+fn parse_data_store(data_store: &str) -> Result<DataStoreConfig, ()> {
+	match data_store {
+		"filesystem" => Ok(DataStoreConfig::Filesystem),
+		"sqlite" => Ok(DataStoreConfig::Sqlite),
+		_ => {
+			println!(
+				"ERROR: bad data store provided. Options are: `filesystem` and `sqlite`. Got {}",
+				data_store
+			);
+			Err(())
+		},
+	}
+}
+
+fn parse_log_level(level: &str) -> Result<Level, ()> {
+	match level {
+		"gossip" => Ok(Level::Gossip),
+		"trace" => Ok(Level::Trace),
+		"debug" => Ok(Level::Debug),
+		"info" => Ok(Level::Info),
+		"warn" => Ok(Level::Warn),
+		"error" => Ok(Level::Error),
+		_ => {
+			println!(
+				"ERROR: bad log level provided. Options are: `gossip`, `trace`, `debug`, `info`, `warn` and `error`. Got {}",
+				level
+			);
+			Err(())
+		},
+	}
+}
+
+fn parse_log_rotation(rotation: &str) -> Result<LogRotation, ()> {
+	let size_mib = rotation.strip_prefix("size:").and_then(|mib| mib.parse::<u64>().ok());
+	match (rotation, size_mib) {
+		("never", _) => Ok(LogRotation::Never),
+		("daily", _) => Ok(LogRotation::Daily),
+		(_, Some(mib)) if mib > 0 => Ok(LogRotation::Size(mib.saturating_mul(1024 * 1024))),
+		_ => {
+			println!(
+				"ERROR: bad log rotation provided. Options are: `never`, `daily` and `size:<MiB>`. Got {}",
+				rotation
+			);
+			Err(())
+		},
+	}
+}
+
 // Default datadir relative to home directory
 #[cfg(target_os = "windows")]
 const DEFAULT_BITCOIN_DATADIR: &str = "AppData/Roaming/Bitcoin";
@@ -362,3 +700,43 @@ mod rpc_auth_tests {
 		assert_eq!(password, EXPECTED_PASSWORD);
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_chain_source() {
+		assert!(matches!(parse_chain_source("bitcoind"), Ok(ChainSourceConfig::Bitcoind)));
+		match parse_chain_source("esplora:http://127.0.0.1:3002") {
+			Ok(ChainSourceConfig::Esplora(url)) => assert_eq!(url, "http://127.0.0.1:3002"),
+			_ => panic!("Expected an Esplora chain source"),
+		}
+		match parse_chain_source("electrum:ssl://electrum.blockstream.info:60002") {
+			Ok(ChainSourceConfig::Electrum(url)) => {
+				assert_eq!(url, "ssl://electrum.blockstream.info:60002")
+			},
+			_ => panic!("Expected an Electrum chain source"),
+		}
+		assert!(parse_chain_source("esplora:").is_err());
+		assert!(parse_chain_source("neutrino").is_err());
+	}
+
+	#[test]
+	fn test_parse_data_store() {
+		assert!(matches!(parse_data_store("filesystem"), Ok(DataStoreConfig::Filesystem)));
+		assert!(matches!(parse_data_store("sqlite"), Ok(DataStoreConfig::Sqlite)));
+		assert!(parse_data_store("postgres").is_err());
+	}
+
+	#[test]
+	fn test_parse_bitcoind_rpc() {
+		let rpc =
+			parse_bitcoind_rpc("testuser:testpassword@127.0.0.1:18443", Network::Regtest).unwrap();
+		assert_eq!(rpc.username, "testuser");
+		assert_eq!(rpc.password, "testpassword");
+		assert_eq!(rpc.host, "127.0.0.1");
+		assert_eq!(rpc.port, 18443);
+		assert!(parse_bitcoind_rpc("testuser:testpassword@127.0.0.1", Network::Regtest).is_err());
+	}
+}
@@ -0,0 +1,127 @@
+use std::sync::Arc;
+use lightning::chain::channelmonitor::Balance;
+use lightning::util::sweep::OutputSpendStatus;
+use ldk::bitcoind_client::BitcoindClient;
+use ldk::common::{ChainMonitor, ChannelManager, OutputSweeper};
+use crate::sweeps::{output_of, print_tracked_output};
+
+/// Prints a claimable balance of a channel, with the height it is waiting for, if any.
+pub(crate) fn print_balance(balance: &Balance, indent: &str) {
+    println!("{}{{", indent);
+    match balance {
+        Balance::ClaimableOnChannelClose { amount_satoshis, transaction_fee_satoshis, .. } => {
+            println!("{}\tkind: claimable_on_channel_close,", indent);
+            println!("{}\tamount_satoshis: {},", indent, amount_satoshis);
+            println!("{}\ttransaction_fee_satoshis: {},", indent, transaction_fee_satoshis);
+        },
+        Balance::ClaimableAwaitingConfirmations { amount_satoshis, confirmation_height, source } => {
+            println!("{}\tkind: awaiting_confirmations,", indent);
+            println!("{}\tamount_satoshis: {},", indent, amount_satoshis);
+            println!("{}\tconfirmation_height: {},", indent, confirmation_height);
+            println!("{}\tsource: {:?},", indent, source);
+        },
+        Balance::ContentiousClaimable { amount_satoshis, timeout_height, payment_hash, .. } => {
+            println!("{}\tkind: contentious_htlc,", indent);
+            println!("{}\tamount_satoshis: {},", indent, amount_satoshis);
+            println!("{}\ttimeout_height: {},", indent, timeout_height);
+            println!("{}\tpayment_hash: {},", indent, payment_hash);
+        },
+        Balance::MaybeTimeoutClaimableHTLC { amount_satoshis, claimable_height, payment_hash, outbound_payment } => {
+            println!("{}\tkind: htlc_awaiting_timeout,", indent);
+            println!("{}\tamount_satoshis: {},", indent, amount_satoshis);
+            println!("{}\tclaimable_height: {},", indent, claimable_height);
+            println!("{}\tpayment_hash: {},", indent, payment_hash);
+            println!("{}\toutbound_payment: {},", indent, outbound_payment);
+        },
+        Balance::MaybePreimageClaimableHTLC { amount_satoshis, expiry_height, payment_hash } => {
+            println!("{}\tkind: htlc_awaiting_preimage,", indent);
+            println!("{}\tamount_satoshis: {},", indent, amount_satoshis);
+            println!("{}\texpiry_height: {},", indent, expiry_height);
+            println!("{}\tpayment_hash: {},", indent, payment_hash);
+        },
+        Balance::CounterpartyRevokedOutputClaimable { amount_satoshis } => {
+            println!("{}\tkind: counterparty_revoked_output,", indent);
+            println!("{}\tamount_satoshis: {},", indent, amount_satoshis);
+        },
+    }
+    println!("{}}},", indent);
+}
+
+/// Prints where each of our sats is: in channels, being claimed on-chain after a close, being
+/// swept to our wallet, or in the wallet itself.
+pub(crate) fn balances_cli(
+    channel_manager: &Arc<ChannelManager>, chain_monitor: &Arc<ChainMonitor>,
+    output_sweeper: &Arc<OutputSweeper>, bitcoind_client: Option<&Arc<BitcoindClient>>,
+) {
+    let open_channels = channel_manager.list_channels();
+    let current_height = channel_manager.current_best_block().height;
+    let mut channels_sats = 0;
+    println!("{{");
+    println!("\tcurrent_height: {},", current_height);
+    println!("\tchannels: [");
+    for (funding_txo, channel_id) in chain_monitor.list_monitors() {
+        let monitor = match chain_monitor.get_monitor(funding_txo) {
+            Ok(monitor) => monitor,
+            Err(()) => continue,
+        };
+        let balances = monitor.get_claimable_balances();
+        if balances.is_empty() {
+            // Everything we could claim from the channel has been claimed and buried.
+            continue;
+        }
+        let is_open = open_channels.iter().any(|channel| channel.channel_id == channel_id);
+        let channel_sats: u64 = balances.iter().map(|balance| balance.claimable_amount_satoshis()).sum();
+        channels_sats += channel_sats;
+        println!("\t\t{{");
+        println!("\t\t\tchannel_id: {},", channel_id);
+        if let Some(counterparty_node_id) = monitor.get_counterparty_node_id() {
+            println!("\t\t\tcounterparty: {},", counterparty_node_id);
+        }
+        println!("\t\t\tfunding_txo: {},", funding_txo);
+        println!("\t\t\tstate: {},", if is_open { "open" } else { "closing" });
+        println!("\t\t\ttotal_sats: {},", channel_sats);
+        println!("\t\t\tbalances: [");
+        for balance in balances.iter() {
+            print_balance(balance, "\t\t\t\t");
+        }
+        println!("\t\t\t],");
+        println!("\t\t}},");
+    }
+    println!("\t],");
+
+    let mut sweeping_sats = 0;
+    println!("\tsweeper_outputs: [");
+    for tracked in output_sweeper.tracked_spendable_outputs() {
+        // Once a sweep is broadcast, its output is the wallet's (which counts it as pending) or
+        // went to the sweep destination, so only outputs not swept yet are still ours to count.
+        if let OutputSpendStatus::PendingInitialBroadcast { .. } = tracked.status {
+            sweeping_sats += output_of(&tracked.descriptor).1.to_sat();
+        }
+        print_tracked_output(&tracked, current_height, "\t\t");
+    }
+    println!("\t],");
+
+    let mut wallet_sats = 0;
+    let wallet_balances = match bitcoind_client {
+        Some(bitcoind_client) => tokio::runtime::Handle::current()
+            .block_on(bitcoind_client.get_wallet_balances())
+            .map_err(|e| e.to_string()),
+        None => Err("there's no wallet without bitcoind".to_string()),
+    };
+    match wallet_balances {
+        Ok(wallet) => {
+            wallet_sats = wallet.trusted_sats + wallet.untrusted_pending_sats + wallet.immature_sats;
+            println!("\tonchain_wallet: {{");
+            println!("\t\ttrusted_sats: {},", wallet.trusted_sats);
+            println!("\t\tuntrusted_pending_sats: {},", wallet.untrusted_pending_sats);
+            println!("\t\timmature_sats: {},", wallet.immature_sats);
+            println!("\t}},");
+        },
+        Err(e) => println!("\tonchain_wallet: unavailable ({}),", e),
+    }
+    println!("\ttotal_channels_sats: {},", channels_sats);
+    println!("\ttotal_sweeping_sats: {},", sweeping_sats);
+    println!("\ttotal_onchain_wallet_sats: {},", wallet_sats);
+    println!("\ttotal_sats: {},", channels_sats + sweeping_sats + wallet_sats);
+    println!("}}");
+}
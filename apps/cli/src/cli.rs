@@ -1,6 +1,6 @@
 use ldk::common::{
 	ChainMonitor, ChannelManager, HTLCStatus, InboundPaymentInfoStorage, MillisatAmount,
-	NetworkGraph, OutboundPaymentInfoStorage, PaymentInfo, PeerManager,
+	NetworkGraph, OutboundPaymentInfoStorage, OutputSweeper, PaymentInfo, PeerManager, Router,
 };
 use bitcoin::hashes::sha256::Hash as Sha256;
 use bitcoin::hashes::Hash;
@@ -17,7 +17,18 @@ use lightning::ln::msgs::SocketAddress;
 use lightning::ln::types::ChannelId;
 use lightning::routing::gossip::NodeId;
 use lightning::sign::KeysManager;
-use lightning_persister::fs_store::FilesystemStore;
+use ldk::data_store::DataStore;
+use ldk::event_stream::EventStream;
+use ldk::bitcoind_client::BitcoindClient;
+use ldk::broadcast_queue::BroadcastQueue;
+use ldk::closed_channels::ClosedChannels;
+use ldk::fee_policy::{FeeEstimates, FeePolicy};
+use ldk::forwarding_history::ForwardingHistory;
+use ldk::logging::LogConfig;
+use ldk::metrics::Metrics;
+use ldk::scoring::{ScorerManager, ScoringParams};
+use ldk::sweep::SweepPolicy;
+use ldk::webhooks::{WebhookConfig, Webhooks};
 use rustyline::DefaultEditor;
 use crate::get_invoice::{get_invoice_cli};
 use crate::nodeinfo::node_info_cli;
@@ -25,17 +36,103 @@ use crate::open_channel::open_channel_cli;
 use crate::send_payment::send_payment_cli;
 use crate::close_channel::close_channel_cli;
 use crate::force_close_channel::force_close_channel_cli;
+use crate::fee_rates::fee_rates_cli;
+use crate::graph::{describe_graph_cli, export_graph_cli, get_chan_info_cli, get_node_info_cli};
+use crate::scorer::{export_scorer_cli, import_scorer_cli, list_scores_cli, reset_scorer_cli};
+use crate::query_route::query_route_cli;
+use crate::probe::probe_cli;
+use crate::list_broadcasts::list_broadcasts_cli;
+use crate::list_forwards::list_forwards_cli;
+use crate::list_closed_channels::list_closed_channels_cli;
+use crate::balances::balances_cli;
+use crate::sweeps::{list_sweeps_cli, sweep_to_cli};
+
+/// Which backend the node uses for chain data, fee estimates and broadcasting.
+pub(crate) enum ChainSourceConfig {
+	Bitcoind,
+	Esplora(String),
+	Electrum(String),
+}
+
+/// The address of bitcoind's RPC interface and the credentials to call it with.
+pub(crate) struct BitcoindRpcInfo {
+	pub(crate) username: String,
+	pub(crate) password: String,
+	pub(crate) host: String,
+	pub(crate) port: u16,
+}
+
+/// Which [`ldk::data_store::DataStore`] the node persists its state in.
+pub(crate) enum DataStoreConfig {
+	Filesystem,
+	Sqlite,
+}
+
+/// Where the key the data directory is encrypted under comes from, if it is encrypted at all.
+pub(crate) enum EncryptionConfig {
+	Disabled,
+	/// Prompt for a passphrase at startup.
+	Passphrase,
+	/// Use the contents of the file at the given path.
+	Keyfile(String),
+}
+
+/// Where every write to the data store is mirrored to.
+pub(crate) enum BackupConfig {
+	/// A directory, usually on a different disk.
+	Directory(String),
+	/// A server run with the `backup-server` binary.
+	Http { url: String, token: Option<String> },
+}
 
 pub(crate) struct LdkUserInfo {
-	pub(crate) bitcoind_rpc_username: String,
-	pub(crate) bitcoind_rpc_password: String,
-	pub(crate) bitcoind_rpc_port: u16,
-	pub(crate) bitcoind_rpc_host: String,
+	/// How to reach bitcoind, which only the bitcoind chain source uses.
+	pub(crate) bitcoind_rpc: Option<BitcoindRpcInfo>,
+	pub(crate) bitcoind_wallet_name: Option<String>,
+	pub(crate) create_bitcoind_wallet: bool,
 	pub(crate) ldk_storage_dir_path: String,
 	pub(crate) ldk_peer_listening_port: u16,
 	pub(crate) ldk_announced_listen_addr: Vec<SocketAddress>,
 	pub(crate) ldk_announced_node_name: [u8; 32],
 	pub(crate) network: Network,
+	pub(crate) chain_source: ChainSourceConfig,
+	pub(crate) zmq_block_endpoint: Option<String>,
+	pub(crate) zmq_rawtx_endpoint: Option<String>,
+	pub(crate) fee_policy: FeePolicy,
+	pub(crate) scoring_params: ScoringParams,
+	pub(crate) data_store: DataStoreConfig,
+	pub(crate) encryption: EncryptionConfig,
+	pub(crate) restore_from_mnemonic: bool,
+	pub(crate) bip39_passphrase: bool,
+	pub(crate) mnemonic_export_path: Option<String>,
+	pub(crate) backup: Option<BackupConfig>,
+	/// How long writes wait for the backup at most, if they shouldn't wait until it has them.
+	pub(crate) backup_max_wait: Option<Duration>,
+	pub(crate) force_unlock: bool,
+	pub(crate) rgs_source: Option<String>,
+	/// Outputs to sweep are held until the next multiple of this height, so they can be batched.
+	pub(crate) sweep_batch_blocks: u32,
+	pub(crate) webhooks: WebhookConfig,
+	/// Where subscribers can follow the node's events, if anywhere.
+	pub(crate) event_stream_addr: Option<SocketAddr>,
+	/// Where Prometheus can scrape the node's metrics, if anywhere.
+	pub(crate) metrics_addr: Option<SocketAddr>,
+	pub(crate) logging: LogConfig,
+}
+
+/// The services the node runs alongside LDK's, shared by the event handler and the CLI.
+#[derive(Clone)]
+pub(crate) struct NodeServices {
+	pub(crate) fee_estimates: Arc<FeeEstimates>,
+	pub(crate) broadcast_queue: Arc<BroadcastQueue>,
+	pub(crate) router: Arc<Router>,
+	pub(crate) scorer_manager: Arc<ScorerManager>,
+	pub(crate) forwarding_history: Arc<ForwardingHistory>,
+	pub(crate) closed_channels: Arc<ClosedChannels>,
+	pub(crate) sweep_policy: Arc<SweepPolicy>,
+	pub(crate) webhooks: Arc<Webhooks>,
+	pub(crate) event_stream: Arc<EventStream>,
+	pub(crate) metrics: Arc<Metrics>,
 }
 
 /// Asks for user input in terms of a Wrapless protocol.
@@ -44,8 +141,20 @@ pub(crate) fn poll_for_user_input_wrapless(
     chain_monitor: Arc<ChainMonitor>, keys_manager: Arc<KeysManager>,
     network_graph: Arc<NetworkGraph>, inbound_payments: Arc<Mutex<InboundPaymentInfoStorage>>,
     outbound_payments: Arc<Mutex<OutboundPaymentInfoStorage>>, ldk_data_dir: String,
-    fs_store: Arc<FilesystemStore>,
+    kv_store: Arc<DataStore>, output_sweeper: Arc<OutputSweeper>,
+    bitcoind_client: Option<Arc<BitcoindClient>>, services: NodeServices,
 ) {
+    let NodeServices {
+        fee_estimates,
+        broadcast_queue,
+        router,
+        scorer_manager,
+        forwarding_history,
+        closed_channels,
+        sweep_policy,
+        metrics,
+        ..
+    } = services;
     let mut rl = DefaultEditor::new().unwrap();
 
     println!(
@@ -71,13 +180,30 @@ pub(crate) fn poll_for_user_input_wrapless(
             match word {
                 "help" => help(),
                 "openchannel" => open_channel_cli(words, &peer_manager, &channel_manager, &ldk_data_dir),
-                "getinvoice" => get_invoice_cli(words, &inbound_payments, &fs_store, &channel_manager),
+                "getinvoice" => get_invoice_cli(words, &inbound_payments, &kv_store, &channel_manager),
                 "nodeinfo" => node_info_cli(&channel_manager, &chain_monitor, &peer_manager, &network_graph),
+                "balances" => balances_cli(&channel_manager, &chain_monitor, &output_sweeper, bitcoind_client.as_ref()),
                 "sendpayment" => send_payment_cli(words, &keys_manager, &outbound_payments, &channel_manager,
-                line.clone(), &fs_store, &network_graph),
+                line.clone(), &kv_store, &network_graph, &metrics),
                 "listchannels" => list_channels(&channel_manager, &network_graph),
+                "listclosedchannels" => list_closed_channels_cli(&closed_channels, &chain_monitor),
+                "queryroute" => query_route_cli(words, &router, &channel_manager, &network_graph),
+                "probe" => probe_cli(words, &channel_manager),
+                "listforwards" => list_forwards_cli(words, &forwarding_history, &network_graph),
+                "describegraph" => describe_graph_cli(&network_graph),
+                "getnodeinfo" => get_node_info_cli(words, &network_graph),
+                "getchaninfo" => get_chan_info_cli(words, &network_graph),
+                "exportgraph" => export_graph_cli(words, &channel_manager, &network_graph),
+                "listscores" => list_scores_cli(words, &scorer_manager, &network_graph),
+                "resetscorer" => reset_scorer_cli(words, &scorer_manager),
+                "exportscorer" => export_scorer_cli(words, &scorer_manager),
+                "importscorer" => import_scorer_cli(words, &scorer_manager),
                 "closechannel" => close_channel_cli(words, &channel_manager),
                 "forceclosechannel" => force_close_channel_cli(words, &channel_manager),
+                "feerates" => fee_rates_cli(&fee_estimates),
+                "listbroadcasts" => list_broadcasts_cli(&broadcast_queue),
+                "listsweeps" => list_sweeps_cli(&output_sweeper, &sweep_policy, &fee_estimates),
+                "sweepto" => sweep_to_cli(words, &sweep_policy),
                 "quit" | "exit" => break,
                 _ => println!("Unknown command. See `\"help\" for available commands."),
             }
@@ -100,12 +226,26 @@ pub(crate) fn help() {
     println!("      closechannel <channel_id> <peer_pubkey>");
     println!("      forceclosechannel <channel_id> <peer_pubkey>");
     println!("      listchannels");
+    println!("      listclosedchannels");
     println!("\n  Peers:");
     // println!("      connectpeer pubkey@host:port");
     // println!("      disconnectpeer <peer_pubkey>");
     // println!("      listpeers");
+    println!("\n  Network graph:");
+    println!("      describegraph");
+    println!("      getnodeinfo <pubkey>");
+    println!("      getchaninfo <short_channel_id>");
+    println!("      exportgraph <json|dot> <max_hops> <path>");
+    println!("\n  Scorer:");
+    println!("      listscores [<short_channel_id>]");
+    println!("      resetscorer [<short_channel_id>]");
+    println!("      exportscorer <path>");
+    println!("      importscorer <path>");
     println!("\n  Payments:");
     println!("      sendpayment <invoice|offer|human readable name> [<amount_msat>]");
+    println!("      queryroute <pubkey|invoice> [<amt_msat>]");
+    println!("      probe <pubkey|invoice> [<amt_msat>]");
+    println!("      listforwards [--since=<time>] [--until=<time>]");
     // println!("      keysend <dest_pubkey> <amt_msats>");
     // println!("      listpayments");
     println!("\n  Invoices:");
@@ -114,6 +254,11 @@ pub(crate) fn help() {
     println!("\n  Other:");
     // println!("      signmessage <message>");
     println!("      nodeinfo");
+    println!("      balances");
+    println!("      feerates");
+    println!("      listbroadcasts");
+    println!("      listsweeps");
+    println!("      sweepto <address|wallet>");
 }
 
 pub(crate) async fn do_connect_peer(
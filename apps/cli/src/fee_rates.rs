@@ -0,0 +1,18 @@
+use std::sync::Arc;
+use ldk::fee_policy::FeeEstimates;
+
+pub(crate) fn fee_rates_cli(fee_estimates: &Arc<FeeEstimates>) {
+    println!("[");
+    for (target, feerate) in fee_estimates.feerates() {
+        let policy = fee_estimates.policy().target(target);
+        println!("\t{{");
+        println!("\t\ttarget: {:?},", target);
+        println!("\t\tsat_per_kw: {},", feerate.sat_per_kw);
+        println!("\t\tsource: {} ({}),", feerate.origin, policy.source);
+        if let Some(updated_at) = feerate.updated_at {
+            println!("\t\tupdated_at: {},", updated_at);
+        }
+        println!("\t}},");
+    }
+    println!("]");
+}
@@ -5,11 +5,11 @@ use lightning::ln::channelmanager::Bolt11InvoiceParameters;
 use lightning::types::payment::PaymentHash;
 use lightning::util::persist::KVStore;
 use lightning::util::ser::Writeable;
-use lightning_persister::fs_store::FilesystemStore;
+use ldk::data_store::DataStore;
 use ldk::common::{ChannelManager, HTLCStatus, InboundPaymentInfoStorage, MillisatAmount, PaymentInfo};
 use ldk::disk::INBOUND_PAYMENTS_FNAME;
 
-pub(crate) fn get_invoice_cli(mut words: SplitWhitespace, inbound_payments: &Arc<Mutex<InboundPaymentInfoStorage>>, fs_store: &Arc<FilesystemStore>, channel_manager: &Arc<ChannelManager>) {
+pub(crate) fn get_invoice_cli(mut words: SplitWhitespace, inbound_payments: &Arc<Mutex<InboundPaymentInfoStorage>>, kv_store: &Arc<DataStore>, channel_manager: &Arc<ChannelManager>) {
     let amt_str = words.next();
     if amt_str.is_none() {
         println!("ERROR: getinvoice requires an amount in millisatoshis");
@@ -41,7 +41,7 @@ pub(crate) fn get_invoice_cli(mut words: SplitWhitespace, inbound_payments: &Arc
         &channel_manager,
         expiry_secs.unwrap(),
     );
-    fs_store
+    kv_store
         .write("", "", INBOUND_PAYMENTS_FNAME, &inbound_payments.encode())
         .unwrap();
 }
@@ -0,0 +1,157 @@
+use std::fs;
+use std::str::SplitWhitespace;
+use std::sync::Arc;
+use lightning::routing::gossip::{ChannelUpdateInfo, NodeId};
+use ldk::common::{ChannelManager, NetworkGraph};
+use ldk::graph_export;
+
+pub(crate) fn describe_graph_cli(network_graph: &Arc<NetworkGraph>) {
+    let graph = network_graph.read_only();
+    let announced_nodes =
+        graph.nodes().unordered_iter().filter(|(_, node)| node.announcement_info.is_some()).count();
+    let total_capacity_sats: u64 =
+        graph.channels().unordered_iter().filter_map(|(_, chan)| chan.capacity_sats).sum();
+    let fully_updated_channels = graph
+        .channels()
+        .unordered_iter()
+        .filter(|(_, chan)| chan.one_to_two.is_some() && chan.two_to_one.is_some())
+        .count();
+    println!("{{");
+    println!("\tnodes: {},", graph.nodes().len());
+    println!("\tannounced_nodes: {},", announced_nodes);
+    println!("\tchannels: {},", graph.channels().len());
+    println!("\tchannels_updated_in_both_directions: {},", fully_updated_channels);
+    println!("\ttotal_capacity_sats: {},", total_capacity_sats);
+    if let Some(timestamp) = network_graph.get_last_rapid_gossip_sync_timestamp() {
+        println!("\tlast_rapid_gossip_sync: {},", timestamp);
+    }
+    println!("}}");
+}
+
+pub(crate) fn get_node_info_cli(mut words: SplitWhitespace, network_graph: &Arc<NetworkGraph>) {
+    let pubkey = match words.next().and_then(ldk::hex_utils::to_compressed_pubkey) {
+        Some(pubkey) => pubkey,
+        None => {
+            println!("ERROR: getnodeinfo requires a valid pubkey: `getnodeinfo <pubkey>`");
+            return;
+        },
+    };
+    let graph = network_graph.read_only();
+    let node = match graph.node(&NodeId::from_pubkey(&pubkey)) {
+        Some(node) => node,
+        None => {
+            println!("ERROR: node {} isn't in the network graph", pubkey);
+            return;
+        },
+    };
+    let total_capacity_sats: u64 = node
+        .channels
+        .iter()
+        .filter_map(|scid| graph.channel(*scid).and_then(|chan| chan.capacity_sats))
+        .sum();
+    println!("{{");
+    println!("\tnode_id: {},", pubkey);
+    if let Some(announcement) = &node.announcement_info {
+        println!("\talias: {},", announcement.alias());
+        println!("\tlast_update: {},", announcement.last_update());
+        println!("\tfeatures: {},", announcement.features());
+        println!("\taddresses: [");
+        for address in announcement.addresses() {
+            println!("\t\t{},", address);
+        }
+        println!("\t],");
+    }
+    println!("\tchannels: {},", node.channels.len());
+    println!("\ttotal_capacity_sats: {},", total_capacity_sats);
+    println!("}}");
+}
+
+fn print_channel_update(direction: &str, update: &Option<ChannelUpdateInfo>) {
+    let update = match update {
+        Some(update) => update,
+        None => return,
+    };
+    println!("\t{}: {{", direction);
+    println!("\t\tenabled: {},", update.enabled);
+    println!("\t\tfee_base_msat: {},", update.fees.base_msat);
+    println!("\t\tfee_proportional_millionths: {},", update.fees.proportional_millionths);
+    println!("\t\tcltv_expiry_delta: {},", update.cltv_expiry_delta);
+    println!("\t\thtlc_minimum_msat: {},", update.htlc_minimum_msat);
+    println!("\t\thtlc_maximum_msat: {},", update.htlc_maximum_msat);
+    println!("\t\tlast_update: {},", update.last_update);
+    println!("\t}},");
+}
+
+pub(crate) fn get_chan_info_cli(mut words: SplitWhitespace, network_graph: &Arc<NetworkGraph>) {
+    let scid = match words.next().map(|scid| scid.parse::<u64>()) {
+        Some(Ok(scid)) => scid,
+        _ => {
+            println!("ERROR: getchaninfo requires a short channel id: `getchaninfo <scid>`");
+            return;
+        },
+    };
+    let graph = network_graph.read_only();
+    let chan = match graph.channel(scid) {
+        Some(chan) => chan,
+        None => {
+            println!("ERROR: channel {} isn't in the network graph", scid);
+            return;
+        },
+    };
+    println!("{{");
+    println!("\tshort_channel_id: {},", scid);
+    println!("\tnode_one: {},", chan.node_one);
+    println!("\tnode_two: {},", chan.node_two);
+    if let Some(capacity_sats) = chan.capacity_sats {
+        println!("\tcapacity_sats: {},", capacity_sats);
+    }
+    print_channel_update("one_to_two", &chan.one_to_two);
+    print_channel_update("two_to_one", &chan.two_to_one);
+    println!("}}");
+}
+
+pub(crate) fn export_graph_cli(
+    mut words: SplitWhitespace, channel_manager: &Arc<ChannelManager>,
+    network_graph: &Arc<NetworkGraph>,
+) {
+    let usage = "`exportgraph <json|dot> <max_hops> <path>`";
+    let (format, max_hops, path) = match (words.next(), words.next(), words.next()) {
+        (Some(format), Some(max_hops), Some(path)) => (format, max_hops, path),
+        _ => {
+            println!("ERROR: exportgraph requires a format, a number of hops and a path: {}", usage);
+            return;
+        },
+    };
+    let max_hops = match max_hops.parse::<u8>() {
+        Ok(max_hops) => max_hops,
+        Err(e) => {
+            println!("ERROR: couldn't parse max_hops: {}", e);
+            return;
+        },
+    };
+
+    let our_node_id = NodeId::from_pubkey(&channel_manager.get_our_node_id());
+    let subgraph = graph_export::ego_subgraph(network_graph, our_node_id, max_hops);
+    let contents = match format {
+        "json" => serde_json::to_string_pretty(&subgraph.to_json()).unwrap(),
+        "dot" => subgraph.to_dot(),
+        _ => {
+            println!("ERROR: unknown format {}, expected json or dot: {}", format, usage);
+            return;
+        },
+    };
+    if let Err(e) = fs::write(path, contents) {
+        println!("ERROR: failed to write {}: {}", path, e);
+        return;
+    }
+    println!(
+        "Exported {} nodes and {} channels within {} hops of our node to {}",
+        subgraph.nodes.len(),
+        subgraph.channels.len(),
+        max_hops,
+        path
+    );
+    if subgraph.nodes.len() == 1 {
+        println!("Our node has no public channels in the network graph yet.");
+    }
+}
@@ -0,0 +1,18 @@
+use std::sync::Arc;
+use ldk::broadcast_queue::BroadcastQueue;
+
+pub(crate) fn list_broadcasts_cli(broadcast_queue: &Arc<BroadcastQueue>) {
+    println!("[");
+    for broadcast in broadcast_queue.list_broadcasts() {
+        println!("\t{{");
+        println!("\t\ttxid: {},", broadcast.txid);
+        println!("\t\tkind: {},", broadcast.kind);
+        println!("\t\tfirst_seen: {},", broadcast.first_seen);
+        println!("\t\tconfirmations: {},", broadcast.confirmations);
+        if let Some(replaced_by) = broadcast.replaced_by {
+            println!("\t\treplaced_by: {},", replaced_by);
+        }
+        println!("\t}},");
+    }
+    println!("]");
+}
@@ -0,0 +1,61 @@
+use std::sync::Arc;
+use ldk::closed_channels::ClosedChannels;
+use ldk::common::ChainMonitor;
+use crate::balances::print_balance;
+
+/// Prints every channel which closed, along with whatever of our balance in it is still being
+/// claimed on-chain.
+pub(crate) fn list_closed_channels_cli(closed_channels: &Arc<ClosedChannels>, chain_monitor: &Arc<ChainMonitor>) {
+    let closed = closed_channels.list();
+    if closed.is_empty() {
+        println!("No channels have closed yet.");
+        return;
+    }
+    println!("[");
+    for details in closed {
+        let channel = &details.channel;
+        println!("\t{{");
+        println!("\t\tchannel_id: {},", channel.channel_id);
+        println!("\t\tuser_channel_id: {},", channel.user_channel_id);
+        if let Some(counterparty_node_id) = channel.counterparty_node_id {
+            println!("\t\tcounterparty: {},", counterparty_node_id);
+        }
+        if let Some(funding_txo) = channel.funding_txo {
+            println!("\t\tfunding_txo: {},", funding_txo);
+        }
+        if let Some(capacity_sats) = channel.capacity_sats {
+            println!("\t\tcapacity_sats: {},", capacity_sats);
+        }
+        if let Some(final_balance_msat) = channel.final_balance_msat {
+            println!("\t\tfinal_balance_msat: {},", final_balance_msat);
+        }
+        println!("\t\treason: {},", channel.reason);
+        println!("\t\tclosed_at: {},", channel.closed_at);
+        match details.closing_tx {
+            Some(closing_tx) => {
+                println!("\t\tclosing_txid: {},", closing_tx.txid);
+                println!("\t\tclosing_tx_height: {},", closing_tx.confirmation_height);
+                println!("\t\tclosing_tx_confirmations: {},", details.closing_tx_confirmations);
+            },
+            None if channel.funding_txo.is_some() => println!("\t\tclosing_txid: unconfirmed,"),
+            None => {},
+        }
+        // Once every output we could claim is claimed and buried the monitor has no balances left.
+        let balances = channel
+            .funding_txo
+            .and_then(|funding_txo| chain_monitor.get_monitor(funding_txo).ok())
+            .map(|monitor| monitor.get_claimable_balances())
+            .unwrap_or_default();
+        if !balances.is_empty() {
+            let pending_sats: u64 = balances.iter().map(|balance| balance.claimable_amount_satoshis()).sum();
+            println!("\t\tpending_onchain_sats: {},", pending_sats);
+            println!("\t\tpending_balances: [");
+            for balance in balances.iter() {
+                print_balance(balance, "\t\t\t");
+            }
+            println!("\t\t],");
+        }
+        println!("\t}},");
+    }
+    println!("]");
+}
@@ -0,0 +1,112 @@
+use std::str::SplitWhitespace;
+use std::sync::Arc;
+use std::time::SystemTime;
+use bitcoin::secp256k1::PublicKey;
+use lightning::routing::gossip::NodeId;
+use ldk::common::NetworkGraph;
+use ldk::forwarding_history::{totals_by_channel, ForwardingHistory};
+
+const USAGE: &str = "`listforwards [--since=<time>] [--until=<time>]`";
+
+/// Parses either a UNIX timestamp or how long ago, e.g. `90m`, `24h` or `7d`.
+fn parse_time(time: &str, now: u64) -> Option<u64> {
+    if let Ok(timestamp) = time.parse::<u64>() {
+        return Some(timestamp);
+    }
+    let unit_secs = match time.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return None,
+    };
+    let count = time[..time.len() - 1].parse::<u64>().ok()?;
+    Some(now.saturating_sub(count.checked_mul(unit_secs)?))
+}
+
+pub(crate) fn list_forwards_cli(
+    words: SplitWhitespace, forwarding_history: &Arc<ForwardingHistory>,
+    network_graph: &Arc<NetworkGraph>,
+) {
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
+    let (mut since, mut until) = (None, None);
+    for word in words {
+        let (name, value) = match word.split_once('=') {
+            Some(name_and_value) => name_and_value,
+            None => {
+                println!("ERROR: unexpected argument {}: {}", word, USAGE);
+                return;
+            },
+        };
+        let time = match parse_time(value, now) {
+            Some(time) => time,
+            None => {
+                println!("ERROR: invalid time {}, expected a UNIX timestamp or e.g. 24h: {}", value, USAGE);
+                return;
+            },
+        };
+        match name {
+            "--since" => since = Some(time),
+            "--until" => until = Some(time),
+            _ => {
+                println!("ERROR: unknown option {}: {}", name, USAGE);
+                return;
+            },
+        }
+    }
+
+    let graph = network_graph.read_only();
+    let node_str = |node_id: &Option<PublicKey>| match node_id {
+        Some(node_id) => match graph
+            .node(&NodeId::from_pubkey(node_id))
+            .and_then(|node| node.announcement_info.as_ref())
+        {
+            Some(announcement) => format!("{} ({})", node_id, announcement.alias()),
+            None => node_id.to_string(),
+        },
+        None => "unknown".to_string(),
+    };
+    let forwards = forwarding_history.list(since, until);
+    println!("{{");
+    println!("\tforwards: [");
+    for forward in forwards.iter() {
+        println!("\t\t{{");
+        println!("\t\t\ttimestamp: {},", forward.timestamp);
+        if let Some(channel_id) = forward.prev_channel_id {
+            println!("\t\t\tprev_channel_id: {},", channel_id);
+        }
+        println!("\t\t\tprev_node: {},", node_str(&forward.prev_node_id));
+        if let Some(channel_id) = forward.next_channel_id {
+            println!("\t\t\tnext_channel_id: {},", channel_id);
+        }
+        println!("\t\t\tnext_node: {},", node_str(&forward.next_node_id));
+        if let Some(amount_msat) = forward.inbound_amount_msat() {
+            println!("\t\t\tinbound_amount_msat: {},", amount_msat);
+        }
+        if let Some(amount_msat) = forward.outbound_amount_msat {
+            println!("\t\t\toutbound_amount_msat: {},", amount_msat);
+        }
+        if let Some(fee_msat) = forward.fee_earned_msat {
+            println!("\t\t\tfee_earned_msat: {},", fee_msat);
+        }
+        println!("\t\t\tclaimed_onchain: {},", forward.claimed_onchain);
+        println!("\t\t}},");
+    }
+    println!("\t],");
+    println!("\tchannels: [");
+    for channel in totals_by_channel(&forwards) {
+        println!("\t\t{{");
+        println!("\t\t\tchannel_id: {},", channel.channel_id);
+        println!("\t\t\tforwards_in: {},", channel.forwards_in);
+        println!("\t\t\tforwards_out: {},", channel.forwards_out);
+        println!("\t\t\tamount_in_msat: {},", channel.amount_in_msat);
+        println!("\t\t\tamount_out_msat: {},", channel.amount_out_msat);
+        println!("\t\t\tfee_earned_msat: {},", channel.fee_earned_msat);
+        println!("\t\t}},");
+    }
+    println!("\t],");
+    println!("\ttotal_forwards: {},", forwards.len());
+    let total_fee_earned_msat: u64 = forwards.iter().filter_map(|forward| forward.fee_earned_msat).sum();
+    println!("\ttotal_fee_earned_msat: {},", total_fee_earned_msat);
+    println!("}}");
+}
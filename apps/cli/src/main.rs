@@ -7,9 +7,38 @@ mod nodeinfo;
 mod send_payment;
 mod close_channel;
 mod force_close_channel;
+mod fee_rates;
+mod list_broadcasts;
+mod list_forwards;
+mod list_closed_channels;
+mod balances;
+mod sweeps;
+mod query_route;
+mod probe;
+mod graph;
+mod scorer;
 
 use ldk::bitcoind_client::BitcoindClient;
-use ldk::disk::{FilesystemLogger, INBOUND_PAYMENTS_FNAME, OUTBOUND_PAYMENTS_FNAME};
+use ldk::bitcoind_zmq;
+use ldk::broadcast_queue::{BroadcastKind, BroadcastQueue, REBROADCAST_INTERVAL};
+use ldk::chain_source::ChainSource;
+use ldk::closed_channels::{ClosedChannel, ClosedChannels};
+use ldk::data_dir_lock::DataDirLock;
+use ldk::data_store::DataStore;
+use ldk::electrum_client::ElectrumClient;
+use ldk::encrypted_store::{self, DataCipher, EncryptedStore};
+use ldk::esplora_client::EsploraClient;
+use ldk::event_stream::EventStream;
+use ldk::fee_policy::FeeEstimates;
+use ldk::forwarding_history::{ForwardRecord, ForwardingHistory};
+use ldk::http_backup::HttpBackupClient;
+use ldk::metrics::Metrics;
+use ldk::notifications::Notification;
+use ldk::rapid_gossip::{RapidGossip, SnapshotSource};
+use ldk::replicating_store::{BackupTarget, MonitorPersister, ReplicatingStore};
+use ldk::scoring::ScorerManager;
+use ldk::sqlite_store::{SqliteStore, SQLITE_DB_FILE_NAME};
+use ldk::disk::{FilesystemLogger, INBOUND_PAYMENTS_FNAME, KEYS_SEED_FNAME, OUTBOUND_PAYMENTS_FNAME};
 use bitcoin::blockdata::transaction::Transaction;
 use bitcoin::consensus::encode;
 use bitcoin::io;
@@ -17,7 +46,8 @@ use bitcoin::network::Network;
 use bitcoin::BlockHash;
 use bitcoin_bech32::WitnessProgram;
 use lightning::chain::{chainmonitor, ChannelMonitorUpdateStatus};
-use lightning::chain::{BestBlock, Filter, Watch};
+use lightning::chain::transaction::OutPoint;
+use lightning::chain::{BestBlock, Confirm, Filter, Watch};
 use lightning::events::bump_transaction::{BumpTransactionEventHandler, Wallet};
 use lightning::events::{Event, PaymentFailureReason, PaymentPurpose};
 use lightning::ln::channelmanager::{self, RecentPaymentDetails};
@@ -35,8 +65,7 @@ use lightning::onion_message::messenger::{
 use lightning::routing::gossip;
 use lightning::routing::gossip::{NodeId, P2PGossipSync};
 use lightning::routing::router::DefaultRouter;
-use lightning::routing::scoring::ProbabilisticScoringFeeParameters;
-use lightning::sign::{EntropySource, InMemorySigner, KeysManager};
+use lightning::sign::{ChangeDestinationSource, EntropySource, InMemorySigner, KeysManager};
 use lightning::types::payment::{PaymentHash, PaymentPreimage, PaymentSecret};
 use lightning::util::config::UserConfig;
 use lightning::util::hash_tables::hash_map::Entry;
@@ -62,23 +91,64 @@ use std::convert::TryInto;
 use std::fmt;
 use std::fs;
 use std::fs::File;
-use std::io::{BufReader, Write};
+use std::io::Write;
 use std::net::ToSocketAddrs;
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::{Arc, Mutex};
+use cli::{
+	BackupConfig, BitcoindRpcInfo, ChainSourceConfig, DataStoreConfig, EncryptionConfig,
+	LdkUserInfo, NodeServices,
+};
 use std::time::{Duration, SystemTime};
 use ldk::common::{ChannelManager, NetworkGraph, BumpTxEventHandler, PeerManager, InboundPaymentInfoStorage, OutboundPaymentInfoStorage, OutputSweeperWrapper, HTLCStatus, MillisatAmount, PaymentInfo, ChainMonitor, OnionMessenger, OutputSweeper, GossipVerifier};
-use ldk::{hex_utils, sweep};
+use ldk::sweep::{KeysDestination, SweepPolicy};
+use ldk::webhooks::Webhooks;
+use ldk::{hex_utils, mnemonic, sweep};
+
+/// Has bitcoind's wallet build, fund and sign a transaction paying to `outputs`.
+async fn create_funding_transaction(
+	bitcoind_client: Option<&BitcoindClient>, outputs: Vec<StdHashMap<String, f64>>,
+) -> Result<Transaction, String> {
+	let bitcoind_client = bitcoind_client
+		.ok_or_else(|| "there's no on-chain wallet to fund it from without bitcoind".to_string())?;
+	let raw_tx = bitcoind_client.create_raw_transaction(outputs).await.map_err(|e| e.to_string())?;
+
+	// Have your wallet put the inputs into the transaction such that the output is
+	// satisfied.
+	let funded_tx = bitcoind_client.fund_raw_transaction(raw_tx).await.map_err(|e| e.to_string())?;
+
+	// Sign the final funding transaction and give it to LDK, who will eventually broadcast it.
+	let signed_tx = bitcoind_client
+		.sign_raw_transaction_with_wallet(funded_tx.hex)
+		.await
+		.map_err(|e| e.to_string())?;
+	if !signed_tx.complete {
+		return Err("the wallet could not fully sign the funding transaction".to_string());
+	}
+	hex_utils::to_vec(&signed_tx.hex)
+		.and_then(|tx_bytes| encode::deserialize(&tx_bytes).ok())
+		.ok_or_else(|| "bitcoind returned an invalid funding transaction".to_string())
+}
 
 async fn handle_ldk_events(
-	channel_manager: Arc<ChannelManager>, bitcoind_client: &BitcoindClient,
+	channel_manager: Arc<ChannelManager>, bitcoind_client: Option<&BitcoindClient>,
 	network_graph: &NetworkGraph, keys_manager: &KeysManager,
-	bump_tx_event_handler: &BumpTxEventHandler, peer_manager: Arc<PeerManager>,
+	bump_tx_event_handler: Option<&BumpTxEventHandler>, peer_manager: Arc<PeerManager>,
 	inbound_payments: Arc<Mutex<InboundPaymentInfoStorage>>,
-	outbound_payments: Arc<Mutex<OutboundPaymentInfoStorage>>, fs_store: Arc<FilesystemStore>,
-	output_sweeper: OutputSweeperWrapper, network: Network, event: Event,
+	outbound_payments: Arc<Mutex<OutboundPaymentInfoStorage>>, kv_store: Arc<DataStore>,
+	output_sweeper: OutputSweeperWrapper, services: &NodeServices, network: Network, event: Event,
 ) {
+	let NodeServices {
+		broadcast_queue,
+		forwarding_history,
+		closed_channels,
+		sweep_policy,
+		webhooks,
+		event_stream,
+		metrics,
+		..
+	} = services;
 	match event {
 		Event::FundingGenerationReady {
 			temporary_channel_id,
@@ -102,18 +172,27 @@ async fn handle_ldk_events(
 				.to_address();
 			let mut outputs = vec![StdHashMap::new()];
 			outputs[0].insert(addr, channel_value_satoshis as f64 / 100_000_000.0);
-			let raw_tx = bitcoind_client.create_raw_transaction(outputs).await;
-
-			// Have your wallet put the inputs into the transaction such that the output is
-			// satisfied.
-			let funded_tx = bitcoind_client.fund_raw_transaction(raw_tx).await;
-
-			// Sign the final funding transaction and give it to LDK, who will eventually broadcast it.
-			let signed_tx = bitcoind_client.sign_raw_transaction_with_wallet(funded_tx.hex).await;
-			assert_eq!(signed_tx.complete, true);
-			let final_tx: Transaction =
-				encode::deserialize(&hex_utils::to_vec(&signed_tx.hex).unwrap()).unwrap();
+			let final_tx = match create_funding_transaction(bitcoind_client, outputs).await {
+				Ok(tx) => tx,
+				Err(e) => {
+					// Without a funding transaction the channel can never be opened, so drop it
+					// rather than leaving the peer waiting for us.
+					println!(
+						"\nERROR: Failed to fund channel with peer {}: {}. Abandoning the channel.",
+						counterparty_node_id, e
+					);
+					print!("> ");
+					std::io::stdout().flush().unwrap();
+					let _ = channel_manager.force_close_without_broadcasting_txn(
+						&temporary_channel_id,
+						&counterparty_node_id,
+						format!("Failed to fund channel: {}", e),
+					);
+					return;
+				},
+			};
 			// Give the funding transaction back to LDK for opening the channel.
+			broadcast_queue.expect_funding(final_tx.compute_txid());
 			if channel_manager
 				.funding_transaction_generated(temporary_channel_id, counterparty_node_id, final_tx)
 				.is_err()
@@ -134,6 +213,7 @@ async fn handle_ldk_events(
 			);
 			print!("> ");
 			std::io::stdout().flush().unwrap();
+			event_stream.publish(&Notification::PaymentClaimable { payment_hash, amount_msat });
 			let payment_preimage = match purpose {
 				PaymentPurpose::Bolt11InvoicePayment { payment_preimage, .. } => payment_preimage,
 				PaymentPurpose::Bolt12OfferPayment { payment_preimage, .. } => payment_preimage,
@@ -149,6 +229,9 @@ async fn handle_ldk_events(
 			);
 			print!("> ");
 			std::io::stdout().flush().unwrap();
+			let notification = Notification::PaymentReceived { payment_hash, amount_msat };
+			webhooks.notify(&notification);
+			event_stream.publish(&notification);
 			let (payment_preimage, payment_secret) = match purpose {
 				PaymentPurpose::Bolt11InvoicePayment {
 					payment_preimage, payment_secret, ..
@@ -178,11 +261,22 @@ async fn handle_ldk_events(
 					});
 				},
 			}
-			fs_store.write("", "", INBOUND_PAYMENTS_FNAME, &inbound.encode()).unwrap();
+			kv_store.write("", "", INBOUND_PAYMENTS_FNAME, &inbound.encode()).unwrap();
 		},
 		Event::PaymentSent {
 			payment_preimage, payment_hash, fee_paid_msat, payment_id, ..
 		} => {
+			let notification = Notification::PaymentSent {
+				payment_id,
+				payment_hash,
+				payment_preimage,
+				fee_paid_msat,
+			};
+			webhooks.notify(&notification);
+			event_stream.publish(&notification);
+			if let Some(payment_id) = payment_id {
+				metrics.payment_succeeded(&payment_id);
+			}
 			let mut outbound = outbound_payments.lock().unwrap();
 			for (id, payment) in outbound.payments.iter_mut() {
 				if *id == payment_id.unwrap() {
@@ -204,7 +298,7 @@ async fn handle_ldk_events(
 					std::io::stdout().flush().unwrap();
 				}
 			}
-			fs_store.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound.encode()).unwrap();
+			kv_store.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound.encode()).unwrap();
 		},
 		Event::OpenChannelRequest {
 			ref temporary_channel_id, ref counterparty_node_id, ..
@@ -237,8 +331,19 @@ async fn handle_ldk_events(
 		},
 		Event::PaymentPathSuccessful { .. } => {},
 		Event::PaymentPathFailed { .. } => {},
-		Event::ProbeSuccessful { .. } => {},
-		Event::ProbeFailed { .. } => {},
+		Event::ProbeSuccessful { payment_id, path, .. } => {
+			println!("\nEVENT: Probe {} succeeded over {} hops", payment_id, path.hops.len());
+			print!("> ");
+			std::io::stdout().flush().unwrap();
+		},
+		Event::ProbeFailed { payment_id, short_channel_id, .. } => {
+			match short_channel_id {
+				Some(scid) => println!("\nEVENT: Probe {} failed at channel {}", payment_id, scid),
+				None => println!("\nEVENT: Probe {} failed", payment_id),
+			}
+			print!("> ");
+			std::io::stdout().flush().unwrap();
+		},
 		Event::PaymentFailed { payment_hash, reason, payment_id, .. } => {
 			if let Some(hash) = payment_hash {
 				print!(
@@ -256,13 +361,21 @@ async fn handle_ldk_events(
 			}
 			print!("> ");
 			std::io::stdout().flush().unwrap();
+			let notification = Notification::PaymentFailed {
+				payment_id,
+				payment_hash,
+				reason: format!("{:?}", reason.unwrap_or(PaymentFailureReason::RetriesExhausted)),
+			};
+			webhooks.notify(&notification);
+			event_stream.publish(&notification);
+			metrics.payment_failed(&payment_id);
 
 			let mut outbound = outbound_payments.lock().unwrap();
 			if outbound.payments.contains_key(&payment_id) {
 				let payment = outbound.payments.get_mut(&payment_id).unwrap();
 				payment.status = HTLCStatus::Failed;
 			}
-			fs_store.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound.encode()).unwrap();
+			kv_store.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound.encode()).unwrap();
 		},
 		Event::InvoiceReceived { .. } => {
 			// We don't use the manual invoice payment logic, so this event should never be seen.
@@ -270,11 +383,33 @@ async fn handle_ldk_events(
 		Event::PaymentForwarded {
 			prev_channel_id,
 			next_channel_id,
+			prev_node_id,
+			next_node_id,
 			total_fee_earned_msat,
 			claim_from_onchain_tx,
 			outbound_amount_forwarded_msat,
 			..
 		} => {
+			event_stream.publish(&Notification::PaymentForwarded {
+				prev_channel_id,
+				next_channel_id,
+				prev_node_id,
+				next_node_id,
+				outbound_amount_msat: outbound_amount_forwarded_msat,
+				fee_earned_msat: total_fee_earned_msat,
+				claimed_onchain: claim_from_onchain_tx,
+			});
+			forwarding_history.record(ForwardRecord {
+				prev_channel_id,
+				next_channel_id,
+				prev_node_id,
+				next_node_id,
+				outbound_amount_msat: outbound_amount_forwarded_msat,
+				fee_earned_msat: total_fee_earned_msat,
+				claimed_onchain: claim_from_onchain_tx,
+				timestamp: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs(),
+			});
+
 			let read_only_network_graph = network_graph.read_only();
 			let nodes = read_only_network_graph.nodes();
 			let channels = channel_manager.list_channels();
@@ -341,9 +476,20 @@ async fn handle_ldk_events(
 			});
 		},
 		Event::SpendableOutputs { outputs, channel_id } => {
-			output_sweeper.0.track_spendable_outputs(outputs, channel_id, false, None).unwrap();
+			let delay_until_height =
+				sweep_policy.delay_until_height(output_sweeper.0.current_best_block().height);
+			output_sweeper
+				.0
+				.track_spendable_outputs(outputs, channel_id, false, delay_until_height)
+				.unwrap();
 		},
-		Event::ChannelPending { channel_id, counterparty_node_id, .. } => {
+		Event::ChannelPending {
+			channel_id, user_channel_id, counterparty_node_id, funding_txo, ..
+		} => {
+			closed_channels.watch_funding(OutPoint {
+				txid: funding_txo.txid,
+				index: funding_txo.vout as u16,
+			});
 			println!(
 				"\nEVENT: Channel {} with peer {} is pending awaiting funding lock-in!",
 				channel_id,
@@ -351,12 +497,15 @@ async fn handle_ldk_events(
 			);
 			print!("> ");
 			std::io::stdout().flush().unwrap();
+			event_stream.publish(&Notification::ChannelPending {
+				channel_id,
+				user_channel_id,
+				counterparty_node_id,
+				funding_txo,
+			});
 		},
 		Event::ChannelReady {
-			ref channel_id,
-			user_channel_id: _,
-			ref counterparty_node_id,
-			channel_type: _,
+			channel_id, user_channel_id, counterparty_node_id, channel_type: _
 		} => {
 			println!(
 				"\nEVENT: Channel {} with peer {} is ready to be used!",
@@ -365,8 +514,30 @@ async fn handle_ldk_events(
 			);
 			print!("> ");
 			std::io::stdout().flush().unwrap();
+			let notification =
+				Notification::ChannelReady { channel_id, user_channel_id, counterparty_node_id };
+			webhooks.notify(&notification);
+			event_stream.publish(&notification);
 		},
-		Event::ChannelClosed { channel_id, reason, counterparty_node_id, .. } => {
+		Event::ChannelClosed {
+			channel_id,
+			user_channel_id,
+			reason,
+			counterparty_node_id,
+			channel_capacity_sats,
+			channel_funding_txo,
+			last_local_balance_msat,
+		} => {
+			closed_channels.record(ClosedChannel {
+				channel_id,
+				user_channel_id,
+				counterparty_node_id,
+				funding_txo: channel_funding_txo,
+				capacity_sats: channel_capacity_sats,
+				final_balance_msat: last_local_balance_msat,
+				reason: reason.to_string(),
+				closed_at: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs(),
+			});
 			println!(
 				"\nEVENT: Channel {} with counterparty {} closed due to: {:?}",
 				channel_id,
@@ -375,6 +546,16 @@ async fn handle_ldk_events(
 			);
 			print!("> ");
 			std::io::stdout().flush().unwrap();
+			let notification = Notification::ChannelClosed {
+				channel_id,
+				user_channel_id,
+				counterparty_node_id,
+				reason: reason.to_string(),
+				capacity_sats: channel_capacity_sats,
+				final_balance_msat: last_local_balance_msat,
+			};
+			webhooks.notify(&notification);
+			event_stream.publish(&notification);
 		},
 		Event::DiscardFunding { .. } => {
 			// A "real" node should probably "lock" the UTXOs spent in funding transactions until
@@ -389,7 +570,15 @@ async fn handle_ldk_events(
 			// We don't use the onion message interception feature, so we have no use for this
 			// event.
 		},
-		Event::BumpTransaction(event) => bump_tx_event_handler.handle_event(&event),
+		Event::BumpTransaction(event) => match bump_tx_event_handler {
+			Some(bump_tx_event_handler) => bump_tx_event_handler.handle_event(&event),
+			None => {
+				// Only anchor channels opened while the node still had a wallet get here.
+				println!("\nERROR: Can't bump a transaction's fee without an on-chain wallet");
+				print!("> ");
+				std::io::stdout().flush().unwrap();
+			},
+		},
 		Event::ConnectionNeeded { node_id, addresses } => {
 			tokio::spawn(async move {
 				for address in addresses {
@@ -407,82 +596,457 @@ async fn handle_ldk_events(
 	}
 }
 
-async fn start_ldk() {
-	let args = match args::parse_startup_args() {
-		Ok(user_args) => user_args,
-		Err(()) => return,
+/// Derives the [`DataCipher`] the data directory at `data_dir` is encrypted under from the
+/// passphrase or keyfile, setting up encryption if it is enabled for the first time.
+fn unlock_data_dir(
+	encryption: &EncryptionConfig, data_dir: &Path,
+) -> Result<Option<DataCipher>, ()> {
+	let encryption_enabled = encrypted_store::is_encryption_enabled(data_dir);
+	let secret = match encryption {
+		EncryptionConfig::Disabled => {
+			if encryption_enabled {
+				println!(
+					"ERROR: {} is encrypted. Start with --encrypt or --encryption-keyfile=<path>.",
+					data_dir.display()
+				);
+				return Err(());
+			}
+			return Ok(None);
+		},
+		EncryptionConfig::Passphrase => {
+			let prompt_password = |prompt: &str| {
+				rpassword::prompt_password(prompt).map_err(|e| {
+					println!("ERROR: Unable to read the passphrase: {}", e);
+				})
+			};
+			let passphrase = prompt_password("Data directory passphrase: ")?;
+			if !encryption_enabled && prompt_password("Repeat the passphrase: ")? != passphrase {
+				println!("ERROR: The passphrases don't match");
+				return Err(());
+			}
+			passphrase.into_bytes()
+		},
+		EncryptionConfig::Keyfile(path) => fs::read(path).map_err(|e| {
+			println!("ERROR: Unable to read encryption keyfile {}: {}", path, e);
+		})?,
 	};
+	if secret.is_empty() {
+		println!("ERROR: The passphrase or keyfile may not be empty");
+		return Err(());
+	}
+	match encrypted_store::unlock(data_dir, &secret) {
+		Ok(data_cipher) => Ok(Some(data_cipher)),
+		Err(e) => {
+			println!("ERROR: Unable to unlock {}: {}", data_dir.display(), e);
+			Err(())
+		},
+	}
+}
 
-	// Initialize the LDK data directory if necessary.
-	let ldk_data_dir = format!("{}/.ldk", args.ldk_storage_dir_path);
-	fs::create_dir_all(ldk_data_dir.clone()).unwrap();
+/// Opens the backup the node's data is mirrored to, making sure it is usable before the node
+/// starts relying on it.
+fn open_backup(config: &BackupConfig, ldk_data_dir: &Path) -> Result<BackupTarget, ()> {
+	match config {
+		BackupConfig::Directory(dir) => {
+			let dir = Path::new(dir);
+			if let Err(e) = fs::create_dir_all(dir) {
+				println!("ERROR: Failed to create backup directory {}: {}", dir.display(), e);
+				return Err(());
+			}
+			let same_dir = match (fs::canonicalize(dir), fs::canonicalize(ldk_data_dir)) {
+				(Ok(backup_dir), Ok(data_dir)) => backup_dir == data_dir,
+				_ => false,
+			};
+			if same_dir {
+				println!("ERROR: The backup directory can't be the node's data directory");
+				return Err(());
+			}
+			Ok(BackupTarget::Filesystem(FilesystemStore::new(dir.to_path_buf())))
+		},
+		BackupConfig::Http { url, token } => {
+			let client = HttpBackupClient::new(url, token.clone()).map_err(|e| {
+				println!("ERROR: {}", e);
+			})?;
+			client.list("", "").map_err(|e| {
+				println!("ERROR: Backup server {} isn't reachable: {}", url, e);
+			})?;
+			Ok(BackupTarget::Http(client))
+		},
+	}
+}
 
-	// ## Setup
-	// Step 1: Initialize the Logger
-	let logger = Arc::new(FilesystemLogger::new(ldk_data_dir.clone()));
+/// Derives a keys seed for a new node from a BIP39 mnemonic, which is either entered by the
+/// operator to restore a node or freshly generated and shown to them once.
+fn new_keys_seed(args: &LdkUserInfo) -> Result<[u8; 32], ()> {
+	let passphrase = if args.bip39_passphrase {
+		let prompt_password = |prompt: &str| {
+			rpassword::prompt_password(prompt).map_err(|e| {
+				println!("ERROR: Unable to read the BIP39 passphrase: {}", e);
+			})
+		};
+		let passphrase = prompt_password("BIP39 passphrase: ")?;
+		if !args.restore_from_mnemonic && prompt_password("Repeat the BIP39 passphrase: ")? != passphrase
+		{
+			println!("ERROR: The passphrases don't match");
+			return Err(());
+		}
+		passphrase
+	} else {
+		String::new()
+	};
 
-	// Initialize our bitcoind client.
+	if args.restore_from_mnemonic {
+		print!("Enter the mnemonic to restore the node from: ");
+		std::io::stdout().flush().unwrap();
+		let mut words = String::new();
+		if let Err(e) = std::io::stdin().read_line(&mut words) {
+			println!("ERROR: Unable to read the mnemonic: {}", e);
+			return Err(());
+		}
+		let mnemonic = mnemonic::parse_mnemonic(&words).map_err(|e| {
+			println!("ERROR: Invalid mnemonic: {}", e);
+		})?;
+		return Ok(mnemonic::keys_seed_from_mnemonic(&mnemonic, &passphrase));
+	}
+
+	let mnemonic = mnemonic::generate_mnemonic();
+	match &args.mnemonic_export_path {
+		Some(path) => {
+			let mut options = fs::OpenOptions::new();
+			options.write(true).create_new(true);
+			#[cfg(unix)]
+			std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+			let written = options.open(path).and_then(|mut f| {
+				writeln!(f, "{}", mnemonic)?;
+				f.sync_all()
+			});
+			if let Err(e) = written {
+				println!("ERROR: Unable to export the mnemonic to {}: {}", path, e);
+				return Err(());
+			}
+			println!("The node's mnemonic was written to {}. Move it somewhere safe.", path);
+		},
+		None => {
+			println!("This is the node's mnemonic. Write it down, it won't be shown again:");
+			for (i, word) in mnemonic.words().enumerate() {
+				println!("{:>2}. {}", i + 1, word);
+			}
+			print!("Press Enter once you have written it down.");
+			std::io::stdout().flush().unwrap();
+			let _ = std::io::stdin().read_line(&mut String::new());
+			// Clear the screen so the words don't stay visible.
+			print!("\x1b[2J\x1b[H");
+		},
+	}
+	Ok(mnemonic::keys_seed_from_mnemonic(&mnemonic, &passphrase))
+}
+
+/// Connects to bitcoind and its wallet, checking that it runs the network we expect.
+async fn connect_bitcoind(
+	args: &LdkUserInfo, rpc: &BitcoindRpcInfo, fee_estimates: &Arc<FeeEstimates>,
+	logger: &Arc<FilesystemLogger>,
+) -> Result<Arc<BitcoindClient>, ()> {
 	let bitcoind_client = match BitcoindClient::new(
-		args.bitcoind_rpc_host.clone(),
-		args.bitcoind_rpc_port,
-		args.bitcoind_rpc_username.clone(),
-		args.bitcoind_rpc_password.clone(),
+		rpc.host.clone(),
+		rpc.port,
+		rpc.username.clone(),
+		rpc.password.clone(),
 		args.network,
+		Arc::clone(fee_estimates),
 		tokio::runtime::Handle::current(),
-		Arc::clone(&logger),
+		Arc::clone(logger),
 	)
-		.await
+	.await
 	{
-		Ok(client) => Arc::new(client),
+		Ok(client) => client,
 		Err(e) => {
 			println!("Failed to connect to bitcoind client: {}", e);
-			return;
+			return Err(());
 		},
 	};
+	let bitcoind_client = match args.bitcoind_wallet_name.clone() {
+		Some(wallet_name) => {
+			match bitcoind_client.with_wallet(wallet_name, args.create_bitcoind_wallet).await {
+				Ok(client) => Arc::new(client),
+				Err(e) => {
+					println!("{}", e);
+					return Err(());
+				},
+			}
+		},
+		None => Arc::new(bitcoind_client),
+	};
 
 	// Check that the bitcoind we've connected to is running the network we expect
-	let bitcoind_chain = bitcoind_client.get_blockchain_info().await.chain;
+	let bitcoind_chain = match bitcoind_client.get_blockchain_info().await {
+		Ok(info) => info.chain,
+		Err(e) => {
+			println!("Failed to get blockchain info from bitcoind: {}", e);
+			return Err(());
+		},
+	};
 	if bitcoind_chain
 		!= match args.network {
-		bitcoin::Network::Bitcoin => "main",
-		bitcoin::Network::Regtest => "regtest",
-		bitcoin::Network::Signet => "signet",
-		bitcoin::Network::Testnet | _ => "test",
-	} {
+			bitcoin::Network::Bitcoin => "main",
+			bitcoin::Network::Regtest => "regtest",
+			bitcoin::Network::Signet => "signet",
+			bitcoin::Network::Testnet | _ => "test",
+		} {
 		println!(
 			"Chain argument ({}) didn't match bitcoind chain ({})",
 			args.network, bitcoind_chain
 		);
-		return;
+		return Err(());
 	}
+	Ok(bitcoind_client)
+}
+
+async fn start_ldk() {
+	let args = match args::parse_startup_args() {
+		Ok(user_args) => user_args,
+		Err(()) => return,
+	};
+
+	// Initialize the LDK data directory if necessary.
+	let ldk_data_dir = format!("{}/.ldk", args.ldk_storage_dir_path);
+	fs::create_dir_all(ldk_data_dir.clone()).unwrap();
+
+	// Make sure no other node runs on the same data directory, until we return.
+	let _data_dir_lock = match DataDirLock::acquire(Path::new(&ldk_data_dir), args.force_unlock) {
+		Ok(lock) => lock,
+		Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+			println!(
+				"ERROR: {}. Another node may be running on it. If it isn't, e.g. because it crashed, restart with --force-unlock.",
+				e
+			);
+			return;
+		},
+		Err(e) => {
+			println!("ERROR: Failed to lock {}: {}", ldk_data_dir, e);
+			return;
+		},
+	};
+
+	// ## Setup
+	// Step 1: Initialize the Logger
+	let logger = match FilesystemLogger::with_config(ldk_data_dir.clone(), args.logging.clone()) {
+		Ok(logger) => Arc::new(logger),
+		Err(e) => {
+			println!("ERROR: Failed to open the log in {}/logs: {}", ldk_data_dir, e);
+			return;
+		},
+	};
+
+	// Derive the key the data directory is encrypted under, if it is.
+	let data_cipher = match unlock_data_dir(&args.encryption, Path::new(&ldk_data_dir)) {
+		Ok(data_cipher) => data_cipher,
+		Err(()) => return,
+	};
+
+	// Initialize the KVStore the node's data is persisted in.
+	let sqlite_db_path = Path::new(&ldk_data_dir).join(SQLITE_DB_FILE_NAME);
+	let manager_file_exists = Path::new(&ldk_data_dir).join("manager").exists();
+	let kv_store = match args.data_store {
+		DataStoreConfig::Filesystem => {
+			// Once migrated, the files left behind in the data directory are stale.
+			if sqlite_db_path.exists() {
+				println!(
+					"ERROR: {} exists, the node's data is kept in SQLite. Start with --data-store=sqlite.",
+					sqlite_db_path.display()
+				);
+				return;
+			}
+			DataStore::Filesystem(FilesystemStore::new(ldk_data_dir.clone().into()))
+		},
+		DataStoreConfig::Sqlite => {
+			let store = match SqliteStore::new(&sqlite_db_path) {
+				Ok(store) => store,
+				Err(e) => {
+					println!("ERROR: Failed to open {}: {}", sqlite_db_path.display(), e);
+					return;
+				},
+			};
+			// Starting from an empty database next to existing filesystem data would create a new
+			// ChannelManager without any of our channels.
+			let has_manager = store
+				.read(
+					persist::CHANNEL_MANAGER_PERSISTENCE_PRIMARY_NAMESPACE,
+					persist::CHANNEL_MANAGER_PERSISTENCE_SECONDARY_NAMESPACE,
+					persist::CHANNEL_MANAGER_PERSISTENCE_KEY,
+				)
+				.is_ok();
+			if manager_file_exists && !has_manager {
+				println!(
+					"ERROR: {} holds filesystem data which hasn't been migrated to SQLite yet. Run `cargo run -p migrate-store -- {}` first.",
+					ldk_data_dir, args.ldk_storage_dir_path
+				);
+				return;
+			}
+			DataStore::Sqlite(store)
+		},
+	};
+	// Mirror every write to the backup, if one is configured. Entries are encrypted before they're
+	// handed to the replicating store, so the backup never sees them in the clear.
+	let (kv_store, replicating_store) = match &args.backup {
+		Some(backup) => {
+			let backup = match open_backup(backup, Path::new(&ldk_data_dir)) {
+				Ok(backup) => backup,
+				Err(()) => return,
+			};
+			let max_wait = args.backup_max_wait;
+			let store =
+				Arc::new(ReplicatingStore::new(kv_store, backup, max_wait, Arc::clone(&logger)));
+			(DataStore::Replicated(Arc::clone(&store)), Some(store))
+		},
+		None => (kv_store, None),
+	};
+	let kv_store = match data_cipher.clone() {
+		Some(cipher) => {
+			// Encrypt anything written before encryption was enabled for the data directory.
+			match encrypted_store::encrypt_existing_data(Path::new(&ldk_data_dir), &kv_store, &cipher)
+			{
+				Ok(0) => {},
+				Ok(count) => println!("Encrypted {} existing entries in {}.", count, ldk_data_dir),
+				Err(e) => {
+					println!("ERROR: Failed to encrypt the existing data in {}: {}", ldk_data_dir, e);
+					return;
+				},
+			}
+			Arc::new(DataStore::Encrypted(Box::new(EncryptedStore::new(kv_store, cipher))))
+		},
+		None => Arc::new(kv_store),
+	};
+	// Bring the backup up to date with anything written while the node ran without it.
+	if let Some(store) = &replicating_store {
+		match store.replicate_existing_data(Path::new(&ldk_data_dir)) {
+			Ok(count) => println!("The backup holds all {} existing entries.", count),
+			Err(e) => {
+				println!("ERROR: Failed to replicate the existing data to the backup: {}", e);
+				return;
+			},
+		}
+	}
+
+	// The fee estimates LDK uses, and bitcoind's wallet funds our channels at, kept up to date by
+	// the chain source. The last estimates are persisted, so we don't start from our fallbacks on
+	// every restart.
+	let fee_estimates = Arc::new(FeeEstimates::new(
+		args.fee_policy.clone(),
+		Arc::clone(&kv_store),
+		Arc::clone(&logger),
+	));
+
+	// Initialize the chain source we get blocks or transactions, fee estimates and broadcasting
+	// from. bitcoind's wallet is also our on-chain wallet, so nodes syncing from Esplora or Electrum
+	// have none.
+	let chain_source = match args.chain_source {
+		ChainSourceConfig::Bitcoind => {
+			let rpc = args.bitcoind_rpc.as_ref().expect("bitcoind's RPC details are always parsed");
+			let client = match connect_bitcoind(&args, rpc, &fee_estimates, &logger).await {
+				Ok(client) => client,
+				Err(()) => return,
+			};
+			client.start_fee_polling();
+			Arc::new(ChainSource::Bitcoind(client))
+		},
+		ChainSourceConfig::Esplora(ref server_url) => {
+			match EsploraClient::new(
+				server_url.clone(),
+				Arc::clone(&fee_estimates),
+				tokio::runtime::Handle::current(),
+				Arc::clone(&logger),
+			)
+			.await
+			{
+				Ok(client) => Arc::new(ChainSource::Esplora(Arc::new(client))),
+				Err(e) => {
+					println!("Failed to connect to Esplora server: {}", e);
+					return;
+				},
+			}
+		},
+		ChainSourceConfig::Electrum(ref server_url) => {
+			match ElectrumClient::new(
+				server_url.clone(),
+				Arc::clone(&fee_estimates),
+				tokio::runtime::Handle::current(),
+				Arc::clone(&logger),
+			)
+			.await
+			{
+				Ok(client) => Arc::new(ChainSource::Electrum(Arc::new(client))),
+				Err(e) => {
+					println!("Failed to connect to Electrum server: {}", e);
+					return;
+				},
+			}
+		},
+	};
+
+	let bitcoind_client = match chain_source.as_ref() {
+		ChainSource::Bitcoind(client) => Some(Arc::clone(client)),
+		ChainSource::Esplora(_) | ChainSource::Electrum(_) => None,
+	};
 
 	// Step 2: Initialize the FeeEstimator
 
-	// BitcoindClient implements the FeeEstimator trait, so it'll act as our fee estimator.
-	let fee_estimator = bitcoind_client.clone();
+	// ChainSource implements the FeeEstimator trait, so it'll act as our fee estimator.
+	let fee_estimator = chain_source.clone();
 
 	// Step 3: Initialize the BroadcasterInterface
 
-	// BitcoindClient implements the BroadcasterInterface trait, so it'll act as our transaction
-	// broadcaster.
-	let broadcaster = bitcoind_client.clone();
+	// Transactions are broadcast through the ChainSource, by way of a BroadcastQueue which tracks
+	// each of them until it confirms and rebroadcasts it in the meantime. Every LDK component gets
+	// its own broadcaster from the queue, tagging its transactions with what they are for.
+	let broadcast_queue = Arc::new(BroadcastQueue::new(
+		Arc::clone(&chain_source),
+		Arc::clone(&kv_store),
+		Arc::clone(&logger),
+	));
+	let broadcaster = broadcast_queue.broadcaster(BroadcastKind::Closing);
 
 	// Step 4: Initialize the KeysManager
 
 	// The key seed that we use to derive the node privkey (that corresponds to the node pubkey) and
 	// other secret key material.
-	let keys_seed_path = format!("{}/keys_seed", ldk_data_dir.clone());
+	let keys_seed_path = format!("{}/{}", ldk_data_dir.clone(), KEYS_SEED_FNAME);
 	let keys_seed = if let Ok(seed) = fs::read(keys_seed_path.clone()) {
-		assert_eq!(seed.len(), 32);
-		let mut key = [0; 32];
-		key.copy_from_slice(&seed);
-		key
+		if args.restore_from_mnemonic {
+			println!(
+				"ERROR: {} already exists, refusing to overwrite it with a restored keys seed",
+				keys_seed_path
+			);
+			return;
+		}
+		match &data_cipher {
+			Some(cipher) => match cipher.decrypt_keys_seed(&seed) {
+				Ok(key) => key,
+				Err(e) => {
+					println!("ERROR: Unable to decrypt keys seed file {}: {}", keys_seed_path, e);
+					return;
+				},
+			},
+			None => {
+				assert_eq!(seed.len(), 32);
+				let mut key = [0; 32];
+				key.copy_from_slice(&seed);
+				key
+			},
+		}
 	} else {
-		let mut key = [0; 32];
-		thread_rng().fill_bytes(&mut key);
+		// The mnemonic is shown before the keys seed is written, so there never is a seed which
+		// wasn't backed up.
+		let key = match new_keys_seed(&args) {
+			Ok(key) => key,
+			Err(()) => return,
+		};
+		let contents = match &data_cipher {
+			Some(cipher) => cipher.encrypt_keys_seed(&key),
+			None => key.to_vec(),
+		};
 		match File::create(keys_seed_path.clone()) {
 			Ok(mut f) => {
-				std::io::Write::write_all(&mut f, &key)
+				std::io::Write::write_all(&mut f, &contents)
 					.expect("Failed to write node keys seed to disk");
 				f.sync_all().expect("Failed to sync node keys seed to disk");
 			},
@@ -496,36 +1060,46 @@ async fn start_ldk() {
 	let cur = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
 	let keys_manager = Arc::new(KeysManager::new(&keys_seed, cur.as_secs(), cur.subsec_nanos()));
 
-	let bump_tx_event_handler = Arc::new(BumpTransactionEventHandler::new(
-		Arc::clone(&broadcaster),
-		Arc::new(Wallet::new(Arc::clone(&bitcoind_client), Arc::clone(&logger))),
-		Arc::clone(&keys_manager),
-		Arc::clone(&logger),
-	));
+	// Bumping the fees of anchor channels' transactions spends on-chain funds, so without a wallet
+	// we can't and don't negotiate anchor channels.
+	let bump_tx_event_handler = bitcoind_client.as_ref().map(|bitcoind_client| {
+		Arc::new(BumpTransactionEventHandler::new(
+			broadcast_queue.broadcaster(BroadcastKind::AnchorBump),
+			Arc::new(Wallet::new(Arc::clone(bitcoind_client), Arc::clone(&logger))),
+			Arc::clone(&keys_manager),
+			Arc::clone(&logger),
+		))
+	});
 
 	// Step 5: Initialize Persistence
-	let fs_store = Arc::new(FilesystemStore::new(ldk_data_dir.clone().into()));
-	let persister = Arc::new(MonitorUpdatingPersister::new(
-		Arc::clone(&fs_store),
+	// When the node's data is replicated, monitor updates are only completed once the backup has
+	// them.
+	let persister = Arc::new(MonitorPersister::new(
+		MonitorUpdatingPersister::new(
+			Arc::clone(&kv_store),
+			Arc::clone(&logger),
+			1000,
+			Arc::clone(&keys_manager),
+			Arc::clone(&keys_manager),
+			Arc::clone(&broadcaster),
+			Arc::clone(&fee_estimator),
+		),
+		replicating_store,
 		Arc::clone(&logger),
-		1000,
-		Arc::clone(&keys_manager),
-		Arc::clone(&keys_manager),
-		Arc::clone(&bitcoind_client),
-		Arc::clone(&bitcoind_client),
 	));
 	// Alternatively, you can use the `FilesystemStore` as a `Persist` directly, at the cost of
 	// larger `ChannelMonitor` update writes (but no deletion or cleanup):
-	//let persister = Arc::clone(&fs_store);
+	//let persister = Arc::clone(&kv_store);
 
 	// Step 6: Initialize the ChainMonitor
 	let chain_monitor: Arc<ChainMonitor> = Arc::new(chainmonitor::ChainMonitor::new(
-		None,
+		chain_source.filter(),
 		Arc::clone(&broadcaster),
 		Arc::clone(&logger),
 		Arc::clone(&fee_estimator),
 		Arc::clone(&persister),
 	));
+	persister.set_chain_monitor(&chain_monitor);
 
 	// Step 7: Read ChannelMonitor state from disk
 	let mut channelmonitors = persister.read_all_channel_monitors_with_updates().unwrap();
@@ -534,30 +1108,48 @@ async fn start_ldk() {
 	//read_channel_monitors(Arc::clone(&persister), Arc::clone(&keys_manager), Arc::clone(&keys_manager)).unwrap();
 
 	// Step 8: Poll for the best chain tip, which may be used by the channel manager & spv client
-	let polled_chain_tip = init::validate_best_block_header(bitcoind_client.as_ref())
-		.await
-		.expect("Failed to fetch best block header and best block");
+	let (polled_chain_tip, polled_best_block) = match chain_source.as_ref() {
+		ChainSource::Bitcoind(client) => {
+			let tip = init::validate_best_block_header(client.as_ref())
+				.await
+				.expect("Failed to fetch best block header and best block");
+			(Some(tip), tip.to_best_block())
+		},
+		ChainSource::Esplora(client) => match client.get_best_block().await {
+			Ok(best_block) => (None, best_block),
+			Err(e) => {
+				println!("Failed to fetch best block from Esplora: {}", e);
+				return;
+			},
+		},
+		ChainSource::Electrum(client) => match client.get_best_block().await {
+			Ok(best_block) => (None, best_block),
+			Err(e) => {
+				println!("Failed to fetch best block from Electrum: {}", e);
+				return;
+			},
+		},
+	};
 
 	// Step 9: Initialize routing ProbabilisticScorer
-	let network_graph_path = format!("{}/network_graph", ldk_data_dir.clone());
 	let network_graph =
-		Arc::new(ldk::disk::read_network(Path::new(&network_graph_path), args.network, logger.clone()));
+		Arc::new(ldk::disk::read_network(&kv_store, args.network, logger.clone()));
 
-	let scorer_path = format!("{}/scorer", ldk_data_dir.clone());
-	let scorer = Arc::new(RwLock::new(ldk::disk::read_scorer(
-		Path::new(&scorer_path),
+	let scorer_manager = Arc::new(ScorerManager::new(
+		args.scoring_params.clone(),
 		Arc::clone(&network_graph),
+		Arc::clone(&kv_store),
 		Arc::clone(&logger),
-	)));
+	));
+	let scorer = scorer_manager.scorer();
 
 	// Step 10: Create Routers
-	let scoring_fee_params = ProbabilisticScoringFeeParameters::default();
 	let router = Arc::new(DefaultRouter::new(
 		network_graph.clone(),
 		logger.clone(),
 		keys_manager.clone(),
 		scorer.clone(),
-		scoring_fee_params,
+		scorer_manager.params().fee.clone(),
 	));
 
 	let message_router =
@@ -566,11 +1158,16 @@ async fn start_ldk() {
 	// Step 11: Initialize the ChannelManager
 	let mut user_config = UserConfig::default();
 	user_config.channel_handshake_limits.force_announced_channel_preference = false;
-	user_config.channel_handshake_config.negotiate_anchors_zero_fee_htlc_tx = true;
+	user_config.channel_handshake_config.negotiate_anchors_zero_fee_htlc_tx =
+		bump_tx_event_handler.is_some();
 	user_config.manually_accept_inbound_channels = true;
 	let mut restarting_node = true;
 	let (channel_manager_blockhash, channel_manager) = {
-		if let Ok(f) = fs::File::open(format!("{}/manager", ldk_data_dir.clone())) {
+		if let Ok(manager_bytes) = kv_store.read(
+			persist::CHANNEL_MANAGER_PERSISTENCE_PRIMARY_NAMESPACE,
+			persist::CHANNEL_MANAGER_PERSISTENCE_SECONDARY_NAMESPACE,
+			persist::CHANNEL_MANAGER_PERSISTENCE_KEY,
+		) {
 			let mut channel_monitor_references = Vec::new();
 			for (_, channel_monitor) in channelmonitors.iter() {
 				channel_monitor_references.push(channel_monitor);
@@ -582,18 +1179,17 @@ async fn start_ldk() {
 				fee_estimator.clone(),
 				chain_monitor.clone(),
 				broadcaster.clone(),
-				router,
+				Arc::clone(&router),
 				Arc::clone(&message_router),
 				logger.clone(),
 				user_config,
 				channel_monitor_references,
 			);
-			<(BlockHash, ChannelManager)>::read(&mut BufReader::new(f), read_args).unwrap()
+			<(BlockHash, ChannelManager)>::read(&mut &manager_bytes[..], read_args).unwrap()
 		} else {
 			// We're starting a fresh node.
 			restarting_node = false;
 
-			let polled_best_block = polled_chain_tip.to_best_block();
 			let polled_best_block_hash = polled_best_block.block_hash;
 			let chain_params =
 				ChainParameters { network: args.network, best_block: polled_best_block };
@@ -601,7 +1197,7 @@ async fn start_ldk() {
 				fee_estimator.clone(),
 				chain_monitor.clone(),
 				broadcaster.clone(),
-				router,
+				Arc::clone(&router),
 				Arc::clone(&message_router),
 				logger.clone(),
 				keys_manager.clone(),
@@ -616,7 +1212,20 @@ async fn start_ldk() {
 	};
 
 	// Step 12: Initialize the OutputSweeper.
-	let (sweeper_best_block, output_sweeper) = match fs_store.read(
+	// Without bitcoind's wallet, outputs are swept to the keys seed's own destination unless the
+	// operator sets one.
+	let sweep_wallet: Arc<dyn ChangeDestinationSource + Send + Sync> = match &bitcoind_client {
+		Some(bitcoind_client) => Arc::clone(bitcoind_client) as _,
+		None => Arc::new(KeysDestination::new(Arc::clone(&keys_manager))),
+	};
+	let sweep_policy = Arc::new(SweepPolicy::new(
+		sweep_wallet,
+		args.network,
+		args.sweep_batch_blocks,
+		Arc::clone(&kv_store),
+		Arc::clone(&logger),
+	));
+	let (sweeper_best_block, output_sweeper) = match kv_store.read(
 		OUTPUT_SWEEPER_PERSISTENCE_PRIMARY_NAMESPACE,
 		OUTPUT_SWEEPER_PERSISTENCE_SECONDARY_NAMESPACE,
 		OUTPUT_SWEEPER_PERSISTENCE_KEY,
@@ -624,24 +1233,24 @@ async fn start_ldk() {
 		Err(e) if e.kind() == io::ErrorKind::NotFound => {
 			let sweeper = OutputSweeper::new(
 				channel_manager.current_best_block(),
-				broadcaster.clone(),
+				broadcast_queue.broadcaster(BroadcastKind::Sweep),
 				fee_estimator.clone(),
-				None,
+				chain_source.filter(),
 				keys_manager.clone(),
-				bitcoind_client.clone(),
-				fs_store.clone(),
+				Arc::clone(&sweep_policy),
+				kv_store.clone(),
 				logger.clone(),
 			);
 			(channel_manager.current_best_block(), sweeper)
 		},
 		Ok(mut bytes) => {
 			let read_args = (
-				broadcaster.clone(),
+				broadcast_queue.broadcaster(BroadcastKind::Sweep),
 				fee_estimator.clone(),
-				None,
+				chain_source.filter(),
 				keys_manager.clone(),
-				bitcoind_client.clone(),
-				fs_store.clone(),
+				Arc::clone(&sweep_policy),
+				kv_store.clone(),
 				logger.clone(),
 			);
 			let mut reader = io::Cursor::new(&mut bytes);
@@ -651,13 +1260,55 @@ async fn start_ldk() {
 		Err(e) => panic!("Failed to read OutputSweeper with {}", e),
 	};
 
-	// Step 13: Sync ChannelMonitors, ChannelManager and OutputSweeper to chain tip
+	// Closed channels are recorded by the spend of their funding output, so we watch the funding
+	// output of every channel we have a monitor for.
+	let closed_channels =
+		Arc::new(ClosedChannels::new(Arc::clone(&kv_store), Arc::clone(&logger)));
+	for (_, channel_monitor) in channelmonitors.iter() {
+		closed_channels.watch_funding(channel_monitor.get_funding_txo().0);
+	}
+
+	// The event stream reports blocks and peer connections as well as LDK's events, so it's set up
+	// before the chain listeners.
+	let event_stream = match EventStream::new(Arc::clone(&kv_store), Arc::clone(&logger)) {
+		Ok(event_stream) => Arc::new(event_stream),
+		Err(e) => {
+			println!("ERROR: Failed to set up the event stream: {}", e);
+			return;
+		},
+	};
+	if let Some(addr) = args.event_stream_addr {
+		match tokio::net::TcpListener::bind(addr).await {
+			Ok(listener) => event_stream.serve(listener),
+			Err(e) => {
+				println!("ERROR: Failed to listen for event stream subscribers on {}: {}", addr, e);
+				return;
+			},
+		}
+	}
+
+	// Step 13: Sync ChannelMonitors, ChannelManager, OutputSweeper, BroadcastQueue and
+	// ClosedChannels to chain tip
+	// A queue we haven't persisted yet has nothing to track, so it can start from wherever the
+	// ChannelManager is. The same goes for closed channels.
+	let broadcast_queue_best_block =
+		broadcast_queue.best_block().unwrap_or_else(|| channel_manager.current_best_block());
+	let closed_channels_best_block =
+		closed_channels.best_block().unwrap_or_else(|| channel_manager.current_best_block());
 	let mut chain_listener_channel_monitors = Vec::new();
 	let mut cache = UnboundedCache::new();
 	let chain_tip = if restarting_node {
 		let mut chain_listeners = vec![
 			(channel_manager_blockhash, &channel_manager as &(dyn chain::Listen + Send + Sync)),
 			(sweeper_best_block.block_hash, &output_sweeper as &(dyn chain::Listen + Send + Sync)),
+			(
+				broadcast_queue_best_block.block_hash,
+				&*broadcast_queue as &(dyn chain::Listen + Send + Sync),
+			),
+			(
+				closed_channels_best_block.block_hash,
+				&*closed_channels as &(dyn chain::Listen + Send + Sync),
+			),
 		];
 
 		for (blockhash, channel_monitor) in channelmonitors.drain(..) {
@@ -676,14 +1327,21 @@ async fn start_ldk() {
 			));
 		}
 
-		init::synchronize_listeners(
-			bitcoind_client.as_ref(),
-			args.network,
-			&mut cache,
-			chain_listeners,
-		)
-			.await
-			.unwrap()
+		match chain_source.as_ref() {
+			ChainSource::Bitcoind(client) => Some(
+				init::synchronize_listeners(
+					client.as_ref(),
+					args.network,
+					&mut cache,
+					chain_listeners,
+				)
+				.await
+				.unwrap(),
+			),
+			// Transaction-based chain sources catch up through `Confirm` once the monitors have
+			// been handed to the `ChainMonitor` below.
+			ChainSource::Esplora(_) | ChainSource::Electrum(_) => None,
+		}
 	} else {
 		polled_chain_tip
 	};
@@ -692,16 +1350,38 @@ async fn start_ldk() {
 	for item in chain_listener_channel_monitors.drain(..) {
 		let channel_monitor = item.1 .0;
 		let funding_outpoint = item.2;
-		assert_eq!(
+		// Monitors are persisted again if the backup is replicated, completing once it has them.
+		assert!(matches!(
 			chain_monitor.watch_channel(funding_outpoint, channel_monitor),
-			Ok(ChannelMonitorUpdateStatus::Completed)
-		);
+			Ok(ChannelMonitorUpdateStatus::Completed | ChannelMonitorUpdateStatus::InProgress)
+		));
 	}
 
 	// Step 15: Optional: Initialize the P2PGossipSync
 	let gossip_sync =
 		Arc::new(P2PGossipSync::new(Arc::clone(&network_graph), None, Arc::clone(&logger)));
 
+	// Optionally fill the network graph from Rapid Gossip Sync snapshots, which is much faster than
+	// waiting for P2P gossip on a fresh node.
+	let rapid_gossip = match &args.rgs_source {
+		Some(source) => match RapidGossip::new(
+			SnapshotSource::parse(source),
+			Arc::clone(&network_graph),
+			Arc::clone(&kv_store),
+			tokio::runtime::Handle::current(),
+			Arc::clone(&logger),
+		)
+		.await
+		{
+			Ok(rapid_gossip) => Some(rapid_gossip),
+			Err(e) => {
+				println!("ERROR: Failed to set up rapid gossip sync: {}", e);
+				return;
+			},
+		},
+		None => None,
+	};
+
 	// Step 16 an OMDomainResolver as a service to other nodes
 	// As a service to other LDK users, using an `OMDomainResolver` allows others to resolve BIP
 	// 353 Human Readable Names for others, providing them DNSSEC proofs over lightning onion
@@ -740,15 +1420,19 @@ async fn start_ldk() {
 		logger.clone(),
 		Arc::clone(&keys_manager),
 	));
+	event_stream.watch_peers(Arc::clone(&peer_manager));
 
-	// Install a GossipVerifier in in the P2PGossipSync
-	let utxo_lookup = GossipVerifier::new(
-		Arc::clone(&bitcoind_client.bitcoind_rpc_client),
-		lightning_block_sync::gossip::TokioSpawner,
-		Arc::clone(&gossip_sync),
-		Arc::clone(&peer_manager),
-	);
-	gossip_sync.add_utxo_lookup(Some(Arc::new(utxo_lookup)));
+	// Install a GossipVerifier in in the P2PGossipSync. This needs full block data, so it's only
+	// available when bitcoind is our chain source.
+	if let ChainSource::Bitcoind(client) = chain_source.as_ref() {
+		let utxo_lookup = GossipVerifier::new(
+			Arc::clone(&client.bitcoind_rpc_client),
+			lightning_block_sync::gossip::TokioSpawner,
+			Arc::clone(&gossip_sync),
+			Arc::clone(&peer_manager),
+		);
+		gossip_sync.add_utxo_lookup(Some(Arc::new(utxo_lookup)));
+	}
 
 	// ## Running LDK
 	// Step 18: Initialize networking
@@ -782,25 +1466,139 @@ async fn start_ldk() {
 	let channel_manager_listener = channel_manager.clone();
 	let chain_monitor_listener = chain_monitor.clone();
 	let output_sweeper_listener = output_sweeper.clone();
-	let bitcoind_block_source = bitcoind_client.clone();
+	let broadcast_queue_listener = Arc::clone(&broadcast_queue);
+	let closed_channels_listener = Arc::clone(&closed_channels);
+	let event_stream_listener = Arc::clone(&event_stream);
 	let network = args.network;
-	tokio::spawn(async move {
-		let chain_poller = poll::ChainPoller::new(bitcoind_block_source.as_ref(), network);
-		let chain_listener =
-			(chain_monitor_listener, &(channel_manager_listener, output_sweeper_listener));
-		let mut spv_client = SpvClient::new(chain_tip, chain_poller, &mut cache, &chain_listener);
-		loop {
-			spv_client.poll_best_tip().await.unwrap();
-			tokio::time::sleep(Duration::from_secs(1)).await;
-		}
-	});
+	match chain_source.as_ref() {
+		ChainSource::Bitcoind(client) => {
+			let bitcoind_block_source = Arc::clone(client);
+			let chain_tip = chain_tip.expect("bitcoind always provides a validated chain tip");
+			// If bitcoind notifies us of new blocks over ZMQ we poll as soon as one arrives, only
+			// falling back to (much less frequent) timed polling in case a notification is lost.
+			let block_notifier = Arc::new(tokio::sync::Notify::new());
+			let poll_interval = match args.zmq_block_endpoint.clone() {
+				Some(endpoint) => {
+					bitcoind_zmq::subscribe_blocks(
+						endpoint,
+						Arc::clone(&block_notifier),
+						Arc::clone(&logger),
+					);
+					Duration::from_secs(30)
+				},
+				None => Duration::from_secs(1),
+			};
+			let poll_logger = Arc::clone(&logger);
+			tokio::spawn(async move {
+				let chain_poller = poll::ChainPoller::new(bitcoind_block_source.as_ref(), network);
+				let chain_listener = (
+					chain_monitor_listener,
+					&(
+						channel_manager_listener,
+						&(
+							output_sweeper_listener,
+							&(
+								broadcast_queue_listener,
+								&(closed_channels_listener, event_stream_listener),
+							),
+						),
+					),
+				);
+				let mut spv_client =
+					SpvClient::new(chain_tip, chain_poller, &mut cache, &chain_listener);
+				loop {
+					// A failed poll is retried on the next notification or timeout.
+					if let Err(e) = spv_client.poll_best_tip().await {
+						use lightning::util::logger::Logger;
+						lightning::log_error!(&*poll_logger, "Failed to poll bitcoind: {:?}", e);
+					}
+					tokio::select! {
+						_ = tokio::time::sleep(poll_interval) => {},
+						_ = block_notifier.notified() => {},
+					}
+				}
+			});
+
+			if let Some(endpoint) = args.zmq_rawtx_endpoint.clone() {
+				let mempool_bitcoind_client = Arc::clone(client);
+				bitcoind_zmq::subscribe_transactions(
+					endpoint,
+					move |tx| {
+						let txid = tx.compute_txid();
+						if mempool_bitcoind_client.take_unseen_broadcast(&txid) {
+							println!("\nEVENT: broadcast transaction {} entered the mempool", txid);
+							print!("> ");
+							std::io::stdout().flush().unwrap();
+						}
+					},
+					Arc::clone(&logger),
+				);
+			}
+		},
+		ChainSource::Esplora(_) | ChainSource::Electrum(_) => {
+			let sync_chain_source = Arc::clone(&chain_source);
+			let sync_logger = Arc::clone(&logger);
+			tokio::spawn(async move {
+				// Each sync walks every registered transaction and output, so we poll less
+				// aggressively than we do with bitcoind.
+				let mut interval = tokio::time::interval(Duration::from_secs(10));
+				interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+				loop {
+					interval.tick().await;
+					let confirmables = vec![
+						Arc::clone(&channel_manager_listener) as Arc<dyn Confirm + Send + Sync>,
+						Arc::clone(&chain_monitor_listener) as Arc<dyn Confirm + Send + Sync>,
+						Arc::clone(&output_sweeper_listener) as Arc<dyn Confirm + Send + Sync>,
+						Arc::clone(&broadcast_queue_listener) as Arc<dyn Confirm + Send + Sync>,
+						Arc::clone(&closed_channels_listener) as Arc<dyn Confirm + Send + Sync>,
+						Arc::clone(&event_stream_listener) as Arc<dyn Confirm + Send + Sync>,
+					];
+					if let Err(e) = sync_chain_source.sync(confirmables).await {
+						use lightning::util::logger::Logger;
+						lightning::log_error!(&*sync_logger, "Failed to sync chain source: {}", e);
+					}
+				}
+			});
+		},
+	}
 
-	let inbound_payments = Arc::new(Mutex::new(ldk::disk::read_inbound_payment_info(Path::new(
-		&format!("{}/{}", ldk_data_dir, INBOUND_PAYMENTS_FNAME),
-	))));
-	let outbound_payments = Arc::new(Mutex::new(ldk::disk::read_outbound_payment_info(Path::new(
-		&format!("{}/{}", ldk_data_dir, OUTBOUND_PAYMENTS_FNAME),
-	))));
+	let inbound_payments =
+		Arc::new(Mutex::new(ldk::disk::read_inbound_payment_info(&kv_store)));
+	let outbound_payments =
+		Arc::new(Mutex::new(ldk::disk::read_outbound_payment_info(&kv_store)));
+	let forwarding_history =
+		Arc::new(ForwardingHistory::new(Arc::clone(&kv_store), Arc::clone(&logger)));
+	let webhooks = match Webhooks::new(
+		args.webhooks.clone(),
+		Arc::clone(&kv_store),
+		&tokio::runtime::Handle::current(),
+		Arc::clone(&logger),
+	) {
+		Ok(webhooks) => webhooks,
+		Err(e) => {
+			println!("ERROR: Failed to set up webhooks: {}", e);
+			return;
+		},
+	};
+	let metrics = Arc::new(Metrics::new(
+		Arc::clone(&channel_manager),
+		Arc::clone(&chain_monitor),
+		Arc::clone(&peer_manager),
+		Arc::clone(&network_graph),
+		bitcoind_client.clone(),
+		Arc::clone(&fee_estimates),
+		Arc::clone(&forwarding_history),
+		Arc::clone(&logger),
+	));
+	if let Some(addr) = args.metrics_addr {
+		match tokio::net::TcpListener::bind(addr).await {
+			Ok(listener) => metrics.serve(listener),
+			Err(e) => {
+				println!("ERROR: Failed to listen for metrics scrapes on {}: {}", addr, e);
+				return;
+			},
+		}
+	}
 	let recent_payments_payment_ids = channel_manager
 		.list_recent_payments()
 		.into_iter()
@@ -822,44 +1620,60 @@ async fn start_ldk() {
 			payment_info.status = HTLCStatus::Failed;
 		}
 	}
-	fs_store
+	kv_store
 		.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound_payments.lock().unwrap().encode())
 		.unwrap();
 
+	let services = NodeServices {
+		fee_estimates,
+		broadcast_queue,
+		router,
+		scorer_manager,
+		forwarding_history,
+		closed_channels,
+		sweep_policy,
+		webhooks,
+		event_stream,
+		metrics,
+	};
+
 	// Step 20: Handle LDK Events
 	let channel_manager_event_listener = Arc::clone(&channel_manager);
-	let bitcoind_client_event_listener = Arc::clone(&bitcoind_client);
+	let bitcoind_client_event_listener = bitcoind_client.clone();
 	let network_graph_event_listener = Arc::clone(&network_graph);
 	let keys_manager_event_listener = Arc::clone(&keys_manager);
 	let inbound_payments_event_listener = Arc::clone(&inbound_payments);
 	let outbound_payments_event_listener = Arc::clone(&outbound_payments);
-	let fs_store_event_listener = Arc::clone(&fs_store);
+	let kv_store_event_listener = Arc::clone(&kv_store);
 	let peer_manager_event_listener = Arc::clone(&peer_manager);
 	let output_sweeper_event_listener = Arc::clone(&output_sweeper);
+	let services_event_listener = services.clone();
 	let network = args.network;
 	let event_handler = move |event: Event| {
 		let channel_manager_event_listener = Arc::clone(&channel_manager_event_listener);
-		let bitcoind_client_event_listener = Arc::clone(&bitcoind_client_event_listener);
+		let bitcoind_client_event_listener = bitcoind_client_event_listener.clone();
 		let network_graph_event_listener = Arc::clone(&network_graph_event_listener);
 		let keys_manager_event_listener = Arc::clone(&keys_manager_event_listener);
-		let bump_tx_event_handler = Arc::clone(&bump_tx_event_handler);
+		let bump_tx_event_handler = bump_tx_event_handler.clone();
 		let inbound_payments_event_listener = Arc::clone(&inbound_payments_event_listener);
 		let outbound_payments_event_listener = Arc::clone(&outbound_payments_event_listener);
-		let fs_store_event_listener = Arc::clone(&fs_store_event_listener);
+		let kv_store_event_listener = Arc::clone(&kv_store_event_listener);
 		let peer_manager_event_listener = Arc::clone(&peer_manager_event_listener);
 		let output_sweeper_event_listener = Arc::clone(&output_sweeper_event_listener);
+		let services_event_listener = services_event_listener.clone();
 		async move {
 			handle_ldk_events(
 				channel_manager_event_listener,
-				&bitcoind_client_event_listener,
+				bitcoind_client_event_listener.as_deref(),
 				&network_graph_event_listener,
 				&keys_manager_event_listener,
-				&bump_tx_event_handler,
+				bump_tx_event_handler.as_deref(),
 				peer_manager_event_listener,
 				inbound_payments_event_listener,
 				outbound_payments_event_listener,
-				fs_store_event_listener,
+				kv_store_event_listener,
 				OutputSweeperWrapper(output_sweeper_event_listener),
+				&services_event_listener,
 				network,
 				event,
 			)
@@ -869,17 +1683,21 @@ async fn start_ldk() {
 	};
 
 	// Step 21: Persist ChannelManager and NetworkGraph
-	let persister = Arc::new(FilesystemStore::new(ldk_data_dir.clone().into()));
+	let persister = Arc::clone(&kv_store);
 
 	// Step 22: Background Processing
 	let (bp_exit, bp_exit_check) = tokio::sync::watch::channel(());
+	let gossip_sync = match &rapid_gossip {
+		Some(rapid_gossip) => GossipSync::Rapid(rapid_gossip.gossip_sync()),
+		None => GossipSync::P2P(gossip_sync.clone()),
+	};
 	let mut background_processor = tokio::spawn(process_events_async(
 		Arc::clone(&persister),
 		event_handler,
 		chain_monitor.clone(),
 		channel_manager.clone(),
 		Some(onion_messenger),
-		GossipSync::p2p(gossip_sync.clone()),
+		gossip_sync,
 		peer_manager.clone(),
 		logger.clone(),
 		Some(scorer.clone()),
@@ -959,6 +1777,18 @@ async fn start_ldk() {
 		}
 	});
 
+	// Regularly rebroadcast transactions that haven't confirmed yet, starting with any left over
+	// from before we restarted.
+	let rebroadcast_queue = Arc::clone(&services.broadcast_queue);
+	tokio::spawn(async move {
+		let mut interval = tokio::time::interval(REBROADCAST_INTERVAL);
+		interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+		loop {
+			interval.tick().await;
+			rebroadcast_queue.rebroadcast_pending();
+		}
+	});
+
 	tokio::spawn(sweep::migrate_deprecated_spendable_outputs(
 		ldk_data_dir.clone(),
 		Arc::clone(&keys_manager),
@@ -972,6 +1802,8 @@ async fn start_ldk() {
 	let cli_chain_monitor = Arc::clone(&chain_monitor);
 	let cli_persister = Arc::clone(&persister);
 	let cli_peer_manager = Arc::clone(&peer_manager);
+	let cli_output_sweeper = Arc::clone(&output_sweeper);
+	let cli_bitcoind_client = bitcoind_client.clone();
 	let cli_poll = tokio::task::spawn_blocking(move || {
 		cli::poll_for_user_input_wrapless(
 			cli_peer_manager,
@@ -983,6 +1815,9 @@ async fn start_ldk() {
 			outbound_payments,
 			ldk_data_dir,
 			cli_persister,
+			cli_output_sweeper,
+			cli_bitcoind_client,
+			services,
 		)
 	});
 
@@ -0,0 +1,25 @@
+use std::str::SplitWhitespace;
+use std::sync::Arc;
+use ldk::common::ChannelManager;
+use crate::query_route::parse_route_destination;
+
+pub(crate) fn probe_cli(words: SplitWhitespace, channel_manager: &Arc<ChannelManager>) {
+    let route_params = match parse_route_destination(words, "probe <pubkey|invoice> [<amt_msat>]") {
+        Ok(route_params) => route_params,
+        Err(()) => return,
+    };
+
+    match channel_manager.send_preflight_probes(route_params, None) {
+        // Paths consisting only of one of our own channels aren't probed.
+        Ok(probes) if probes.is_empty() => {
+            println!("Nothing to probe, the destination is reachable over our own channels")
+        },
+        Ok(probes) => {
+            for (_, payment_id) in probes.iter() {
+                println!("Sent probe {}", payment_id);
+            }
+            println!("The result of each probe will be reported as an event.");
+        },
+        Err(e) => println!("ERROR: failed to send probes: {:?}", e),
+    }
+}
@@ -0,0 +1,124 @@
+use std::str::{FromStr, SplitWhitespace};
+use std::sync::Arc;
+use lightning::bolt11_invoice::Bolt11Invoice;
+use lightning::ln::bolt11_payment::{payment_parameters_from_invoice, payment_parameters_from_variable_amount_invoice};
+use lightning::ln::channelmanager::MIN_FINAL_CLTV_EXPIRY_DELTA;
+use lightning::routing::gossip::NodeId;
+use lightning::routing::router::{PaymentParameters, RouteParameters, Router as _};
+use ldk::common::{ChannelManager, NetworkGraph, Router};
+
+/// Parses `<pubkey|invoice> [<amt_msat>]` into the parameters of a route to the destination. The
+/// amount can only be left out for invoices which specify one.
+pub(crate) fn parse_route_destination(mut words: SplitWhitespace, usage: &str) -> Result<RouteParameters, ()> {
+    let destination = match words.next() {
+        Some(destination) => destination,
+        None => {
+            println!("ERROR: missing destination: `{}`", usage);
+            return Err(());
+        },
+    };
+    let amt_msat = match words.next().map(|amt| amt.parse::<u64>()) {
+        Some(Ok(amt)) => Some(amt),
+        Some(Err(e)) => {
+            println!("ERROR: couldn't parse amt_msat: {}", e);
+            return Err(());
+        },
+        None => None,
+    };
+
+    if let Some(pubkey) = ldk::hex_utils::to_compressed_pubkey(destination) {
+        let amt_msat = match amt_msat {
+            Some(amt_msat) => amt_msat,
+            None => {
+                println!("ERROR: an amount is required when the destination is a pubkey: `{}`", usage);
+                return Err(());
+            },
+        };
+        // A bare pubkey doesn't tell us the final CLTV expiry delta the destination requires, so
+        // assume the smallest one LDK accepts.
+        let payment_params = PaymentParameters::from_node_id(pubkey, MIN_FINAL_CLTV_EXPIRY_DELTA as u32);
+        return Ok(RouteParameters::from_payment_params_and_value(payment_params, amt_msat));
+    }
+
+    let invoice = match Bolt11Invoice::from_str(destination) {
+        Ok(invoice) => invoice,
+        Err(e) => {
+            println!("ERROR: destination is neither a pubkey nor a valid invoice: {}", e);
+            return Err(());
+        },
+    };
+    let params = match (invoice.amount_milli_satoshis(), amt_msat) {
+        (Some(_), None) => payment_parameters_from_invoice(&invoice),
+        (None, Some(amt_msat)) => payment_parameters_from_variable_amount_invoice(&invoice, amt_msat),
+        (Some(invoice_amt), Some(amt_msat)) => {
+            println!("ERROR: the invoice is for {} msat, not {} msat", invoice_amt, amt_msat);
+            return Err(());
+        },
+        (None, None) => {
+            println!("ERROR: the invoice doesn't specify an amount: `{}`", usage);
+            return Err(());
+        },
+    };
+    match params {
+        Ok((_, _, route_params)) => Ok(route_params),
+        Err(()) => {
+            println!("ERROR: couldn't build payment parameters from the invoice");
+            Err(())
+        },
+    }
+}
+
+pub(crate) fn query_route_cli(
+    words: SplitWhitespace, router: &Arc<Router>, channel_manager: &Arc<ChannelManager>,
+    network_graph: &Arc<NetworkGraph>,
+) {
+    let route_params = match parse_route_destination(words, "queryroute <pubkey|invoice> [<amt_msat>]") {
+        Ok(route_params) => route_params,
+        Err(()) => return,
+    };
+
+    let first_hops = channel_manager.list_usable_channels();
+    let route = match router.find_route(
+        &channel_manager.get_our_node_id(),
+        &route_params,
+        Some(&first_hops.iter().collect::<Vec<_>>()),
+        channel_manager.compute_inflight_htlcs(),
+    ) {
+        Ok(route) => route,
+        Err(e) => {
+            println!("ERROR: no route found: {}", e.err);
+            return;
+        },
+    };
+
+    let graph = network_graph.read_only();
+    println!("{{");
+    println!("\ttotal_amount_msat: {},", route.get_total_amount());
+    println!("\ttotal_fees_msat: {},", route.get_total_fees());
+    println!("\tpaths: [");
+    for path in route.paths.iter() {
+        println!("\t\t{{");
+        println!("\t\t\tamount_msat: {},", path.final_value_msat());
+        println!("\t\t\tfees_msat: {},", path.fee_msat());
+        let total_cltv_expiry_delta: u32 = path.hops.iter().map(|hop| hop.cltv_expiry_delta).sum();
+        println!("\t\t\ttotal_cltv_expiry_delta: {},", total_cltv_expiry_delta);
+        println!("\t\t\thops: [");
+        for hop in path.hops.iter() {
+            println!("\t\t\t\t{{");
+            println!("\t\t\t\t\tpubkey: {},", hop.pubkey);
+            if let Some(node) = graph.node(&NodeId::from_pubkey(&hop.pubkey)) {
+                if let Some(announcement) = &node.announcement_info {
+                    println!("\t\t\t\t\talias: {},", announcement.alias());
+                }
+            }
+            println!("\t\t\t\t\tshort_channel_id: {},", hop.short_channel_id);
+            println!("\t\t\t\t\tfee_msat: {},", hop.fee_msat);
+            println!("\t\t\t\t\tcltv_expiry_delta: {},", hop.cltv_expiry_delta);
+            println!("\t\t\t\t}},");
+        }
+        println!("\t\t\t],");
+        println!("\t\t}},");
+    }
+    println!("\t]");
+    println!("}}");
+}
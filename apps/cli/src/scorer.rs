@@ -0,0 +1,146 @@
+use std::fs;
+use std::str::SplitWhitespace;
+use std::sync::Arc;
+use lightning::routing::gossip::NodeId;
+use ldk::common::NetworkGraph;
+use ldk::scoring::ScorerManager;
+
+fn parse_scid(word: Option<&str>, usage: &str) -> Result<Option<u64>, ()> {
+    match word.map(|scid| scid.parse::<u64>()) {
+        Some(Ok(scid)) => Ok(Some(scid)),
+        Some(Err(e)) => {
+            println!("ERROR: couldn't parse short_channel_id: {}: `{}`", e, usage);
+            Err(())
+        },
+        None => Ok(None),
+    }
+}
+
+/// Prints the liquidity the scorer estimates is available in each direction of the channels it
+/// learned about, or of a single channel along with the history it keeps for it.
+pub(crate) fn list_scores_cli(
+    mut words: SplitWhitespace, scorer_manager: &Arc<ScorerManager>,
+    network_graph: &Arc<NetworkGraph>,
+) {
+    let only_scid = match parse_scid(words.next(), "listscores [<short_channel_id>]") {
+        Ok(scid) => scid,
+        Err(()) => return,
+    };
+
+    let scorer = scorer_manager.scorer();
+    let scorer = scorer.read().unwrap();
+    let graph = network_graph.read_only();
+    let mut scids = match only_scid {
+        Some(scid) => vec![scid],
+        None => graph.channels().unordered_keys().copied().collect::<Vec<_>>(),
+    };
+    scids.sort_unstable();
+
+    let alias = |node_id: &NodeId| {
+        graph
+            .node(node_id)
+            .and_then(|node| node.announcement_info.as_ref())
+            .map(|announcement| announcement.alias().to_string())
+    };
+    let mut printed = 0;
+    println!("[");
+    for scid in scids {
+        let channel = match graph.channel(scid) {
+            Some(channel) => channel,
+            None => continue,
+        };
+        for (source, target) in [(channel.node_one, channel.node_two), (channel.node_two, channel.node_one)].iter() {
+            let (min_liquidity_msat, max_liquidity_msat) =
+                match scorer.estimated_channel_liquidity_range(scid, target) {
+                    Some(range) => range,
+                    None => continue,
+                };
+            printed += 1;
+            println!("\t{{");
+            println!("\t\tshort_channel_id: {},", scid);
+            println!("\t\tfrom: {},", source);
+            if let Some(alias) = alias(source) {
+                println!("\t\tfrom_alias: {},", alias);
+            }
+            println!("\t\tto: {},", target);
+            if let Some(alias) = alias(target) {
+                println!("\t\tto_alias: {},", alias);
+            }
+            if let Some(capacity_sats) = channel.capacity_sats {
+                println!("\t\tcapacity_sats: {},", capacity_sats);
+            }
+            println!("\t\tmin_liquidity_msat: {},", min_liquidity_msat);
+            println!("\t\tmax_liquidity_msat: {},", max_liquidity_msat);
+            if only_scid.is_some() {
+                if let Some((min_buckets, max_buckets)) =
+                    scorer.historical_estimated_channel_liquidity_probabilities(scid, target)
+                {
+                    println!("\t\thistorical_min_liquidity_buckets: {:?},", min_buckets);
+                    println!("\t\thistorical_max_liquidity_buckets: {:?},", max_buckets);
+                }
+            }
+            println!("\t}},");
+        }
+    }
+    println!("]");
+    if printed == 0 {
+        match only_scid {
+            Some(scid) => println!("The scorer hasn't learned anything about channel {}.", scid),
+            None => println!("The scorer hasn't learned anything about any channel yet."),
+        }
+    }
+}
+
+pub(crate) fn reset_scorer_cli(mut words: SplitWhitespace, scorer_manager: &Arc<ScorerManager>) {
+    let scid = match parse_scid(words.next(), "resetscorer [<short_channel_id>]") {
+        Ok(scid) => scid,
+        Err(()) => return,
+    };
+    match scid {
+        Some(scid) => match scorer_manager.reset_channel(scid) {
+            Ok(true) => println!("Forgot what the scorer learned about channel {}", scid),
+            Ok(false) => println!("The scorer hadn't learned anything about channel {}", scid),
+            Err(e) => println!("ERROR: failed to reset channel {}: {}", scid, e),
+        },
+        None => match scorer_manager.reset() {
+            Ok(()) => println!("Reset the scorer"),
+            Err(e) => println!("ERROR: failed to reset the scorer: {}", e),
+        },
+    }
+}
+
+pub(crate) fn export_scorer_cli(mut words: SplitWhitespace, scorer_manager: &Arc<ScorerManager>) {
+    let path = match words.next() {
+        Some(path) => path,
+        None => {
+            println!("ERROR: exportscorer requires a path: `exportscorer <path>`");
+            return;
+        },
+    };
+    let serialized = scorer_manager.export();
+    match fs::write(path, &serialized) {
+        Ok(()) => println!("Exported the scorer ({} bytes) to {}", serialized.len(), path),
+        Err(e) => println!("ERROR: failed to write {}: {}", path, e),
+    }
+}
+
+pub(crate) fn import_scorer_cli(mut words: SplitWhitespace, scorer_manager: &Arc<ScorerManager>) {
+    let path = match words.next() {
+        Some(path) => path,
+        None => {
+            println!("ERROR: importscorer requires a path: `importscorer <path>`");
+            return;
+        },
+    };
+    let serialized = match fs::read(path) {
+        Ok(serialized) => serialized,
+        Err(e) => {
+            println!("ERROR: failed to read {}: {}", path, e);
+            return;
+        },
+    };
+    match scorer_manager.import(&serialized) {
+        Ok(()) => println!("Imported the scorer from {}", path),
+        Err(e) => println!("ERROR: failed to import {}: {}", path, e),
+    }
+}
@@ -13,13 +13,15 @@ use lightning::onion_message::messenger::Destination;
 use lightning::sign::{EntropySource, KeysManager};
 use lightning::util::persist::KVStore;
 use lightning::util::ser::Writeable;
-use lightning_persister::fs_store::FilesystemStore;
+use ldk::data_store::DataStore;
 use ldk::common::{ChannelManager, HTLCStatus, MillisatAmount, NetworkGraph, OutboundPaymentInfoStorage, PaymentInfo};
 use ldk::disk::OUTBOUND_PAYMENTS_FNAME;
+use ldk::metrics::Metrics;
 
+#[allow(clippy::too_many_arguments)]
 pub (crate) fn send_payment_cli(mut words: SplitWhitespace, keys_manager: &Arc<KeysManager>, outbound_payments: &Arc<Mutex<OutboundPaymentInfoStorage>>,
-                                channel_manager: &Arc<ChannelManager>, mut line: String, fs_store: &Arc<FilesystemStore>,
-                                network_graph: &Arc<NetworkGraph>) {
+                                channel_manager: &Arc<ChannelManager>, mut line: String, kv_store: &Arc<DataStore>,
+                                network_graph: &Arc<NetworkGraph>, metrics: &Metrics) {
     let invoice_str = words.next();
     if invoice_str.is_none() {
         println!("ERROR: sendpayment requires an invoice: `sendpayment <invoice> [amount_msat]`");
@@ -86,18 +88,20 @@ pub (crate) fn send_payment_cli(mut words: SplitWhitespace, keys_manager: &Arc<K
                 amt_msat: MillisatAmount(Some(amt_msat)),
             },
         );
-        fs_store
+        kv_store
             .write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound_payments.encode())
             .unwrap();
 
         let retry = Retry::Timeout(Duration::from_secs(10));
         let amt = Some(amt_msat);
+        metrics.payment_started(payment_id);
         let pay = channel_manager
             .pay_for_offer(&offer, None, amt, None, payment_id, retry, None);
         if pay.is_ok() {
             println!("Payment in flight");
         } else {
             println!("ERROR: Failed to pay: {:?}", pay);
+            metrics.payment_failed(&payment_id);
         }
     } else if let Ok(hrn) = HumanReadableName::from_encoded(invoice_str) {
         let random_bytes = keys_manager.get_secure_random_bytes();
@@ -149,7 +153,7 @@ pub (crate) fn send_payment_cli(mut words: SplitWhitespace, keys_manager: &Arc<K
                 amt_msat: MillisatAmount(Some(amt_msat)),
             },
         );
-        fs_store
+        kv_store
             .write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound_payments.encode())
             .unwrap();
 
@@ -157,11 +161,13 @@ pub (crate) fn send_payment_cli(mut words: SplitWhitespace, keys_manager: &Arc<K
         let pay = |a, b, c, d, e, f| {
             channel_manager.pay_for_offer_from_human_readable_name(a, b, c, d, e, f)
         };
+        metrics.payment_started(payment_id);
         let pay = pay(hrn, amt_msat, payment_id, retry, None, dns_resolvers);
         if pay.is_ok() {
             println!("Payment in flight");
         } else {
             println!("ERROR: Failed to pay");
+            metrics.payment_failed(&payment_id);
         }
     } else {
         match Bolt11Invoice::from_str(invoice_str) {
@@ -170,7 +176,8 @@ pub (crate) fn send_payment_cli(mut words: SplitWhitespace, keys_manager: &Arc<K
                 &invoice,
                 user_provided_amt,
                 &mut outbound_payments.lock().unwrap(),
-                Arc::clone(&fs_store),
+                Arc::clone(&kv_store),
+                metrics,
             ),
             Err(e) => {
                 println!("ERROR: invalid invoice: {:?}", e);
@@ -181,7 +188,7 @@ pub (crate) fn send_payment_cli(mut words: SplitWhitespace, keys_manager: &Arc<K
 
 fn send_payment(
     channel_manager: &ChannelManager, invoice: &Bolt11Invoice, required_amount_msat: Option<u64>,
-    outbound_payments: &mut OutboundPaymentInfoStorage, fs_store: Arc<FilesystemStore>,
+    outbound_payments: &mut OutboundPaymentInfoStorage, kv_store: Arc<DataStore>, metrics: &Metrics,
 ) {
     let payment_id = PaymentId((*invoice.payment_hash()).to_byte_array());
     let payment_secret = Some(*invoice.payment_secret());
@@ -224,8 +231,9 @@ fn send_payment(
             amt_msat: MillisatAmount(invoice.amount_milli_satoshis()),
         },
     );
-    fs_store.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound_payments.encode()).unwrap();
+    kv_store.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound_payments.encode()).unwrap();
 
+    metrics.payment_started(payment_id);
     match channel_manager.send_payment(
         payment_hash,
         recipient_onion,
@@ -242,8 +250,9 @@ fn send_payment(
         Err(e) => {
             println!("ERROR: failed to send payment: {:?}", e);
             print!("> ");
+            metrics.payment_failed(&payment_id);
             outbound_payments.payments.get_mut(&payment_id).unwrap().status = HTLCStatus::Failed;
-            fs_store.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound_payments.encode()).unwrap();
+            kv_store.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound_payments.encode()).unwrap();
         },
     };
 }
\ No newline at end of file
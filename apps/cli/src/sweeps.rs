@@ -0,0 +1,89 @@
+use std::str::SplitWhitespace;
+use std::sync::Arc;
+use bitcoin::{Amount, OutPoint};
+use lightning::chain::chaininterface::{ConfirmationTarget, FeeEstimator};
+use lightning::sign::SpendableOutputDescriptor;
+use lightning::util::sweep::{OutputSpendStatus, TrackedSpendableOutput};
+use ldk::common::OutputSweeper;
+use ldk::fee_policy::FeeEstimates;
+use ldk::sweep::SweepPolicy;
+
+/// The output a descriptor describes and its value.
+pub(crate) fn output_of(descriptor: &SpendableOutputDescriptor) -> (OutPoint, Amount) {
+    match descriptor {
+        SpendableOutputDescriptor::StaticOutput { outpoint, output, .. } => (outpoint.into_bitcoin_outpoint(), output.value),
+        SpendableOutputDescriptor::DelayedPaymentOutput(descriptor) => (descriptor.outpoint.into_bitcoin_outpoint(), descriptor.output.value),
+        SpendableOutputDescriptor::StaticPaymentOutput(descriptor) => (descriptor.outpoint.into_bitcoin_outpoint(), descriptor.output.value),
+    }
+}
+
+/// Prints an output the sweeper tracks, with how far its sweep has come.
+pub(crate) fn print_tracked_output(tracked: &TrackedSpendableOutput, current_height: u32, indent: &str) {
+    let (outpoint, value) = output_of(&tracked.descriptor);
+    println!("{}{{", indent);
+    println!("{}\toutpoint: {},", indent, outpoint);
+    println!("{}\tamount_sats: {},", indent, value.to_sat());
+    if let Some(channel_id) = tracked.channel_id {
+        println!("{}\tchannel_id: {},", indent, channel_id);
+    }
+    match &tracked.status {
+        OutputSpendStatus::PendingInitialBroadcast { delayed_until_height } => {
+            println!("{}\tstatus: pending_initial_broadcast,", indent);
+            if let Some(height) = delayed_until_height {
+                println!("{}\tdelayed_until_height: {},", indent, height);
+                println!("{}\tblocks_until_sweep: {},", indent, height.saturating_sub(current_height));
+            }
+        },
+        OutputSpendStatus::PendingFirstConfirmation { latest_broadcast_height, latest_spending_tx, .. } => {
+            println!("{}\tstatus: pending_first_confirmation,", indent);
+            println!("{}\tspending_txid: {},", indent, latest_spending_tx.compute_txid());
+            println!("{}\tlatest_broadcast_height: {},", indent, latest_broadcast_height);
+        },
+        OutputSpendStatus::PendingThresholdConfirmations { latest_spending_tx, confirmation_height, .. } => {
+            println!("{}\tstatus: pending_threshold_confirmations,", indent);
+            println!("{}\tspending_txid: {},", indent, latest_spending_tx.compute_txid());
+            println!("{}\tconfirmation_height: {},", indent, confirmation_height);
+        },
+    }
+    println!("{}}},", indent);
+}
+
+pub(crate) fn list_sweeps_cli(
+    output_sweeper: &Arc<OutputSweeper>, sweep_policy: &Arc<SweepPolicy>, fee_estimates: &Arc<FeeEstimates>,
+) {
+    let current_height = output_sweeper.current_best_block().height;
+    println!("{{");
+    match sweep_policy.destination() {
+        Some(address) => println!("\tdestination: {},", address),
+        None => println!("\tdestination: wallet,"),
+    }
+    println!("\tbatch_blocks: {},", sweep_policy.batch_blocks());
+    println!("\tfee_source: {},", fee_estimates.policy().target(ConfirmationTarget::OutputSpendingFee).source);
+    let feerate = fee_estimates.get_est_sat_per_1000_weight(ConfirmationTarget::OutputSpendingFee);
+    println!("\tfeerate_sat_per_kw: {},", feerate);
+    println!("\tcurrent_height: {},", current_height);
+    println!("\toutputs: [");
+    for tracked in output_sweeper.tracked_spendable_outputs() {
+        print_tracked_output(&tracked, current_height, "\t\t");
+    }
+    println!("\t],");
+    println!("}}");
+}
+
+pub(crate) fn sweep_to_cli(mut words: SplitWhitespace, sweep_policy: &Arc<SweepPolicy>) {
+    let address = match words.next() {
+        Some("wallet") => None,
+        Some(address) => Some(address),
+        None => {
+            println!("ERROR: sweepto requires an address: `sweepto <address|wallet>`");
+            return;
+        },
+    };
+    match sweep_policy.set_destination(address) {
+        Ok(()) => match address {
+            Some(address) => println!("Sweeping outputs to {}", address),
+            None => println!("Sweeping outputs to the on-chain wallet"),
+        },
+        Err(e) => println!("ERROR: {}", e),
+    }
+}
@@ -0,0 +1,70 @@
+//! Copies the state of a node persisted with the filesystem data store into a SQLite database in
+//! the same data directory, so that it can be started with `--data-store=sqlite`.
+
+use ldk::data_dir_lock::DataDirLock;
+use ldk::sqlite_store::{self, SqliteStore, SQLITE_DB_FILE_NAME};
+use lightning::util::persist::MigratableKVStore;
+use std::env;
+use std::path::Path;
+use std::process;
+
+fn main() {
+	let args: Vec<String> = env::args().collect();
+	if args.len() != 2 {
+		println!("Usage: `cargo run -p migrate-store -- <ldk_storage_directory_path>`");
+		process::exit(1);
+	}
+	let ldk_data_dir = Path::new(&args[1]).join(".ldk");
+	if !ldk_data_dir.join("manager").exists() {
+		println!("ERROR: {} doesn't hold any node data to migrate", ldk_data_dir.display());
+		process::exit(1);
+	}
+	// The node must not write to the filesystem data while it is being migrated.
+	let data_dir_lock = match DataDirLock::acquire(&ldk_data_dir, false) {
+		Ok(lock) => lock,
+		Err(e) => {
+			println!(
+				"ERROR: Failed to lock {}: {}. Stop the node first.",
+				ldk_data_dir.display(),
+				e
+			);
+			process::exit(1);
+		},
+	};
+	let res = migrate(&ldk_data_dir);
+	// Exiting skips destructors, so release the lock explicitly first.
+	drop(data_dir_lock);
+	if res.is_err() {
+		process::exit(1);
+	}
+}
+
+fn migrate(ldk_data_dir: &Path) -> Result<(), ()> {
+	let db_path = ldk_data_dir.join(SQLITE_DB_FILE_NAME);
+	let store = SqliteStore::new(&db_path).map_err(|e| {
+		println!("ERROR: Failed to open {}: {}", db_path.display(), e);
+	})?;
+	match store.list_all_keys() {
+		Ok(keys) if keys.is_empty() => {},
+		Ok(_) => {
+			println!(
+				"ERROR: {} already holds node data, refusing to overwrite it",
+				db_path.display()
+			);
+			return Err(());
+		},
+		Err(e) => {
+			println!("ERROR: Failed to read {}: {}", db_path.display(), e);
+			return Err(());
+		},
+	}
+
+	let count = sqlite_store::import_filesystem_data(ldk_data_dir, &store).map_err(|e| {
+		println!("ERROR: Migration failed, nothing was written: {}", e);
+	})?;
+	println!("Migrated {} entries into {}.", count, db_path.display());
+	println!(
+		"Start the node with --data-store=sqlite from now on. The original files were left in place and can be removed once it has started successfully."
+	);
+	Ok(())
+}
@@ -1,8 +1,9 @@
 use crate::convert::{
 	BlockchainInfo, FeeResponse, FundedTx, ListUnspentResponse, MempoolMinFeeResponse, NewAddress,
-	RawTx, SignedTx,
+	RawTx, SignedTx, WalletBalances,
 };
 use crate::disk::FilesystemLogger;
+use crate::fee_policy::{FeeEstimates, FeePolicy, FeeSource};
 use crate::hex_utils;
 use base64;
 use bitcoin::address::Address;
@@ -21,28 +22,40 @@ use lightning::log_error;
 use lightning::sign::ChangeDestinationSource;
 use lightning::util::logger::Logger;
 use lightning_block_sync::http::HttpEndpoint;
-use lightning_block_sync::rpc::RpcClient;
-use lightning_block_sync::{AsyncBlockSourceResult, BlockData, BlockHeaderData, BlockSource};
+use lightning_block_sync::http::JsonResponse;
+use lightning_block_sync::rpc::{RpcClient, RpcError};
+use lightning_block_sync::{
+	AsyncBlockSourceResult, BlockData, BlockHeaderData, BlockSource, BlockSourceErrorKind,
+	BlockSourceResult,
+};
 use serde_json;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
 use std::future::Future;
 use std::str::FromStr;
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use tokio::runtime::{self, Runtime};
 
 pub struct BitcoindClient {
 	pub bitcoind_rpc_client: Arc<RpcClient>,
+	/// Client for wallet RPCs, which are routed to the configured wallet if there is one.
+	wallet_rpc_client: Arc<RpcClient>,
 	network: Network,
 	host: String,
 	port: u16,
+	/// The RPC path wallet calls are sent to, e.g. `/wallet/ldk`.
+	wallet_path: String,
 	rpc_user: String,
 	rpc_password: String,
-	fees: Arc<HashMap<ConfirmationTarget, AtomicU32>>,
+	fee_estimates: Arc<FeeEstimates>,
 	main_runtime_handle: runtime::Handle,
 	inner_runtime: Arc<Runtime>,
+	/// Transactions we broadcast which bitcoind has not yet reported entering its mempool.
+	unseen_broadcasts: Arc<Mutex<HashSet<Txid>>>,
+	rpc_errors: Arc<RpcErrorCounts>,
 	logger: Arc<FilesystemLogger>,
 }
 
@@ -50,27 +63,172 @@ impl BlockSource for BitcoindClient {
 	fn get_header<'a>(
 		&'a self, header_hash: &'a BlockHash, height_hint: Option<u32>,
 	) -> AsyncBlockSourceResult<'a, BlockHeaderData> {
-		Box::pin(async move { self.bitcoind_rpc_client.get_header(header_hash, height_hint).await })
+		Box::pin(async move {
+			let res = self.bitcoind_rpc_client.get_header(header_hash, height_hint).await;
+			self.rpc_errors.record_block_source("getblockheader", &res);
+			res
+		})
 	}
 
 	fn get_block<'a>(
 		&'a self, header_hash: &'a BlockHash,
 	) -> AsyncBlockSourceResult<'a, BlockData> {
-		Box::pin(async move { self.bitcoind_rpc_client.get_block(header_hash).await })
+		Box::pin(async move {
+			let res = self.bitcoind_rpc_client.get_block(header_hash).await;
+			self.rpc_errors.record_block_source("getblock", &res);
+			res
+		})
 	}
 
 	fn get_best_block<'a>(&'a self) -> AsyncBlockSourceResult<(BlockHash, Option<u32>)> {
-		Box::pin(async move { self.bitcoind_rpc_client.get_best_block().await })
+		Box::pin(async move {
+			let res = self.bitcoind_rpc_client.get_best_block().await;
+			self.rpc_errors.record_block_source("getblockchaininfo", &res);
+			res
+		})
+	}
+}
+
+/// How many times we try an RPC call before giving up on a transient failure.
+const RPC_MAX_ATTEMPTS: u32 = 5;
+
+/// How long we wait before the first retry of an RPC call. The delay doubles on every attempt.
+const RPC_INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+
+/// bitcoind's `RPC_IN_WARMUP` error code, returned while it is still loading on startup.
+const RPC_IN_WARMUP: i64 = -28;
+
+/// bitcoind's `RPC_WALLET_NOT_FOUND` error code, returned when loading a wallet that does not exist.
+const RPC_WALLET_NOT_FOUND: i64 = -18;
+
+/// An error returned when talking to bitcoind over RPC.
+#[derive(Debug)]
+pub enum BitcoindError {
+	/// bitcoind could not be reached or was not ready to serve the request, even after retrying.
+	Unavailable(std::io::Error),
+	/// bitcoind processed the request but returned an error.
+	Rpc { code: i64, message: String },
+	/// bitcoind's response could not be interpreted.
+	InvalidResponse(String),
+}
+
+impl BitcoindError {
+	/// Whether the call may succeed if retried, e.g. because bitcoind was briefly unreachable.
+	pub fn is_transient(&self) -> bool {
+		match self {
+			BitcoindError::Unavailable(_) => true,
+			BitcoindError::Rpc { code, .. } => *code == RPC_IN_WARMUP,
+			BitcoindError::InvalidResponse(_) => false,
+		}
 	}
 }
 
-/// The minimum feerate we are allowed to send, as specify by LDK.
-const MIN_FEERATE: u32 = 253;
+impl From<std::io::Error> for BitcoindError {
+	fn from(e: std::io::Error) -> Self {
+		match e.kind() {
+			std::io::ErrorKind::InvalidData => BitcoindError::InvalidResponse(e.to_string()),
+			std::io::ErrorKind::Other => {
+				match e.get_ref().and_then(|inner| inner.downcast_ref::<RpcError>()) {
+					Some(rpc_error) => BitcoindError::Rpc {
+						code: rpc_error.code,
+						message: rpc_error.message.clone(),
+					},
+					// Non-JSON HTTP errors, e.g. bitcoind's work queue being full.
+					None => BitcoindError::Unavailable(e),
+				}
+			},
+			_ => BitcoindError::Unavailable(e),
+		}
+	}
+}
+
+impl fmt::Display for BitcoindError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			BitcoindError::Unavailable(e) => write!(f, "bitcoind is unavailable: {}", e),
+			BitcoindError::Rpc { code, message } => {
+				write!(f, "bitcoind returned RPC error {}: {}", code, message)
+			},
+			BitcoindError::InvalidResponse(e) => write!(f, "invalid response from bitcoind: {}", e),
+		}
+	}
+}
+
+impl std::error::Error for BitcoindError {}
+
+/// Counts the RPC calls to bitcoind which failed, by method and kind of error. Every failed
+/// attempt is counted, including those which were retried.
+#[derive(Default)]
+pub struct RpcErrorCounts {
+	counts: Mutex<HashMap<(String, &'static str), u64>>,
+}
+
+impl RpcErrorCounts {
+	fn record(&self, method: &str, error: &BitcoindError) {
+		let kind = match error {
+			BitcoindError::Unavailable(_) => "unavailable",
+			BitcoindError::Rpc { .. } => "rpc",
+			BitcoindError::InvalidResponse(_) => "invalid_response",
+		};
+		*self.counts.lock().unwrap().entry((method.to_string(), kind)).or_insert(0) += 1;
+	}
+
+	/// Block sources only tell whether an error is transient, which we count as bitcoind being
+	/// unavailable, e.g. while it's restarting.
+	fn record_block_source<T>(&self, method: &str, res: &BlockSourceResult<T>) {
+		if let Err(e) = res {
+			let kind = match e.kind() {
+				BlockSourceErrorKind::Transient => "unavailable",
+				BlockSourceErrorKind::Persistent => "rpc",
+			};
+			*self.counts.lock().unwrap().entry((method.to_string(), kind)).or_insert(0) += 1;
+		}
+	}
+
+	/// The number of failed calls as `(method, kind, count)`, sorted by method and kind.
+	pub fn counts(&self) -> Vec<(String, &'static str, u64)> {
+		let counts = self.counts.lock().unwrap();
+		let mut counts = counts
+			.iter()
+			.map(|((method, kind), count)| (method.clone(), *kind, *count))
+			.collect::<Vec<_>>();
+		counts.sort();
+		counts
+	}
+}
+
+/// Calls `method` on bitcoind, retrying with exponential backoff as long as the failure is
+/// transient.
+async fn call_with_retry<T>(
+	rpc_client: &RpcClient, rpc_errors: &RpcErrorCounts, method: &str, params: &[serde_json::Value],
+) -> Result<T, BitcoindError>
+where
+	JsonResponse: TryFrom<Vec<u8>, Error = std::io::Error> + TryInto<T, Error = std::io::Error>,
+{
+	let mut backoff = RPC_INITIAL_BACKOFF;
+	let mut attempt = 1;
+	loop {
+		match rpc_client.call_method::<T>(method, params).await {
+			Ok(res) => return Ok(res),
+			Err(e) => {
+				let err = BitcoindError::from(e);
+				rpc_errors.record(method, &err);
+				if !err.is_transient() || attempt >= RPC_MAX_ATTEMPTS {
+					return Err(err);
+				}
+			},
+		}
+		tokio::time::sleep(backoff).await;
+		backoff *= 2;
+		attempt += 1;
+	}
+}
 
 impl BitcoindClient {
+	#[allow(clippy::too_many_arguments)]
 	pub async fn new(
 		host: String, port: u16, rpc_user: String, rpc_password: String, network: Network,
-		handle: runtime::Handle, logger: Arc<FilesystemLogger>,
+		fee_estimates: Arc<FeeEstimates>, handle: runtime::Handle, logger: Arc<FilesystemLogger>,
 	) -> std::io::Result<Self> {
 		let http_endpoint = HttpEndpoint::for_host(host.clone()).with_port(port);
 		let rpc_credentials =
@@ -83,22 +241,11 @@ impl BitcoindClient {
 				std::io::Error::new(std::io::ErrorKind::PermissionDenied,
 									"Failed to make initial call to bitcoind - please check your RPC user/password and access settings")
 			})?;
-		let mut fees: HashMap<ConfirmationTarget, AtomicU32> = HashMap::new();
-		fees.insert(ConfirmationTarget::MaximumFeeEstimate, AtomicU32::new(50000));
-		fees.insert(ConfirmationTarget::UrgentOnChainSweep, AtomicU32::new(5000));
-		fees.insert(
-			ConfirmationTarget::MinAllowedAnchorChannelRemoteFee,
-			AtomicU32::new(MIN_FEERATE),
-		);
-		fees.insert(
-			ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee,
-			AtomicU32::new(MIN_FEERATE),
+		let wallet_path = "/".to_string();
+		let wallet_rpc_client = RpcClient::new(
+			&rpc_credentials,
+			HttpEndpoint::for_host(host.clone()).with_port(port).with_path(wallet_path.clone()),
 		);
-		fees.insert(ConfirmationTarget::AnchorChannelFee, AtomicU32::new(MIN_FEERATE));
-		fees.insert(ConfirmationTarget::NonAnchorChannelFee, AtomicU32::new(2000));
-		fees.insert(ConfirmationTarget::ChannelCloseMinimum, AtomicU32::new(MIN_FEERATE));
-		fees.insert(ConfirmationTarget::OutputSpendingFee, AtomicU32::new(MIN_FEERATE));
-
 		let mut builder = runtime::Builder::new_multi_thread();
 		let runtime =
 			builder.enable_all().worker_threads(1).thread_name("rpc-worker").build().unwrap();
@@ -110,136 +257,149 @@ impl BitcoindClient {
 
 		let client = Self {
 			bitcoind_rpc_client: Arc::new(bitcoind_rpc_client),
+			wallet_rpc_client: Arc::new(wallet_rpc_client),
 			host,
 			port,
+			wallet_path,
 			rpc_user,
 			rpc_password,
 			network,
-			fees: Arc::new(fees),
+			fee_estimates,
 			main_runtime_handle: handle.clone(),
 			inner_runtime,
+			unseen_broadcasts: Arc::new(Mutex::new(HashSet::new())),
+			rpc_errors: Arc::new(RpcErrorCounts::default()),
 			logger,
 		};
+		Ok(client)
+	}
+
+	/// Starts keeping our [`FeeEstimates`] up to date with bitcoind's estimates. Only needed when
+	/// bitcoind is the chain source, otherwise the chain source refreshes them.
+	pub fn start_fee_polling(&self) {
 		BitcoindClient::poll_for_fee_estimates(
-			client.fees.clone(),
-			client.bitcoind_rpc_client.clone(),
-			handle,
+			self.fee_estimates.clone(),
+			self.bitcoind_rpc_client.clone(),
+			self.rpc_errors.clone(),
+			self.logger.clone(),
+			self.main_runtime_handle.clone(),
 		);
-		Ok(client)
+	}
+
+	/// Sends wallet RPCs to the bitcoind wallet `wallet_name` rather than bitcoind's default
+	/// wallet, which lets the node share a bitcoind with other wallets loaded. The wallet is loaded
+	/// if it isn't yet, and created if it doesn't exist and `create` is set.
+	pub async fn with_wallet(mut self, wallet_name: String, create: bool) -> std::io::Result<Self> {
+		BitcoindClient::load_wallet(
+			&self.bitcoind_rpc_client,
+			&self.rpc_errors,
+			&wallet_name,
+			create,
+		)
+		.await
+		.map_err(|e| {
+			std::io::Error::new(
+				std::io::ErrorKind::NotFound,
+				format!("Failed to load bitcoind wallet {}: {}", wallet_name, e),
+			)
+		})?;
+		self.wallet_path = format!("/wallet/{}", encode_path_segment(&wallet_name));
+		self.wallet_rpc_client = Arc::new(self.get_new_wallet_rpc_client());
+		Ok(self)
+	}
+
+	/// Makes sure the wallet `wallet_name` is loaded in bitcoind, creating it if `create` is set
+	/// and it doesn't exist yet.
+	async fn load_wallet(
+		rpc_client: &RpcClient, rpc_errors: &RpcErrorCounts, wallet_name: &str, create: bool,
+	) -> Result<(), BitcoindError> {
+		let loaded_wallets: serde_json::Value =
+			call_with_retry(rpc_client, rpc_errors, "listwallets", &[]).await?;
+		let is_loaded = loaded_wallets
+			.as_array()
+			.is_some_and(|wallets| wallets.iter().any(|wallet| wallet == wallet_name));
+		if is_loaded {
+			return Ok(());
+		}
+		let loaded: Result<serde_json::Value, _> = call_with_retry(
+			rpc_client,
+			rpc_errors,
+			"loadwallet",
+			&[serde_json::json!(wallet_name)],
+		)
+		.await;
+		match loaded {
+			Err(BitcoindError::Rpc { code: RPC_WALLET_NOT_FOUND, .. }) if create => {
+				let params = [serde_json::json!(wallet_name)];
+				let _: serde_json::Value =
+					call_with_retry(rpc_client, rpc_errors, "createwallet", &params).await?;
+				Ok(())
+			},
+			res => res.map(|_| ()),
+		}
 	}
 
 	fn poll_for_fee_estimates(
-		fees: Arc<HashMap<ConfirmationTarget, AtomicU32>>, rpc_client: Arc<RpcClient>,
+		fee_estimates: Arc<FeeEstimates>, rpc_client: Arc<RpcClient>,
+		rpc_errors: Arc<RpcErrorCounts>, logger: Arc<FilesystemLogger>,
 		handle: tokio::runtime::Handle,
 	) {
 		handle.spawn(async move {
 			loop {
-				let mempoolmin_estimate = {
-					let resp = rpc_client
-						.call_method::<MempoolMinFeeResponse>("getmempoolinfo", &vec![])
-						.await
-						.unwrap();
-					match resp.feerate_sat_per_kw {
-						Some(feerate) => std::cmp::max(feerate, MIN_FEERATE),
-						None => MIN_FEERATE,
-					}
-				};
-				let background_estimate = {
-					let background_conf_target = serde_json::json!(144);
-					let background_estimate_mode = serde_json::json!("ECONOMICAL");
-					let resp = rpc_client
-						.call_method::<FeeResponse>(
-							"estimatesmartfee",
-							&vec![background_conf_target, background_estimate_mode],
-						)
-						.await
-						.unwrap();
-					match resp.feerate_sat_per_kw {
-						Some(feerate) => std::cmp::max(feerate, MIN_FEERATE),
-						None => MIN_FEERATE,
-					}
-				};
-
-				let normal_estimate = {
-					let normal_conf_target = serde_json::json!(18);
-					let normal_estimate_mode = serde_json::json!("ECONOMICAL");
-					let resp = rpc_client
-						.call_method::<FeeResponse>(
-							"estimatesmartfee",
-							&vec![normal_conf_target, normal_estimate_mode],
-						)
-						.await
-						.unwrap();
-					match resp.feerate_sat_per_kw {
-						Some(feerate) => std::cmp::max(feerate, MIN_FEERATE),
-						None => 2000,
-					}
-				};
-
-				let high_prio_estimate = {
-					let high_prio_conf_target = serde_json::json!(6);
-					let high_prio_estimate_mode = serde_json::json!("CONSERVATIVE");
-					let resp = rpc_client
-						.call_method::<FeeResponse>(
-							"estimatesmartfee",
-							&vec![high_prio_conf_target, high_prio_estimate_mode],
-						)
-						.await
-						.unwrap();
-
-					match resp.feerate_sat_per_kw {
-						Some(feerate) => std::cmp::max(feerate, MIN_FEERATE),
-						None => 5000,
-					}
-				};
-
-				let very_high_prio_estimate = {
-					let high_prio_conf_target = serde_json::json!(2);
-					let high_prio_estimate_mode = serde_json::json!("CONSERVATIVE");
-					let resp = rpc_client
-						.call_method::<FeeResponse>(
-							"estimatesmartfee",
-							&vec![high_prio_conf_target, high_prio_estimate_mode],
-						)
-						.await
-						.unwrap();
-
-					match resp.feerate_sat_per_kw {
-						Some(feerate) => std::cmp::max(feerate, MIN_FEERATE),
-						None => 50000,
-					}
-				};
-
-				fees.get(&ConfirmationTarget::MaximumFeeEstimate)
-					.unwrap()
-					.store(very_high_prio_estimate, Ordering::Release);
-				fees.get(&ConfirmationTarget::UrgentOnChainSweep)
-					.unwrap()
-					.store(high_prio_estimate, Ordering::Release);
-				fees.get(&ConfirmationTarget::MinAllowedAnchorChannelRemoteFee)
-					.unwrap()
-					.store(mempoolmin_estimate, Ordering::Release);
-				fees.get(&ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee)
-					.unwrap()
-					.store(background_estimate - 250, Ordering::Release);
-				fees.get(&ConfirmationTarget::AnchorChannelFee)
-					.unwrap()
-					.store(background_estimate, Ordering::Release);
-				fees.get(&ConfirmationTarget::NonAnchorChannelFee)
-					.unwrap()
-					.store(normal_estimate, Ordering::Release);
-				fees.get(&ConfirmationTarget::ChannelCloseMinimum)
-					.unwrap()
-					.store(background_estimate, Ordering::Release);
-				fees.get(&ConfirmationTarget::OutputSpendingFee)
-					.unwrap()
-					.store(background_estimate, Ordering::Release);
-
-				tokio::time::sleep(Duration::from_secs(60)).await;
+				let policy = fee_estimates.policy();
+				let estimates =
+					BitcoindClient::fetch_fee_estimates(&rpc_client, &rpc_errors, policy, &logger)
+						.await;
+				fee_estimates.update(&estimates);
+				fee_estimates.warn_if_stale();
+				tokio::time::sleep(policy.poll_interval).await;
 			}
 		});
 	}
 
+	/// Fetches an estimate in sat/KW for every source `policy` uses. Sources bitcoind has no
+	/// estimate for yet are left out, as are all remaining ones once a call fails.
+	async fn fetch_fee_estimates(
+		rpc_client: &RpcClient, rpc_errors: &RpcErrorCounts, policy: &FeePolicy,
+		logger: &FilesystemLogger,
+	) -> HashMap<FeeSource, u32> {
+		let mut estimates = HashMap::new();
+		for source in policy.sources() {
+			let feerate = match source {
+				FeeSource::MempoolMinimum => call_with_retry::<MempoolMinFeeResponse>(
+					rpc_client,
+					rpc_errors,
+					"getmempoolinfo",
+					&[],
+				)
+				.await
+				.map(|resp| resp.feerate_sat_per_kw),
+				FeeSource::Estimate { blocks, mode } => {
+					let params = [serde_json::json!(blocks), serde_json::json!(mode.as_str())];
+					call_with_retry::<FeeResponse>(
+						rpc_client,
+						rpc_errors,
+						"estimatesmartfee",
+						&params,
+					)
+					.await
+					.map(|resp| resp.feerate_sat_per_kw)
+				},
+			};
+			match feerate {
+				Ok(Some(feerate)) => {
+					estimates.insert(source, feerate);
+				},
+				Ok(None) => {},
+				Err(e) => {
+					log_error!(logger, "Failed to fetch fee estimates from bitcoind: {}", e);
+					break;
+				},
+			}
+		}
+		estimates
+	}
+
 	fn run_future_in_blocking_context<F: Future + Send + 'static>(&self, future: F) -> F::Output
 	where
 		F::Output: Send + 'static,
@@ -279,18 +439,29 @@ impl BitcoindClient {
 		RpcClient::new(&rpc_credentials, http_endpoint)
 	}
 
-	pub async fn create_raw_transaction(&self, outputs: Vec<HashMap<String, f64>>) -> RawTx {
+	/// Like [`BitcoindClient::get_new_rpc_client`], but for wallet RPCs.
+	pub fn get_new_wallet_rpc_client(&self) -> RpcClient {
+		let http_endpoint = HttpEndpoint::for_host(self.host.clone())
+			.with_port(self.port)
+			.with_path(self.wallet_path.clone());
+		let rpc_credentials = base64::encode(format!("{}:{}", self.rpc_user, self.rpc_password));
+		RpcClient::new(&rpc_credentials, http_endpoint)
+	}
+
+	pub async fn create_raw_transaction(
+		&self, outputs: Vec<HashMap<String, f64>>,
+	) -> Result<RawTx, BitcoindError> {
 		let outputs_json = serde_json::json!(outputs);
-		self.bitcoind_rpc_client
-			.call_method::<RawTx>(
-				"createrawtransaction",
-				&vec![serde_json::json!([]), outputs_json],
-			)
-			.await
-			.unwrap()
+		call_with_retry(
+			&self.bitcoind_rpc_client,
+			&self.rpc_errors,
+			"createrawtransaction",
+			&[serde_json::json!([]), outputs_json],
+		)
+		.await
 	}
 
-	pub async fn fund_raw_transaction(&self, raw_tx: RawTx) -> FundedTx {
+	pub async fn fund_raw_transaction(&self, raw_tx: RawTx) -> Result<FundedTx, BitcoindError> {
 		let raw_tx_json = serde_json::json!(raw_tx.0);
 		let options = serde_json::json!({
 			// LDK gives us feerates in satoshis per KW but Bitcoin Core here expects fees
@@ -305,62 +476,80 @@ impl BitcoindClient {
 			// change address or to a new channel output negotiated with the same node.
 			"replaceable": false,
 		});
-		self.bitcoind_rpc_client
-			.call_method("fundrawtransaction", &[raw_tx_json, options])
+		let params = [raw_tx_json, options];
+		call_with_retry(&self.wallet_rpc_client, &self.rpc_errors, "fundrawtransaction", &params)
 			.await
-			.unwrap()
 	}
 
-	pub async fn send_raw_transaction(&self, raw_tx: RawTx) {
+	pub async fn send_raw_transaction(&self, raw_tx: RawTx) -> Result<Txid, BitcoindError> {
 		let raw_tx_json = serde_json::json!(raw_tx.0);
-		self.bitcoind_rpc_client
-			.call_method::<Txid>("sendrawtransaction", &[raw_tx_json])
+		let params = [raw_tx_json];
+		call_with_retry(&self.bitcoind_rpc_client, &self.rpc_errors, "sendrawtransaction", &params)
 			.await
-			.unwrap();
 	}
 
 	pub fn sign_raw_transaction_with_wallet(
 		&self, tx_hex: String,
-	) -> impl Future<Output = SignedTx> {
+	) -> impl Future<Output = Result<SignedTx, BitcoindError>> {
 		let tx_hex_json = serde_json::json!(tx_hex);
-		let rpc_client = self.get_new_rpc_client();
+		let rpc_client = self.get_new_wallet_rpc_client();
+		let rpc_errors = Arc::clone(&self.rpc_errors);
 		async move {
-			rpc_client
-				.call_method("signrawtransactionwithwallet", &vec![tx_hex_json])
-				.await
-				.unwrap()
+			let params = [tx_hex_json];
+			call_with_retry(&rpc_client, &rpc_errors, "signrawtransactionwithwallet", &params).await
 		}
 	}
 
-	pub fn get_new_address(&self) -> impl Future<Output = Address> {
+	pub fn get_new_address(&self) -> impl Future<Output = Result<Address, BitcoindError>> {
 		let addr_args = vec![serde_json::json!("LDK output address")];
 		let network = self.network;
-		let rpc_client = self.get_new_rpc_client();
+		let rpc_client = self.get_new_wallet_rpc_client();
+		let rpc_errors = Arc::clone(&self.rpc_errors);
 		async move {
-			let addr =
-				rpc_client.call_method::<NewAddress>("getnewaddress", &addr_args).await.unwrap();
-			Address::from_str(addr.0.as_str()).unwrap().require_network(network).unwrap()
+			let addr = call_with_retry::<NewAddress>(
+				&rpc_client,
+				&rpc_errors,
+				"getnewaddress",
+				&addr_args,
+			)
+			.await?;
+			Address::from_str(addr.0.as_str())
+				.map_err(|e| BitcoindError::InvalidResponse(e.to_string()))?
+				.require_network(network)
+				.map_err(|e| BitcoindError::InvalidResponse(e.to_string()))
 		}
 	}
 
-	pub async fn get_blockchain_info(&self) -> BlockchainInfo {
-		self.bitcoind_rpc_client
-			.call_method::<BlockchainInfo>("getblockchaininfo", &vec![])
-			.await
-			.unwrap()
+	pub async fn get_blockchain_info(&self) -> Result<BlockchainInfo, BitcoindError> {
+		call_with_retry(&self.bitcoind_rpc_client, &self.rpc_errors, "getblockchaininfo", &[]).await
 	}
 
-	pub fn list_unspent(&self) -> impl Future<Output = ListUnspentResponse> {
-		let rpc_client = self.get_new_rpc_client();
-		async move {
-			rpc_client.call_method::<ListUnspentResponse>("listunspent", &vec![]).await.unwrap()
-		}
+	/// Returns whether `txid` is a transaction we broadcast, and if so stops tracking it. Used to
+	/// report when our broadcasts show up in bitcoind's mempool via ZMQ `rawtx` notifications.
+	pub fn take_unseen_broadcast(&self, txid: &Txid) -> bool {
+		self.unseen_broadcasts.lock().unwrap().remove(txid)
+	}
+
+	pub async fn get_wallet_balances(&self) -> Result<WalletBalances, BitcoindError> {
+		let rpc_client = self.get_new_wallet_rpc_client();
+		call_with_retry(&rpc_client, &self.rpc_errors, "getbalances", &[]).await
+	}
+
+	pub fn list_unspent(&self) -> impl Future<Output = Result<ListUnspentResponse, BitcoindError>> {
+		let rpc_client = self.get_new_wallet_rpc_client();
+		let rpc_errors = Arc::clone(&self.rpc_errors);
+		async move { call_with_retry(&rpc_client, &rpc_errors, "listunspent", &[]).await }
+	}
+
+	/// The RPC calls to bitcoind which failed so far.
+	pub fn rpc_errors(&self) -> &RpcErrorCounts {
+		&self.rpc_errors
 	}
 }
 
 impl FeeEstimator for BitcoindClient {
 	fn get_est_sat_per_1000_weight(&self, confirmation_target: ConfirmationTarget) -> u32 {
-		self.fees.get(&confirmation_target).unwrap().load(Ordering::Acquire)
+		self.fee_estimates.get_est_sat_per_1000_weight(confirmation_target)
 	}
 }
 
@@ -374,26 +563,31 @@ impl BroadcasterInterface for BitcoindClient {
 		// Sadly, Bitcoin Core has an arbitrary restriction on `submitpackage` - it must actually
 		// contain a package (see https://github.com/bitcoin/bitcoin/issues/31085).
 		let txn = txs.iter().map(|tx| encode::serialize_hex(tx)).collect::<Vec<_>>();
+		let txids = txs.iter().map(|tx| tx.compute_txid()).collect::<Vec<_>>();
+		self.unseen_broadcasts.lock().unwrap().extend(txids.iter().cloned());
+		let unseen_broadcasts = Arc::clone(&self.unseen_broadcasts);
 		let bitcoind_rpc_client = Arc::clone(&self.bitcoind_rpc_client);
+		let rpc_errors = Arc::clone(&self.rpc_errors);
 		let logger = Arc::clone(&self.logger);
 		self.main_runtime_handle.spawn(async move {
-			let res = if txn.len() == 1 {
-				let tx_json = serde_json::json!(txn[0]);
-				bitcoind_rpc_client
-					.call_method::<serde_json::Value>("sendrawtransaction", &[tx_json])
-					.await
+			let (method, tx_json) = if txn.len() == 1 {
+				("sendrawtransaction", serde_json::json!(txn[0]))
 			} else {
-				let tx_json = serde_json::json!(txn);
-				bitcoind_rpc_client
-					.call_method::<serde_json::Value>("submitpackage", &[tx_json])
-					.await
+				("submitpackage", serde_json::json!(txn))
 			};
+			let res = bitcoind_rpc_client.call_method::<serde_json::Value>(method, &[tx_json]).await;
 			// This may error due to RL calling `broadcast_transactions` with the same transaction
 			// multiple times, but the error is safe to ignore.
 			match res {
 				Ok(_) => {}
 				Err(e) => {
+					// Rejected transactions will never be reported as entering the mempool.
+					let mut unseen_broadcasts = unseen_broadcasts.lock().unwrap();
+					txids.iter().for_each(|txid| {
+						unseen_broadcasts.remove(txid);
+					});
 					let err_str = e.get_ref().unwrap().to_string();
+					rpc_errors.record(method, &BitcoindError::from(e));
 					log_error!(logger,
 						"Warning, failed to broadcast a transaction, this is likely okay but may indicate an error: {}\nTransactions: {:?}",
 						err_str,
@@ -407,16 +601,18 @@ impl BroadcasterInterface for BitcoindClient {
 
 impl ChangeDestinationSource for BitcoindClient {
 	fn get_change_destination_script(&self) -> Result<ScriptBuf, ()> {
-		let future = self.get_new_address();
-		Ok(self.run_future_in_blocking_context(async move { future.await.script_pubkey() }))
+		self.get_change_script()
 	}
 }
 
 impl WalletSource for BitcoindClient {
 	fn list_confirmed_utxos(&self) -> Result<Vec<Utxo>, ()> {
 		let future = self.list_unspent();
-		let utxos = self.run_future_in_blocking_context(async move { future.await.0 });
+		let utxos = self.run_future_in_blocking_context(future).map_err(|e| {
+			log_error!(self.logger, "Failed to list wallet UTXOs: {}", e);
+		})?;
 		Ok(utxos
+			.0
 			.into_iter()
 			.filter_map(|utxo| {
 				let outpoint = OutPoint { txid: utxo.txid, vout: utxo.vout };
@@ -449,7 +645,10 @@ impl WalletSource for BitcoindClient {
 
 	fn get_change_script(&self) -> Result<ScriptBuf, ()> {
 		let future = self.get_new_address();
-		Ok(self.run_future_in_blocking_context(async move { future.await.script_pubkey() }))
+		let address = self.run_future_in_blocking_context(future).map_err(|e| {
+			log_error!(self.logger, "Failed to get a new address from the wallet: {}", e);
+		})?;
+		Ok(address.script_pubkey())
 	}
 
 	fn sign_psbt(&self, tx: Psbt) -> Result<Transaction, ()> {
@@ -457,8 +656,174 @@ impl WalletSource for BitcoindClient {
 		let _ = tx.unsigned_tx.consensus_encode(&mut tx_bytes).map_err(|_| ());
 		let tx_hex = hex_utils::hex_str(&tx_bytes);
 		let future = self.sign_raw_transaction_with_wallet(tx_hex);
-		let signed_tx = self.run_future_in_blocking_context(async move { future.await });
+		let signed_tx = self.run_future_in_blocking_context(future).map_err(|e| {
+			log_error!(self.logger, "Failed to sign a transaction with the wallet: {}", e);
+		})?;
 		let signed_tx_bytes = hex_utils::to_vec(&signed_tx.hex).ok_or(())?;
 		Transaction::consensus_decode(&mut signed_tx_bytes.as_slice()).map_err(|_| ())
 	}
 }
+
+/// Percent-encodes `segment` so it can be used as a single segment of a URL path.
+fn encode_path_segment(segment: &str) -> String {
+	segment
+		.bytes()
+		.map(|b| match b {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+				(b as char).to_string()
+			},
+			_ => format!("%{:02X}", b),
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::{test_data_dir, test_fee_estimates, MockHttpServer};
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	fn rpc_client(server: &MockHttpServer) -> RpcClient {
+		let port = server.url().rsplit(':').next().unwrap().parse().unwrap();
+		let endpoint = HttpEndpoint::for_host("127.0.0.1".to_string()).with_port(port);
+		RpcClient::new(&base64::encode("user:pass"), endpoint)
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_transient_errors_are_retried() {
+		let calls = Arc::new(AtomicUsize::new(0));
+		let server_calls = Arc::clone(&calls);
+		let server = MockHttpServer::start(move |_| {
+			// The HTTP client resends a failed request once by itself, so this fails two of our
+			// attempts.
+			if server_calls.fetch_add(1, Ordering::AcqRel) < 4 {
+				(503, "Work queue depth exceeded".to_string())
+			} else {
+				(200, r#"{"result":"0200","error":null,"id":"0"}"#.to_string())
+			}
+		})
+		.await;
+
+		let rpc_errors = RpcErrorCounts::default();
+		let raw_tx = call_with_retry::<RawTx>(
+			&rpc_client(&server),
+			&rpc_errors,
+			"createrawtransaction",
+			&[],
+		)
+		.await;
+		assert_eq!(raw_tx.unwrap().0, "0200");
+		assert_eq!(calls.load(Ordering::Acquire), 5);
+		assert_eq!(
+			rpc_errors.counts(),
+			vec![("createrawtransaction".to_string(), "unavailable", 2)]
+		);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_rpc_errors_are_not_retried() {
+		let server = MockHttpServer::start(|_| {
+			let body =
+				r#"{"result":null,"error":{"code":-6,"message":"Insufficient funds"},"id":"0"}"#;
+			(500, body.to_string())
+		})
+		.await;
+
+		let rpc_errors = RpcErrorCounts::default();
+		let res = call_with_retry::<FundedTx>(
+			&rpc_client(&server),
+			&rpc_errors,
+			"fundrawtransaction",
+			&[],
+		)
+		.await;
+		match res {
+			Err(BitcoindError::Rpc { code, message }) => {
+				assert_eq!(code, -6);
+				assert_eq!(message, "Insufficient funds");
+			},
+			_ => panic!("Expected an RPC error"),
+		}
+		assert_eq!(rpc_errors.counts(), vec![("fundrawtransaction".to_string(), "rpc", 1)]);
+		// A single attempt, which the HTTP client may have resent once.
+		assert!(server.requests().len() <= 2);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_wallet_balances_are_parsed() {
+		let server = MockHttpServer::start(|_| {
+			let mine =
+				serde_json::json!({"trusted": 1.5, "untrusted_pending": 0.0001, "immature": 0.0});
+			let result = serde_json::json!({ "mine": mine });
+			(200, serde_json::json!({"result": result, "error": null}).to_string())
+		})
+		.await;
+
+		let rpc_errors = RpcErrorCounts::default();
+		let balances = call_with_retry::<WalletBalances>(
+			&rpc_client(&server),
+			&rpc_errors,
+			"getbalances",
+			&[],
+		)
+		.await;
+		let balances = balances.unwrap();
+		assert_eq!(balances.trusted_sats, 150_000_000);
+		assert_eq!(balances.untrusted_pending_sats, 10_000);
+		assert_eq!(balances.immature_sats, 0);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_wallet_rpcs_are_routed_to_wallet() {
+		let server = MockHttpServer::start(|req| {
+			let body: serde_json::Value = serde_json::from_str(&req.body).unwrap();
+			let result = match body["method"].as_str().unwrap() {
+				"getblockchaininfo" => serde_json::json!({
+					"blocks": 0,
+					"bestblockhash": "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+					"chain": "regtest",
+				}),
+				"listwallets" => serde_json::json!([]),
+				"loadwallet" => {
+					let error = serde_json::json!({"code": -18, "message": "Wallet not found"});
+					return (500, serde_json::json!({"result": null, "error": error}).to_string());
+				},
+				"createwallet" => serde_json::json!({"name": "ldk node"}),
+				"getmempoolinfo" => {
+					serde_json::json!({"maxmempool": 300000000, "mempoolminfee": 0.00001})
+				},
+				"estimatesmartfee" => serde_json::json!({"feerate": 0.0001}),
+				"getnewaddress" => {
+					serde_json::json!("bcrt1qs758ursh4q9z627kt3pp5yysm78ddny6txaqgw")
+				},
+				_ => return (404, String::new()),
+			};
+			(200, serde_json::json!({"result": result, "error": null}).to_string())
+		})
+		.await;
+		let port = server.url().rsplit(':').next().unwrap().parse().unwrap();
+		let logger_dir =
+			std::env::temp_dir().join(format!("bitcoind-client-test-{}", std::process::id()));
+		let logger = Arc::new(FilesystemLogger::new(logger_dir.to_str().unwrap().to_string()));
+		let client = BitcoindClient::new(
+			"127.0.0.1".to_string(),
+			port,
+			"user".to_string(),
+			"pass".to_string(),
+			Network::Regtest,
+			test_fee_estimates(&test_data_dir("bitcoind-client")),
+			tokio::runtime::Handle::current(),
+			logger,
+		)
+		.await
+		.unwrap()
+		.with_wallet("ldk node".to_string(), true)
+		.await
+		.unwrap();
+		assert!(server.requests().iter().any(|req| req.body.contains("createwallet")));
+
+		client.get_new_address().await.unwrap();
+		let req = server.wait_for_request("/wallet/ldk%20node").await;
+		assert!(req.body.contains("getnewaddress"));
+	}
+}
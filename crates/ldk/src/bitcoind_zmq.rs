@@ -0,0 +1,86 @@
+use crate::disk::FilesystemLogger;
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::consensus::encode;
+use lightning::util::logger::Logger;
+use lightning::{log_error, log_info, log_trace};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use zeromq::{Socket, SocketRecv, SubSocket, ZmqMessage};
+
+/// How long we wait before reconnecting after losing a ZMQ subscription.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Subscribes to bitcoind's `hashblock` and `rawblock` ZMQ notifications published at `endpoint`
+/// (e.g. `tcp://127.0.0.1:28332`) and wakes `block_notifier` whenever a new block arrives, so that
+/// the chain tip can be polled right away rather than on the next timer tick.
+///
+/// Either topic may be enabled in bitcoind (via `-zmqpubhashblock` or `-zmqpubrawblock`), we only
+/// use the notification as a trigger.
+pub fn subscribe_blocks(
+	endpoint: String, block_notifier: Arc<Notify>, logger: Arc<FilesystemLogger>,
+) {
+	tokio::spawn(async move {
+		run_subscription(&endpoint, &["hashblock", "rawblock"], &logger, |_| {
+			block_notifier.notify_one();
+		})
+		.await
+	});
+}
+
+/// Subscribes to bitcoind's `rawtx` ZMQ notifications published at `endpoint` and hands every
+/// transaction entering bitcoind's mempool to `on_transaction`.
+pub fn subscribe_transactions<F: Fn(Transaction) + Send + Sync + 'static>(
+	endpoint: String, on_transaction: F, logger: Arc<FilesystemLogger>,
+) {
+	tokio::spawn(async move {
+		run_subscription(&endpoint, &["rawtx"], &logger, |message| {
+			let tx_bytes = match message.get(1) {
+				Some(tx_bytes) => tx_bytes,
+				None => return,
+			};
+			match encode::deserialize::<Transaction>(tx_bytes) {
+				Ok(tx) => on_transaction(tx),
+				Err(e) => log_error!(logger, "Failed to decode ZMQ rawtx notification: {}", e),
+			}
+		})
+		.await
+	});
+}
+
+async fn run_subscription<F: Fn(&ZmqMessage)>(
+	endpoint: &str, topics: &[&str], logger: &FilesystemLogger, handler: F,
+) {
+	loop {
+		let mut socket = SubSocket::new();
+		let subscribed = async {
+			socket.connect(endpoint).await?;
+			for topic in topics {
+				socket.subscribe(topic).await?;
+			}
+			Ok::<(), zeromq::ZmqError>(())
+		};
+		if let Err(e) = subscribed.await {
+			log_error!(logger, "Failed to subscribe to bitcoind ZMQ at {}: {}", endpoint, e);
+			tokio::time::sleep(RECONNECT_DELAY).await;
+			continue;
+		}
+		log_info!(logger, "Subscribed to bitcoind ZMQ {:?} notifications at {}", topics, endpoint);
+
+		loop {
+			match socket.recv().await {
+				Ok(message) => {
+					// bitcoind sends multipart messages of the form [topic, body, sequence].
+					let topic = message.get(0).map(|t| String::from_utf8_lossy(t).into_owned());
+					log_trace!(logger, "Received bitcoind ZMQ {:?} notification", topic);
+					handler(&message);
+				},
+				Err(e) => {
+					log_error!(logger, "Lost bitcoind ZMQ subscription at {}: {}", endpoint, e);
+					break;
+				},
+			}
+		}
+		tokio::time::sleep(RECONNECT_DELAY).await;
+	}
+}
@@ -0,0 +1,623 @@
+use crate::chain_source::ChainSource;
+use crate::data_store::DataStore;
+use crate::disk::{FilesystemLogger, BROADCAST_QUEUE_FNAME};
+use crate::fee_policy::unix_time_secs;
+use bitcoin::block::Header;
+use bitcoin::blockdata::transaction::{OutPoint, Transaction};
+use bitcoin::{BlockHash, Txid};
+use lightning::chain::chaininterface::BroadcasterInterface;
+use lightning::chain::transaction::TransactionData;
+use lightning::chain::{BestBlock, Confirm, Filter, Listen};
+use lightning::util::logger::Logger;
+use lightning::util::persist::KVStore;
+use lightning::util::ser::{Readable, Writeable};
+use lightning::{impl_writeable_tlv_based, impl_writeable_tlv_based_enum, log_error, log_info};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often transactions which haven't confirmed yet are handed to the chain source again.
+pub const REBROADCAST_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The number of confirmations after which a transaction can no longer be reorganized out of the
+/// chain, as far as LDK is concerned.
+const ANTI_REORG_DELAY: u32 = 6;
+
+/// How long we keep confirmed or replaced transactions around for `listbroadcasts`.
+const HISTORY_PERIOD_SECS: u64 = 14 * 24 * 60 * 60;
+
+/// Why a transaction was broadcast.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BroadcastKind {
+	/// A channel funding transaction.
+	Funding,
+	/// A cooperative closing transaction, or a commitment or HTLC transaction spending a channel
+	/// unilaterally.
+	Closing,
+	/// A transaction sweeping funds from a closed channel back to our on-chain wallet.
+	Sweep,
+	/// A child transaction bumping the fee of an anchor channel's commitment or HTLC transaction.
+	AnchorBump,
+}
+
+impl_writeable_tlv_based_enum!(BroadcastKind,
+	(0, Funding) => {},
+	(2, Closing) => {},
+	(4, Sweep) => {},
+	(6, AnchorBump) => {},
+);
+
+impl fmt::Display for BroadcastKind {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			BroadcastKind::Funding => write!(f, "funding"),
+			BroadcastKind::Closing => write!(f, "closing"),
+			BroadcastKind::Sweep => write!(f, "sweep"),
+			BroadcastKind::AnchorBump => write!(f, "anchor bump"),
+		}
+	}
+}
+
+#[derive(Clone)]
+struct TrackedBroadcast {
+	tx: Transaction,
+	kind: BroadcastKind,
+	/// When we first broadcast the transaction, in seconds since the UNIX epoch.
+	first_seen: u64,
+	confirmation_height: Option<u32>,
+	confirmation_hash: Option<BlockHash>,
+	/// The transaction spending the same inputs which we broadcast later or which confirmed
+	/// instead, if any.
+	replaced_by: Option<Txid>,
+	/// The first transaction of the package this one was last broadcast in and its position in
+	/// it, if it was broadcast along with others, e.g. an anchor commitment and the child paying
+	/// its fee. Packages are rebroadcast together, as their parents may not enter the mempool
+	/// on their own.
+	package: Option<(Txid, u16)>,
+}
+
+impl_writeable_tlv_based!(TrackedBroadcast, {
+	(0, tx, required),
+	(2, kind, required),
+	(4, first_seen, required),
+	(6, confirmation_height, option),
+	(8, confirmation_hash, option),
+	(10, replaced_by, option),
+	(12, package, option),
+});
+
+impl TrackedBroadcast {
+	fn is_pending(&self) -> bool {
+		self.confirmation_height.is_none() && self.replaced_by.is_none()
+	}
+
+	fn confirmations(&self, best_block: &Option<BestBlock>) -> u32 {
+		match (self.confirmation_height, best_block) {
+			(Some(height), Some(best_block)) => best_block.height.saturating_sub(height) + 1,
+			(Some(_), None) => 1,
+			(None, _) => 0,
+		}
+	}
+}
+
+/// What [`BroadcastQueue::list_broadcasts`] reports about each transaction.
+pub struct BroadcastDetails {
+	pub txid: Txid,
+	pub kind: BroadcastKind,
+	/// When we first broadcast the transaction, in seconds since the UNIX epoch.
+	pub first_seen: u64,
+	pub confirmations: u32,
+	pub replaced_by: Option<Txid>,
+}
+
+struct PersistedQueue {
+	best_block: Option<BestBlock>,
+	broadcasts: Vec<TrackedBroadcast>,
+	expected_funding: Vec<Txid>,
+}
+
+impl_writeable_tlv_based!(PersistedQueue, {
+	(0, best_block, option),
+	(2, broadcasts, required_vec),
+	(4, expected_funding, required_vec),
+});
+
+struct State {
+	/// The block we last heard about, which confirmations are counted up to.
+	best_block: Option<BestBlock>,
+	broadcasts: HashMap<Txid, TrackedBroadcast>,
+	/// Funding transactions we handed to the `ChannelManager` but which it hasn't broadcast yet.
+	expected_funding: HashSet<Txid>,
+}
+
+/// Records every transaction we broadcast, follows its confirmation status through the
+/// [`Listen`] or [`Confirm`] interface, depending on the chain source, and hands it to the chain
+/// source again every [`REBROADCAST_INTERVAL`] until it confirms.
+///
+/// LDK components broadcast through a [`TrackedBroadcaster`] obtained from
+/// [`BroadcastQueue::broadcaster`], which tags their transactions with a [`BroadcastKind`].
+pub struct BroadcastQueue {
+	chain_source: Arc<ChainSource>,
+	filter: Option<Arc<dyn Filter + Send + Sync>>,
+	state: Mutex<State>,
+	kv_store: Arc<DataStore>,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl BroadcastQueue {
+	/// Loads the broadcasts persisted in `kv_store` and registers them with the chain source's
+	/// [`Filter`], if it has one.
+	pub fn new(
+		chain_source: Arc<ChainSource>, kv_store: Arc<DataStore>,
+		logger: Arc<FilesystemLogger>,
+	) -> Self {
+		let mut state = State {
+			best_block: None,
+			broadcasts: HashMap::new(),
+			expected_funding: HashSet::new(),
+		};
+		if let Ok(bytes) = kv_store.read("", "", BROADCAST_QUEUE_FNAME) {
+			match PersistedQueue::read(&mut &bytes[..]) {
+				Ok(persisted) => {
+					log_info!(logger, "Loaded {} tracked broadcasts", persisted.broadcasts.len());
+					state.best_block = persisted.best_block;
+					for broadcast in persisted.broadcasts {
+						state.broadcasts.insert(broadcast.tx.compute_txid(), broadcast);
+					}
+					state.expected_funding = persisted.expected_funding.into_iter().collect();
+				},
+				Err(e) => log_error!(logger, "Failed to read broadcast queue: {:?}", e),
+			}
+		}
+		let filter = chain_source.filter();
+		if let Some(filter) = &filter {
+			for (txid, broadcast) in state.broadcasts.iter() {
+				register_tx(filter.as_ref(), txid, &broadcast.tx);
+			}
+		}
+		Self { chain_source, filter, state: Mutex::new(state), kv_store, logger }
+	}
+
+	/// Returns a [`BroadcasterInterface`] recording the transactions given to it as `kind`.
+	pub fn broadcaster(self: &Arc<Self>, kind: BroadcastKind) -> Arc<TrackedBroadcaster> {
+		Arc::new(TrackedBroadcaster { queue: Arc::clone(self), kind })
+	}
+
+	/// The block the queue was last synced to, if any.
+	pub fn best_block(&self) -> Option<BestBlock> {
+		self.state.lock().unwrap().best_block
+	}
+
+	/// Marks `txid` as a funding transaction, so that it is recorded as such when the
+	/// `ChannelManager` broadcasts it.
+	pub fn expect_funding(&self, txid: Txid) {
+		let encoded = {
+			let mut state = self.state.lock().unwrap();
+			state.expected_funding.insert(txid);
+			encode_state(&state)
+		};
+		self.persist(encoded);
+	}
+
+	/// Returns every tracked transaction, oldest first.
+	pub fn list_broadcasts(&self) -> Vec<BroadcastDetails> {
+		let state = self.state.lock().unwrap();
+		let mut broadcasts = state
+			.broadcasts
+			.iter()
+			.map(|(txid, broadcast)| BroadcastDetails {
+				txid: *txid,
+				kind: broadcast.kind,
+				first_seen: broadcast.first_seen,
+				confirmations: broadcast.confirmations(&state.best_block),
+				replaced_by: broadcast.replaced_by,
+			})
+			.collect::<Vec<_>>();
+		broadcasts.sort_by_key(|broadcast| broadcast.first_seen);
+		broadcasts
+	}
+
+	/// Hands every transaction that hasn't confirmed or been replaced yet to the chain source
+	/// again, oldest first. Transactions broadcast as a package are rebroadcast together.
+	pub fn rebroadcast_pending(&self) {
+		let packages = self.pending_packages();
+		if packages.is_empty() {
+			return;
+		}
+		let count = packages.iter().map(Vec::len).sum::<usize>();
+		log_info!(self.logger, "Rebroadcasting {} unconfirmed transactions", count);
+		for txs in packages {
+			self.chain_source.broadcast_transactions(&txs.iter().collect::<Vec<_>>());
+		}
+	}
+
+	/// Groups the pending transactions into the packages they were broadcast in, oldest first.
+	fn pending_packages(&self) -> Vec<Vec<Transaction>> {
+		let state = self.state.lock().unwrap();
+		let mut packages: HashMap<Txid, Vec<&TrackedBroadcast>> = HashMap::new();
+		for (txid, broadcast) in state.broadcasts.iter() {
+			if broadcast.is_pending() {
+				let package_id = broadcast.package.map_or(*txid, |(first_txid, _)| first_txid);
+				packages.entry(package_id).or_default().push(broadcast);
+			}
+		}
+		let mut packages = packages.into_values().collect::<Vec<_>>();
+		for package in packages.iter_mut() {
+			package.sort_by_key(|broadcast| broadcast.package.map(|(_, position)| position));
+		}
+		packages.sort_by_key(|package| package.iter().map(|broadcast| broadcast.first_seen).min());
+		packages
+			.into_iter()
+			.map(|package| package.into_iter().map(|broadcast| broadcast.tx.clone()).collect())
+			.collect()
+	}
+
+	fn broadcast(&self, txs: &[&Transaction], kind: BroadcastKind) {
+		let now = unix_time_secs();
+		let encoded = {
+			let mut state = self.state.lock().unwrap();
+			let mut changed = false;
+			let package_id = txs.first().filter(|_| txs.len() > 1).map(|tx| tx.compute_txid());
+			for (position, tx) in txs.iter().enumerate() {
+				let txid = tx.compute_txid();
+				let package = package_id.map(|package_id| (package_id, position as u16));
+				if let Some(broadcast) = state.broadcasts.get_mut(&txid) {
+					// A transaction we broadcast alone before may be broadcast again with a child
+					// bumping its fee, and is rebroadcast with it from then on.
+					if package.is_some() && broadcast.package != package {
+						broadcast.package = package;
+						changed = true;
+					}
+					continue;
+				}
+				let kind = if state.expected_funding.remove(&txid) {
+					BroadcastKind::Funding
+				} else {
+					kind
+				};
+				// A new transaction spending the inputs of one we broadcast earlier, such as a
+				// sweep at a higher feerate, supersedes it.
+				let inputs = tx.input.iter().map(|input| input.previous_output).collect();
+				for broadcast in state.broadcasts.values_mut() {
+					if broadcast.is_pending() && spends_any(&broadcast.tx, &inputs) {
+						broadcast.replaced_by = Some(txid);
+					}
+				}
+				if let Some(filter) = &self.filter {
+					register_tx(filter.as_ref(), &txid, tx);
+				}
+				log_info!(self.logger, "Tracking {} transaction {}", kind, txid);
+				let broadcast = TrackedBroadcast {
+					tx: (*tx).clone(),
+					kind,
+					first_seen: now,
+					confirmation_height: None,
+					confirmation_hash: None,
+					replaced_by: None,
+					package,
+				};
+				state.broadcasts.insert(txid, broadcast);
+				changed = true;
+			}
+			if changed {
+				Some(encode_state(&state))
+			} else {
+				None
+			}
+		};
+		if let Some(encoded) = encoded {
+			self.persist(encoded);
+		}
+		self.chain_source.broadcast_transactions(txs);
+	}
+
+	/// Records the confirmation of tracked transactions among `txdata`, and marks pending ones
+	/// as replaced if a conflicting transaction confirmed instead.
+	fn process_transactions(
+		&self, state: &mut State, header: &Header, txdata: &TransactionData, height: u32,
+	) {
+		let block_hash = header.block_hash();
+		for (_, tx) in txdata.iter() {
+			let txid = tx.compute_txid();
+			if let Some(broadcast) = state.broadcasts.get_mut(&txid) {
+				log_info!(self.logger, "Broadcast transaction {} confirmed at {}", txid, height);
+				broadcast.confirmation_height = Some(height);
+				broadcast.confirmation_hash = Some(block_hash);
+				continue;
+			}
+			let inputs = tx.input.iter().map(|input| input.previous_output).collect();
+			for (conflicting_txid, broadcast) in state.broadcasts.iter_mut() {
+				if broadcast.is_pending() && spends_any(&broadcast.tx, &inputs) {
+					log_info!(
+						self.logger,
+						"Broadcast transaction {} was replaced by {} confirmed at {}",
+						conflicting_txid,
+						txid,
+						height
+					);
+					broadcast.replaced_by = Some(txid);
+				}
+			}
+		}
+	}
+
+	/// Moves the queue's tip to `best_block` and forgets transactions which are buried deep
+	/// enough and have been around for longer than our history period.
+	fn update_best_block(&self, state: &mut State, best_block: BestBlock) {
+		state.best_block = Some(best_block);
+		let now = unix_time_secs();
+		let best_block = state.best_block;
+		state.broadcasts.retain(|_, broadcast| {
+			let resolved = broadcast.confirmations(&best_block) >= ANTI_REORG_DELAY
+				|| broadcast.replaced_by.is_some();
+			!resolved || now.saturating_sub(broadcast.first_seen) < HISTORY_PERIOD_SECS
+		});
+	}
+
+	fn persist(&self, encoded: Vec<u8>) {
+		if let Err(e) = self.kv_store.write("", "", BROADCAST_QUEUE_FNAME, &encoded) {
+			log_error!(self.logger, "Failed to persist broadcast queue: {}", e);
+		}
+	}
+}
+
+fn encode_state(state: &State) -> Vec<u8> {
+	PersistedQueue {
+		best_block: state.best_block,
+		broadcasts: state.broadcasts.values().cloned().collect(),
+		expected_funding: state.expected_funding.iter().copied().collect(),
+	}
+	.encode()
+}
+
+fn spends_any(tx: &Transaction, outpoints: &HashSet<OutPoint>) -> bool {
+	tx.input.iter().any(|input| outpoints.contains(&input.previous_output))
+}
+
+/// The best block once the block at `height` with `header` is disconnected. The genesis block
+/// has no parent, so disconnecting it leaves its (all zeros) previous hash at height 0.
+pub(crate) fn best_block_before(header: &Header, height: u32) -> BestBlock {
+	BestBlock::new(header.prev_blockhash, height.saturating_sub(1))
+}
+
+fn register_tx(filter: &(dyn Filter + Send + Sync), txid: &Txid, tx: &Transaction) {
+	if let Some(output) = tx.output.first() {
+		filter.register_tx(txid, &output.script_pubkey);
+	}
+}
+
+impl Listen for BroadcastQueue {
+	fn filtered_block_connected(&self, header: &Header, txdata: &TransactionData, height: u32) {
+		let encoded = {
+			let mut state = self.state.lock().unwrap();
+			self.process_transactions(&mut state, header, txdata, height);
+			self.update_best_block(&mut state, BestBlock::new(header.block_hash(), height));
+			encode_state(&state)
+		};
+		self.persist(encoded);
+	}
+
+	fn block_disconnected(&self, header: &Header, height: u32) {
+		let encoded = {
+			let mut state = self.state.lock().unwrap();
+			for broadcast in state.broadcasts.values_mut() {
+				if matches!(broadcast.confirmation_height, Some(h) if h >= height) {
+					broadcast.confirmation_height = None;
+					broadcast.confirmation_hash = None;
+				}
+			}
+			state.best_block = Some(best_block_before(header, height));
+			encode_state(&state)
+		};
+		self.persist(encoded);
+	}
+}
+
+impl Confirm for BroadcastQueue {
+	fn transactions_confirmed(&self, header: &Header, txdata: &TransactionData, height: u32) {
+		let encoded = {
+			let mut state = self.state.lock().unwrap();
+			self.process_transactions(&mut state, header, txdata, height);
+			encode_state(&state)
+		};
+		self.persist(encoded);
+	}
+
+	fn transaction_unconfirmed(&self, txid: &Txid) {
+		let encoded = {
+			let mut state = self.state.lock().unwrap();
+			match state.broadcasts.get_mut(txid) {
+				Some(broadcast) => {
+					broadcast.confirmation_height = None;
+					broadcast.confirmation_hash = None;
+				},
+				None => return,
+			}
+			encode_state(&state)
+		};
+		self.persist(encoded);
+	}
+
+	fn best_block_updated(&self, header: &Header, height: u32) {
+		let encoded = {
+			let mut state = self.state.lock().unwrap();
+			self.update_best_block(&mut state, BestBlock::new(header.block_hash(), height));
+			encode_state(&state)
+		};
+		self.persist(encoded);
+	}
+
+	fn get_relevant_txids(&self) -> Vec<(Txid, u32, Option<BlockHash>)> {
+		let state = self.state.lock().unwrap();
+		state
+			.broadcasts
+			.iter()
+			.filter_map(|(txid, broadcast)| {
+				let height = broadcast.confirmation_height?;
+				Some((*txid, height, broadcast.confirmation_hash))
+			})
+			.collect()
+	}
+}
+
+/// The [`BroadcasterInterface`] handed to LDK components, which records every transaction in
+/// the [`BroadcastQueue`] before broadcasting it.
+pub struct TrackedBroadcaster {
+	queue: Arc<BroadcastQueue>,
+	kind: BroadcastKind,
+}
+
+impl BroadcasterInterface for TrackedBroadcaster {
+	fn broadcast_transactions(&self, txs: &[&Transaction]) {
+		self.queue.broadcast(txs, self.kind);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::electrum_client::ElectrumClient;
+	use crate::test_utils::{test_data_dir, test_fee_estimates, test_store, MockElectrumServer};
+	use bitcoin::absolute::LockTime;
+	use bitcoin::block::{Block, Version as BlockVersion};
+	use bitcoin::consensus::encode;
+	use bitcoin::hashes::Hash;
+	use bitcoin::transaction::Version;
+	use bitcoin::{Amount, CompactTarget, ScriptBuf, Sequence, TxIn, TxMerkleNode, TxOut, Witness};
+	use serde_json::json;
+
+	// The regtest genesis block header.
+	const TIP_HEADER_HEX: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4adae5494dffff7f2002000000";
+
+	fn respond(method: &str, _params: &serde_json::Value) -> serde_json::Value {
+		match method {
+			"blockchain.headers.subscribe" => json!({ "hex": TIP_HEADER_HEX, "height": 0 }),
+			"blockchain.relayfee" | "blockchain.estimatefee" => json!(0.00001),
+			"blockchain.transaction.broadcast" => {
+				json!("0000000000000000000000000000000000000000000000000000000000000000")
+			},
+			_ => serde_json::Value::Null,
+		}
+	}
+
+	async fn chain_source(server: &MockElectrumServer, data_dir: &str) -> Arc<ChainSource> {
+		let logger = Arc::new(FilesystemLogger::new(data_dir.to_string()));
+		let client = ElectrumClient::new(
+			server.url(),
+			test_fee_estimates(data_dir),
+			tokio::runtime::Handle::current(),
+			logger,
+		)
+		.await
+		.unwrap();
+		Arc::new(ChainSource::Electrum(Arc::new(client)))
+	}
+
+	fn broadcast_queue(chain_source: Arc<ChainSource>, data_dir: &str) -> Arc<BroadcastQueue> {
+		let (kv_store, logger) = test_store(data_dir);
+		Arc::new(BroadcastQueue::new(chain_source, kv_store, logger))
+	}
+
+	fn spending_tx(prev_txid_byte: u8, value: u64) -> Transaction {
+		Transaction {
+			version: Version::TWO,
+			lock_time: LockTime::ZERO,
+			input: vec![TxIn {
+				previous_output: OutPoint {
+					txid: Txid::from_byte_array([prev_txid_byte; 32]),
+					vout: 0,
+				},
+				script_sig: ScriptBuf::new(),
+				sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+				witness: Witness::new(),
+			}],
+			output: vec![TxOut { value: Amount::from_sat(value), script_pubkey: ScriptBuf::new() }],
+		}
+	}
+
+	fn block(prev_blockhash: BlockHash, txdata: Vec<Transaction>) -> Block {
+		let header = Header {
+			version: BlockVersion::TWO,
+			prev_blockhash,
+			merkle_root: TxMerkleNode::all_zeros(),
+			time: 0,
+			bits: CompactTarget::from_consensus(0x207fffff),
+			nonce: 0,
+		};
+		Block { header, txdata }
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_packages_are_rebroadcast_together() {
+		let data_dir = test_data_dir("broadcast-packages");
+		let server = MockElectrumServer::start(respond).await;
+		let queue = broadcast_queue(chain_source(&server, &data_dir).await, &data_dir);
+
+		// A commitment broadcast alone, then again with a child bumping its fee.
+		let commitment_tx = spending_tx(1, 100_000);
+		queue.broadcaster(BroadcastKind::Closing).broadcast_transactions(&[&commitment_tx]);
+		let mut anchor_tx = spending_tx(2, 10_000);
+		anchor_tx.input[0].previous_output =
+			OutPoint { txid: commitment_tx.compute_txid(), vout: 0 };
+		let package = [&commitment_tx, &anchor_tx];
+		queue.broadcaster(BroadcastKind::AnchorBump).broadcast_transactions(&package);
+		let sweep_tx = spending_tx(3, 50_000);
+		queue.broadcaster(BroadcastKind::Sweep).broadcast_transactions(&[&sweep_tx]);
+
+		let mut packages = queue.pending_packages();
+		packages.sort_by_key(Vec::len);
+		assert_eq!(packages, vec![vec![sweep_tx], vec![commitment_tx.clone(), anchor_tx]]);
+
+		// The package survives a restart.
+		let queue = broadcast_queue(chain_source(&server, &data_dir).await, &data_dir);
+		let packages = queue.pending_packages();
+		assert!(packages.iter().any(|package| package.len() == 2 && package[0] == commitment_tx));
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_broadcasts_are_tracked_until_confirmed() {
+		let data_dir = test_data_dir("broadcast-queue");
+		let server = MockElectrumServer::start(respond).await;
+		let queue = broadcast_queue(chain_source(&server, &data_dir).await, &data_dir);
+
+		let funding_tx = spending_tx(1, 100_000);
+		queue.expect_funding(funding_tx.compute_txid());
+		queue.broadcaster(BroadcastKind::Closing).broadcast_transactions(&[&funding_tx]);
+		let sweep_tx = spending_tx(2, 50_000);
+		queue.broadcaster(BroadcastKind::Sweep).broadcast_transactions(&[&sweep_tx]);
+		// A sweep of the same output at a higher feerate replaces the first one.
+		let bumped_sweep_tx = spending_tx(2, 49_000);
+		queue.broadcaster(BroadcastKind::Sweep).broadcast_transactions(&[&bumped_sweep_tx]);
+
+		let broadcasts = queue.list_broadcasts();
+		assert_eq!(broadcasts.len(), 3);
+		let funding = broadcasts.iter().find(|b| b.txid == funding_tx.compute_txid()).unwrap();
+		assert_eq!(funding.kind, BroadcastKind::Funding);
+		assert_eq!(funding.confirmations, 0);
+		let sweep = broadcasts.iter().find(|b| b.txid == sweep_tx.compute_txid()).unwrap();
+		assert_eq!(sweep.replaced_by, Some(bumped_sweep_tx.compute_txid()));
+
+		let first_block = block(BlockHash::all_zeros(), vec![funding_tx.clone()]);
+		queue.block_connected(&first_block, 1);
+		let second_block = block(first_block.block_hash(), Vec::new());
+		queue.block_connected(&second_block, 2);
+
+		// Only the bumped sweep is still pending, and it survives a restart.
+		let queue = broadcast_queue(chain_source(&server, &data_dir).await, &data_dir);
+		let broadcasts = queue.list_broadcasts();
+		let funding = broadcasts.iter().find(|b| b.txid == funding_tx.compute_txid()).unwrap();
+		assert_eq!(funding.confirmations, 2);
+		queue.rebroadcast_pending();
+		let broadcasts = server.wait_for_requests("blockchain.transaction.broadcast", 4).await;
+		let bumped_sweep_hex = json!([encode::serialize_hex(&bumped_sweep_tx)]);
+		assert_eq!(broadcasts.iter().filter(|params| **params == bumped_sweep_hex).count(), 2);
+
+		// Reorganizing the funding transaction's block out of the chain makes it pending again.
+		queue.block_disconnected(&second_block.header, 2);
+		queue.block_disconnected(&first_block.header, 1);
+		let broadcasts = queue.list_broadcasts();
+		let funding = broadcasts.iter().find(|b| b.txid == funding_tx.compute_txid()).unwrap();
+		assert_eq!(funding.confirmations, 0);
+	}
+}
@@ -0,0 +1,72 @@
+use crate::bitcoind_client::BitcoindClient;
+use crate::electrum_client::ElectrumClient;
+use crate::esplora_client::EsploraClient;
+use bitcoin::blockdata::transaction::Transaction;
+use lightning::chain::chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator};
+use lightning::chain::{Confirm, Filter};
+use lightning_transaction_sync::TxSyncError;
+use std::sync::Arc;
+
+/// The backend LDK gets its chain data, fee estimates and transaction broadcasting from.
+///
+/// Note that only bitcoind brings an on-chain wallet to fund channels and receive swept funds.
+/// Nodes using another chain source run without one.
+pub enum ChainSource {
+	/// Blocks are fetched from bitcoind over RPC and connected via the `Listen` interface.
+	Bitcoind(Arc<BitcoindClient>),
+	/// Relevant transactions are fetched from an Esplora server and fed via the `Confirm`
+	/// interface.
+	Esplora(Arc<EsploraClient>),
+	/// Relevant transactions are fetched from an Electrum server and fed via the `Confirm`
+	/// interface.
+	Electrum(Arc<ElectrumClient>),
+}
+
+impl ChainSource {
+	/// Returns the [`Filter`] that needs to be registered with the `ChainMonitor` and
+	/// `OutputSweeper`, if the chain source only learns about transactions LDK is interested in.
+	pub fn filter(&self) -> Option<Arc<dyn Filter + Send + Sync>> {
+		match self {
+			ChainSource::Bitcoind(_) => None,
+			ChainSource::Esplora(client) => Some(client.filter()),
+			ChainSource::Electrum(client) => Some(client.filter()),
+		}
+	}
+
+	/// Synchronizes the given `confirmables` with the chain if this is a transaction-based chain
+	/// source. bitcoind connects full blocks through an `SpvClient` instead, so this does nothing
+	/// for it.
+	pub async fn sync(
+		&self, confirmables: Vec<Arc<dyn Confirm + Send + Sync>>,
+	) -> Result<(), TxSyncError> {
+		match self {
+			ChainSource::Bitcoind(_) => Ok(()),
+			ChainSource::Esplora(client) => client.sync(confirmables).await,
+			ChainSource::Electrum(client) => client.sync(confirmables).await,
+		}
+	}
+}
+
+impl FeeEstimator for ChainSource {
+	fn get_est_sat_per_1000_weight(&self, confirmation_target: ConfirmationTarget) -> u32 {
+		match self {
+			ChainSource::Bitcoind(client) => {
+				client.get_est_sat_per_1000_weight(confirmation_target)
+			},
+			ChainSource::Esplora(client) => client.get_est_sat_per_1000_weight(confirmation_target),
+			ChainSource::Electrum(client) => {
+				client.get_est_sat_per_1000_weight(confirmation_target)
+			},
+		}
+	}
+}
+
+impl BroadcasterInterface for ChainSource {
+	fn broadcast_transactions(&self, txs: &[&Transaction]) {
+		match self {
+			ChainSource::Bitcoind(client) => client.broadcast_transactions(txs),
+			ChainSource::Esplora(client) => client.broadcast_transactions(txs),
+			ChainSource::Electrum(client) => client.broadcast_transactions(txs),
+		}
+	}
+}
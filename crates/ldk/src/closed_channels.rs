@@ -0,0 +1,371 @@
+use crate::broadcast_queue::best_block_before;
+use crate::data_store::DataStore;
+use crate::disk::{FilesystemLogger, CLOSED_CHANNELS_FNAME};
+use bitcoin::block::Header;
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::{BlockHash, Txid};
+use lightning::chain::transaction::{OutPoint, TransactionData};
+use lightning::chain::{BestBlock, Confirm, Listen};
+use lightning::ln::types::ChannelId;
+use lightning::util::logger::Logger;
+use lightning::util::persist::KVStore;
+use lightning::util::ser::{Readable, Writeable};
+use lightning::{impl_writeable_tlv_based, log_error, log_info};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// A channel as it was when it closed, as reported by `Event::ChannelClosed`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClosedChannel {
+	pub channel_id: ChannelId,
+	pub user_channel_id: u128,
+	pub counterparty_node_id: Option<PublicKey>,
+	/// `None` if the channel closed before it was funded.
+	pub funding_txo: Option<OutPoint>,
+	pub capacity_sats: Option<u64>,
+	/// Our balance in the channel when it closed, before any on-chain fees.
+	pub final_balance_msat: Option<u64>,
+	/// Why the channel closed, as LDK describes its `ClosureReason`.
+	pub reason: String,
+	/// When the channel closed, in seconds since the UNIX epoch.
+	pub closed_at: u64,
+}
+
+impl_writeable_tlv_based!(ClosedChannel, {
+	(0, channel_id, required),
+	(2, user_channel_id, required),
+	(4, counterparty_node_id, option),
+	(6, funding_txo, option),
+	(8, capacity_sats, option),
+	(10, final_balance_msat, option),
+	(12, reason, required),
+	(14, closed_at, required),
+});
+
+/// A confirmed transaction spending a channel's funding output, i.e. the one which closed it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FundingSpend {
+	pub funding_txo: OutPoint,
+	pub txid: Txid,
+	pub confirmation_height: u32,
+	pub confirmation_hash: BlockHash,
+}
+
+impl_writeable_tlv_based!(FundingSpend, {
+	(0, funding_txo, required),
+	(2, txid, required),
+	(4, confirmation_height, required),
+	(6, confirmation_hash, required),
+});
+
+/// What [`ClosedChannels::list`] reports about each channel.
+pub struct ClosedChannelDetails {
+	pub channel: ClosedChannel,
+	/// The transaction which closed the channel, once it confirmed.
+	pub closing_tx: Option<FundingSpend>,
+	pub closing_tx_confirmations: u32,
+}
+
+struct PersistedClosedChannels {
+	best_block: Option<BestBlock>,
+	channels: Vec<ClosedChannel>,
+	funding_spends: Vec<FundingSpend>,
+}
+
+impl_writeable_tlv_based!(PersistedClosedChannels, {
+	(0, best_block, option),
+	(2, channels, required_vec),
+	(4, funding_spends, required_vec),
+});
+
+struct State {
+	best_block: Option<BestBlock>,
+	channels: Vec<ClosedChannel>,
+	funding_spends: HashMap<OutPoint, FundingSpend>,
+	/// The funding outputs of every channel we have a monitor for. Their spends are recorded as
+	/// soon as they confirm, as LDK only tells us a channel closed once it processed the block
+	/// which closed it.
+	watched_funding: HashSet<OutPoint>,
+}
+
+/// Every channel which closed, with the transaction which closed it, kept so closed channels can
+/// still be accounted for once they are gone from the `ChannelManager`.
+pub struct ClosedChannels {
+	state: Mutex<State>,
+	kv_store: Arc<DataStore>,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl ClosedChannels {
+	/// Loads the closed channels persisted in `kv_store`.
+	pub fn new(kv_store: Arc<DataStore>, logger: Arc<FilesystemLogger>) -> Self {
+		let mut state = State {
+			best_block: None,
+			channels: Vec::new(),
+			funding_spends: HashMap::new(),
+			watched_funding: HashSet::new(),
+		};
+		if let Ok(bytes) = kv_store.read("", "", CLOSED_CHANNELS_FNAME) {
+			match PersistedClosedChannels::read(&mut &bytes[..]) {
+				Ok(persisted) => {
+					log_info!(logger, "Loaded {} closed channels", persisted.channels.len());
+					state.best_block = persisted.best_block;
+					state.channels = persisted.channels;
+					for spend in persisted.funding_spends {
+						state.funding_spends.insert(spend.funding_txo, spend);
+					}
+				},
+				Err(e) => log_error!(logger, "Failed to read closed channels: {:?}", e),
+			}
+		}
+		Self { state: Mutex::new(state), kv_store, logger }
+	}
+
+	/// The block the closed channels were last synced to, if any.
+	pub fn best_block(&self) -> Option<BestBlock> {
+		self.state.lock().unwrap().best_block
+	}
+
+	/// Watches for the confirmation of a transaction spending a channel's funding output. Funding
+	/// outputs aren't persisted, so every channel monitor's has to be watched again at startup.
+	pub fn watch_funding(&self, funding_txo: OutPoint) {
+		self.state.lock().unwrap().watched_funding.insert(funding_txo);
+	}
+
+	/// Records a channel which closed.
+	pub fn record(&self, channel: ClosedChannel) {
+		let encoded = {
+			let mut state = self.state.lock().unwrap();
+			if let Some(funding_txo) = channel.funding_txo {
+				state.watched_funding.insert(funding_txo);
+			}
+			state.channels.push(channel);
+			encode_state(&state)
+		};
+		self.persist(encoded);
+	}
+
+	/// Returns every closed channel, in the order they closed.
+	pub fn list(&self) -> Vec<ClosedChannelDetails> {
+		let state = self.state.lock().unwrap();
+		state
+			.channels
+			.iter()
+			.map(|channel| {
+				let closing_tx = channel
+					.funding_txo
+					.and_then(|funding_txo| state.funding_spends.get(&funding_txo))
+					.copied();
+				let closing_tx_confirmations = match (&closing_tx, &state.best_block) {
+					(Some(spend), Some(best_block)) => {
+						best_block.height.saturating_sub(spend.confirmation_height) + 1
+					},
+					(Some(_), None) => 1,
+					(None, _) => 0,
+				};
+				ClosedChannelDetails {
+					channel: channel.clone(),
+					closing_tx,
+					closing_tx_confirmations,
+				}
+			})
+			.collect()
+	}
+
+	fn process_transactions(
+		&self, state: &mut State, header: &Header, txdata: &TransactionData, height: u32,
+	) {
+		for (_, tx) in txdata.iter() {
+			for input in tx.input.iter() {
+				let funding_txo = OutPoint {
+					txid: input.previous_output.txid,
+					index: input.previous_output.vout as u16,
+				};
+				if input.previous_output.vout > u16::MAX as u32
+					|| !state.watched_funding.contains(&funding_txo)
+				{
+					continue;
+				}
+				let txid = tx.compute_txid();
+				log_info!(
+					self.logger,
+					"Funding output {} was spent by {} at {}",
+					funding_txo,
+					txid,
+					height
+				);
+				let spend = FundingSpend {
+					funding_txo,
+					txid,
+					confirmation_height: height,
+					confirmation_hash: header.block_hash(),
+				};
+				state.funding_spends.insert(funding_txo, spend);
+			}
+		}
+	}
+
+	fn persist(&self, encoded: Vec<u8>) {
+		if let Err(e) = self.kv_store.write("", "", CLOSED_CHANNELS_FNAME, &encoded) {
+			log_error!(self.logger, "Failed to persist closed channels: {}", e);
+		}
+	}
+}
+
+fn encode_state(state: &State) -> Vec<u8> {
+	PersistedClosedChannels {
+		best_block: state.best_block,
+		channels: state.channels.clone(),
+		funding_spends: state.funding_spends.values().copied().collect(),
+	}
+	.encode()
+}
+
+impl Listen for ClosedChannels {
+	fn filtered_block_connected(&self, header: &Header, txdata: &TransactionData, height: u32) {
+		let encoded = {
+			let mut state = self.state.lock().unwrap();
+			self.process_transactions(&mut state, header, txdata, height);
+			state.best_block = Some(BestBlock::new(header.block_hash(), height));
+			encode_state(&state)
+		};
+		self.persist(encoded);
+	}
+
+	fn block_disconnected(&self, header: &Header, height: u32) {
+		let encoded = {
+			let mut state = self.state.lock().unwrap();
+			state.funding_spends.retain(|_, spend| spend.confirmation_height < height);
+			state.best_block = Some(best_block_before(header, height));
+			encode_state(&state)
+		};
+		self.persist(encoded);
+	}
+}
+
+impl Confirm for ClosedChannels {
+	fn transactions_confirmed(&self, header: &Header, txdata: &TransactionData, height: u32) {
+		let encoded = {
+			let mut state = self.state.lock().unwrap();
+			self.process_transactions(&mut state, header, txdata, height);
+			encode_state(&state)
+		};
+		self.persist(encoded);
+	}
+
+	fn transaction_unconfirmed(&self, txid: &Txid) {
+		let encoded = {
+			let mut state = self.state.lock().unwrap();
+			let spends = state.funding_spends.len();
+			state.funding_spends.retain(|_, spend| spend.txid != *txid);
+			if state.funding_spends.len() == spends {
+				return;
+			}
+			encode_state(&state)
+		};
+		self.persist(encoded);
+	}
+
+	fn best_block_updated(&self, header: &Header, height: u32) {
+		let encoded = {
+			let mut state = self.state.lock().unwrap();
+			state.best_block = Some(BestBlock::new(header.block_hash(), height));
+			encode_state(&state)
+		};
+		self.persist(encoded);
+	}
+
+	fn get_relevant_txids(&self) -> Vec<(Txid, u32, Option<BlockHash>)> {
+		let state = self.state.lock().unwrap();
+		state
+			.funding_spends
+			.values()
+			.map(|spend| (spend.txid, spend.confirmation_height, Some(spend.confirmation_hash)))
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::fee_policy::unix_time_secs;
+	use crate::test_utils::{test_data_dir, test_store};
+	use bitcoin::absolute::LockTime;
+	use bitcoin::block::Version as BlockVersion;
+	use bitcoin::hashes::Hash;
+	use bitcoin::transaction::Version;
+	use bitcoin::{
+		Amount, CompactTarget, ScriptBuf, Sequence, Transaction, TxIn, TxMerkleNode, TxOut, Witness,
+	};
+
+	fn header(prev_blockhash: BlockHash, nonce: u32) -> Header {
+		Header {
+			version: BlockVersion::TWO,
+			prev_blockhash,
+			merkle_root: TxMerkleNode::all_zeros(),
+			time: 0,
+			bits: CompactTarget::from_consensus(0x207fffff),
+			nonce,
+		}
+	}
+
+	fn spend_of(outpoint: OutPoint) -> Transaction {
+		Transaction {
+			version: Version::TWO,
+			lock_time: LockTime::ZERO,
+			input: vec![TxIn {
+				previous_output: outpoint.into_bitcoin_outpoint(),
+				script_sig: ScriptBuf::new(),
+				sequence: Sequence::MAX,
+				witness: Witness::new(),
+			}],
+			output: vec![TxOut { value: Amount::from_sat(1000), script_pubkey: ScriptBuf::new() }],
+		}
+	}
+
+	#[test]
+	fn test_closing_tx_is_recorded_before_the_close() {
+		let (kv_store, logger) = test_store(&test_data_dir("closed-channels"));
+		let closed_channels = ClosedChannels::new(Arc::clone(&kv_store), Arc::clone(&logger));
+		let funding_txo = OutPoint { txid: Txid::from_byte_array([1; 32]), index: 0 };
+		closed_channels.watch_funding(funding_txo);
+
+		// The closing transaction confirms before LDK tells us the channel closed.
+		let closing_tx = spend_of(funding_txo);
+		let unrelated_tx = spend_of(OutPoint { txid: Txid::from_byte_array([2; 32]), index: 0 });
+		let block = header(BlockHash::all_zeros(), 1);
+		closed_channels.filtered_block_connected(
+			&block,
+			&[(0, &unrelated_tx), (1, &closing_tx)],
+			100,
+		);
+		closed_channels.filtered_block_connected(&header(block.block_hash(), 2), &[], 101);
+		closed_channels.record(ClosedChannel {
+			channel_id: ChannelId([3; 32]),
+			user_channel_id: 42,
+			counterparty_node_id: None,
+			funding_txo: Some(funding_txo),
+			capacity_sats: Some(100_000),
+			final_balance_msat: Some(40_000_000),
+			reason: "CounterpartyForceClosed".to_string(),
+			closed_at: unix_time_secs(),
+		});
+
+		// Both survive a restart.
+		let closed_channels = ClosedChannels::new(kv_store, logger);
+		let details = closed_channels.list();
+		assert_eq!(details.len(), 1);
+		assert_eq!(details[0].channel.final_balance_msat, Some(40_000_000));
+		let spend = details[0].closing_tx.unwrap();
+		assert_eq!(spend.txid, closing_tx.compute_txid());
+		assert_eq!(details[0].closing_tx_confirmations, 2);
+		assert_eq!(
+			closed_channels.get_relevant_txids(),
+			vec![(closing_tx.compute_txid(), 100, Some(block.block_hash()))]
+		);
+
+		// The closing transaction is forgotten if it is reorganized out of the chain.
+		closed_channels.transaction_unconfirmed(&closing_tx.compute_txid());
+		assert!(closed_channels.list()[0].closing_tx.is_none());
+	}
+}
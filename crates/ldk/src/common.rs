@@ -1,5 +1,10 @@
 use crate::bitcoind_client::BitcoindClient;
+use crate::broadcast_queue::TrackedBroadcaster;
+use crate::chain_source::ChainSource;
+use crate::data_store::DataStore;
 use crate::disk::FilesystemLogger;
+use crate::replicating_store::MonitorPersister;
+use crate::sweep::SweepPolicy;
 use bitcoin::io;
 use lightning::chain::chainmonitor;
 use lightning::chain::Filter;
@@ -16,20 +21,20 @@ use lightning::onion_message::messenger::{
 };
 use lightning::routing::gossip;
 use lightning::routing::gossip::P2PGossipSync;
+use lightning::routing::router::DefaultRouter;
+use lightning::routing::scoring::{ProbabilisticScorer, ProbabilisticScoringFeeParameters};
 use lightning::sign::{InMemorySigner, KeysManager};
 use lightning::types::payment::{PaymentHash, PaymentPreimage, PaymentSecret};
 use lightning::util::hash_tables::HashMap;
-use lightning::util::persist::MonitorUpdatingPersister;
 use lightning::util::ser::{Readable, Writeable, Writer};
 use lightning::util::sweep as ldk_sweep;
 use lightning::{impl_writeable_tlv_based, impl_writeable_tlv_based_enum};
 use lightning_dns_resolver::OMDomainResolver;
 use lightning_net_tokio::SocketDescriptor;
-use lightning_persister::fs_store::FilesystemStore;
 use std::convert::TryInto;
 use std::fmt;
 use std::io::Write;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 #[derive(Copy, Clone)]
 pub enum HTLCStatus {
     Pending,
@@ -100,19 +105,10 @@ impl_writeable_tlv_based!(OutboundPaymentInfoStorage, {
 pub type ChainMonitor = chainmonitor::ChainMonitor<
     InMemorySigner,
     Arc<dyn Filter + Send + Sync>,
-    Arc<BitcoindClient>,
-    Arc<BitcoindClient>,
+    Arc<TrackedBroadcaster>,
+    Arc<ChainSource>,
     Arc<FilesystemLogger>,
-    Arc<
-        MonitorUpdatingPersister<
-            Arc<FilesystemStore>,
-            Arc<FilesystemLogger>,
-            Arc<KeysManager>,
-            Arc<KeysManager>,
-            Arc<BitcoindClient>,
-            Arc<BitcoindClient>,
-        >,
-    >,
+    Arc<MonitorPersister>,
 >;
 
 pub type GossipVerifier = lightning_block_sync::gossip::GossipVerifier<
@@ -134,10 +130,24 @@ pub type PeerManager = LdkPeerManager<
 >;
 
 pub type ChannelManager =
-SimpleArcChannelManager<ChainMonitor, BitcoindClient, BitcoindClient, FilesystemLogger>;
+SimpleArcChannelManager<ChainMonitor, TrackedBroadcaster, ChainSource, FilesystemLogger>;
 
 pub type NetworkGraph = gossip::NetworkGraph<Arc<FilesystemLogger>>;
 
+pub type Scorer = ProbabilisticScorer<Arc<NetworkGraph>, Arc<FilesystemLogger>>;
+
+pub type Router = DefaultRouter<
+    Arc<NetworkGraph>,
+    Arc<FilesystemLogger>,
+    Arc<KeysManager>,
+    Arc<RwLock<Scorer>>,
+    ProbabilisticScoringFeeParameters,
+    Scorer,
+>;
+
+pub type RapidGossipSync =
+    lightning_rapid_gossip_sync::RapidGossipSync<Arc<NetworkGraph>, Arc<FilesystemLogger>>;
+
 // Note that if you do not use an `OMDomainResolver` here you should use SimpleArcOnionMessenger
 // instead.
 pub type OnionMessenger = LdkOnionMessenger<
@@ -153,18 +163,18 @@ pub type OnionMessenger = LdkOnionMessenger<
 >;
 
 pub type BumpTxEventHandler = BumpTransactionEventHandler<
-    Arc<BitcoindClient>,
+    Arc<TrackedBroadcaster>,
     Arc<Wallet<Arc<BitcoindClient>, Arc<FilesystemLogger>>>,
     Arc<KeysManager>,
     Arc<FilesystemLogger>,
 >;
 
 pub type OutputSweeper = ldk_sweep::OutputSweeper<
-    Arc<BitcoindClient>,
-    Arc<BitcoindClient>,
-    Arc<BitcoindClient>,
+    Arc<TrackedBroadcaster>,
+    Arc<SweepPolicy>,
+    Arc<ChainSource>,
     Arc<dyn Filter + Send + Sync>,
-    Arc<FilesystemStore>,
+    Arc<DataStore>,
     Arc<FilesystemLogger>,
     Arc<KeysManager>,
 >;
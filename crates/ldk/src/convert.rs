@@ -3,6 +3,13 @@ use lightning_block_sync::http::JsonResponse;
 use std::convert::TryInto;
 use std::str::FromStr;
 
+fn invalid_field(field: &str) -> std::io::Error {
+	std::io::Error::new(
+		std::io::ErrorKind::InvalidData,
+		format!("missing or invalid `{}` in bitcoind response", field),
+	)
+}
+
 pub struct FundedTx {
 	pub changepos: i64,
 	pub hex: String,
@@ -12,8 +19,8 @@ impl TryInto<FundedTx> for JsonResponse {
 	type Error = std::io::Error;
 	fn try_into(self) -> std::io::Result<FundedTx> {
 		Ok(FundedTx {
-			changepos: self.0["changepos"].as_i64().unwrap(),
-			hex: self.0["hex"].as_str().unwrap().to_string(),
+			changepos: self.0["changepos"].as_i64().ok_or_else(|| invalid_field("changepos"))?,
+			hex: self.0["hex"].as_str().ok_or_else(|| invalid_field("hex"))?.to_string(),
 		})
 	}
 }
@@ -23,7 +30,7 @@ pub struct RawTx(pub String);
 impl TryInto<RawTx> for JsonResponse {
 	type Error = std::io::Error;
 	fn try_into(self) -> std::io::Result<RawTx> {
-		Ok(RawTx(self.0.as_str().unwrap().to_string()))
+		Ok(RawTx(self.0.as_str().ok_or_else(|| invalid_field("hex"))?.to_string()))
 	}
 }
 
@@ -36,8 +43,8 @@ impl TryInto<SignedTx> for JsonResponse {
 	type Error = std::io::Error;
 	fn try_into(self) -> std::io::Result<SignedTx> {
 		Ok(SignedTx {
-			hex: self.0["hex"].as_str().unwrap().to_string(),
-			complete: self.0["complete"].as_bool().unwrap(),
+			hex: self.0["hex"].as_str().ok_or_else(|| invalid_field("hex"))?.to_string(),
+			complete: self.0["complete"].as_bool().ok_or_else(|| invalid_field("complete"))?,
 		})
 	}
 }
@@ -46,7 +53,7 @@ pub struct NewAddress(pub String);
 impl TryInto<NewAddress> for JsonResponse {
 	type Error = std::io::Error;
 	fn try_into(self) -> std::io::Result<NewAddress> {
-		Ok(NewAddress(self.0.as_str().unwrap().to_string()))
+		Ok(NewAddress(self.0.as_str().ok_or_else(|| invalid_field("address"))?.to_string()))
 	}
 }
 
@@ -83,7 +90,6 @@ impl TryInto<MempoolMinFeeResponse> for JsonResponse {
 	type Error = std::io::Error;
 	fn try_into(self) -> std::io::Result<MempoolMinFeeResponse> {
 		let errored = !self.0["errors"].is_null();
-		assert_eq!(self.0["maxmempool"].as_u64(), Some(300000000));
 		Ok(MempoolMinFeeResponse {
 			errored,
 			feerate_sat_per_kw: match self.0["mempoolminfee"].as_f64() {
@@ -109,10 +115,41 @@ impl TryInto<BlockchainInfo> for JsonResponse {
 	type Error = std::io::Error;
 	fn try_into(self) -> std::io::Result<BlockchainInfo> {
 		Ok(BlockchainInfo {
-			latest_height: self.0["blocks"].as_u64().unwrap() as usize,
-			latest_blockhash: BlockHash::from_str(self.0["bestblockhash"].as_str().unwrap())
-				.unwrap(),
-			chain: self.0["chain"].as_str().unwrap().to_string(),
+			latest_height: self.0["blocks"].as_u64().ok_or_else(|| invalid_field("blocks"))?
+				as usize,
+			latest_blockhash: self.0["bestblockhash"]
+				.as_str()
+				.and_then(|hash| BlockHash::from_str(hash).ok())
+				.ok_or_else(|| invalid_field("bestblockhash"))?,
+			chain: self.0["chain"].as_str().ok_or_else(|| invalid_field("chain"))?.to_string(),
+		})
+	}
+}
+
+/// The balances of bitcoind's wallet, in satoshis, as reported by `getbalances`.
+pub struct WalletBalances {
+	/// Confirmed outputs, and unconfirmed ones the wallet created itself.
+	pub trusted_sats: u64,
+	/// Unconfirmed outputs from transactions created by others.
+	pub untrusted_pending_sats: u64,
+	/// Coinbase outputs which haven't matured yet.
+	pub immature_sats: u64,
+}
+
+impl TryInto<WalletBalances> for JsonResponse {
+	type Error = std::io::Error;
+	fn try_into(self) -> std::io::Result<WalletBalances> {
+		let sats = |field: &str| {
+			self.0["mine"][field]
+				.as_f64()
+				.and_then(|amount| bitcoin::Amount::from_btc(amount).ok())
+				.map(|amount| amount.to_sat())
+				.ok_or_else(|| invalid_field(field))
+		};
+		Ok(WalletBalances {
+			trusted_sats: sats("trusted")?,
+			untrusted_pending_sats: sats("untrusted_pending")?,
+			immature_sats: sats("immature")?,
 		})
 	}
 }
@@ -132,19 +169,28 @@ impl TryInto<ListUnspentResponse> for JsonResponse {
 		let utxos = self
 			.0
 			.as_array()
-			.unwrap()
+			.ok_or_else(|| invalid_field("utxos"))?
 			.iter()
-			.map(|utxo| ListUnspentUtxo {
-				txid: Txid::from_str(&utxo["txid"].as_str().unwrap().to_string()).unwrap(),
-				vout: utxo["vout"].as_u64().unwrap() as u32,
-				amount: bitcoin::Amount::from_btc(utxo["amount"].as_f64().unwrap())
-					.unwrap()
-					.to_sat(),
-				address: Address::from_str(&utxo["address"].as_str().unwrap().to_string())
-					.unwrap()
-					.assume_checked(), // the expected network is not known at this point
+			.map(|utxo| {
+				Ok(ListUnspentUtxo {
+					txid: utxo["txid"]
+						.as_str()
+						.and_then(|txid| Txid::from_str(txid).ok())
+						.ok_or_else(|| invalid_field("txid"))?,
+					vout: utxo["vout"].as_u64().ok_or_else(|| invalid_field("vout"))? as u32,
+					amount: utxo["amount"]
+						.as_f64()
+						.and_then(|amount| bitcoin::Amount::from_btc(amount).ok())
+						.ok_or_else(|| invalid_field("amount"))?
+						.to_sat(),
+					address: utxo["address"]
+						.as_str()
+						.and_then(|address| Address::from_str(address).ok())
+						.ok_or_else(|| invalid_field("address"))?
+						.assume_checked(), // the expected network is not known at this point
+				})
 			})
-			.collect();
+			.collect::<Result<Vec<_>, Self::Error>>()?;
 		Ok(ListUnspentResponse(utxos))
 	}
 }
@@ -0,0 +1,163 @@
+use crate::disk::LOCK_FNAME;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// An exclusive claim on a node's data directory, held for as long as the node runs.
+///
+/// Two nodes running on the same data directory would overwrite each other's state and could
+/// broadcast revoked commitment transactions, losing funds. The lock is a file in the data
+/// directory recording the PID and host of the process holding it, created atomically so only one
+/// process can ever succeed. It is removed once the lock is dropped. A process which crashes
+/// leaves the file behind. If it was on our host and isn't running anymore, the lock is taken
+/// over automatically. Otherwise, it has to be removed by acquiring the lock with `force` set.
+pub struct DataDirLock {
+	path: PathBuf,
+}
+
+impl DataDirLock {
+	/// Acquires the lock on `data_dir`, failing with [`io::ErrorKind::AlreadyExists`] if another
+	/// process holds it. With `force` set, a lock left behind is taken over instead.
+	pub fn acquire(data_dir: &Path, force: bool) -> io::Result<Self> {
+		let path = data_dir.join(LOCK_FNAME);
+		if force {
+			remove_lock_file(&path)?;
+		}
+
+		let mut removed_stale_lock = false;
+		let mut file = loop {
+			match OpenOptions::new().write(true).create_new(true).open(&path) {
+				Ok(file) => break file,
+				Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+					let contents = fs::read_to_string(&path).unwrap_or_default();
+					if !removed_stale_lock && is_stale(&contents) {
+						remove_lock_file(&path)?;
+						removed_stale_lock = true;
+						continue;
+					}
+					return Err(io::Error::new(
+						io::ErrorKind::AlreadyExists,
+						format!(
+							"{} is locked by {}",
+							data_dir.display(),
+							describe_owner(&contents)
+						),
+					));
+				},
+				Err(e) => return Err(e),
+			}
+		};
+		let lock = Self { path };
+		file.write_all(format!("{}\n{}\n", process::id(), hostname()).as_bytes())?;
+		file.sync_all()?;
+		Ok(lock)
+	}
+}
+
+impl Drop for DataDirLock {
+	fn drop(&mut self) {
+		let _ = fs::remove_file(&self.path);
+	}
+}
+
+fn remove_lock_file(path: &Path) -> io::Result<()> {
+	match fs::remove_file(path) {
+		Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+		_ => Ok(()),
+	}
+}
+
+/// Whether the lock was left behind by a process on our host which isn't running anymore.
+fn is_stale(lock_contents: &str) -> bool {
+	let mut lines = lock_contents.lines();
+	match (lines.next().map(str::parse::<u32>), lines.next()) {
+		(Some(Ok(pid)), Some(host)) => host == hostname() && !is_running(pid),
+		_ => false,
+	}
+}
+
+#[cfg(not(target_os = "windows"))]
+fn is_running(pid: u32) -> bool {
+	// Anything out of range for a PID wraps to a negative one, which can't be running.
+	let pid = pid as libc::pid_t;
+	if pid <= 0 {
+		return false;
+	}
+	// Signal 0 only checks whether the process exists. We may lack permission to signal it, but
+	// then it still exists.
+	let res = unsafe { libc::kill(pid, 0) };
+	res == 0 || io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+#[cfg(target_os = "windows")]
+fn is_running(_pid: u32) -> bool {
+	true
+}
+
+fn describe_owner(lock_contents: &str) -> String {
+	let mut lines = lock_contents.lines();
+	match (lines.next(), lines.next()) {
+		(Some(pid), Some(host)) => format!("PID {} on host {}", pid, host),
+		_ => "an unknown process".to_string(),
+	}
+}
+
+fn hostname() -> String {
+	let hostname = fs::read_to_string("/proc/sys/kernel/hostname")
+		.or_else(|_| fs::read_to_string("/etc/hostname"))
+		.ok()
+		.or_else(|| std::env::var("HOSTNAME").ok())
+		.or_else(|| std::env::var("COMPUTERNAME").ok());
+	match hostname.as_deref().map(str::trim) {
+		Some(hostname) if !hostname.is_empty() => hostname.to_string(),
+		_ => "unknown".to_string(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::test_data_dir;
+
+	#[test]
+	fn test_lock_is_exclusive() {
+		let data_dir = PathBuf::from(test_data_dir("data-dir-lock"));
+		fs::create_dir_all(&data_dir).unwrap();
+
+		let lock = DataDirLock::acquire(&data_dir, false).unwrap();
+		let err = DataDirLock::acquire(&data_dir, false).err().unwrap();
+		assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+		assert!(err.to_string().contains(&format!("PID {} on host", process::id())));
+
+		// Dropping the lock releases it.
+		drop(lock);
+		let lock = DataDirLock::acquire(&data_dir, false).unwrap();
+
+		// A lock left behind by a process which may still be running can only be taken over by
+		// force.
+		std::mem::forget(lock);
+		assert!(DataDirLock::acquire(&data_dir, false).is_err());
+		let _lock = DataDirLock::acquire(&data_dir, true).unwrap();
+		assert!(DataDirLock::acquire(&data_dir, false).is_err());
+	}
+
+	#[test]
+	fn test_stale_locks_are_taken_over() {
+		let data_dir = PathBuf::from(test_data_dir("data-dir-lock-stale"));
+		fs::create_dir_all(&data_dir).unwrap();
+		let path = data_dir.join(LOCK_FNAME);
+		// PIDs are never this high, so no process with it can be running.
+		let dead_pid = i32::MAX;
+
+		// A lock left behind on another host may still be held.
+		fs::write(&path, format!("{}\nother-host\n", dead_pid)).unwrap();
+		assert!(DataDirLock::acquire(&data_dir, false).is_err());
+
+		// One left behind on our host by a process which is gone is taken over.
+		fs::write(&path, format!("{}\n{}\n", dead_pid, hostname())).unwrap();
+		let _lock = DataDirLock::acquire(&data_dir, false).unwrap();
+		let contents = fs::read_to_string(&path).unwrap();
+		assert!(contents.starts_with(&format!("{}\n", process::id())));
+	}
+}
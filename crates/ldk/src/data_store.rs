@@ -0,0 +1,94 @@
+use crate::encrypted_store::EncryptedStore;
+use crate::replicating_store::ReplicatingStore;
+use crate::sqlite_store::SqliteStore;
+use lightning::io;
+use lightning::util::persist::{KVStore, MigratableKVStore};
+use lightning_persister::fs_store::FilesystemStore;
+use std::sync::Arc;
+
+/// The [`KVStore`] all of the node's state is persisted to.
+///
+/// Note that the keys seed, the channel peer list and the logs are always kept as plain files in
+/// the data directory, regardless of which store is selected. When encryption is enabled, the keys
+/// seed file is encrypted separately, see [`crate::encrypted_store::DataCipher`].
+pub enum DataStore {
+	/// Every entry is a file within the data directory.
+	Filesystem(FilesystemStore),
+	/// Every entry is a row in a SQLite database within the data directory.
+	Sqlite(SqliteStore),
+	/// One of the above, with every write mirrored to a backup.
+	Replicated(Arc<ReplicatingStore>),
+	/// One of the above, with every entry encrypted.
+	Encrypted(Box<EncryptedStore<DataStore>>),
+}
+
+impl KVStore for DataStore {
+	fn read(
+		&self, primary_namespace: &str, secondary_namespace: &str, key: &str,
+	) -> io::Result<Vec<u8>> {
+		match self {
+			DataStore::Filesystem(store) => store.read(primary_namespace, secondary_namespace, key),
+			DataStore::Sqlite(store) => store.read(primary_namespace, secondary_namespace, key),
+			DataStore::Replicated(store) => store.read(primary_namespace, secondary_namespace, key),
+			DataStore::Encrypted(store) => store.read(primary_namespace, secondary_namespace, key),
+		}
+	}
+
+	fn write(
+		&self, primary_namespace: &str, secondary_namespace: &str, key: &str, buf: &[u8],
+	) -> io::Result<()> {
+		match self {
+			DataStore::Filesystem(store) => {
+				store.write(primary_namespace, secondary_namespace, key, buf)
+			},
+			DataStore::Sqlite(store) => {
+				store.write(primary_namespace, secondary_namespace, key, buf)
+			},
+			DataStore::Replicated(store) => {
+				store.write(primary_namespace, secondary_namespace, key, buf)
+			},
+			DataStore::Encrypted(store) => {
+				store.write(primary_namespace, secondary_namespace, key, buf)
+			},
+		}
+	}
+
+	fn remove(
+		&self, primary_namespace: &str, secondary_namespace: &str, key: &str, lazy: bool,
+	) -> io::Result<()> {
+		match self {
+			DataStore::Filesystem(store) => {
+				store.remove(primary_namespace, secondary_namespace, key, lazy)
+			},
+			DataStore::Sqlite(store) => {
+				store.remove(primary_namespace, secondary_namespace, key, lazy)
+			},
+			DataStore::Replicated(store) => {
+				store.remove(primary_namespace, secondary_namespace, key, lazy)
+			},
+			DataStore::Encrypted(store) => {
+				store.remove(primary_namespace, secondary_namespace, key, lazy)
+			},
+		}
+	}
+
+	fn list(&self, primary_namespace: &str, secondary_namespace: &str) -> io::Result<Vec<String>> {
+		match self {
+			DataStore::Filesystem(store) => store.list(primary_namespace, secondary_namespace),
+			DataStore::Sqlite(store) => store.list(primary_namespace, secondary_namespace),
+			DataStore::Replicated(store) => store.list(primary_namespace, secondary_namespace),
+			DataStore::Encrypted(store) => store.list(primary_namespace, secondary_namespace),
+		}
+	}
+}
+
+impl MigratableKVStore for DataStore {
+	fn list_all_keys(&self) -> io::Result<Vec<(String, String, String)>> {
+		match self {
+			DataStore::Filesystem(store) => store.list_all_keys(),
+			DataStore::Sqlite(store) => store.list_all_keys(),
+			DataStore::Replicated(store) => store.list_all_keys(),
+			DataStore::Encrypted(store) => store.list_all_keys(),
+		}
+	}
+}
@@ -1,10 +1,14 @@
 use crate::common::{InboundPaymentInfoStorage, NetworkGraph, OutboundPaymentInfoStorage};
+use crate::data_store::DataStore;
 use bitcoin::secp256k1::PublicKey;
 use bitcoin::Network;
-use chrono::Utc;
 use lightning::routing::scoring::{ProbabilisticScorer, ProbabilisticScoringDecayParameters};
 use lightning::util::hash_tables::{new_hash_map, HashMap};
-use lightning::util::logger::{Level, Logger, Record};
+use lightning::util::persist::{
+	KVStore, NETWORK_GRAPH_PERSISTENCE_KEY, NETWORK_GRAPH_PERSISTENCE_PRIMARY_NAMESPACE,
+	NETWORK_GRAPH_PERSISTENCE_SECONDARY_NAMESPACE, SCORER_PERSISTENCE_KEY,
+	SCORER_PERSISTENCE_PRIMARY_NAMESPACE, SCORER_PERSISTENCE_SECONDARY_NAMESPACE,
+};
 use lightning::util::ser::{Readable, ReadableArgs};
 use std::fs;
 use std::fs::File;
@@ -14,47 +18,21 @@ use std::path::Path;
 use std::sync::Arc;
 use crate::hex_utils;
 
+pub const KEYS_SEED_FNAME: &str = "keys_seed";
+pub const ENCRYPTION_PARAMS_FNAME: &str = "encryption_params";
+pub const LOCK_FNAME: &str = "lock";
 pub const INBOUND_PAYMENTS_FNAME: &str = "inbound_payments";
 pub const OUTBOUND_PAYMENTS_FNAME: &str = "outbound_payments";
+pub const FEE_ESTIMATES_FNAME: &str = "fee_estimates";
+pub const BROADCAST_QUEUE_FNAME: &str = "broadcast_queue";
+pub const CLOSED_CHANNELS_FNAME: &str = "closed_channels";
+pub const SWEEP_DESTINATION_FNAME: &str = "sweep_destination";
+pub const FORWARDING_HISTORY_NAMESPACE: &str = "forwarding_history";
+pub const WEBHOOK_OUTBOX_NAMESPACE: &str = "webhook_outbox";
+pub const EVENT_SEQUENCE_FNAME: &str = "event_sequence";
+
+pub use crate::logging::FilesystemLogger;
 
-pub struct FilesystemLogger {
-	data_dir: String,
-}
-impl FilesystemLogger {
-	pub fn new(data_dir: String) -> Self {
-		let logs_path = format!("{}/logs", data_dir);
-		fs::create_dir_all(logs_path.clone()).unwrap();
-		Self { data_dir: logs_path }
-	}
-}
-impl Logger for FilesystemLogger {
-	fn log(&self, record: Record) {
-		if record.level == Level::Gossip {
-			// Gossip-level logs are incredibly verbose, and thus we skip them by default.
-			return;
-		}
-		let raw_log = record.args.to_string();
-		let log = format!(
-			"{} {:<5} [{}:{}] {}\n",
-			// Note that a "real" lightning node almost certainly does *not* want subsecond
-			// precision for message-receipt information as it makes log entries a target for
-			// deanonymization attacks. For testing, however, its quite useful.
-			Utc::now().format("%Y-%m-%d %H:%M:%S%.3f"),
-			record.level.to_string(),
-			record.module_path,
-			record.line,
-			raw_log
-		);
-		let logs_file_path = format!("{}/logs.txt", self.data_dir.clone());
-		fs::OpenOptions::new()
-			.create(true)
-			.append(true)
-			.open(logs_file_path)
-			.unwrap()
-			.write_all(log.as_bytes())
-			.unwrap();
-	}
-}
 pub fn persist_channel_peer(path: &Path, peer_info: &str) -> std::io::Result<()> {
 	let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
 	file.write_all(format!("{}\n", peer_info).as_bytes())
@@ -113,28 +91,32 @@ pub fn read_channel_peer_data(
 }
 
 pub fn read_network(
-	path: &Path, network: Network, logger: Arc<FilesystemLogger>,
+	store: &DataStore, network: Network, logger: Arc<FilesystemLogger>,
 ) -> NetworkGraph {
-	if let Ok(file) = File::open(path) {
-		if let Ok(graph) = NetworkGraph::read(&mut BufReader::new(file), logger.clone()) {
+	if let Ok(bytes) = store.read(
+		NETWORK_GRAPH_PERSISTENCE_PRIMARY_NAMESPACE,
+		NETWORK_GRAPH_PERSISTENCE_SECONDARY_NAMESPACE,
+		NETWORK_GRAPH_PERSISTENCE_KEY,
+	) {
+		if let Ok(graph) = NetworkGraph::read(&mut &bytes[..], logger.clone()) {
 			return graph;
 		}
 	}
 	NetworkGraph::new(network, logger)
 }
 
-pub fn read_inbound_payment_info(path: &Path) -> InboundPaymentInfoStorage {
-	if let Ok(file) = File::open(path) {
-		if let Ok(info) = InboundPaymentInfoStorage::read(&mut BufReader::new(file)) {
+pub fn read_inbound_payment_info(store: &DataStore) -> InboundPaymentInfoStorage {
+	if let Ok(bytes) = store.read("", "", INBOUND_PAYMENTS_FNAME) {
+		if let Ok(info) = InboundPaymentInfoStorage::read(&mut &bytes[..]) {
 			return info;
 		}
 	}
 	InboundPaymentInfoStorage { payments: new_hash_map() }
 }
 
-pub fn read_outbound_payment_info(path: &Path) -> OutboundPaymentInfoStorage {
-	if let Ok(file) = File::open(path) {
-		if let Ok(info) = OutboundPaymentInfoStorage::read(&mut BufReader::new(file)) {
+pub fn read_outbound_payment_info(store: &DataStore) -> OutboundPaymentInfoStorage {
+	if let Ok(bytes) = store.read("", "", OUTBOUND_PAYMENTS_FNAME) {
+		if let Ok(info) = OutboundPaymentInfoStorage::read(&mut &bytes[..]) {
 			return info;
 		}
 	}
@@ -142,12 +124,16 @@ pub fn read_outbound_payment_info(path: &Path) -> OutboundPaymentInfoStorage {
 }
 
 pub fn read_scorer(
-	path: &Path, graph: Arc<NetworkGraph>, logger: Arc<FilesystemLogger>,
+	store: &DataStore, params: ProbabilisticScoringDecayParameters, graph: Arc<NetworkGraph>,
+	logger: Arc<FilesystemLogger>,
 ) -> ProbabilisticScorer<Arc<NetworkGraph>, Arc<FilesystemLogger>> {
-	let params = ProbabilisticScoringDecayParameters::default();
-	if let Ok(file) = File::open(path) {
-		let args = (params.clone(), Arc::clone(&graph), Arc::clone(&logger));
-		if let Ok(scorer) = ProbabilisticScorer::read(&mut BufReader::new(file), args) {
+	if let Ok(bytes) = store.read(
+		SCORER_PERSISTENCE_PRIMARY_NAMESPACE,
+		SCORER_PERSISTENCE_SECONDARY_NAMESPACE,
+		SCORER_PERSISTENCE_KEY,
+	) {
+		let args = (params, Arc::clone(&graph), Arc::clone(&logger));
+		if let Ok(scorer) = ProbabilisticScorer::read(&mut &bytes[..], args) {
 			return scorer;
 		}
 	}
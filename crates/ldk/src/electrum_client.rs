@@ -0,0 +1,285 @@
+use crate::disk::FilesystemLogger;
+use crate::fee_policy::{FeeEstimates, FeeSource};
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::consensus::encode;
+use electrum_client::{Client, ElectrumApi};
+use lightning::chain::chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator};
+use lightning::chain::{BestBlock, Confirm, Filter};
+use lightning::util::logger::Logger;
+use lightning::{log_error, log_info};
+use lightning_transaction_sync::{ElectrumSyncClient, TxSyncError};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio::runtime;
+
+/// A chain backend talking the Electrum protocol to an Electrum server.
+///
+/// Like [`crate::esplora_client::EsploraClient`], chain data is fed to LDK through the
+/// transaction-based [`Confirm`] interface: the script hashes of everything the `ChainMonitor` and
+/// `OutputSweeper` register via the [`Filter`] returned by [`ElectrumClient::filter`] are looked
+/// up on each sync. Fee estimation and broadcasting go over the same server connection.
+///
+/// The underlying client is blocking, so all calls into it are made from tokio's blocking pool.
+pub struct ElectrumClient {
+	sync_client: Arc<ElectrumSyncClient<Arc<FilesystemLogger>>>,
+	fee_estimates: Arc<FeeEstimates>,
+	main_runtime_handle: runtime::Handle,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl ElectrumClient {
+	pub async fn new(
+		server_url: String, fee_estimates: Arc<FeeEstimates>, handle: runtime::Handle,
+		logger: Arc<FilesystemLogger>,
+	) -> std::io::Result<Self> {
+		let sync_logger = Arc::clone(&logger);
+		let sync_client = tokio::task::spawn_blocking(move || {
+			let sync_client = ElectrumSyncClient::new(server_url, sync_logger).ok()?;
+			sync_client.client().block_headers_subscribe().ok()?;
+			Some(sync_client)
+		})
+		.await
+		.unwrap()
+		.ok_or_else(|| {
+			std::io::Error::new(
+				std::io::ErrorKind::NotConnected,
+				"Failed to make initial call to the Electrum server - please check its URL",
+			)
+		})?;
+		let client = Self {
+			sync_client: Arc::new(sync_client),
+			fee_estimates,
+			main_runtime_handle: handle.clone(),
+			logger,
+		};
+		client.update_fee_estimates().await;
+		ElectrumClient::poll_for_fee_estimates(
+			client.fee_estimates.clone(),
+			client.sync_client.clone(),
+			client.logger.clone(),
+			handle,
+		);
+		Ok(client)
+	}
+
+	/// Returns the [`Filter`] LDK should register transactions and outputs with so that they are
+	/// picked up by [`ElectrumClient::sync`].
+	pub fn filter(&self) -> Arc<dyn Filter + Send + Sync> {
+		self.sync_client.clone()
+	}
+
+	/// Synchronizes the given `confirmables` with the current state of the chain.
+	pub async fn sync(
+		&self, confirmables: Vec<Arc<dyn Confirm + Send + Sync>>,
+	) -> Result<(), TxSyncError> {
+		let sync_client = Arc::clone(&self.sync_client);
+		tokio::task::spawn_blocking(move || sync_client.sync(confirmables)).await.unwrap()
+	}
+
+	/// Fetches the current chain tip, which a fresh node starts from.
+	pub async fn get_best_block(&self) -> Result<BestBlock, electrum_client::Error> {
+		let sync_client = Arc::clone(&self.sync_client);
+		let tip =
+			tokio::task::spawn_blocking(move || sync_client.client().block_headers_subscribe())
+				.await
+				.unwrap()?;
+		Ok(BestBlock::new(tip.header.block_hash(), tip.height as u32))
+	}
+
+	pub(crate) async fn update_fee_estimates(&self) {
+		let fee_estimates = Arc::clone(&self.fee_estimates);
+		let sync_client = Arc::clone(&self.sync_client);
+		let logger = Arc::clone(&self.logger);
+		tokio::task::spawn_blocking(move || {
+			ElectrumClient::fetch_fee_estimates(&fee_estimates, sync_client.client(), &logger)
+		})
+		.await
+		.unwrap()
+	}
+
+	fn fetch_fee_estimates(
+		fee_estimates: &FeeEstimates, client: &Client, logger: &FilesystemLogger,
+	) {
+		let mut feerates = HashMap::new();
+		for source in fee_estimates.policy().sources() {
+			let feerate_btc_per_kvbyte = match source {
+				FeeSource::MempoolMinimum => client.relay_fee(),
+				FeeSource::Estimate { blocks, .. } => client.estimate_fee(blocks as usize),
+			};
+			let feerate_btc_per_kvbyte = match feerate_btc_per_kvbyte {
+				Ok(feerate) => feerate,
+				Err(e) => {
+					log_error!(logger, "Failed to fetch fee estimates from Electrum: {}", e);
+					return;
+				},
+			};
+			// Electrum servers give us feerates in BTC/KvB, which we need to convert to
+			// satoshis/KW. Thus, we first multiply by 10^8 to get satoshis, then divide by 4 to
+			// convert virtual-bytes into weight units. Servers without an estimate for a target
+			// return -1.
+			if feerate_btc_per_kvbyte > 0.0 {
+				let feerate_sat_per_kw = (feerate_btc_per_kvbyte * 100_000_000.0 / 4.0).round();
+				feerates.insert(source, feerate_sat_per_kw as u32);
+			}
+		}
+		fee_estimates.update(&feerates);
+	}
+
+	fn poll_for_fee_estimates(
+		fee_estimates: Arc<FeeEstimates>,
+		sync_client: Arc<ElectrumSyncClient<Arc<FilesystemLogger>>>, logger: Arc<FilesystemLogger>,
+		handle: runtime::Handle,
+	) {
+		handle.spawn(async move {
+			loop {
+				tokio::time::sleep(fee_estimates.policy().poll_interval).await;
+				let fee_estimates = Arc::clone(&fee_estimates);
+				let sync_client = Arc::clone(&sync_client);
+				let logger = Arc::clone(&logger);
+				let _ = tokio::task::spawn_blocking(move || {
+					ElectrumClient::fetch_fee_estimates(
+						&fee_estimates,
+						sync_client.client(),
+						&logger,
+					);
+					fee_estimates.warn_if_stale();
+				})
+				.await;
+			}
+		});
+	}
+}
+
+impl FeeEstimator for ElectrumClient {
+	fn get_est_sat_per_1000_weight(&self, confirmation_target: ConfirmationTarget) -> u32 {
+		self.fee_estimates.get_est_sat_per_1000_weight(confirmation_target)
+	}
+}
+
+impl BroadcasterInterface for ElectrumClient {
+	fn broadcast_transactions(&self, txs: &[&Transaction]) {
+		// The Electrum protocol has no package relay, so we submit the transactions one by one in
+		// the order LDK hands them to us, which ensures parents are broadcast before their
+		// children.
+		let txn = txs.iter().map(|tx| (*tx).clone()).collect::<Vec<_>>();
+		let sync_client = Arc::clone(&self.sync_client);
+		let logger = Arc::clone(&self.logger);
+		self.main_runtime_handle.spawn_blocking(move || {
+			for tx in txn {
+				// This may error due to RL calling `broadcast_transactions` with the same
+				// transaction multiple times, but the error is safe to ignore.
+				match sync_client.client().transaction_broadcast(&tx) {
+					Ok(txid) => {
+						log_info!(logger, "Broadcast transaction {} via Electrum", txid);
+					},
+					Err(e) => {
+						log_error!(logger,
+							"Warning, failed to broadcast a transaction, this is likely okay but may indicate an error: {}\nTransaction: {}",
+							e,
+							encode::serialize_hex(&tx));
+						print!("Warning, failed to broadcast a transaction, this is likely okay but may indicate an error: {}\n> ", e);
+					},
+				}
+			}
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::{test_data_dir, test_fee_estimates, MockElectrumServer};
+	use bitcoin::absolute::LockTime;
+	use bitcoin::transaction::Version;
+	use serde_json::json;
+
+	// The regtest genesis block header.
+	const TIP_HEADER_HEX: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4adae5494dffff7f2002000000";
+
+	fn test_logger() -> Arc<FilesystemLogger> {
+		let dir = std::env::temp_dir().join(format!("electrum-client-test-{}", std::process::id()));
+		Arc::new(FilesystemLogger::new(dir.to_str().unwrap().to_string()))
+	}
+
+	fn fee_estimates() -> Arc<FeeEstimates> {
+		test_fee_estimates(&test_data_dir("electrum-client"))
+	}
+
+	fn respond(method: &str, params: &serde_json::Value) -> serde_json::Value {
+		match method {
+			"blockchain.headers.subscribe" => json!({ "hex": TIP_HEADER_HEX, "height": 0 }),
+			"blockchain.relayfee" => json!(0.00001),
+			"blockchain.estimatefee" => match params[0].as_u64() {
+				Some(2) => json!(0.0002),
+				Some(6) => json!(0.0001),
+				Some(18) => json!(-1),
+				_ => json!(0.00002),
+			},
+			"blockchain.transaction.broadcast" => {
+				json!("0000000000000000000000000000000000000000000000000000000000000000")
+			},
+			_ => serde_json::Value::Null,
+		}
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_fee_estimates_are_converted() {
+		let server = MockElectrumServer::start(respond).await;
+		let client = ElectrumClient::new(
+			server.url(),
+			fee_estimates(),
+			tokio::runtime::Handle::current(),
+			test_logger(),
+		)
+		.await
+		.unwrap();
+
+		assert_eq!(
+			client.get_est_sat_per_1000_weight(ConfirmationTarget::MaximumFeeEstimate),
+			5000
+		);
+		assert_eq!(
+			client.get_est_sat_per_1000_weight(ConfirmationTarget::UrgentOnChainSweep),
+			2500
+		);
+		// The server has no estimate for 18 blocks, so we keep our fallback.
+		assert_eq!(
+			client.get_est_sat_per_1000_weight(ConfirmationTarget::NonAnchorChannelFee),
+			2000
+		);
+		assert_eq!(client.get_est_sat_per_1000_weight(ConfirmationTarget::AnchorChannelFee), 500);
+		assert_eq!(
+			client
+				.get_est_sat_per_1000_weight(ConfirmationTarget::MinAllowedAnchorChannelRemoteFee),
+			253
+		);
+
+		let best_block = client.get_best_block().await.unwrap();
+		assert_eq!(best_block.height, 0);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_broadcast_sends_raw_tx() {
+		let server = MockElectrumServer::start(respond).await;
+		let client = ElectrumClient::new(
+			server.url(),
+			fee_estimates(),
+			tokio::runtime::Handle::current(),
+			test_logger(),
+		)
+		.await
+		.unwrap();
+
+		let tx = Transaction {
+			version: Version::TWO,
+			lock_time: LockTime::ZERO,
+			input: Vec::new(),
+			output: Vec::new(),
+		};
+		client.broadcast_transactions(&[&tx]);
+
+		let params = server.wait_for_request("blockchain.transaction.broadcast").await;
+		assert_eq!(params, json!([encode::serialize_hex(&tx)]));
+	}
+}
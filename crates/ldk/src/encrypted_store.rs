@@ -0,0 +1,403 @@
+use crate::data_store::DataStore;
+use crate::disk::{ENCRYPTION_PARAMS_FNAME, KEYS_SEED_FNAME};
+use crate::sqlite_store;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use lightning::impl_writeable_tlv_based;
+use lightning::io;
+use lightning::util::persist::{KVStore, MigratableKVStore};
+use lightning::util::ser::{Readable, Writeable};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// Prepended to everything we encrypt, so encrypted data can be told apart from plaintext.
+const MAGIC: [u8; 4] = *b"LDKE";
+const FORMAT_VERSION: u8 = 1;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + 1 + NONCE_LEN;
+
+const KEY_CHECK_AAD: &[u8] = b"key_check";
+
+/// How the data directory's encryption key is derived from the passphrase or keyfile, and whether
+/// all of the data written before encryption was enabled has been encrypted yet.
+///
+/// Stored in plaintext next to the keys seed, as it is needed before anything can be decrypted.
+struct EncryptionParams {
+	salt: [u8; 16],
+	m_cost: u32,
+	t_cost: u32,
+	p_cost: u32,
+	/// An empty plaintext encrypted under the key, to tell a wrong passphrase apart from
+	/// corrupted data.
+	key_check: Vec<u8>,
+	existing_data_encrypted: bool,
+}
+
+impl_writeable_tlv_based!(EncryptionParams, {
+	(0, salt, required),
+	(2, m_cost, required),
+	(4, t_cost, required),
+	(6, p_cost, required),
+	(8, key_check, required_vec),
+	(10, existing_data_encrypted, required),
+});
+
+/// Encrypts and authenticates the node's data with XChaCha20-Poly1305 under a key derived from a
+/// passphrase or keyfile with Argon2id.
+///
+/// Every piece of data is bound to the name it is stored under, so entries can't be swapped for
+/// one another without being noticed.
+#[derive(Clone)]
+pub struct DataCipher {
+	cipher: XChaCha20Poly1305,
+}
+
+impl DataCipher {
+	fn encrypt(&self, aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+		let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+		let ciphertext = self
+			.cipher
+			.encrypt(&nonce, Payload { msg: plaintext, aad })
+			.expect("Encryption only fails for plaintexts far larger than we ever write");
+		let mut data = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+		data.extend_from_slice(&MAGIC);
+		data.push(FORMAT_VERSION);
+		data.extend_from_slice(&nonce);
+		data.extend_from_slice(&ciphertext);
+		data
+	}
+
+	fn decrypt(&self, aad: &[u8], data: &[u8]) -> io::Result<Vec<u8>> {
+		if !is_encrypted(data) {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "Data is not encrypted"));
+		}
+		if data[MAGIC.len()] != FORMAT_VERSION {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("Unknown encryption format version {}", data[MAGIC.len()]),
+			));
+		}
+		let nonce = XNonce::from_slice(&data[MAGIC.len() + 1..HEADER_LEN]);
+		self.cipher.decrypt(nonce, Payload { msg: &data[HEADER_LEN..], aad }).map_err(|_| {
+			io::Error::new(
+				io::ErrorKind::InvalidData,
+				"Failed to decrypt data, it was either tampered with or encrypted under another key",
+			)
+		})
+	}
+
+	/// Encrypts the node's keys seed for storage in [`KEYS_SEED_FNAME`].
+	pub fn encrypt_keys_seed(&self, seed: &[u8; 32]) -> Vec<u8> {
+		self.encrypt(KEYS_SEED_FNAME.as_bytes(), seed)
+	}
+
+	/// Decrypts the contents of [`KEYS_SEED_FNAME`] written by [`Self::encrypt_keys_seed`].
+	pub fn decrypt_keys_seed(&self, data: &[u8]) -> io::Result<[u8; 32]> {
+		let seed = self.decrypt(KEYS_SEED_FNAME.as_bytes(), data)?;
+		let mut key = [0; 32];
+		if seed.len() != key.len() {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid keys seed length"));
+		}
+		key.copy_from_slice(&seed);
+		Ok(key)
+	}
+}
+
+/// Returns whether `data` was written by a [`DataCipher`].
+pub fn is_encrypted(data: &[u8]) -> bool {
+	data.len() >= HEADER_LEN && data.starts_with(&MAGIC)
+}
+
+/// Returns whether encryption was ever enabled for the data directory at `data_dir`.
+pub fn is_encryption_enabled(data_dir: &Path) -> bool {
+	data_dir.join(ENCRYPTION_PARAMS_FNAME).exists()
+}
+
+/// Derives the data directory's [`DataCipher`] from `secret`, the passphrase or keyfile contents.
+///
+/// The first time this is called for a data directory, a fresh salt is generated and the
+/// parameters needed to derive the same key again are stored in it. Afterwards, an error of kind
+/// [`io::ErrorKind::PermissionDenied`] is returned if `secret` doesn't match.
+pub fn unlock(data_dir: &Path, secret: &[u8]) -> io::Result<DataCipher> {
+	unlock_with_kdf_params(data_dir, secret, Params::default())
+}
+
+fn unlock_with_kdf_params(
+	data_dir: &Path, secret: &[u8], new_params: Params,
+) -> io::Result<DataCipher> {
+	if let Some(params) = read_params(data_dir)? {
+		let cipher = derive_cipher(secret, &params)?;
+		return match cipher.decrypt(KEY_CHECK_AAD, &params.key_check) {
+			Ok(_) => Ok(cipher),
+			Err(_) => Err(io::Error::new(
+				io::ErrorKind::PermissionDenied,
+				"Wrong passphrase or keyfile for this data directory",
+			)),
+		};
+	}
+
+	let mut salt = [0; 16];
+	OsRng.fill_bytes(&mut salt);
+	let mut params = EncryptionParams {
+		salt,
+		m_cost: new_params.m_cost(),
+		t_cost: new_params.t_cost(),
+		p_cost: new_params.p_cost(),
+		key_check: Vec::new(),
+		existing_data_encrypted: false,
+	};
+	let cipher = derive_cipher(secret, &params)?;
+	params.key_check = cipher.encrypt(KEY_CHECK_AAD, &[]);
+	write_params(data_dir, &params)?;
+	Ok(cipher)
+}
+
+/// Encrypts the keys seed and every entry of `store` which were written before encryption was
+/// enabled for the data directory at `data_dir`, returning how many entries were encrypted.
+///
+/// `store` must be the unwrapped store, not an [`EncryptedStore`]. Once everything is encrypted,
+/// this is recorded in the data directory and later calls return immediately. If interrupted, it
+/// picks up where it left off, as anything already encrypted is skipped.
+pub fn encrypt_existing_data(
+	data_dir: &Path, store: &DataStore, cipher: &DataCipher,
+) -> io::Result<usize> {
+	let mut params = match read_params(data_dir)? {
+		Some(params) => params,
+		None => return Err(io::Error::new(io::ErrorKind::NotFound, "Encryption is not enabled")),
+	};
+	if params.existing_data_encrypted {
+		return Ok(0);
+	}
+
+	let seed_path = data_dir.join(KEYS_SEED_FNAME);
+	if let Ok(seed) = fs::read(&seed_path) {
+		if !is_encrypted(&seed) {
+			if seed.len() != 32 {
+				return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid keys seed length"));
+			}
+			let mut key = [0; 32];
+			key.copy_from_slice(&seed);
+			write_file_atomically(&seed_path, &cipher.encrypt_keys_seed(&key))?;
+		}
+	}
+
+	let keys = list_keys(data_dir, store)?;
+	let mut encrypted = 0;
+	for (primary_namespace, secondary_namespace, key) in keys {
+		let value = store.read(&primary_namespace, &secondary_namespace, &key)?;
+		if is_encrypted(&value) {
+			continue;
+		}
+		let aad = entry_aad(&primary_namespace, &secondary_namespace, &key);
+		store.write(
+			&primary_namespace,
+			&secondary_namespace,
+			&key,
+			&cipher.encrypt(&aad, &value),
+		)?;
+		encrypted += 1;
+	}
+
+	params.existing_data_encrypted = true;
+	write_params(data_dir, &params)?;
+	Ok(encrypted)
+}
+
+/// Lists the keys of every entry `store` holds, which keeps them in `data_dir`.
+pub(crate) fn list_keys(
+	data_dir: &Path, store: &DataStore,
+) -> io::Result<Vec<(String, String, String)>> {
+	match store {
+		DataStore::Filesystem(_) => Ok(sqlite_store::read_filesystem_entries(data_dir)?
+			.into_iter()
+			.map(|(primary_namespace, secondary_namespace, key, _)| {
+				(primary_namespace, secondary_namespace, key)
+			})
+			.collect()),
+		DataStore::Sqlite(store) => store.list_all_keys(),
+		DataStore::Replicated(store) => list_keys(data_dir, store.primary()),
+		DataStore::Encrypted(_) => Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"The store's entries are encrypted, list those of the store it wraps instead",
+		)),
+	}
+}
+
+/// A [`KVStore`] encrypting every entry before handing it to the wrapped store, and decrypting it
+/// when read back. Namespaces and keys are passed through as-is.
+pub struct EncryptedStore<S: KVStore> {
+	inner: S,
+	cipher: DataCipher,
+}
+
+impl<S: KVStore> EncryptedStore<S> {
+	pub fn new(inner: S, cipher: DataCipher) -> Self {
+		Self { inner, cipher }
+	}
+}
+
+impl<S: KVStore> KVStore for EncryptedStore<S> {
+	fn read(
+		&self, primary_namespace: &str, secondary_namespace: &str, key: &str,
+	) -> io::Result<Vec<u8>> {
+		let data = self.inner.read(primary_namespace, secondary_namespace, key)?;
+		let aad = entry_aad(primary_namespace, secondary_namespace, key);
+		self.cipher.decrypt(&aad, &data).map_err(|e| {
+			io::Error::new(
+				e.kind(),
+				format!("{}/{}/{}: {}", primary_namespace, secondary_namespace, key, e),
+			)
+		})
+	}
+
+	fn write(
+		&self, primary_namespace: &str, secondary_namespace: &str, key: &str, buf: &[u8],
+	) -> io::Result<()> {
+		let aad = entry_aad(primary_namespace, secondary_namespace, key);
+		let data = self.cipher.encrypt(&aad, buf);
+		self.inner.write(primary_namespace, secondary_namespace, key, &data)
+	}
+
+	fn remove(
+		&self, primary_namespace: &str, secondary_namespace: &str, key: &str, lazy: bool,
+	) -> io::Result<()> {
+		self.inner.remove(primary_namespace, secondary_namespace, key, lazy)
+	}
+
+	fn list(&self, primary_namespace: &str, secondary_namespace: &str) -> io::Result<Vec<String>> {
+		self.inner.list(primary_namespace, secondary_namespace)
+	}
+}
+
+impl<S: MigratableKVStore> MigratableKVStore for EncryptedStore<S> {
+	fn list_all_keys(&self) -> io::Result<Vec<(String, String, String)>> {
+		self.inner.list_all_keys()
+	}
+}
+
+fn entry_aad(primary_namespace: &str, secondary_namespace: &str, key: &str) -> Vec<u8> {
+	format!("{}/{}/{}", primary_namespace, secondary_namespace, key).into_bytes()
+}
+
+fn derive_cipher(secret: &[u8], params: &EncryptionParams) -> io::Result<DataCipher> {
+	let kdf_params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(32))
+		.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+	let mut key = [0; 32];
+	Argon2::new(Algorithm::Argon2id, Version::V0x13, kdf_params)
+		.hash_password_into(secret, &params.salt, &mut key)
+		.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+	Ok(DataCipher { cipher: XChaCha20Poly1305::new(&key.into()) })
+}
+
+fn read_params(data_dir: &Path) -> io::Result<Option<EncryptionParams>> {
+	let bytes = match fs::read(data_dir.join(ENCRYPTION_PARAMS_FNAME)) {
+		Ok(bytes) => bytes,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+		Err(e) => return Err(e.into()),
+	};
+	match EncryptionParams::read(&mut &bytes[..]) {
+		Ok(params) => Ok(Some(params)),
+		Err(e) => Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("Failed to read {}: {:?}", ENCRYPTION_PARAMS_FNAME, e),
+		)),
+	}
+}
+
+fn write_params(data_dir: &Path, params: &EncryptionParams) -> io::Result<()> {
+	write_file_atomically(&data_dir.join(ENCRYPTION_PARAMS_FNAME), &params.encode())
+}
+
+fn write_file_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+	let tmp_path = path.with_extension("tmp");
+	let mut file = fs::File::create(&tmp_path)?;
+	file.write_all(data)?;
+	file.sync_all()?;
+	fs::rename(&tmp_path, path)?;
+	if let Some(parent) = path.parent() {
+		fs::File::open(parent)?.sync_all()?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::test_data_dir;
+	use lightning_persister::fs_store::FilesystemStore;
+	use std::path::PathBuf;
+
+	// Far cheaper than the defaults, to keep the tests fast.
+	fn test_kdf_params() -> Params {
+		Params::new(Params::MIN_M_COST, 1, 1, None).unwrap()
+	}
+
+	#[test]
+	fn test_entries_are_encrypted_and_bound_to_their_key() {
+		let data_dir = PathBuf::from(test_data_dir("encrypted-store"));
+		fs::create_dir_all(&data_dir).unwrap();
+		let cipher = unlock_with_kdf_params(&data_dir, b"passphrase", test_kdf_params()).unwrap();
+		let store = EncryptedStore::new(FilesystemStore::new(data_dir.clone()), cipher);
+
+		store.write("", "", "inbound_payments", b"preimage").unwrap();
+		store.write("monitors", "", "chan_a", b"monitor").unwrap();
+		assert_eq!(store.read("", "", "inbound_payments").unwrap(), b"preimage".to_vec());
+		assert_eq!(store.list("monitors", "").unwrap(), vec!["chan_a".to_string()]);
+
+		let raw = fs::read(data_dir.join("inbound_payments")).unwrap();
+		assert!(is_encrypted(&raw));
+		assert!(!raw.windows(8).any(|window| window == b"preimage"));
+
+		// An entry moved under another key no longer decrypts.
+		fs::write(data_dir.join("monitors").join("chan_b"), &raw).unwrap();
+		assert_eq!(
+			store.read("monitors", "", "chan_b").unwrap_err().kind(),
+			io::ErrorKind::InvalidData
+		);
+	}
+
+	#[test]
+	fn test_unlock_requires_the_same_secret() {
+		let data_dir = PathBuf::from(test_data_dir("encrypted-store-unlock"));
+		fs::create_dir_all(&data_dir).unwrap();
+		assert!(!is_encryption_enabled(&data_dir));
+		let cipher = unlock_with_kdf_params(&data_dir, b"passphrase", test_kdf_params()).unwrap();
+		assert!(is_encryption_enabled(&data_dir));
+		let encrypted_seed = cipher.encrypt_keys_seed(&[7; 32]);
+
+		let err = unlock(&data_dir, b"wrong passphrase").err().unwrap();
+		assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+		let cipher = unlock(&data_dir, b"passphrase").unwrap();
+		assert_eq!(cipher.decrypt_keys_seed(&encrypted_seed).unwrap(), [7; 32]);
+	}
+
+	#[test]
+	fn test_encrypt_existing_data() {
+		let data_dir = PathBuf::from(test_data_dir("encrypted-store-existing"));
+		fs::create_dir_all(&data_dir).unwrap();
+		let store = DataStore::Filesystem(FilesystemStore::new(data_dir.clone()));
+		fs::write(data_dir.join(KEYS_SEED_FNAME), [3; 32]).unwrap();
+		store.write("", "", "manager", &[1, 2, 3]).unwrap();
+		store.write("monitors", "", "chan_a", &[4]).unwrap();
+
+		let cipher = unlock_with_kdf_params(&data_dir, b"passphrase", test_kdf_params()).unwrap();
+		// Pretend we were interrupted after encrypting the manager.
+		let aad = entry_aad("", "", "manager");
+		store.write("", "", "manager", &cipher.encrypt(&aad, &[1, 2, 3])).unwrap();
+		assert_eq!(encrypt_existing_data(&data_dir, &store, &cipher).unwrap(), 1);
+
+		let seed = fs::read(data_dir.join(KEYS_SEED_FNAME)).unwrap();
+		assert_eq!(cipher.decrypt_keys_seed(&seed).unwrap(), [3; 32]);
+		let store = EncryptedStore::new(store, cipher.clone());
+		assert_eq!(store.read("", "", "manager").unwrap(), vec![1, 2, 3]);
+		assert_eq!(store.read("monitors", "", "chan_a").unwrap(), vec![4]);
+
+		// Only ever done once.
+		let store = DataStore::Filesystem(FilesystemStore::new(data_dir.clone()));
+		assert_eq!(encrypt_existing_data(&data_dir, &store, &cipher).unwrap(), 0);
+	}
+}
@@ -0,0 +1,249 @@
+use crate::disk::FilesystemLogger;
+use crate::fee_policy::{FeeEstimates, FeeSource};
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::consensus::encode;
+use esplora_client::AsyncClient;
+use lightning::chain::chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator};
+use lightning::chain::{BestBlock, Confirm, Filter};
+use lightning::util::logger::Logger;
+use lightning::{log_error, log_info};
+use lightning_transaction_sync::{EsploraSyncClient, TxSyncError};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio::runtime;
+
+/// A chain backend talking to an Esplora HTTP server.
+///
+/// Chain data is fed to LDK through the transaction-based [`Confirm`] interface rather than by
+/// connecting full blocks, which means the [`Filter`] returned by [`EsploraClient::filter`] has to
+/// be handed to the `ChainMonitor` and `OutputSweeper` so they can register what to watch.
+pub struct EsploraClient {
+	sync_client: Arc<EsploraSyncClient<Arc<FilesystemLogger>>>,
+	fee_estimates: Arc<FeeEstimates>,
+	main_runtime_handle: runtime::Handle,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl EsploraClient {
+	pub async fn new(
+		server_url: String, fee_estimates: Arc<FeeEstimates>, handle: runtime::Handle,
+		logger: Arc<FilesystemLogger>,
+	) -> std::io::Result<Self> {
+		let sync_client = EsploraSyncClient::new(server_url, Arc::clone(&logger));
+		let _dummy = sync_client.client().get_height().await.map_err(|_| {
+			std::io::Error::new(
+				std::io::ErrorKind::NotConnected,
+				"Failed to make initial call to the Esplora server - please check its URL",
+			)
+		})?;
+		let client = Self {
+			sync_client: Arc::new(sync_client),
+			fee_estimates,
+			main_runtime_handle: handle.clone(),
+			logger,
+		};
+		client.update_fee_estimates().await;
+		EsploraClient::poll_for_fee_estimates(
+			client.fee_estimates.clone(),
+			client.sync_client.clone(),
+			client.logger.clone(),
+			handle,
+		);
+		Ok(client)
+	}
+
+	/// Returns the [`Filter`] LDK should register transactions and outputs with so that they are
+	/// picked up by [`EsploraClient::sync`].
+	pub fn filter(&self) -> Arc<dyn Filter + Send + Sync> {
+		self.sync_client.clone()
+	}
+
+	/// Synchronizes the given `confirmables` with the current state of the chain.
+	pub async fn sync(
+		&self, confirmables: Vec<Arc<dyn Confirm + Send + Sync>>,
+	) -> Result<(), TxSyncError> {
+		self.sync_client.sync(confirmables).await
+	}
+
+	/// Fetches the current chain tip, which a fresh node starts from.
+	pub async fn get_best_block(&self) -> Result<BestBlock, esplora_client::Error> {
+		let client = self.sync_client.client();
+		let block_hash = client.get_tip_hash().await?;
+		let height = client.get_block_status(&block_hash).await?.height.unwrap_or(0);
+		Ok(BestBlock::new(block_hash, height))
+	}
+
+	pub(crate) async fn update_fee_estimates(&self) {
+		EsploraClient::fetch_fee_estimates(
+			&self.fee_estimates,
+			self.sync_client.client(),
+			&self.logger,
+		)
+		.await
+	}
+
+	async fn fetch_fee_estimates(
+		fee_estimates: &FeeEstimates, client: &AsyncClient, logger: &FilesystemLogger,
+	) {
+		let estimates = match client.get_fee_estimates().await {
+			Ok(estimates) => estimates,
+			Err(e) => {
+				log_error!(logger, "Failed to fetch fee estimates from Esplora: {}", e);
+				return;
+			},
+		};
+		// Esplora gives us a map from confirmation target (in blocks) to a feerate in sat/vB,
+		// which we need to convert to sat/KW by multiplying by 1000 and dividing by 4 to convert
+		// virtual-bytes into weight units.
+		let feerates: HashMap<FeeSource, u32> = fee_estimates
+			.policy()
+			.sources()
+			.into_iter()
+			.filter_map(|source| {
+				// Esplora has no notion of a mempool minimum fee, so we use the slowest target it
+				// provides instead.
+				let target = match source {
+					FeeSource::MempoolMinimum => 1008,
+					FeeSource::Estimate { blocks, .. } => blocks as usize,
+				};
+				let sat_per_vb = esplora_client::convert_fee_rate(target, estimates.clone())?;
+				Some((source, (sat_per_vb as f64 * 250.0).round() as u32))
+			})
+			.collect();
+		fee_estimates.update(&feerates);
+	}
+
+	fn poll_for_fee_estimates(
+		fee_estimates: Arc<FeeEstimates>,
+		sync_client: Arc<EsploraSyncClient<Arc<FilesystemLogger>>>, logger: Arc<FilesystemLogger>,
+		handle: runtime::Handle,
+	) {
+		handle.spawn(async move {
+			loop {
+				tokio::time::sleep(fee_estimates.policy().poll_interval).await;
+				EsploraClient::fetch_fee_estimates(&fee_estimates, sync_client.client(), &logger)
+					.await;
+				fee_estimates.warn_if_stale();
+			}
+		});
+	}
+}
+
+impl FeeEstimator for EsploraClient {
+	fn get_est_sat_per_1000_weight(&self, confirmation_target: ConfirmationTarget) -> u32 {
+		self.fee_estimates.get_est_sat_per_1000_weight(confirmation_target)
+	}
+}
+
+impl BroadcasterInterface for EsploraClient {
+	fn broadcast_transactions(&self, txs: &[&Transaction]) {
+		// Esplora has no package relay endpoint, so we submit the transactions one by one in the
+		// order LDK hands them to us, which ensures parents are broadcast before their children.
+		let txn = txs.iter().map(|tx| (*tx).clone()).collect::<Vec<_>>();
+		let sync_client = Arc::clone(&self.sync_client);
+		let logger = Arc::clone(&self.logger);
+		self.main_runtime_handle.spawn(async move {
+			for tx in txn {
+				// This may error due to RL calling `broadcast_transactions` with the same
+				// transaction multiple times, but the error is safe to ignore.
+				match sync_client.client().broadcast(&tx).await {
+					Ok(()) => {
+						log_info!(logger, "Broadcast transaction {} via Esplora", tx.compute_txid());
+					},
+					Err(e) => {
+						log_error!(logger,
+							"Warning, failed to broadcast a transaction, this is likely okay but may indicate an error: {}\nTransaction: {}",
+							e,
+							encode::serialize_hex(&tx));
+						print!("Warning, failed to broadcast a transaction, this is likely okay but may indicate an error: {}\n> ", e);
+					},
+				}
+			}
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::{test_data_dir, test_fee_estimates, MockHttpServer};
+	use bitcoin::absolute::LockTime;
+	use bitcoin::transaction::Version;
+
+	fn test_logger() -> Arc<FilesystemLogger> {
+		let dir = std::env::temp_dir().join(format!("esplora-client-test-{}", std::process::id()));
+		Arc::new(FilesystemLogger::new(dir.to_str().unwrap().to_string()))
+	}
+
+	fn fee_estimates() -> Arc<FeeEstimates> {
+		test_fee_estimates(&test_data_dir("esplora-client"))
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_fee_estimates_are_converted() {
+		let server = MockHttpServer::start(|req| match req.path.as_str() {
+			"/blocks/tip/height" => (200, "100".to_string()),
+			"/fee-estimates" => (200, r#"{"1": 40.0, "2": 20.0, "6": 10.0, "144": 2.0}"#.into()),
+			_ => (404, String::new()),
+		})
+		.await;
+		let client = EsploraClient::new(
+			server.url(),
+			fee_estimates(),
+			tokio::runtime::Handle::current(),
+			test_logger(),
+		)
+		.await
+		.unwrap();
+
+		assert_eq!(
+			client.get_est_sat_per_1000_weight(ConfirmationTarget::MaximumFeeEstimate),
+			5000
+		);
+		assert_eq!(
+			client.get_est_sat_per_1000_weight(ConfirmationTarget::UrgentOnChainSweep),
+			2500
+		);
+		assert_eq!(
+			client.get_est_sat_per_1000_weight(ConfirmationTarget::NonAnchorChannelFee),
+			2500
+		);
+		assert_eq!(client.get_est_sat_per_1000_weight(ConfirmationTarget::AnchorChannelFee), 500);
+		assert_eq!(
+			client
+				.get_est_sat_per_1000_weight(ConfirmationTarget::MinAllowedAnchorChannelRemoteFee),
+			500
+		);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_broadcast_posts_tx() {
+		let server = MockHttpServer::start(|req| match req.path.as_str() {
+			"/blocks/tip/height" => (200, "100".to_string()),
+			"/tx" => (200, String::new()),
+			_ => (404, String::new()),
+		})
+		.await;
+		let client = EsploraClient::new(
+			server.url(),
+			fee_estimates(),
+			tokio::runtime::Handle::current(),
+			test_logger(),
+		)
+		.await
+		.unwrap();
+
+		let tx = Transaction {
+			version: Version::TWO,
+			lock_time: LockTime::ZERO,
+			input: Vec::new(),
+			output: Vec::new(),
+		};
+		client.broadcast_transactions(&[&tx]);
+
+		let req = server.wait_for_request("/tx").await;
+		assert_eq!(req.method, "POST");
+		assert_eq!(req.body, encode::serialize_hex(&tx));
+	}
+}
@@ -0,0 +1,451 @@
+use crate::common::PeerManager;
+use crate::data_store::DataStore;
+use crate::disk::{FilesystemLogger, EVENT_SEQUENCE_FNAME};
+use crate::notifications::Notification;
+use bitcoin::block::Header;
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::{BlockHash, Txid};
+use lightning::chain::transaction::TransactionData;
+use lightning::chain::{Confirm, Listen};
+use lightning::log_error;
+use lightning::util::logger::Logger;
+use lightning::util::persist::KVStore;
+use lightning::util::ser::{Readable, Writeable};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+
+/// The path events are streamed from.
+pub const EVENTS_PATH: &str = "/events";
+
+/// How many of the latest events are kept for subscribers resuming after a reconnect.
+const BUFFERED_EVENTS: usize = 10_000;
+/// Sequence numbers are reserved in blocks of this many, so the next free one only has to be
+/// persisted once per block rather than for every event.
+const SEQUENCE_RESERVATION: u64 = 1000;
+/// How often idle subscribers are sent a comment, which keeps proxies from closing the connection
+/// and lets us notice subscribers which went away.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+/// Requests with more header lines than this are rejected.
+const MAX_HEADERS: usize = 100;
+/// How often the connected peers are checked for peers which connected or disconnected.
+const PEER_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+struct State {
+	/// The latest events as their sequence number and JSON, oldest first.
+	buffer: VecDeque<(u64, String)>,
+	next_seq: u64,
+	/// Sequence numbers below this are reserved in the data store, see [`SEQUENCE_RESERVATION`].
+	reserved_seq: u64,
+	best_block_hash: Option<BlockHash>,
+}
+
+/// A feed of the node's [`Notification`]s, served to subscribers such as dashboards as
+/// Server-Sent Events.
+///
+/// Every event gets a sequence number, which keeps increasing across restarts. The latest
+/// [`BUFFERED_EVENTS`] events are kept in memory, so a subscriber reconnecting with the sequence
+/// number of the last event it saw gets everything it missed. If some of those events are no
+/// longer buffered, e.g. because the node restarted in between, it is told so with an
+/// `events_missed` event first.
+///
+/// Besides the events passed to [`EventStream::publish`], it reports new blocks as a chain
+/// listener and peer connections once [`EventStream::watch_peers`] is called.
+pub struct EventStream {
+	state: Mutex<State>,
+	latest_seq: watch::Sender<u64>,
+	kv_store: Arc<DataStore>,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl EventStream {
+	pub fn new(kv_store: Arc<DataStore>, logger: Arc<FilesystemLogger>) -> io::Result<Self> {
+		let mut next_seq = 1;
+		if let Ok(bytes) = kv_store.read("", "", EVENT_SEQUENCE_FNAME) {
+			next_seq = u64::read(&mut &bytes[..])
+				.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+		}
+		let reserved_seq = next_seq + SEQUENCE_RESERVATION;
+		kv_store.write("", "", EVENT_SEQUENCE_FNAME, &reserved_seq.encode())?;
+		let state =
+			State { buffer: VecDeque::new(), next_seq, reserved_seq, best_block_hash: None };
+		Ok(Self {
+			state: Mutex::new(state),
+			latest_seq: watch::channel(next_seq - 1).0,
+			kv_store,
+			logger,
+		})
+	}
+
+	/// Assigns `notification` the next sequence number and sends it to every subscriber.
+	pub fn publish(&self, notification: &Notification) {
+		let mut state = self.state.lock().unwrap();
+		let seq = state.next_seq;
+		if seq >= state.reserved_seq {
+			let reserved_seq = seq + SEQUENCE_RESERVATION;
+			match self.kv_store.write("", "", EVENT_SEQUENCE_FNAME, &reserved_seq.encode()) {
+				Ok(()) => state.reserved_seq = reserved_seq,
+				Err(e) => log_error!(self.logger, "Failed to persist event sequence: {}", e),
+			}
+		}
+		state.next_seq += 1;
+		let created_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+		let json = serde_json::json!({
+			"seq": seq,
+			"type": notification.event_type(),
+			"created_at": created_at,
+			"data": notification.data(),
+		});
+		if state.buffer.len() == BUFFERED_EVENTS {
+			state.buffer.pop_front();
+		}
+		state.buffer.push_back((seq, json.to_string()));
+		self.latest_seq.send_replace(seq);
+	}
+
+	/// Reports peers connecting to and disconnecting from `peer_manager`, by comparing its peers
+	/// every [`PEER_POLL_INTERVAL`] in the background. A peer which disconnects and connects again
+	/// in between isn't reported.
+	pub fn watch_peers(self: &Arc<Self>, peer_manager: Arc<PeerManager>) {
+		let event_stream = Arc::clone(self);
+		tokio::spawn(async move {
+			let mut peers = HashMap::new();
+			let mut interval = tokio::time::interval(PEER_POLL_INTERVAL);
+			loop {
+				interval.tick().await;
+				let current_peers = peer_manager
+					.list_peers()
+					.into_iter()
+					.map(|peer| (peer.counterparty_node_id, peer.is_inbound_connection))
+					.collect();
+				for notification in peer_changes(&peers, &current_peers) {
+					event_stream.publish(&notification);
+				}
+				peers = current_peers;
+			}
+		});
+	}
+
+	/// Accepts subscribers on `listener` in the background. They `GET` [`EVENTS_PATH`] and resume
+	/// after the sequence number in the `Last-Event-ID` header or the `since` query parameter, if
+	/// either is given.
+	pub fn serve(self: &Arc<Self>, listener: TcpListener) {
+		let event_stream = Arc::clone(self);
+		tokio::spawn(async move {
+			loop {
+				let stream = match listener.accept().await {
+					Ok((stream, _)) => stream,
+					Err(e) => {
+						log_error!(event_stream.logger, "Failed to accept subscriber: {}", e);
+						continue;
+					},
+				};
+				let event_stream = Arc::clone(&event_stream);
+				tokio::spawn(async move {
+					let _ = event_stream.serve_subscriber(stream).await;
+				});
+			}
+		});
+	}
+
+	async fn serve_subscriber(&self, stream: TcpStream) -> io::Result<()> {
+		let mut stream = BufReader::new(stream);
+		let resume_seq = match read_request(&mut stream).await? {
+			Ok(resume_seq) => resume_seq,
+			Err((status, reason)) => {
+				let response = format!(
+					"HTTP/1.1 {} {}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+					status,
+					reason,
+					reason.len(),
+					reason
+				);
+				return stream.get_mut().write_all(response.as_bytes()).await;
+			},
+		};
+		let stream = stream.get_mut();
+		stream
+			.write_all(
+				b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n",
+			)
+			.await?;
+
+		// Subscribe before looking at the buffer, so no event published in between is lost.
+		let mut latest_seq = self.latest_seq.subscribe();
+		let mut cursor = resume_seq.unwrap_or_else(|| *latest_seq.borrow_and_update());
+		loop {
+			let (missed, events) = self.events_after(cursor);
+			let mut frames = String::new();
+			if let Some(first_available_seq) = missed {
+				let json = serde_json::json!({
+					"type": "events_missed",
+					"data": {
+						"resume_seq": cursor,
+						"first_available_seq": first_available_seq,
+					},
+				});
+				frames.push_str(&format!("data: {}\n\n", json));
+				cursor = first_available_seq - 1;
+			}
+			for (seq, json) in events {
+				frames.push_str(&format!("id: {}\ndata: {}\n\n", seq, json));
+				cursor = seq;
+			}
+			if !frames.is_empty() {
+				stream.write_all(frames.as_bytes()).await?;
+			}
+
+			tokio::select! {
+				changed = latest_seq.changed() => {
+					if changed.is_err() {
+						return Ok(());
+					}
+				},
+				_ = tokio::time::sleep(KEEP_ALIVE_INTERVAL) => {
+					stream.write_all(b": keep-alive\n\n").await?;
+				},
+			}
+		}
+	}
+
+	/// Returns the buffered events after `cursor`, along with the first sequence number still
+	/// available if some events after `cursor` are no longer buffered.
+	fn events_after(&self, cursor: u64) -> (Option<u64>, Vec<(u64, String)>) {
+		let state = self.state.lock().unwrap();
+		let first_available_seq =
+			state.buffer.front().map(|(seq, _)| *seq).unwrap_or(state.next_seq);
+		// A cursor past our latest event was handed out by another run of the node, e.g. one
+		// whose data was restored from an older backup, so we can't tell what it missed.
+		let missed = cursor.saturating_add(1) < first_available_seq || cursor >= state.next_seq;
+		let cursor = if missed { first_available_seq - 1 } else { cursor };
+		let events = state.buffer.iter().filter(|(seq, _)| *seq > cursor).cloned().collect();
+		(if missed { Some(first_available_seq) } else { None }, events)
+	}
+
+	fn block_connected(&self, header: &Header, height: u32) {
+		let block_hash = header.block_hash();
+		{
+			let mut state = self.state.lock().unwrap();
+			if state.best_block_hash == Some(block_hash) {
+				return;
+			}
+			state.best_block_hash = Some(block_hash);
+		}
+		self.publish(&Notification::BlockConnected { height, block_hash });
+	}
+}
+
+/// Reads a subscriber's request, returning the sequence number to resume after if it gave one,
+/// or the status and reason to reject it with.
+async fn read_request(
+	stream: &mut BufReader<TcpStream>,
+) -> io::Result<Result<Option<u64>, (u16, &'static str)>> {
+	let mut request_line = String::new();
+	stream.read_line(&mut request_line).await?;
+	let mut parts = request_line.split_whitespace();
+	let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+
+	let mut headers = HashMap::new();
+	loop {
+		if headers.len() == MAX_HEADERS {
+			return Ok(Err((431, "Request Header Fields Too Large")));
+		}
+		let mut line = String::new();
+		if stream.read_line(&mut line).await? == 0 {
+			return Err(io::ErrorKind::UnexpectedEof.into());
+		}
+		let line = line.trim_end();
+		if line.is_empty() {
+			break;
+		}
+		if let Some((name, value)) = line.split_once(':') {
+			headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+		}
+	}
+
+	let (path, query) = target.split_once('?').unwrap_or((target, ""));
+	if path != EVENTS_PATH {
+		return Ok(Err((404, "Not Found")));
+	}
+	if method != "GET" {
+		return Ok(Err((405, "Method Not Allowed")));
+	}
+	// Browsers reconnect with the ID of the last event they saw in `Last-Event-ID`, while still
+	// requesting the URL they first connected with, so the header takes precedence.
+	let since = query.split('&').find_map(|param| param.strip_prefix("since="));
+	match headers.get("last-event-id").map(|id| id.as_str()).or(since) {
+		Some(seq) => match seq.parse() {
+			Ok(seq) => Ok(Ok(Some(seq))),
+			Err(_) => Ok(Err((400, "Bad Request"))),
+		},
+		None => Ok(Ok(None)),
+	}
+}
+
+/// The peers which disconnected and connected between two snapshots of our peers, each mapping
+/// their node ID to whether they connected to us.
+fn peer_changes(
+	before: &HashMap<PublicKey, bool>, after: &HashMap<PublicKey, bool>,
+) -> Vec<Notification> {
+	let disconnected = before
+		.keys()
+		.filter(|node_id| !after.contains_key(node_id))
+		.map(|node_id| Notification::PeerDisconnected { node_id: *node_id });
+	let connected = after.iter().filter(|(node_id, _)| !before.contains_key(node_id)).map(
+		|(node_id, inbound)| Notification::PeerConnected { node_id: *node_id, inbound: *inbound },
+	);
+	disconnected.chain(connected).collect()
+}
+
+impl Listen for EventStream {
+	fn filtered_block_connected(&self, header: &Header, _txdata: &TransactionData, height: u32) {
+		self.block_connected(header, height);
+	}
+
+	fn block_disconnected(&self, _header: &Header, _height: u32) {}
+}
+
+impl Confirm for EventStream {
+	fn transactions_confirmed(&self, _header: &Header, _txdata: &TransactionData, _height: u32) {}
+
+	fn transaction_unconfirmed(&self, _txid: &Txid) {}
+
+	fn best_block_updated(&self, header: &Header, height: u32) {
+		self.block_connected(header, height);
+	}
+
+	fn get_relevant_txids(&self) -> Vec<(Txid, u32, Option<BlockHash>)> {
+		Vec::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::{test_data_dir, test_store};
+	use bitcoin::secp256k1::{Secp256k1, SecretKey};
+	use lightning::types::payment::PaymentHash;
+
+	fn test_event_stream(data_dir: &str) -> Arc<EventStream> {
+		let (kv_store, logger) = test_store(data_dir);
+		Arc::new(EventStream::new(kv_store, logger).unwrap())
+	}
+
+	fn payment(amount_msat: u64) -> Notification {
+		Notification::PaymentReceived { payment_hash: PaymentHash([1; 32]), amount_msat }
+	}
+
+	/// Subscribes to `event_stream` with the given request headers and returns the connection
+	/// once the response headers have been read.
+	async fn subscribe(event_stream: &Arc<EventStream>, headers: &str) -> BufReader<TcpStream> {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		event_stream.serve(listener);
+		let mut stream = TcpStream::connect(addr).await.unwrap();
+		let request = format!("GET {} HTTP/1.1\r\n{}\r\n", EVENTS_PATH, headers);
+		stream.write_all(request.as_bytes()).await.unwrap();
+		let mut stream = BufReader::new(stream);
+		let mut status_line = String::new();
+		stream.read_line(&mut status_line).await.unwrap();
+		assert!(status_line.starts_with("HTTP/1.1 200"), "{}", status_line);
+		loop {
+			let mut line = String::new();
+			stream.read_line(&mut line).await.unwrap();
+			if line == "\r\n" {
+				return stream;
+			}
+		}
+	}
+
+	/// Reads the next event, returning its ID, if it has one, and its JSON.
+	async fn next_event(stream: &mut BufReader<TcpStream>) -> (Option<u64>, serde_json::Value) {
+		let (mut id, mut data) = (None, None);
+		let read = async {
+			loop {
+				let mut line = String::new();
+				stream.read_line(&mut line).await.unwrap();
+				if let Some(value) = line.strip_prefix("id: ") {
+					id = Some(value.trim().parse().unwrap());
+				} else if let Some(value) = line.strip_prefix("data: ") {
+					data = Some(serde_json::from_str(value.trim()).unwrap());
+				} else if line == "\n" && data.is_some() {
+					return;
+				}
+			}
+		};
+		tokio::time::timeout(Duration::from_secs(5), read).await.unwrap();
+		(id, data.unwrap())
+	}
+
+	#[tokio::test]
+	async fn test_subscribers_resume_after_last_event_id() {
+		let event_stream = test_event_stream(&test_data_dir("event-stream"));
+		for amount_msat in 1..=3 {
+			event_stream.publish(&payment(amount_msat));
+		}
+
+		let mut stream = subscribe(&event_stream, "Last-Event-ID: 1\r\n").await;
+		for seq in 2..=3 {
+			let (id, event) = next_event(&mut stream).await;
+			assert_eq!(id, Some(seq));
+			assert_eq!(event["seq"], seq);
+			assert_eq!(event["type"], "payment_received");
+			assert_eq!(event["data"]["amount_msat"], seq);
+		}
+
+		// Without a sequence number to resume after, only new events are sent.
+		let mut new_stream = subscribe(&event_stream, "").await;
+		event_stream.publish(&payment(4));
+		assert_eq!(next_event(&mut stream).await.0, Some(4));
+		assert_eq!(next_event(&mut new_stream).await.0, Some(4));
+	}
+
+	#[tokio::test]
+	async fn test_missed_events_are_reported_after_restart() {
+		let data_dir = test_data_dir("event-stream-restart");
+		let event_stream = test_event_stream(&data_dir);
+		event_stream.publish(&payment(1));
+		event_stream.publish(&payment(2));
+
+		// The events buffered before the restart are gone, and sequence numbers continue past
+		// any handed out before it.
+		let event_stream = test_event_stream(&data_dir);
+		let mut stream = subscribe(&event_stream, "").await;
+		event_stream.publish(&payment(3));
+		let (_, event) = next_event(&mut stream).await;
+		let seq = event["seq"].as_u64().unwrap();
+		assert!(seq > 2);
+
+		let mut resumed = subscribe(&event_stream, "Last-Event-ID: 1\r\n").await;
+		let (id, missed) = next_event(&mut resumed).await;
+		assert_eq!(id, None);
+		assert_eq!(missed["type"], "events_missed");
+		assert_eq!(missed["data"]["resume_seq"], 1);
+		assert_eq!(missed["data"]["first_available_seq"], seq);
+		assert_eq!(next_event(&mut resumed).await.0, Some(seq));
+	}
+
+	#[test]
+	fn test_peer_changes() {
+		let secp_ctx = Secp256k1::new();
+		let node = |byte| {
+			PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[byte; 32]).unwrap())
+		};
+		let (a, b, c) = (node(1), node(2), node(3));
+		let before = HashMap::from([(a, true), (b, false)]);
+		let after = HashMap::from([(b, false), (c, true)]);
+
+		assert_eq!(
+			peer_changes(&before, &after),
+			vec![
+				Notification::PeerDisconnected { node_id: a },
+				Notification::PeerConnected { node_id: c, inbound: true },
+			]
+		);
+		assert!(peer_changes(&after, &after).is_empty());
+	}
+}
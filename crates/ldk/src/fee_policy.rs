@@ -0,0 +1,582 @@
+use crate::data_store::DataStore;
+use crate::disk::{FilesystemLogger, FEE_ESTIMATES_FNAME};
+use lightning::chain::chaininterface::{ConfirmationTarget, FeeEstimator};
+use lightning::util::logger::Logger;
+use lightning::util::persist::KVStore;
+use lightning::util::ser::{Readable, Writeable};
+use lightning::{
+	impl_writeable_tlv_based, impl_writeable_tlv_based_enum, log_error, log_info, log_warn,
+};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The minimum feerate we are allowed to send, as specify by LDK.
+pub const MIN_FEERATE: u32 = 253;
+
+/// Every [`ConfirmationTarget`] LDK may ask us for a feerate.
+pub const CONFIRMATION_TARGETS: [ConfirmationTarget; 8] = [
+	ConfirmationTarget::MaximumFeeEstimate,
+	ConfirmationTarget::UrgentOnChainSweep,
+	ConfirmationTarget::MinAllowedAnchorChannelRemoteFee,
+	ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee,
+	ConfirmationTarget::AnchorChannelFee,
+	ConfirmationTarget::NonAnchorChannelFee,
+	ConfirmationTarget::ChannelCloseMinimum,
+	ConfirmationTarget::OutputSpendingFee,
+];
+
+/// The estimate mode passed to bitcoind's `estimatesmartfee`. Esplora and Electrum servers have no
+/// such notion, so it is ignored for them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EstimateMode {
+	Economical,
+	Conservative,
+}
+
+impl EstimateMode {
+	/// The name bitcoind knows the mode by.
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			EstimateMode::Economical => "ECONOMICAL",
+			EstimateMode::Conservative => "CONSERVATIVE",
+		}
+	}
+}
+
+impl_writeable_tlv_based_enum!(EstimateMode,
+	(0, Economical) => {},
+	(2, Conservative) => {},
+);
+
+/// What a feerate estimate is based on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FeeSource {
+	/// The lowest feerate the chain source accepts transactions at: bitcoind's mempool minimum
+	/// fee or an Electrum server's relay fee. Esplora reports neither, so its estimate for the
+	/// slowest target, 1008 blocks, is used instead.
+	MempoolMinimum,
+	/// The feerate expected to get a transaction confirmed within `blocks` blocks.
+	Estimate { blocks: u16, mode: EstimateMode },
+}
+
+impl_writeable_tlv_based_enum!(FeeSource,
+	(0, MempoolMinimum) => {},
+	(2, Estimate) => {
+		(0, blocks, required),
+		(2, mode, required),
+	},
+);
+
+impl fmt::Display for FeeSource {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			FeeSource::MempoolMinimum => write!(f, "mempool minimum"),
+			FeeSource::Estimate { blocks, mode } => {
+				write!(f, "{} block {} estimate", blocks, mode.as_str().to_lowercase())
+			},
+		}
+	}
+}
+
+/// How the feerate for a single [`ConfirmationTarget`] is derived. All feerates are in sat/KW.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TargetFeePolicy {
+	pub source: FeeSource,
+	/// Added to the estimate before it is bounded, may be negative.
+	pub offset: i32,
+	/// The lowest feerate we use. Never below [`MIN_FEERATE`], regardless of what is configured.
+	pub floor: u32,
+	/// The highest feerate we use.
+	pub ceiling: u32,
+	/// The feerate used while no estimate is available for `source`.
+	pub fallback: u32,
+}
+
+impl TargetFeePolicy {
+	fn new(source: FeeSource, offset: i32, fallback: u32) -> Self {
+		Self { source, offset, floor: MIN_FEERATE, ceiling: u32::MAX, fallback }
+	}
+
+	fn estimate(blocks: u16, mode: EstimateMode, fallback: u32) -> Self {
+		Self::new(FeeSource::Estimate { blocks, mode }, 0, fallback)
+	}
+
+	/// Turns the estimate for our source, if any, into the feerate to use.
+	pub fn feerate(&self, estimate: Option<u32>) -> Feerate {
+		let floor = std::cmp::max(self.floor, MIN_FEERATE);
+		let (sat_per_kw, origin) = match estimate {
+			Some(estimate) => {
+				let adjusted = (estimate as i64 + self.offset as i64).clamp(0, u32::MAX as i64);
+				(adjusted as u32, FeerateOrigin::Estimate)
+			},
+			None => (self.fallback, FeerateOrigin::Fallback),
+		};
+		if sat_per_kw < floor {
+			Feerate { sat_per_kw: floor, origin: FeerateOrigin::Floor, updated_at: None }
+		} else if sat_per_kw > self.ceiling {
+			Feerate { sat_per_kw: self.ceiling, origin: FeerateOrigin::Ceiling, updated_at: None }
+		} else {
+			Feerate { sat_per_kw, origin, updated_at: None }
+		}
+	}
+}
+
+/// Maps every [`ConfirmationTarget`] to how its feerate is estimated.
+#[derive(Clone, Debug)]
+pub struct FeePolicy {
+	targets: HashMap<ConfirmationTarget, TargetFeePolicy>,
+	/// How often estimates are refreshed from the chain source.
+	pub poll_interval: Duration,
+	/// How old an estimate may get before we warn that the chain source stopped refreshing it.
+	pub stale_after: Duration,
+}
+
+impl Default for FeePolicy {
+	fn default() -> Self {
+		let background =
+			|fallback| TargetFeePolicy::estimate(144, EstimateMode::Economical, fallback);
+		let mut targets = HashMap::new();
+		targets.insert(
+			ConfirmationTarget::MaximumFeeEstimate,
+			TargetFeePolicy::estimate(2, EstimateMode::Conservative, 50000),
+		);
+		targets.insert(
+			ConfirmationTarget::UrgentOnChainSweep,
+			TargetFeePolicy::estimate(6, EstimateMode::Conservative, 5000),
+		);
+		targets.insert(
+			ConfirmationTarget::MinAllowedAnchorChannelRemoteFee,
+			TargetFeePolicy::new(FeeSource::MempoolMinimum, 0, MIN_FEERATE),
+		);
+		targets.insert(
+			ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee,
+			TargetFeePolicy { offset: -250, ..background(MIN_FEERATE) },
+		);
+		targets.insert(ConfirmationTarget::AnchorChannelFee, background(MIN_FEERATE));
+		targets.insert(
+			ConfirmationTarget::NonAnchorChannelFee,
+			TargetFeePolicy::estimate(18, EstimateMode::Economical, 2000),
+		);
+		targets.insert(ConfirmationTarget::ChannelCloseMinimum, background(MIN_FEERATE));
+		targets.insert(ConfirmationTarget::OutputSpendingFee, background(MIN_FEERATE));
+		Self {
+			targets,
+			poll_interval: Duration::from_secs(60),
+			stale_after: Duration::from_secs(30 * 60),
+		}
+	}
+}
+
+impl FeePolicy {
+	/// Parses a fee policy from JSON. Anything not given keeps its default, e.g.:
+	///
+	/// ```json
+	/// {
+	///   "poll_interval_secs": 30,
+	///   "stale_after_secs": 1800,
+	///   "targets": {
+	///     "UrgentOnChainSweep": { "blocks": 3, "mode": "conservative", "floor": 2500, "ceiling": 100000, "fallback": 10000 },
+	///     "MinAllowedAnchorChannelRemoteFee": { "blocks": "mempoolmin" },
+	///     "MinAllowedNonAnchorChannelRemoteFee": { "offset": -250 }
+	///   }
+	/// }
+	/// ```
+	///
+	/// Targets are named as in [`ConfirmationTarget`] and feerates are in sat/KW.
+	pub fn from_json(json: &str) -> Result<Self, String> {
+		let value: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+		let mut policy = FeePolicy::default();
+		if let Some(secs) = value.get("poll_interval_secs") {
+			let secs = secs
+				.as_u64()
+				.filter(|secs| *secs > 0)
+				.ok_or("poll_interval_secs must be a positive integer")?;
+			policy.poll_interval = Duration::from_secs(secs);
+		}
+		if let Some(secs) = value.get("stale_after_secs") {
+			let secs = secs.as_u64().ok_or("stale_after_secs must be an integer")?;
+			policy.stale_after = Duration::from_secs(secs);
+		}
+		if let Some(targets) = value.get("targets") {
+			let targets = targets.as_object().ok_or("targets must be an object")?;
+			for (name, overrides) in targets {
+				let target = CONFIRMATION_TARGETS
+					.iter()
+					.find(|target| format!("{:?}", target) == *name)
+					.ok_or_else(|| format!("unknown confirmation target {}", name))?;
+				let target_policy = policy.targets.get_mut(target).unwrap();
+				apply_overrides(target_policy, overrides)
+					.map_err(|e| format!("{}: {}", name, e))?;
+			}
+		}
+		Ok(policy)
+	}
+
+	pub fn target(&self, confirmation_target: ConfirmationTarget) -> &TargetFeePolicy {
+		&self.targets[&confirmation_target]
+	}
+
+	pub fn target_mut(&mut self, confirmation_target: ConfirmationTarget) -> &mut TargetFeePolicy {
+		self.targets.get_mut(&confirmation_target).unwrap()
+	}
+
+	/// The distinct sources the chain source has to provide estimates for.
+	pub fn sources(&self) -> Vec<FeeSource> {
+		let mut sources = Vec::new();
+		for target in CONFIRMATION_TARGETS.iter() {
+			let source = self.target(*target).source;
+			if !sources.contains(&source) {
+				sources.push(source);
+			}
+		}
+		sources
+	}
+}
+
+fn apply_overrides(policy: &mut TargetFeePolicy, overrides: &Value) -> Result<(), String> {
+	let overrides = overrides.as_object().ok_or("expected an object")?;
+	for key in overrides.keys() {
+		if !["blocks", "mode", "offset", "floor", "ceiling", "fallback"].contains(&key.as_str()) {
+			return Err(format!("unknown setting {}", key));
+		}
+	}
+	match overrides.get("blocks") {
+		Some(Value::String(s)) if s == "mempoolmin" => policy.source = FeeSource::MempoolMinimum,
+		Some(blocks) => {
+			let blocks = blocks
+				.as_u64()
+				.filter(|blocks| (1..=1008).contains(blocks))
+				.ok_or("blocks must be between 1 and 1008, or \"mempoolmin\"")?;
+			let mode = match policy.source {
+				FeeSource::Estimate { mode, .. } => mode,
+				FeeSource::MempoolMinimum => EstimateMode::Economical,
+			};
+			policy.source = FeeSource::Estimate { blocks: blocks as u16, mode };
+		},
+		None => {},
+	}
+	if let Some(mode) = overrides.get("mode") {
+		let mode = match mode.as_str() {
+			Some("economical") => EstimateMode::Economical,
+			Some("conservative") => EstimateMode::Conservative,
+			_ => return Err("mode must be \"economical\" or \"conservative\"".to_string()),
+		};
+		match &mut policy.source {
+			FeeSource::Estimate { mode: source_mode, .. } => *source_mode = mode,
+			FeeSource::MempoolMinimum => {
+				return Err("mode can't be set for the mempool minimum".to_string())
+			},
+		}
+	}
+	if let Some(offset) = overrides.get("offset") {
+		policy.offset = offset
+			.as_i64()
+			.and_then(|offset| i32::try_from(offset).ok())
+			.ok_or("offset must be an integer")?;
+	}
+	let feerate = |key: &str| -> Result<Option<u32>, String> {
+		match overrides.get(key) {
+			Some(value) => value
+				.as_u64()
+				.and_then(|value| u32::try_from(value).ok())
+				.map(Some)
+				.ok_or_else(|| format!("{} must be a feerate in sat/KW", key)),
+			None => Ok(None),
+		}
+	};
+	policy.floor = feerate("floor")?.unwrap_or(policy.floor);
+	policy.ceiling = feerate("ceiling")?.unwrap_or(policy.ceiling);
+	policy.fallback = feerate("fallback")?.unwrap_or(policy.fallback);
+	if policy.ceiling < std::cmp::max(policy.floor, MIN_FEERATE) {
+		return Err("ceiling must not be below floor".to_string());
+	}
+	Ok(())
+}
+
+/// How the current feerate for a target was arrived at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeerateOrigin {
+	/// The chain source's latest estimate, with the target's offset applied.
+	Estimate,
+	/// An earlier estimate, possibly from before a restart, as the chain source didn't provide a
+	/// fresh one.
+	Cached,
+	/// The chain source never provided an estimate, so the configured fallback is used.
+	Fallback,
+	/// The feerate was raised to the configured floor.
+	Floor,
+	/// The feerate was lowered to the configured ceiling.
+	Ceiling,
+}
+
+impl fmt::Display for FeerateOrigin {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			FeerateOrigin::Estimate => write!(f, "estimate"),
+			FeerateOrigin::Cached => write!(f, "cached estimate"),
+			FeerateOrigin::Fallback => write!(f, "fallback"),
+			FeerateOrigin::Floor => write!(f, "floor"),
+			FeerateOrigin::Ceiling => write!(f, "ceiling"),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Feerate {
+	pub sat_per_kw: u32,
+	pub origin: FeerateOrigin,
+	/// When the estimate the feerate is based on was fetched, in seconds since the UNIX epoch.
+	pub updated_at: Option<u64>,
+}
+
+/// The last estimate we got for a [`FeeSource`], persisted so we have something better than the
+/// fallbacks to go by after a restart.
+#[derive(Clone, Copy)]
+struct CachedEstimate {
+	source: FeeSource,
+	sat_per_kw: u32,
+	updated_at: u64,
+}
+
+impl_writeable_tlv_based!(CachedEstimate, {
+	(0, source, required),
+	(2, sat_per_kw, required),
+	(4, updated_at, required),
+});
+
+struct CachedEstimates {
+	estimates: Vec<CachedEstimate>,
+}
+
+impl_writeable_tlv_based!(CachedEstimates, {
+	(0, estimates, required_vec),
+});
+
+struct State {
+	estimates: HashMap<FeeSource, CachedEstimate>,
+	feerates: HashMap<ConfirmationTarget, Feerate>,
+	/// Sources we already warned about having gone stale.
+	stale_sources: HashSet<FeeSource>,
+}
+
+/// The current feerate for every [`ConfirmationTarget`], updated by the chain source's fee
+/// poller according to a [`FeePolicy`] and read by LDK through [`FeeEstimator`].
+///
+/// The last estimate for every source is persisted, so that after a restart, or while the chain
+/// source has no estimate for a source, we keep using it rather than the configured fallback.
+pub struct FeeEstimates {
+	policy: FeePolicy,
+	state: RwLock<State>,
+	kv_store: Arc<DataStore>,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl FeeEstimates {
+	/// Loads the estimates persisted in `kv_store`. Targets without one start out at their
+	/// fallback until the first estimates come in.
+	pub fn new(
+		policy: FeePolicy, kv_store: Arc<DataStore>, logger: Arc<FilesystemLogger>,
+	) -> Self {
+		let mut estimates = HashMap::new();
+		if let Ok(bytes) = kv_store.read("", "", FEE_ESTIMATES_FNAME) {
+			match CachedEstimates::read(&mut &bytes[..]) {
+				Ok(cached) => {
+					log_info!(logger, "Loaded {} cached fee estimates", cached.estimates.len());
+					for estimate in cached.estimates {
+						estimates.insert(estimate.source, estimate);
+					}
+				},
+				Err(e) => log_error!(logger, "Failed to read cached fee estimates: {:?}", e),
+			}
+		}
+		let mut state =
+			State { estimates, feerates: HashMap::new(), stale_sources: HashSet::new() };
+		compute_feerates(&policy, &mut state, &HashSet::new());
+		Self { policy, state: RwLock::new(state), kv_store, logger }
+	}
+
+	pub fn policy(&self) -> &FeePolicy {
+		&self.policy
+	}
+
+	/// Records the chain source's latest `estimates` in sat/KW and recomputes every target's
+	/// feerate. Sources missing from `estimates` keep their previous estimate, if any.
+	pub fn update(&self, estimates: &HashMap<FeeSource, u32>) {
+		let now = unix_time_secs();
+		let encoded = {
+			let mut state = self.state.write().unwrap();
+			for (source, sat_per_kw) in estimates {
+				let estimate =
+					CachedEstimate { source: *source, sat_per_kw: *sat_per_kw, updated_at: now };
+				state.estimates.insert(*source, estimate);
+				state.stale_sources.remove(source);
+			}
+			compute_feerates(&self.policy, &mut state, &estimates.keys().copied().collect());
+			CachedEstimates { estimates: state.estimates.values().copied().collect() }.encode()
+		};
+		if let Err(e) = self.kv_store.write("", "", FEE_ESTIMATES_FNAME, &encoded) {
+			log_error!(self.logger, "Failed to persist fee estimates: {}", e);
+		}
+	}
+
+	/// Logs a warning for every estimate in use that the chain source hasn't refreshed for longer
+	/// than the policy's `stale_after`. Each source is only warned about once until it is
+	/// refreshed.
+	pub fn warn_if_stale(&self) {
+		let now = unix_time_secs();
+		let mut state = self.state.write().unwrap();
+		let newly_stale = state
+			.estimates
+			.values()
+			.filter(|estimate| {
+				now.saturating_sub(estimate.updated_at) > self.policy.stale_after.as_secs()
+			})
+			.filter(|estimate| !state.stale_sources.contains(&estimate.source))
+			.copied()
+			.collect::<Vec<_>>();
+		for estimate in newly_stale {
+			log_warn!(
+				self.logger,
+				"The {} of {} sat/KW is {} minutes old, the chain source has not refreshed it",
+				estimate.source,
+				estimate.sat_per_kw,
+				now.saturating_sub(estimate.updated_at) / 60
+			);
+			state.stale_sources.insert(estimate.source);
+		}
+	}
+
+	/// Returns the current feerate of every target.
+	pub fn feerates(&self) -> Vec<(ConfirmationTarget, Feerate)> {
+		let state = self.state.read().unwrap();
+		CONFIRMATION_TARGETS.iter().map(|target| (*target, state.feerates[target])).collect()
+	}
+}
+
+/// Recomputes every target's feerate from the estimates in `state`, of which those for
+/// `fresh_sources` were just fetched.
+fn compute_feerates(policy: &FeePolicy, state: &mut State, fresh_sources: &HashSet<FeeSource>) {
+	for target in CONFIRMATION_TARGETS.iter() {
+		let target_policy = policy.target(*target);
+		let estimate = state.estimates.get(&target_policy.source);
+		let mut feerate = target_policy.feerate(estimate.map(|estimate| estimate.sat_per_kw));
+		if feerate.origin == FeerateOrigin::Estimate
+			&& !fresh_sources.contains(&target_policy.source)
+		{
+			feerate.origin = FeerateOrigin::Cached;
+		}
+		feerate.updated_at = estimate.map(|estimate| estimate.updated_at);
+		state.feerates.insert(*target, feerate);
+	}
+}
+
+pub(crate) fn unix_time_secs() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+impl FeeEstimator for FeeEstimates {
+	fn get_est_sat_per_1000_weight(&self, confirmation_target: ConfirmationTarget) -> u32 {
+		self.state.read().unwrap().feerates[&confirmation_target].sat_per_kw
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::{test_data_dir, test_fee_estimates};
+
+	fn feerate(estimates: &FeeEstimates, target: ConfirmationTarget) -> (u32, FeerateOrigin) {
+		let feerates: HashMap<_, _> = estimates.feerates().into_iter().collect();
+		(feerates[&target].sat_per_kw, feerates[&target].origin)
+	}
+
+	#[test]
+	fn test_feerates_follow_policy() {
+		let estimates = test_fee_estimates(&test_data_dir("fee-policy"));
+		let urgent = ConfirmationTarget::UrgentOnChainSweep;
+		assert_eq!(estimates.get_est_sat_per_1000_weight(urgent), 5000);
+
+		let mut fetched = HashMap::new();
+		fetched.insert(FeeSource::Estimate { blocks: 6, mode: EstimateMode::Conservative }, 7000);
+		fetched.insert(FeeSource::Estimate { blocks: 144, mode: EstimateMode::Economical }, 300);
+		estimates.update(&fetched);
+
+		assert_eq!(feerate(&estimates, urgent), (7000, FeerateOrigin::Estimate));
+		assert_eq!(
+			feerate(&estimates, ConfirmationTarget::AnchorChannelFee),
+			(300, FeerateOrigin::Estimate)
+		);
+		assert_eq!(
+			feerate(&estimates, ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee),
+			(MIN_FEERATE, FeerateOrigin::Floor)
+		);
+		assert_eq!(
+			feerate(&estimates, ConfirmationTarget::NonAnchorChannelFee),
+			(2000, FeerateOrigin::Fallback)
+		);
+
+		// Without a fresh estimate, we keep using the previous one.
+		fetched.remove(&FeeSource::Estimate { blocks: 6, mode: EstimateMode::Conservative });
+		estimates.update(&fetched);
+		assert_eq!(feerate(&estimates, urgent), (7000, FeerateOrigin::Cached));
+	}
+
+	#[test]
+	fn test_estimates_are_persisted() {
+		let data_dir = test_data_dir("fee-policy");
+		let estimates = test_fee_estimates(&data_dir);
+		let mut fetched = HashMap::new();
+		fetched.insert(FeeSource::Estimate { blocks: 18, mode: EstimateMode::Economical }, 3000);
+		estimates.update(&fetched);
+
+		let reloaded = test_fee_estimates(&data_dir);
+		assert_eq!(
+			feerate(&reloaded, ConfirmationTarget::NonAnchorChannelFee),
+			(3000, FeerateOrigin::Cached)
+		);
+		assert_eq!(
+			feerate(&reloaded, ConfirmationTarget::UrgentOnChainSweep),
+			(5000, FeerateOrigin::Fallback)
+		);
+	}
+
+	#[test]
+	fn test_policy_from_json() {
+		let policy = FeePolicy::from_json(
+			r#"{
+				"poll_interval_secs": 30,
+				"targets": {
+					"UrgentOnChainSweep": { "blocks": 3, "floor": 1000, "ceiling": 20000 },
+					"AnchorChannelFee": { "blocks": "mempoolmin" }
+				}
+			}"#,
+		)
+		.unwrap();
+		assert_eq!(policy.poll_interval, Duration::from_secs(30));
+		let urgent = policy.target(ConfirmationTarget::UrgentOnChainSweep);
+		assert_eq!(
+			urgent.source,
+			FeeSource::Estimate { blocks: 3, mode: EstimateMode::Conservative }
+		);
+		assert_eq!(urgent.feerate(Some(500)).origin, FeerateOrigin::Floor);
+		assert_eq!(urgent.feerate(Some(50000)).sat_per_kw, 20000);
+		assert_eq!(
+			policy.target(ConfirmationTarget::AnchorChannelFee).source,
+			FeeSource::MempoolMinimum
+		);
+		assert_eq!(policy.sources().len(), 5);
+
+		assert!(FeePolicy::from_json(r#"{"targets": {"Urgent": {}}}"#).is_err());
+		assert!(
+			FeePolicy::from_json(r#"{"targets": {"AnchorChannelFee": {"blocks": 0}}}"#).is_err()
+		);
+		assert!(FeePolicy::from_json(
+			r#"{"targets": {"AnchorChannelFee": {"floor": 5000, "ceiling": 1000}}}"#
+		)
+		.is_err());
+	}
+}
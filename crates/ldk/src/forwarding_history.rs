@@ -0,0 +1,206 @@
+use crate::data_store::DataStore;
+use crate::disk::{FilesystemLogger, FORWARDING_HISTORY_NAMESPACE};
+use crate::hex_utils;
+use bitcoin::secp256k1::PublicKey;
+use lightning::io;
+use lightning::ln::types::ChannelId;
+use lightning::util::logger::Logger;
+use lightning::util::persist::KVStore;
+use lightning::util::ser::{Readable, Writeable};
+use lightning::{impl_writeable_tlv_based, log_error, log_info};
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// A payment we forwarded and were paid for, as reported by `Event::PaymentForwarded`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForwardRecord {
+	pub prev_channel_id: Option<ChannelId>,
+	pub next_channel_id: Option<ChannelId>,
+	pub prev_node_id: Option<PublicKey>,
+	pub next_node_id: Option<PublicKey>,
+	pub outbound_amount_msat: Option<u64>,
+	pub fee_earned_msat: Option<u64>,
+	/// Whether the downstream HTLC was claimed on-chain rather than by an `update_fulfill_htlc`.
+	pub claimed_onchain: bool,
+	/// When the forward was claimed, in seconds since the UNIX epoch.
+	pub timestamp: u64,
+}
+
+impl_writeable_tlv_based!(ForwardRecord, {
+	(0, prev_channel_id, option),
+	(2, next_channel_id, option),
+	(4, prev_node_id, option),
+	(6, next_node_id, option),
+	(8, outbound_amount_msat, option),
+	(10, fee_earned_msat, option),
+	(12, claimed_onchain, required),
+	(14, timestamp, required),
+});
+
+impl ForwardRecord {
+	/// The amount we received from the previous hop, if we know both what we forwarded and what
+	/// we earned doing so.
+	pub fn inbound_amount_msat(&self) -> Option<u64> {
+		Some(self.outbound_amount_msat? + self.fee_earned_msat?)
+	}
+}
+
+/// What went through one channel in a set of forwards.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ChannelForwardTotals {
+	pub channel_id: ChannelId,
+	/// The number of forwards which came in over the channel.
+	pub forwards_in: usize,
+	/// The number of forwards which went out over the channel.
+	pub forwards_out: usize,
+	pub amount_in_msat: u64,
+	pub amount_out_msat: u64,
+	/// The fees earned on forwards which went out over the channel, as that is the channel the
+	/// fee was charged for.
+	pub fee_earned_msat: u64,
+}
+
+/// Sums up `forwards` per channel, ordered by the fees earned, highest first.
+pub fn totals_by_channel(forwards: &[ForwardRecord]) -> Vec<ChannelForwardTotals> {
+	let mut totals: HashMap<ChannelId, ChannelForwardTotals> = HashMap::new();
+	for forward in forwards {
+		if let Some(channel_id) = forward.prev_channel_id {
+			let channel = totals.entry(channel_id).or_insert_with(|| empty_totals(channel_id));
+			channel.forwards_in += 1;
+			channel.amount_in_msat += forward.inbound_amount_msat().unwrap_or(0);
+		}
+		if let Some(channel_id) = forward.next_channel_id {
+			let channel = totals.entry(channel_id).or_insert_with(|| empty_totals(channel_id));
+			channel.forwards_out += 1;
+			channel.amount_out_msat += forward.outbound_amount_msat.unwrap_or(0);
+			channel.fee_earned_msat += forward.fee_earned_msat.unwrap_or(0);
+		}
+	}
+	let mut totals = totals.into_values().collect::<Vec<_>>();
+	totals.sort_by(|a, b| {
+		b.fee_earned_msat.cmp(&a.fee_earned_msat).then(a.channel_id.0.cmp(&b.channel_id.0))
+	});
+	totals
+}
+
+fn empty_totals(channel_id: ChannelId) -> ChannelForwardTotals {
+	ChannelForwardTotals {
+		channel_id,
+		forwards_in: 0,
+		forwards_out: 0,
+		amount_in_msat: 0,
+		amount_out_msat: 0,
+		fee_earned_msat: 0,
+	}
+}
+
+/// Every payment we forwarded, kept so routing income can be accounted for after the events
+/// reporting it are long gone.
+///
+/// Each forward is persisted under its own key, so recording one doesn't rewrite the whole
+/// history.
+pub struct ForwardingHistory {
+	forwards: Mutex<Vec<ForwardRecord>>,
+	kv_store: Arc<DataStore>,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl ForwardingHistory {
+	/// Loads the forwards persisted in `kv_store`.
+	pub fn new(kv_store: Arc<DataStore>, logger: Arc<FilesystemLogger>) -> Self {
+		let mut forwards = Vec::new();
+		let keys = match kv_store.list(FORWARDING_HISTORY_NAMESPACE, "") {
+			Ok(keys) => keys,
+			Err(e) => {
+				log_error!(logger, "Failed to list forwarding history: {}", e);
+				Vec::new()
+			},
+		};
+		for key in keys {
+			let res = kv_store.read(FORWARDING_HISTORY_NAMESPACE, "", &key).and_then(|bytes| {
+				ForwardRecord::read(&mut &bytes[..])
+					.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))
+			});
+			match res {
+				Ok(forward) => forwards.push(forward),
+				Err(e) => log_error!(logger, "Failed to read forward {}: {}", key, e),
+			}
+		}
+		forwards.sort_by_key(|forward| forward.timestamp);
+		if !forwards.is_empty() {
+			log_info!(logger, "Loaded {} forwards", forwards.len());
+		}
+		Self { forwards: Mutex::new(forwards), kv_store, logger }
+	}
+
+	/// Records a forward and persists it before returning.
+	pub fn record(&self, forward: ForwardRecord) {
+		// Keys sort by when the forward was claimed, and are made unique by a random suffix.
+		let suffix = hex_utils::hex_str(&rand::thread_rng().gen::<[u8; 8]>());
+		let key = format!("{:020}-{}", forward.timestamp, suffix);
+		let encoded = forward.encode();
+		self.forwards.lock().unwrap().push(forward);
+		if let Err(e) = self.kv_store.write(FORWARDING_HISTORY_NAMESPACE, "", &key, &encoded) {
+			log_error!(self.logger, "Failed to persist forward {}: {}", key, e);
+		}
+	}
+
+	/// The forwards claimed within `since..until`, in seconds since the UNIX epoch, oldest first.
+	pub fn list(&self, since: Option<u64>, until: Option<u64>) -> Vec<ForwardRecord> {
+		self.forwards
+			.lock()
+			.unwrap()
+			.iter()
+			.filter(|forward| since.is_none_or(|since| forward.timestamp >= since))
+			.filter(|forward| until.is_none_or(|until| forward.timestamp < until))
+			.cloned()
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::{test_data_dir, test_store};
+
+	fn forward(
+		prev: u8, next: u8, outbound_amount_msat: u64, fee: u64, timestamp: u64,
+	) -> ForwardRecord {
+		ForwardRecord {
+			prev_channel_id: Some(ChannelId([prev; 32])),
+			next_channel_id: Some(ChannelId([next; 32])),
+			prev_node_id: None,
+			next_node_id: None,
+			outbound_amount_msat: Some(outbound_amount_msat),
+			fee_earned_msat: Some(fee),
+			claimed_onchain: false,
+			timestamp,
+		}
+	}
+
+	#[test]
+	fn test_forwards_are_persisted_and_totalled() {
+		let (kv_store, logger) = test_store(&test_data_dir("forwarding-history"));
+		let history = ForwardingHistory::new(Arc::clone(&kv_store), Arc::clone(&logger));
+		history.record(forward(1, 2, 100_000, 10, 1000));
+		history.record(forward(2, 1, 50_000, 5, 2000));
+		history.record(forward(1, 3, 200_000, 30, 3000));
+		assert_eq!(kv_store.list(FORWARDING_HISTORY_NAMESPACE, "").unwrap().len(), 3);
+
+		let history = ForwardingHistory::new(kv_store, logger);
+		assert_eq!(history.list(None, None).len(), 3);
+		let recent = history.list(Some(2000), None);
+		assert_eq!(recent.iter().map(|f| f.timestamp).collect::<Vec<_>>(), vec![2000, 3000]);
+		assert_eq!(history.list(Some(1000), Some(3000)).len(), 2);
+
+		let totals = totals_by_channel(&history.list(None, None));
+		let channel_ids = totals.iter().map(|t| t.channel_id).collect::<Vec<_>>();
+		assert_eq!(channel_ids, vec![ChannelId([3; 32]), ChannelId([2; 32]), ChannelId([1; 32])]);
+		let channel_one = &totals[2];
+		assert_eq!((channel_one.forwards_in, channel_one.forwards_out), (2, 1));
+		assert_eq!(channel_one.amount_in_msat, 100_010 + 200_030);
+		assert_eq!(channel_one.amount_out_msat, 50_000);
+		assert_eq!(channel_one.fee_earned_msat, 5);
+	}
+}
@@ -0,0 +1,265 @@
+use crate::common::NetworkGraph;
+use lightning::routing::gossip::{ChannelInfo, ChannelUpdateInfo, NodeId};
+use serde_json::{json, Value};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+
+/// A node within a [`Subgraph`].
+pub struct SubgraphNode {
+	pub node_id: NodeId,
+	pub alias: Option<String>,
+	/// How many channels away from the center of the subgraph the node is.
+	pub hops: u8,
+}
+
+/// The part of the network graph within a number of hops of one node, e.g. our own.
+pub struct Subgraph {
+	pub center: NodeId,
+	pub max_hops: u8,
+	/// Ordered by distance from the center.
+	pub nodes: Vec<SubgraphNode>,
+	/// Every channel between two of the [`Subgraph::nodes`], ordered by short channel id.
+	pub channels: Vec<(u64, ChannelInfo)>,
+}
+
+/// Collects the nodes within `max_hops` channels of `center`, and the channels between them.
+pub fn ego_subgraph(network_graph: &NetworkGraph, center: NodeId, max_hops: u8) -> Subgraph {
+	let graph = network_graph.read_only();
+	let mut hops = HashMap::new();
+	hops.insert(center, 0);
+	let mut queue = VecDeque::new();
+	queue.push_back(center);
+	while let Some(node_id) = queue.pop_front() {
+		let distance = hops[&node_id];
+		if distance == max_hops {
+			continue;
+		}
+		let node = match graph.node(&node_id) {
+			Some(node) => node,
+			None => continue,
+		};
+		for scid in node.channels.iter() {
+			let channel = match graph.channel(*scid) {
+				Some(channel) => channel,
+				None => continue,
+			};
+			let peer =
+				if channel.node_one == node_id { channel.node_two } else { channel.node_one };
+			if let Entry::Vacant(entry) = hops.entry(peer) {
+				entry.insert(distance + 1);
+				queue.push_back(peer);
+			}
+		}
+	}
+
+	let mut nodes = hops
+		.iter()
+		.map(|(node_id, hops)| SubgraphNode {
+			node_id: *node_id,
+			alias: graph
+				.node(node_id)
+				.and_then(|node| node.announcement_info.as_ref())
+				.map(|announcement| announcement.alias().to_string()),
+			hops: *hops,
+		})
+		.collect::<Vec<_>>();
+	nodes.sort_by_key(|node| (node.hops, node.node_id));
+
+	let mut channels = graph
+		.channels()
+		.unordered_iter()
+		.filter(|(_, channel)| {
+			hops.contains_key(&channel.node_one) && hops.contains_key(&channel.node_two)
+		})
+		.map(|(scid, channel)| (*scid, channel.clone()))
+		.collect::<Vec<_>>();
+	channels.sort_by_key(|(scid, _)| *scid);
+
+	Subgraph { center, max_hops, nodes, channels }
+}
+
+fn direction_json(update: &Option<ChannelUpdateInfo>) -> Value {
+	match update {
+		Some(update) => json!({
+			"enabled": update.enabled,
+			"fee_base_msat": update.fees.base_msat,
+			"fee_proportional_millionths": update.fees.proportional_millionths,
+			"cltv_expiry_delta": update.cltv_expiry_delta,
+			"htlc_minimum_msat": update.htlc_minimum_msat,
+			"htlc_maximum_msat": update.htlc_maximum_msat,
+			"last_update": update.last_update,
+		}),
+		None => Value::Null,
+	}
+}
+
+/// Escapes `s` for use within a double-quoted DOT string.
+fn dot_escape(s: &str) -> String {
+	s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl Subgraph {
+	pub fn to_json(&self) -> Value {
+		let nodes = self
+			.nodes
+			.iter()
+			.map(|node| {
+				json!({
+					"node_id": node.node_id.to_string(),
+					"alias": node.alias,
+					"hops": node.hops,
+				})
+			})
+			.collect::<Vec<_>>();
+		let channels = self
+			.channels
+			.iter()
+			.map(|(scid, channel)| {
+				json!({
+					"short_channel_id": scid,
+					"node_one": channel.node_one.to_string(),
+					"node_two": channel.node_two.to_string(),
+					"capacity_sats": channel.capacity_sats,
+					"one_to_two": direction_json(&channel.one_to_two),
+					"two_to_one": direction_json(&channel.two_to_one),
+				})
+			})
+			.collect::<Vec<_>>();
+		json!({
+			"center": self.center.to_string(),
+			"max_hops": self.max_hops,
+			"nodes": nodes,
+			"channels": channels,
+		})
+	}
+
+	/// Renders the subgraph as a Graphviz digraph with an edge for every direction of a channel
+	/// we have an update for, labelled with its fees and the channel's capacity.
+	pub fn to_dot(&self) -> String {
+		let mut dot = String::from("digraph network {\n");
+		for node in self.nodes.iter() {
+			let id = node.node_id.to_string();
+			let label = match &node.alias {
+				Some(alias) => format!("{}\\n{}", dot_escape(alias), &id[..16]),
+				None => id[..16].to_string(),
+			};
+			let style = if node.node_id == self.center { ", style=filled" } else { "" };
+			writeln!(dot, "\t\"{}\" [label=\"{}\"{}];", id, label, style).unwrap();
+		}
+		for (scid, channel) in self.channels.iter() {
+			let capacity = match channel.capacity_sats {
+				Some(capacity) => format!("{} sat", capacity),
+				None => "unknown capacity".to_string(),
+			};
+			let directions = [
+				(channel.node_one, channel.node_two, &channel.one_to_two),
+				(channel.node_two, channel.node_one, &channel.two_to_one),
+			];
+			for (from, to, update) in directions.iter() {
+				let update = match update {
+					Some(update) => update,
+					None => continue,
+				};
+				let style = if update.enabled { "" } else { ", style=dashed" };
+				writeln!(
+					dot,
+					"\t\"{}\" -> \"{}\" [label=\"{}\\n{}\\n{} msat + {} ppm\"{}];",
+					from,
+					to,
+					scid,
+					capacity,
+					update.fees.base_msat,
+					update.fees.proportional_millionths,
+					style
+				)
+				.unwrap();
+			}
+		}
+		dot.push_str("}\n");
+		dot
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::disk::FilesystemLogger;
+	use crate::test_utils::test_data_dir;
+	use bitcoin::constants::ChainHash;
+	use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+	use bitcoin::Network;
+	use lightning::ln::msgs::UnsignedChannelUpdate;
+	use lightning::types::features::ChannelFeatures;
+	use std::sync::Arc;
+	use std::time::{SystemTime, UNIX_EPOCH};
+
+	fn pubkey(byte: u8) -> PublicKey {
+		PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[byte; 32]).unwrap())
+	}
+
+	/// Builds the line a - b - c - d, with updates for the a -> b direction only.
+	fn line_graph() -> NetworkGraph {
+		let logger = Arc::new(FilesystemLogger::new(test_data_dir("graph-export")));
+		let graph = NetworkGraph::new(Network::Regtest, logger);
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+		for (scid, (one, two)) in [(1, 2), (2, 3), (3, 4)].iter().enumerate() {
+			graph
+				.add_channel_from_partial_announcement(
+					scid as u64 + 1,
+					now,
+					ChannelFeatures::empty(),
+					pubkey(*one),
+					pubkey(*two),
+				)
+				.unwrap();
+		}
+		graph
+			.update_channel_unsigned(&UnsignedChannelUpdate {
+				chain_hash: ChainHash::using_genesis_block(Network::Regtest),
+				short_channel_id: 1,
+				timestamp: now as u32,
+				message_flags: 1,
+				// From the first node of the announcement, a.
+				channel_flags: 0,
+				cltv_expiry_delta: 40,
+				htlc_minimum_msat: 1,
+				htlc_maximum_msat: 100_000,
+				fee_base_msat: 1000,
+				fee_proportional_millionths: 100,
+				excess_data: Vec::new(),
+			})
+			.unwrap();
+		graph
+	}
+
+	#[test]
+	fn test_subgraph_is_limited_to_max_hops() {
+		let graph = line_graph();
+		let center = NodeId::from_pubkey(&pubkey(1));
+		let subgraph = ego_subgraph(&graph, center, 2);
+		let hops = subgraph.nodes.iter().map(|node| node.hops).collect::<Vec<_>>();
+		assert_eq!(hops, vec![0, 1, 2]);
+		assert_eq!(subgraph.nodes[0].node_id, center);
+		let scids = subgraph.channels.iter().map(|(scid, _)| *scid).collect::<Vec<_>>();
+		assert_eq!(scids, vec![1, 2]);
+
+		let json = subgraph.to_json();
+		assert_eq!(json["nodes"].as_array().unwrap().len(), 3);
+		let channel = &json["channels"][0];
+		let update = if channel["one_to_two"].is_null() {
+			&channel["two_to_one"]
+		} else {
+			&channel["one_to_two"]
+		};
+		assert_eq!(update["fee_base_msat"], 1000);
+		assert_eq!(update["fee_proportional_millionths"], 100);
+
+		// Only the direction with an update becomes an edge.
+		let dot = subgraph.to_dot();
+		assert!(dot.starts_with("digraph network {"));
+		assert_eq!(dot.matches(" -> ").count(), 1);
+		assert!(dot.contains(&format!("\"{}\" -> ", center)));
+		assert!(dot.contains("1000 msat + 100 ppm"));
+	}
+}
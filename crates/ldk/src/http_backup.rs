@@ -0,0 +1,345 @@
+use crate::sqlite_store::is_valid_kvstore_str;
+use lightning::io;
+use lightning::util::persist::KVStore;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The path entries are read, written and removed at, identified by the `primary_namespace`,
+/// `secondary_namespace` and `key` query parameters.
+pub const OBJECTS_PATH: &str = "/v1/objects";
+/// The path the keys of a namespace are listed at, identified by the `primary_namespace` and
+/// `secondary_namespace` query parameters. Keys are returned one per line.
+pub const KEYS_PATH: &str = "/v1/keys";
+/// The path the highest version the server accepted for any entry is read at, as a decimal number.
+pub const VERSION_PATH: &str = "/v1/version";
+/// The header carrying an entry's version. Writes and removals with a version lower than the one
+/// the server already has for an entry are rejected with `409 Conflict`.
+pub const VERSION_HEADER: &str = "X-Backup-Version";
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A [`KVStore`] backed by the backup server shipped as the `backup-server` binary, talking plain
+/// HTTP/1.1 to it.
+///
+/// Every write and removal carries a version increasing with every request, so a delayed request
+/// can't overwrite the result of a later one. Versions continue from the highest one the server
+/// has, rather than following our clock, so they keep increasing across restarts even if the
+/// clock steps backwards. A write the server rejects as stale is an error: it means something
+/// else, e.g. a second node restored from the same backup, wrote a later version.
+pub struct HttpBackupClient {
+	host: String,
+	port: u16,
+	path_prefix: String,
+	token: Option<String>,
+	/// The last version we sent, once it was fetched from the server.
+	last_version: Mutex<Option<u64>>,
+}
+
+impl HttpBackupClient {
+	/// Creates a client for the server at `url`, e.g. `http://127.0.0.1:9737`. If given, `token`
+	/// is sent as a bearer token with every request.
+	pub fn new(url: &str, token: Option<String>) -> io::Result<Self> {
+		let invalid_url =
+			|| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid backup URL {}", url));
+		let rest = url.strip_prefix("http://").ok_or_else(invalid_url)?;
+		let (authority, path_prefix) = match rest.find('/') {
+			Some(idx) => (&rest[..idx], rest[idx..].trim_end_matches('/')),
+			None => (rest, ""),
+		};
+		let (host, port) = match authority.rsplit_once(':') {
+			Some((host, port)) => (host, port.parse().map_err(|_| invalid_url())?),
+			None => (authority, 80),
+		};
+		if host.is_empty() {
+			return Err(invalid_url());
+		}
+		Ok(Self {
+			host: host.to_string(),
+			port,
+			path_prefix: path_prefix.to_string(),
+			token,
+			last_version: Mutex::new(None),
+		})
+	}
+
+	/// Returns a version greater than any handed out before and than any the server accepted
+	/// before we first asked it.
+	fn next_version(&self) -> io::Result<u64> {
+		let mut last_version = self.last_version.lock().unwrap();
+		let last = match *last_version {
+			Some(last) => last,
+			None => match self.request("GET", VERSION_PATH, &[], None, &[])? {
+				(200, body) => String::from_utf8_lossy(&body).trim().parse().map_err(|_| {
+					io::Error::new(
+						io::ErrorKind::InvalidData,
+						"Invalid version from the backup server",
+					)
+				})?,
+				(status, body) => return Err(unexpected_status("get version", status, &body)),
+			},
+		};
+		let next = last
+			.checked_add(1)
+			.ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Backup versions are exhausted"))?;
+		*last_version = Some(next);
+		Ok(next)
+	}
+
+	fn request(
+		&self, method: &str, path: &str, query: &[(&str, &str)], version: Option<u64>, body: &[u8],
+	) -> io::Result<(u16, Vec<u8>)> {
+		for (name, value) in query {
+			if !is_valid_kvstore_str(value) {
+				return Err(io::Error::new(
+					io::ErrorKind::InvalidInput,
+					format!("Invalid {} {}", name, value),
+				));
+			}
+		}
+		let query = query
+			.iter()
+			.map(|(name, value)| format!("{}={}", name, value))
+			.collect::<Vec<_>>()
+			.join("&");
+		let target = if query.is_empty() {
+			format!("{}{}", self.path_prefix, path)
+		} else {
+			format!("{}{}?{}", self.path_prefix, path, query)
+		};
+
+		let mut request = format!(
+			"{} {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\nContent-Length: {}\r\n",
+			method,
+			target,
+			self.host,
+			self.port,
+			body.len()
+		);
+		if let Some(token) = &self.token {
+			request.push_str(&format!("Authorization: Bearer {}\r\n", token));
+		}
+		if let Some(version) = version {
+			request.push_str(&format!("{}: {}\r\n", VERSION_HEADER, version));
+		}
+		request.push_str("\r\n");
+
+		let addr = (self.host.as_str(), self.port).to_socket_addrs()?.next().ok_or_else(|| {
+			io::Error::new(io::ErrorKind::NotFound, format!("Unable to resolve {}", self.host))
+		})?;
+		let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+		stream.set_read_timeout(Some(TIMEOUT))?;
+		stream.set_write_timeout(Some(TIMEOUT))?;
+		stream.write_all(request.as_bytes())?;
+		stream.write_all(body)?;
+
+		let mut reader = BufReader::new(stream);
+		let mut status_line = String::new();
+		reader.read_line(&mut status_line)?;
+		let status = status_line.split_whitespace().nth(1).and_then(|s| s.parse().ok());
+		let status = status.ok_or_else(|| {
+			io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("Invalid response from the backup server: {}", status_line.trim()),
+			)
+		})?;
+		let mut content_length = None;
+		loop {
+			let mut line = String::new();
+			if reader.read_line(&mut line)? == 0 {
+				break;
+			}
+			let line = line.trim_end();
+			if line.is_empty() {
+				break;
+			}
+			if let Some((name, value)) = line.split_once(':') {
+				if name.trim().eq_ignore_ascii_case("content-length") {
+					content_length = value.trim().parse::<usize>().ok();
+				}
+			}
+		}
+		let mut response_body = Vec::new();
+		match content_length {
+			Some(len) => {
+				response_body.resize(len, 0);
+				reader.read_exact(&mut response_body)?;
+			},
+			None => {
+				reader.read_to_end(&mut response_body)?;
+			},
+		}
+		Ok((status, response_body))
+	}
+}
+
+fn unexpected_status(operation: &str, status: u16, body: &[u8]) -> io::Error {
+	let kind = match status {
+		401 | 403 => io::ErrorKind::PermissionDenied,
+		// The server has a later version of the entry, which we didn't write.
+		409 => io::ErrorKind::AlreadyExists,
+		_ => io::ErrorKind::Other,
+	};
+	io::Error::new(
+		kind,
+		format!(
+			"Backup server failed to {} with status {}: {}",
+			operation,
+			status,
+			String::from_utf8_lossy(body)
+		),
+	)
+}
+
+impl KVStore for HttpBackupClient {
+	fn read(
+		&self, primary_namespace: &str, secondary_namespace: &str, key: &str,
+	) -> io::Result<Vec<u8>> {
+		let query = [
+			("primary_namespace", primary_namespace),
+			("secondary_namespace", secondary_namespace),
+			("key", key),
+		];
+		match self.request("GET", OBJECTS_PATH, &query, None, &[])? {
+			(200, body) => Ok(body),
+			(404, _) => Err(io::Error::new(
+				io::ErrorKind::NotFound,
+				format!("{}/{}/{} not found", primary_namespace, secondary_namespace, key),
+			)),
+			(status, body) => Err(unexpected_status("read", status, &body)),
+		}
+	}
+
+	fn write(
+		&self, primary_namespace: &str, secondary_namespace: &str, key: &str, buf: &[u8],
+	) -> io::Result<()> {
+		let query = [
+			("primary_namespace", primary_namespace),
+			("secondary_namespace", secondary_namespace),
+			("key", key),
+		];
+		match self.request("PUT", OBJECTS_PATH, &query, Some(self.next_version()?), buf)? {
+			(200, _) => Ok(()),
+			(status, body) => Err(unexpected_status("write", status, &body)),
+		}
+	}
+
+	fn remove(
+		&self, primary_namespace: &str, secondary_namespace: &str, key: &str, _lazy: bool,
+	) -> io::Result<()> {
+		let query = [
+			("primary_namespace", primary_namespace),
+			("secondary_namespace", secondary_namespace),
+			("key", key),
+		];
+		match self.request("DELETE", OBJECTS_PATH, &query, Some(self.next_version()?), &[])? {
+			(200, _) | (404, _) => Ok(()),
+			(status, body) => Err(unexpected_status("remove", status, &body)),
+		}
+	}
+
+	fn list(&self, primary_namespace: &str, secondary_namespace: &str) -> io::Result<Vec<String>> {
+		let query = [
+			("primary_namespace", primary_namespace),
+			("secondary_namespace", secondary_namespace),
+		];
+		match self.request("GET", KEYS_PATH, &query, None, &[])? {
+			(200, body) => Ok(String::from_utf8_lossy(&body)
+				.lines()
+				.filter(|key| !key.is_empty())
+				.map(String::from)
+				.collect()),
+			(status, body) => Err(unexpected_status("list", status, &body)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::MockHttpServer;
+	use std::collections::HashMap;
+	use std::sync::{Arc, Mutex};
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_requests_follow_the_protocol() {
+		let objects = Arc::new(Mutex::new(HashMap::new()));
+		let server_objects = Arc::clone(&objects);
+		let server = MockHttpServer::start(move |request| {
+			if request.path == VERSION_PATH {
+				return (200, "41".to_string());
+			}
+			let mut objects = server_objects.lock().unwrap();
+			let (path, query) = request.path.split_once('?').unwrap();
+			match (request.method.as_str(), path) {
+				("PUT", OBJECTS_PATH) if query.ends_with("key=stale") => {
+					(409, "Version is not newer".to_string())
+				},
+				("PUT", OBJECTS_PATH) => {
+					objects.insert(query.to_string(), request.body.clone());
+					(200, String::new())
+				},
+				("GET", OBJECTS_PATH) => match objects.get(query) {
+					Some(body) => (200, body.clone()),
+					None => (404, String::new()),
+				},
+				("DELETE", OBJECTS_PATH) => {
+					objects.remove(query);
+					(200, String::new())
+				},
+				("GET", KEYS_PATH) => {
+					let keys = objects
+						.keys()
+						.filter(|k| k.starts_with(query))
+						.map(|k| k.rsplit('=').next().unwrap())
+						.collect::<Vec<_>>();
+					(200, keys.join("\n"))
+				},
+				_ => (400, "unexpected request".to_string()),
+			}
+		})
+		.await;
+
+		let client = HttpBackupClient::new(&server.url(), None).unwrap();
+		tokio::task::spawn_blocking(move || {
+			client.write("monitors", "", "chan_a", b"monitor").unwrap();
+			assert_eq!(client.read("monitors", "", "chan_a").unwrap(), b"monitor".to_vec());
+			assert_eq!(client.list("monitors", "").unwrap(), vec!["chan_a".to_string()]);
+			client.remove("monitors", "", "chan_a", false).unwrap();
+			assert_eq!(
+				client.read("monitors", "", "chan_a").unwrap_err().kind(),
+				io::ErrorKind::NotFound
+			);
+			assert!(client.write("monitors", "", "chan_a/..", b"").is_err());
+			// A stale write is never taken as replicated.
+			let err = client.write("monitors", "", "stale", b"").unwrap_err();
+			assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+		})
+		.await
+		.unwrap();
+
+		// The version is only fetched once, and the ones we send continue from it.
+		let requests = server.requests();
+		assert_eq!(requests[0].method, "GET");
+		assert_eq!(requests[0].path, VERSION_PATH);
+		assert_eq!(requests[1].method, "PUT");
+		assert_eq!(
+			requests[1].path,
+			"/v1/objects?primary_namespace=monitors&secondary_namespace=&key=chan_a"
+		);
+		assert_eq!(requests[1].header(VERSION_HEADER), Some("42"));
+		assert_eq!(requests[4].method, "DELETE");
+		assert_eq!(requests[4].header(VERSION_HEADER), Some("43"));
+		assert!(requests[5..].iter().all(|request| request.path != VERSION_PATH));
+	}
+
+	#[test]
+	fn test_urls_are_parsed() {
+		let client = HttpBackupClient::new("http://127.0.0.1:9737/backup/", None).unwrap();
+		assert_eq!(client.path_prefix, "/backup");
+		assert_eq!(client.port, 9737);
+		assert!(HttpBackupClient::new("https://127.0.0.1", None).is_err());
+		assert!(HttpBackupClient::new("http://:9737", None).is_err());
+	}
+}
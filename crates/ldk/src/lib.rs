@@ -1,6 +1,31 @@
 pub mod bitcoind_client;
+pub mod bitcoind_zmq;
+pub mod broadcast_queue;
+pub mod chain_source;
+pub mod closed_channels;
 pub mod convert;
+pub mod data_dir_lock;
+pub mod data_store;
 pub mod disk;
+pub mod electrum_client;
+pub mod encrypted_store;
+pub mod esplora_client;
+pub mod event_stream;
+pub mod fee_policy;
+pub mod forwarding_history;
+pub mod graph_export;
 pub mod hex_utils;
+pub mod http_backup;
+pub mod logging;
+pub mod metrics;
+pub mod mnemonic;
+pub mod notifications;
+pub mod rapid_gossip;
+pub mod replicating_store;
+pub mod scoring;
+pub mod sqlite_store;
 pub mod sweep;
-pub mod common;
\ No newline at end of file
+pub mod webhooks;
+pub mod common;
+#[cfg(test)]
+mod test_utils;
@@ -0,0 +1,404 @@
+//! The node's log, written to `<data dir>/logs/logs.txt` by a background thread.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use lightning::util::logger::{Level, Logger, Record};
+use serde_json::{Map, Value};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+
+const LOG_FILE_NAME: &str = "logs.txt";
+
+/// When the log file is moved aside and a new one started.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogRotation {
+	Never,
+	/// Once a line would take the log file over this many bytes.
+	Size(u64),
+	/// At the first line logged on a new UTC day.
+	Daily,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+	Text,
+	/// One JSON object per line, with the record's peer, channel and payment hash as fields.
+	JsonLines,
+}
+
+/// What gets logged and how the log files are written.
+#[derive(Clone, Debug)]
+pub struct LogConfig {
+	/// Records less severe than this are dropped, unless a module filter says otherwise.
+	pub min_level: Level,
+	/// The minimum level of records from a module and its submodules, e.g.
+	/// `("lightning::routing", Level::Warn)`. The filter naming the most specific module wins.
+	pub module_levels: Vec<(String, Level)>,
+	pub rotation: LogRotation,
+	/// How many rotated log files are kept. Older ones are deleted.
+	pub max_rotated_files: usize,
+	pub format: LogFormat,
+}
+
+impl Default for LogConfig {
+	fn default() -> Self {
+		Self {
+			// Gossip-level logs are incredibly verbose, and thus we skip them by default.
+			min_level: Level::Trace,
+			module_levels: Vec::new(),
+			rotation: LogRotation::Size(100 * 1024 * 1024),
+			max_rotated_files: 10,
+			format: LogFormat::Text,
+		}
+	}
+}
+
+impl LogConfig {
+	/// Whether a record of `level` logged from `module_path` should be written.
+	fn enabled(&self, level: Level, module_path: &str) -> bool {
+		let matches = |module: &&(String, Level)| {
+			let module = module.0.as_str();
+			module_path == module
+				|| (module_path.starts_with(module)
+					&& module_path[module.len()..].starts_with("::"))
+		};
+		let filter =
+			self.module_levels.iter().filter(matches).max_by_key(|(module, _)| module.len());
+		level >= filter.map_or(self.min_level, |(_, min_level)| *min_level)
+	}
+}
+
+enum LogMessage {
+	Line(String),
+	/// Asks the writer to flush everything sent before and acknowledge it.
+	Flush(Sender<()>),
+}
+
+/// Writes log lines to `<data dir>/logs/logs.txt`.
+///
+/// Lines are handed to a background thread, which writes them through a buffer and flushes it
+/// whenever it runs out of lines to write, so logging never waits on the disk and bursts of
+/// records, e.g. from gossip, don't cost a write each.
+pub struct FilesystemLogger {
+	config: LogConfig,
+	sender: Option<Sender<LogMessage>>,
+	writer: Option<JoinHandle<()>>,
+}
+
+impl FilesystemLogger {
+	/// Logs to `data_dir` with the default [`LogConfig`].
+	pub fn new(data_dir: String) -> Self {
+		Self::with_config(data_dir, LogConfig::default()).unwrap()
+	}
+
+	pub fn with_config(data_dir: String, config: LogConfig) -> io::Result<Self> {
+		let logs_dir = Path::new(&data_dir).join("logs");
+		fs::create_dir_all(&logs_dir)?;
+		let writer = LogWriter::open(logs_dir, config.rotation, config.max_rotated_files)?;
+		let (sender, receiver) = mpsc::channel();
+		let writer = thread::Builder::new()
+			.name("ldk-log-writer".to_string())
+			.spawn(move || writer.run(receiver))?;
+		Ok(Self { config, sender: Some(sender), writer: Some(writer) })
+	}
+
+	/// Waits until everything logged so far has been written to the log file.
+	pub fn flush(&self) {
+		let (ack_sender, ack_receiver) = mpsc::channel();
+		if self.send(LogMessage::Flush(ack_sender)) {
+			let _ = ack_receiver.recv();
+		}
+	}
+
+	/// Returns whether the writer is still there to receive the message.
+	fn send(&self, message: LogMessage) -> bool {
+		self.sender.as_ref().is_some_and(|sender| sender.send(message).is_ok())
+	}
+
+	fn format_text(record: &Record) -> String {
+		format!(
+			"{} {:<5} [{}:{}] {}\n",
+			// Note that a "real" lightning node almost certainly does *not* want subsecond
+			// precision for message-receipt information as it makes log entries a target for
+			// deanonymization attacks. For testing, however, its quite useful.
+			Utc::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+			record.level.to_string(),
+			record.module_path,
+			record.line,
+			record.args
+		)
+	}
+
+	fn format_json(record: &Record) -> String {
+		let mut fields = Map::new();
+		let time = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+		fields.insert("time".to_string(), Value::from(time));
+		fields.insert("level".to_string(), Value::from(record.level.to_string()));
+		fields.insert("module".to_string(), Value::from(record.module_path));
+		fields.insert("line".to_string(), Value::from(record.line));
+		fields.insert("message".to_string(), Value::from(record.args.to_string()));
+		if let Some(peer_id) = record.peer_id {
+			fields.insert("peer_id".to_string(), Value::from(peer_id.to_string()));
+		}
+		if let Some(channel_id) = record.channel_id {
+			fields.insert("channel_id".to_string(), Value::from(channel_id.to_string()));
+		}
+		if let Some(payment_hash) = record.payment_hash {
+			fields.insert("payment_hash".to_string(), Value::from(payment_hash.to_string()));
+		}
+		format!("{}\n", Value::Object(fields))
+	}
+}
+
+impl Logger for FilesystemLogger {
+	fn log(&self, record: Record) {
+		if !self.config.enabled(record.level, record.module_path) {
+			return;
+		}
+		let line = match self.config.format {
+			LogFormat::Text => Self::format_text(&record),
+			LogFormat::JsonLines => Self::format_json(&record),
+		};
+		self.send(LogMessage::Line(line));
+	}
+}
+
+impl Drop for FilesystemLogger {
+	fn drop(&mut self) {
+		// Hanging up lets the writer finish the lines it was sent and exit.
+		self.sender.take();
+		if let Some(writer) = self.writer.take() {
+			let _ = writer.join();
+		}
+	}
+}
+
+/// The background thread's end: the open log file and when to rotate it.
+struct LogWriter {
+	logs_dir: PathBuf,
+	rotation: LogRotation,
+	max_rotated_files: usize,
+	file: BufWriter<File>,
+	/// How many bytes the log file holds, including those still buffered.
+	size: u64,
+	/// The UTC day the log file was last written on.
+	day: NaiveDate,
+	/// The number the next rotated file is named with, one past the newest one on disk.
+	next_rotation: u64,
+}
+
+impl LogWriter {
+	fn open(
+		logs_dir: PathBuf, rotation: LogRotation, max_rotated_files: usize,
+	) -> io::Result<Self> {
+		let path = logs_dir.join(LOG_FILE_NAME);
+		let file = fs::OpenOptions::new().create(true).append(true).open(&path)?;
+		let metadata = file.metadata()?;
+		// A log left by a previous run is rotated on the first line if it's from an earlier day.
+		let day = match metadata.modified() {
+			Ok(modified) if metadata.len() > 0 => DateTime::<Utc>::from(modified).date_naive(),
+			_ => Utc::now().date_naive(),
+		};
+		let size = metadata.len();
+		let next_rotation =
+			rotated_files(&logs_dir)?.last().map_or(0, |(number, _)| number.saturating_add(1));
+		Ok(Self {
+			logs_dir,
+			rotation,
+			max_rotated_files,
+			file: BufWriter::new(file),
+			size,
+			day,
+			next_rotation,
+		})
+	}
+
+	fn run(mut self, receiver: Receiver<LogMessage>) {
+		loop {
+			let message = match receiver.try_recv() {
+				Ok(message) => message,
+				Err(TryRecvError::Empty) => {
+					self.flush();
+					match receiver.recv() {
+						Ok(message) => message,
+						Err(_) => break,
+					}
+				},
+				Err(TryRecvError::Disconnected) => break,
+			};
+			match message {
+				LogMessage::Line(line) => {
+					let res = self.write_line(&line);
+					self.report(res);
+				},
+				LogMessage::Flush(ack) => {
+					self.flush();
+					let _ = ack.send(());
+				},
+			}
+		}
+		self.flush();
+	}
+
+	fn flush(&mut self) {
+		let res = self.file.flush();
+		self.report(res);
+	}
+
+	/// There's nowhere to log a failure to log, so it goes to stderr instead.
+	fn report(&self, res: io::Result<()>) {
+		if let Err(e) = res {
+			eprintln!("ERROR: Failed to write to the log in {}: {}", self.logs_dir.display(), e);
+		}
+	}
+
+	fn write_line(&mut self, line: &str) -> io::Result<()> {
+		let today = Utc::now().date_naive();
+		let rotate = match self.rotation {
+			LogRotation::Never => false,
+			LogRotation::Size(max_size) => {
+				self.size > 0 && self.size + line.len() as u64 > max_size
+			},
+			LogRotation::Daily => self.day != today,
+		};
+		if rotate {
+			self.rotate()?;
+		}
+		self.file.write_all(line.as_bytes())?;
+		self.size += line.len() as u64;
+		self.day = today;
+		Ok(())
+	}
+
+	/// Moves the log file to `logs.<rotation number>.<time of rotation>.txt`, starts a new one
+	/// and deletes the rotated files we no longer keep.
+	fn rotate(&mut self) -> io::Result<()> {
+		self.file.flush()?;
+		// The zero-padded number keeps the names unique and in rotation order, even when several
+		// rotations happen within the same millisecond.
+		let rotated_at = Utc::now().format("%Y-%m-%dT%H-%M-%S%.3f");
+		let rotated_name = format!("logs.{:06}.{}.txt", self.next_rotation, rotated_at);
+		self.next_rotation += 1;
+		fs::rename(self.logs_dir.join(LOG_FILE_NAME), self.logs_dir.join(rotated_name))?;
+		let path = self.logs_dir.join(LOG_FILE_NAME);
+		self.file = BufWriter::new(fs::OpenOptions::new().create(true).append(true).open(path)?);
+		self.size = 0;
+
+		let rotated_files = rotated_files(&self.logs_dir)?;
+		let expired = rotated_files.len().saturating_sub(self.max_rotated_files);
+		for (_, name) in &rotated_files[..expired] {
+			fs::remove_file(self.logs_dir.join(name))?;
+		}
+		Ok(())
+	}
+}
+
+/// Lists the rotated log files in `logs_dir` with their rotation numbers, oldest first.
+fn rotated_files(logs_dir: &Path) -> io::Result<Vec<(u64, String)>> {
+	let mut rotated_files = Vec::new();
+	for entry in fs::read_dir(logs_dir)? {
+		let name = entry?.file_name().to_string_lossy().into_owned();
+		let number = name
+			.strip_prefix("logs.")
+			.filter(|rest| rest.ends_with(".txt"))
+			.and_then(|rest| rest.split('.').next())
+			.and_then(|number| number.parse::<u64>().ok());
+		if let Some(number) = number {
+			rotated_files.push((number, name));
+		}
+	}
+	rotated_files.sort();
+	Ok(rotated_files)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::test_data_dir;
+	use lightning::{log_debug, log_gossip, log_info, log_trace, log_warn};
+
+	fn read_log(data_dir: &str) -> String {
+		fs::read_to_string(Path::new(data_dir).join("logs").join(LOG_FILE_NAME)).unwrap()
+	}
+
+	#[test]
+	fn test_module_filters() {
+		let config = LogConfig {
+			min_level: Level::Info,
+			module_levels: vec![
+				("lightning::routing".to_string(), Level::Warn),
+				("lightning::routing::gossip".to_string(), Level::Gossip),
+			],
+			..LogConfig::default()
+		};
+		assert!(config.enabled(Level::Info, "lightning::ln::channelmanager"));
+		assert!(!config.enabled(Level::Debug, "lightning::ln::channelmanager"));
+		assert!(!config.enabled(Level::Info, "lightning::routing::router"));
+		assert!(config.enabled(Level::Warn, "lightning::routing"));
+		assert!(config.enabled(Level::Gossip, "lightning::routing::gossip"));
+		// Filters apply to whole path segments only.
+		assert!(config.enabled(Level::Info, "lightning::routingx"));
+
+		let data_dir = test_data_dir("log-filters");
+		let logger = FilesystemLogger::new(data_dir.clone());
+		log_gossip!(logger, "too verbose");
+		log_trace!(logger, "traced");
+		logger.flush();
+		assert_eq!(read_log(&data_dir).lines().count(), 1);
+		assert!(read_log(&data_dir).contains("traced"));
+	}
+
+	#[test]
+	fn test_size_rotation_keeps_the_newest_files() {
+		let data_dir = test_data_dir("log-rotation");
+		let config = LogConfig {
+			rotation: LogRotation::Size(200),
+			max_rotated_files: 2,
+			..LogConfig::default()
+		};
+		let logger = FilesystemLogger::with_config(data_dir.clone(), config).unwrap();
+		for i in 0..10 {
+			log_info!(logger, "entry {} {}", i, "x".repeat(100));
+		}
+		logger.flush();
+
+		let logs_dir = Path::new(&data_dir).join("logs");
+		let mut files = fs::read_dir(&logs_dir)
+			.unwrap()
+			.map(|entry| entry.unwrap().file_name().into_string().unwrap())
+			.collect::<Vec<_>>();
+		files.sort();
+		assert_eq!(files.len(), 3);
+		assert!(fs::read_to_string(logs_dir.join(&files[0])).unwrap().contains("entry 7 "));
+		assert!(fs::read_to_string(logs_dir.join(&files[1])).unwrap().contains("entry 8 "));
+		assert_eq!(files[2], LOG_FILE_NAME);
+		assert!(read_log(&data_dir).contains("entry 9 "));
+	}
+
+	#[test]
+	fn test_json_lines() {
+		let data_dir = test_data_dir("log-json");
+		let config = LogConfig {
+			min_level: Level::Debug,
+			format: LogFormat::JsonLines,
+			..LogConfig::default()
+		};
+		let logger = FilesystemLogger::with_config(data_dir.clone(), config).unwrap();
+		log_trace!(logger, "dropped");
+		log_debug!(logger, "a \"quoted\" message");
+		log_warn!(logger, "second");
+		drop(logger);
+
+		let log = read_log(&data_dir);
+		let lines = log.lines().map(|line| serde_json::from_str(line).unwrap());
+		let lines: Vec<Value> = lines.collect();
+		assert_eq!(lines.len(), 2);
+		assert_eq!(lines[0]["level"], "DEBUG");
+		assert_eq!(lines[0]["message"], "a \"quoted\" message");
+		assert_eq!(lines[0]["module"], module_path!());
+		assert_eq!(lines[1]["level"], "WARN");
+		assert!(lines[1].get("peer_id").is_none());
+	}
+}
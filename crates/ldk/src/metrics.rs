@@ -0,0 +1,372 @@
+use crate::bitcoind_client::BitcoindClient;
+use crate::common::{ChainMonitor, ChannelManager, NetworkGraph, PeerManager};
+use crate::disk::FilesystemLogger;
+use crate::fee_policy::FeeEstimates;
+use crate::forwarding_history::ForwardingHistory;
+use lightning::chain::channelmonitor::Balance;
+use lightning::ln::channelmanager::PaymentId;
+use lightning::log_error;
+use lightning::util::logger::Logger;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Write};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// The path metrics are scraped from.
+pub const METRICS_PATH: &str = "/metrics";
+
+/// The upper bounds of the payment duration histogram's buckets, in seconds.
+const PAYMENT_DURATION_BUCKETS: [f64; 9] = [0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+/// Requests with more header lines than this are rejected.
+const MAX_HEADERS: usize = 100;
+
+/// Builds a scrape in Prometheus' text exposition format.
+#[derive(Default)]
+struct Exposition(String);
+
+impl Exposition {
+	fn metric(&mut self, name: &str, kind: &str, help: &str) -> &mut Self {
+		let _ = writeln!(self.0, "# HELP {} {}", name, help);
+		let _ = writeln!(self.0, "# TYPE {} {}", name, kind);
+		self
+	}
+
+	fn sample(
+		&mut self, name: &str, labels: &[(&str, &str)], value: impl fmt::Display,
+	) -> &mut Self {
+		self.0.push_str(name);
+		if !labels.is_empty() {
+			let labels = labels
+				.iter()
+				.map(|(label, value)| format!("{}=\"{}\"", label, value))
+				.collect::<Vec<_>>();
+			let _ = write!(self.0, "{{{}}}", labels.join(","));
+		}
+		let _ = writeln!(self.0, " {}", value);
+		self
+	}
+
+	/// Adds a metric with a single sample and no labels.
+	fn unlabeled(&mut self, name: &str, kind: &str, help: &str, value: impl fmt::Display) {
+		self.metric(name, kind, help).sample(name, &[], value);
+	}
+}
+
+/// The outcomes of the payments we sent since startup.
+#[derive(Default)]
+struct PaymentStats {
+	/// When each payment still in flight was started.
+	started: HashMap<PaymentId, Instant>,
+	succeeded: u64,
+	failed: u64,
+	/// How many successful payments took at most each of [`PAYMENT_DURATION_BUCKETS`].
+	duration_buckets: [u64; PAYMENT_DURATION_BUCKETS.len()],
+	duration_count: u64,
+	duration_sum_secs: f64,
+}
+
+impl PaymentStats {
+	fn succeeded(&mut self, payment_id: &PaymentId, now: Instant) {
+		self.succeeded += 1;
+		// Payments started before a restart weren't timed.
+		if let Some(started) = self.started.remove(payment_id) {
+			let duration_secs = now.duration_since(started).as_secs_f64();
+			for (bucket, bound) in self.duration_buckets.iter_mut().zip(PAYMENT_DURATION_BUCKETS) {
+				if duration_secs <= bound {
+					*bucket += 1;
+				}
+			}
+			self.duration_count += 1;
+			self.duration_sum_secs += duration_secs;
+		}
+	}
+
+	fn failed(&mut self, payment_id: &PaymentId) {
+		self.failed += 1;
+		self.started.remove(payment_id);
+	}
+
+	fn render(&self, out: &mut Exposition) {
+		let name = "lightning_payments_total";
+		out.metric(name, "counter", "Payments sent since startup, by outcome.")
+			.sample(name, &[("status", "succeeded")], self.succeeded)
+			.sample(name, &[("status", "failed")], self.failed);
+
+		let name = "lightning_payment_duration_seconds";
+		let help = "How long successful payments took from being sent to being claimed.";
+		out.metric(name, "histogram", help);
+		let bucket_name = format!("{}_bucket", name);
+		for (count, bound) in self.duration_buckets.iter().zip(PAYMENT_DURATION_BUCKETS) {
+			out.sample(&bucket_name, &[("le", &bound.to_string())], count);
+		}
+		out.sample(&bucket_name, &[("le", "+Inf")], self.duration_count)
+			.sample(&format!("{}_sum", name), &[], self.duration_sum_secs)
+			.sample(&format!("{}_count", name), &[], self.duration_count);
+	}
+}
+
+/// Exports the state of the node in Prometheus' text format, for scraping from [`METRICS_PATH`].
+///
+/// Almost everything is read from the node's components when scraped. Only the outcomes and
+/// durations of payments we send are tracked here, from [`Metrics::payment_started`] to
+/// [`Metrics::payment_succeeded`] or [`Metrics::payment_failed`].
+pub struct Metrics {
+	channel_manager: Arc<ChannelManager>,
+	chain_monitor: Arc<ChainMonitor>,
+	peer_manager: Arc<PeerManager>,
+	network_graph: Arc<NetworkGraph>,
+	bitcoind_client: Option<Arc<BitcoindClient>>,
+	fee_estimates: Arc<FeeEstimates>,
+	forwarding_history: Arc<ForwardingHistory>,
+	payments: Mutex<PaymentStats>,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl Metrics {
+	#[allow(clippy::too_many_arguments)]
+	pub fn new(
+		channel_manager: Arc<ChannelManager>, chain_monitor: Arc<ChainMonitor>,
+		peer_manager: Arc<PeerManager>, network_graph: Arc<NetworkGraph>,
+		bitcoind_client: Option<Arc<BitcoindClient>>, fee_estimates: Arc<FeeEstimates>,
+		forwarding_history: Arc<ForwardingHistory>, logger: Arc<FilesystemLogger>,
+	) -> Self {
+		Self {
+			channel_manager,
+			chain_monitor,
+			peer_manager,
+			network_graph,
+			bitcoind_client,
+			fee_estimates,
+			forwarding_history,
+			payments: Mutex::new(PaymentStats::default()),
+			logger,
+		}
+	}
+
+	pub fn payment_started(&self, payment_id: PaymentId) {
+		self.payments.lock().unwrap().started.insert(payment_id, Instant::now());
+	}
+
+	pub fn payment_succeeded(&self, payment_id: &PaymentId) {
+		self.payments.lock().unwrap().succeeded(payment_id, Instant::now());
+	}
+
+	pub fn payment_failed(&self, payment_id: &PaymentId) {
+		self.payments.lock().unwrap().failed(payment_id);
+	}
+
+	/// Returns every metric in Prometheus' text format.
+	pub fn render(&self) -> String {
+		let mut out = Exposition::default();
+		self.render_channels(&mut out);
+
+		let peers = self.peer_manager.list_peers();
+		let inbound = peers.iter().filter(|peer| peer.is_inbound_connection).count();
+		let name = "lightning_peers";
+		out.metric(name, "gauge", "Connected peers, by who opened the connection.")
+			.sample(name, &[("direction", "inbound")], inbound)
+			.sample(name, &[("direction", "outbound")], peers.len() - inbound);
+
+		let graph = self.network_graph.read_only();
+		out.unlabeled(
+			"lightning_graph_nodes",
+			"gauge",
+			"Nodes in the network graph.",
+			graph.nodes().len(),
+		);
+		let channels = graph.channels().len();
+		out.unlabeled(
+			"lightning_graph_channels",
+			"gauge",
+			"Channels in the network graph.",
+			channels,
+		);
+
+		self.payments.lock().unwrap().render(&mut out);
+
+		let forwards = self.forwarding_history.list(None, None);
+		let forwarded_msat: u64 = forwards.iter().filter_map(|f| f.outbound_amount_msat).sum();
+		let fees_msat: u64 = forwards.iter().filter_map(|f| f.fee_earned_msat).sum();
+		out.unlabeled(
+			"lightning_forwards_total",
+			"counter",
+			"Payments we forwarded.",
+			forwards.len(),
+		);
+		let help = "The amount we forwarded to the next hops.";
+		out.unlabeled("lightning_forwarded_msat_total", "counter", help, forwarded_msat);
+		let help = "The fees we earned forwarding payments.";
+		out.unlabeled("lightning_forward_fees_msat_total", "counter", help, fees_msat);
+
+		let name = "lightning_fee_estimate_sat_per_kw";
+		out.metric(name, "gauge", "The feerate we use for each of LDK's confirmation targets.");
+		for (target, feerate) in self.fee_estimates.feerates() {
+			out.sample(name, &[("target", &format!("{:?}", target))], feerate.sat_per_kw);
+		}
+
+		let height = self.channel_manager.current_best_block().height;
+		let help = "The height of the best block we know of.";
+		out.unlabeled("lightning_chain_tip_height", "gauge", help, height);
+
+		if let Some(bitcoind_client) = &self.bitcoind_client {
+			let name = "bitcoind_rpc_errors_total";
+			let help = "Failed RPC calls to bitcoind, by method and kind of error.";
+			out.metric(name, "counter", help);
+			for (method, kind, count) in bitcoind_client.rpc_errors().counts() {
+				out.sample(name, &[("method", &method), ("kind", kind)], count);
+			}
+		}
+		out.0
+	}
+
+	fn render_channels(&self, out: &mut Exposition) {
+		let channels = self.channel_manager.list_channels();
+		let (mut pending, mut inactive, mut active) = (0, 0, 0);
+		let (mut local_msat, mut remote_msat) = (0, 0);
+		for channel in channels.iter() {
+			match (channel.is_channel_ready, channel.is_usable) {
+				(false, _) => pending += 1,
+				(true, false) => inactive += 1,
+				(true, true) => active += 1,
+			}
+			local_msat += channel.outbound_capacity_msat;
+			remote_msat += channel.inbound_capacity_msat;
+		}
+		// Closed channels keep their monitor until everything we could claim from them is claimed.
+		let open_channel_ids = channels.iter().map(|c| c.channel_id).collect::<HashSet<_>>();
+		let closing = self
+			.chain_monitor
+			.list_monitors()
+			.into_iter()
+			.filter(|(_, channel_id)| !open_channel_ids.contains(channel_id))
+			.filter_map(|(funding_txo, _)| self.chain_monitor.get_monitor(funding_txo).ok())
+			.filter(|monitor| !monitor.get_claimable_balances().is_empty())
+			.count();
+
+		let name = "lightning_channels";
+		out.metric(name, "gauge", "Our channels, by state.")
+			.sample(name, &[("state", "pending")], pending)
+			.sample(name, &[("state", "inactive")], inactive)
+			.sample(name, &[("state", "active")], active)
+			.sample(name, &[("state", "closing")], closing);
+
+		let name = "lightning_channel_balance_msat";
+		let help = "What our open channels let us send and receive, excluding channel reserves.";
+		out.metric(name, "gauge", help).sample(name, &[("side", "local")], local_msat).sample(
+			name,
+			&[("side", "remote")],
+			remote_msat,
+		);
+
+		let mut claimable_sats = BTreeMap::new();
+		for balance in self.chain_monitor.get_claimable_balances(&[]) {
+			let kind = match balance {
+				Balance::ClaimableOnChannelClose { .. } => "claimable_on_channel_close",
+				Balance::ClaimableAwaitingConfirmations { .. } => "awaiting_confirmations",
+				Balance::ContentiousClaimable { .. } => "contentious_htlc",
+				Balance::MaybeTimeoutClaimableHTLC { .. } => "htlc_awaiting_timeout",
+				Balance::MaybePreimageClaimableHTLC { .. } => "htlc_awaiting_preimage",
+				Balance::CounterpartyRevokedOutputClaimable { .. } => "counterparty_revoked_output",
+			};
+			*claimable_sats.entry(kind).or_insert(0) += balance.claimable_amount_satoshis();
+		}
+		let name = "lightning_claimable_balance_sats";
+		out.metric(name, "gauge", "What we could claim on-chain from our channels, by kind.");
+		for (kind, sats) in claimable_sats {
+			out.sample(name, &[("kind", kind)], sats);
+		}
+	}
+
+	/// Answers scrapes on `listener` in the background.
+	pub fn serve(self: &Arc<Self>, listener: TcpListener) {
+		let metrics = Arc::clone(self);
+		tokio::spawn(async move {
+			loop {
+				let stream = match listener.accept().await {
+					Ok((stream, _)) => stream,
+					Err(e) => {
+						log_error!(metrics.logger, "Failed to accept metrics scrape: {}", e);
+						continue;
+					},
+				};
+				let metrics = Arc::clone(&metrics);
+				tokio::spawn(async move {
+					let _ = metrics.serve_scrape(stream).await;
+				});
+			}
+		});
+	}
+
+	async fn serve_scrape(&self, stream: TcpStream) -> io::Result<()> {
+		let mut stream = BufReader::new(stream);
+		let mut request_line = String::new();
+		stream.read_line(&mut request_line).await?;
+		for _ in 0..MAX_HEADERS {
+			let mut line = String::new();
+			if stream.read_line(&mut line).await? == 0 || line.trim_end().is_empty() {
+				break;
+			}
+		}
+		let mut parts = request_line.split_whitespace();
+		let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+		let path = target.split('?').next().unwrap_or_default();
+		let (status, content_type, body) = match (method, path) {
+			("GET", METRICS_PATH) => {
+				("200 OK", "text/plain; version=0.0.4; charset=utf-8", self.render())
+			},
+			(_, METRICS_PATH) => ("405 Method Not Allowed", "text/plain", String::new()),
+			_ => ("404 Not Found", "text/plain", String::new()),
+		};
+		let response = format!(
+			"HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+			status,
+			content_type,
+			body.len(),
+			body
+		);
+		stream.get_mut().write_all(response.as_bytes()).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	#[test]
+	fn test_payment_durations_are_bucketed() {
+		let mut stats = PaymentStats::default();
+		let now = Instant::now();
+		for (id, secs) in [(1, 1), (2, 7), (3, 0)] {
+			stats.started.insert(PaymentId([id; 32]), now - Duration::from_secs(secs));
+		}
+		stats.succeeded(&PaymentId([1; 32]), now);
+		stats.succeeded(&PaymentId([2; 32]), now);
+		stats.failed(&PaymentId([3; 32]));
+		// Started before a restart, so counted but not timed.
+		stats.succeeded(&PaymentId([4; 32]), now);
+		assert!(stats.started.is_empty());
+
+		let mut out = Exposition::default();
+		stats.render(&mut out);
+		let lines = out.0.lines().collect::<Vec<_>>();
+		for expected in [
+			"lightning_payments_total{status=\"succeeded\"} 3",
+			"lightning_payments_total{status=\"failed\"} 1",
+			"lightning_payment_duration_seconds_bucket{le=\"0.5\"} 0",
+			"lightning_payment_duration_seconds_bucket{le=\"1\"} 1",
+			"lightning_payment_duration_seconds_bucket{le=\"5\"} 1",
+			"lightning_payment_duration_seconds_bucket{le=\"10\"} 2",
+			"lightning_payment_duration_seconds_bucket{le=\"+Inf\"} 2",
+			"lightning_payment_duration_seconds_sum 8",
+			"lightning_payment_duration_seconds_count 2",
+			"# TYPE lightning_payment_duration_seconds histogram",
+		] {
+			assert!(lines.contains(&expected), "missing {} in\n{}", expected, out.0);
+		}
+	}
+}
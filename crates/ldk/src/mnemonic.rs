@@ -0,0 +1,70 @@
+use bip39::Mnemonic;
+use rand::{thread_rng, Rng};
+
+/// The number of words in the mnemonics we generate, encoding 256 bits of entropy.
+pub const MNEMONIC_WORD_COUNT: usize = 24;
+
+/// Generates a new random BIP39 mnemonic to derive the node's keys seed from.
+pub fn generate_mnemonic() -> Mnemonic {
+	let mut entropy = [0; 32];
+	thread_rng().fill_bytes(&mut entropy);
+	Mnemonic::from_entropy(&entropy).expect("32 bytes are a valid entropy length")
+}
+
+/// Parses a mnemonic entered by the operator, ignoring case and extra whitespace.
+pub fn parse_mnemonic(words: &str) -> Result<Mnemonic, bip39::Error> {
+	let words = words.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+	Mnemonic::parse(words)
+}
+
+/// Derives the node's keys seed from `mnemonic` and the optional BIP39 `passphrase`, which is empty
+/// if none was set.
+///
+/// The keys seed is the first 32 bytes of the 64-byte BIP39 seed, so the same mnemonic and
+/// passphrase always recreate the same node keys.
+pub fn keys_seed_from_mnemonic(mnemonic: &Mnemonic, passphrase: &str) -> [u8; 32] {
+	let seed = mnemonic.to_seed(passphrase);
+	let mut keys_seed = [0; 32];
+	keys_seed.copy_from_slice(&seed[..32]);
+	keys_seed
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::hex_utils;
+
+	#[test]
+	fn test_keys_seed_follows_bip39() {
+		// The first test vector of the BIP39 reference implementation.
+		let mnemonic = parse_mnemonic(
+			"abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+		)
+		.unwrap();
+		assert_eq!(
+			hex_utils::hex_str(&keys_seed_from_mnemonic(&mnemonic, "TREZOR")),
+			"c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e5349553"
+		);
+		assert_ne!(
+			keys_seed_from_mnemonic(&mnemonic, ""),
+			keys_seed_from_mnemonic(&mnemonic, "TREZOR")
+		);
+	}
+
+	#[test]
+	fn test_generated_mnemonic_restores_the_same_seed() {
+		let mnemonic = generate_mnemonic();
+		assert_eq!(mnemonic.word_count(), MNEMONIC_WORD_COUNT);
+
+		let words = mnemonic.to_string().to_uppercase().replace(' ', "  \n");
+		let restored = parse_mnemonic(&words).unwrap();
+		assert_eq!(
+			keys_seed_from_mnemonic(&restored, "passphrase"),
+			keys_seed_from_mnemonic(&mnemonic, "passphrase")
+		);
+
+		// A mistyped word fails the checksum, or isn't a word at all.
+		assert!(parse_mnemonic(&["abandon"; 12].join(" ")).is_err());
+		assert!(parse_mnemonic(&format!("{} notaword", ["abandon"; 11].join(" "))).is_err());
+	}
+}
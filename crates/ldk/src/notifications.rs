@@ -0,0 +1,185 @@
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::{BlockHash, OutPoint};
+use lightning::ln::channelmanager::PaymentId;
+use lightning::ln::types::ChannelId;
+use lightning::types::payment::{PaymentHash, PaymentPreimage};
+use serde_json::{json, Value};
+
+/// Something the node did which is worth telling other systems about, e.g. a backend crediting
+/// the payments it receives.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Notification {
+	/// A payment to us arrived and is about to be claimed.
+	PaymentClaimable {
+		payment_hash: PaymentHash,
+		amount_msat: u64,
+	},
+	/// We claimed a payment to us, so the funds are ours.
+	PaymentReceived {
+		payment_hash: PaymentHash,
+		amount_msat: u64,
+	},
+	/// A payment we sent was claimed by its recipient.
+	PaymentSent {
+		payment_id: Option<PaymentId>,
+		payment_hash: PaymentHash,
+		payment_preimage: PaymentPreimage,
+		fee_paid_msat: Option<u64>,
+	},
+	/// A payment we sent failed and won't be retried.
+	PaymentFailed {
+		payment_id: PaymentId,
+		payment_hash: Option<PaymentHash>,
+		reason: String,
+	},
+	/// We forwarded a payment and its next hop claimed it.
+	PaymentForwarded {
+		prev_channel_id: Option<ChannelId>,
+		next_channel_id: Option<ChannelId>,
+		prev_node_id: Option<PublicKey>,
+		next_node_id: Option<PublicKey>,
+		outbound_amount_msat: Option<u64>,
+		fee_earned_msat: Option<u64>,
+		claimed_onchain: bool,
+	},
+	/// A channel's funding transaction was created and is waiting to confirm.
+	ChannelPending {
+		channel_id: ChannelId,
+		user_channel_id: u128,
+		counterparty_node_id: PublicKey,
+		funding_txo: OutPoint,
+	},
+	ChannelReady {
+		channel_id: ChannelId,
+		user_channel_id: u128,
+		counterparty_node_id: PublicKey,
+	},
+	ChannelClosed {
+		channel_id: ChannelId,
+		user_channel_id: u128,
+		counterparty_node_id: Option<PublicKey>,
+		reason: String,
+		capacity_sats: Option<u64>,
+		final_balance_msat: Option<u64>,
+	},
+	PeerConnected {
+		node_id: PublicKey,
+		inbound: bool,
+	},
+	PeerDisconnected {
+		node_id: PublicKey,
+	},
+	/// The chain tip moved to a new block.
+	BlockConnected {
+		height: u32,
+		block_hash: BlockHash,
+	},
+}
+
+impl Notification {
+	/// The name the notification is known by in its JSON form.
+	pub fn event_type(&self) -> &'static str {
+		match self {
+			Notification::PaymentClaimable { .. } => "payment_claimable",
+			Notification::PaymentReceived { .. } => "payment_received",
+			Notification::PaymentSent { .. } => "payment_sent",
+			Notification::PaymentFailed { .. } => "payment_failed",
+			Notification::PaymentForwarded { .. } => "payment_forwarded",
+			Notification::ChannelPending { .. } => "channel_pending",
+			Notification::ChannelReady { .. } => "channel_ready",
+			Notification::ChannelClosed { .. } => "channel_closed",
+			Notification::PeerConnected { .. } => "peer_connected",
+			Notification::PeerDisconnected { .. } => "peer_disconnected",
+			Notification::BlockConnected { .. } => "block_connected",
+		}
+	}
+
+	/// The details of the notification as a JSON object. Hashes, preimages and IDs are hex, and
+	/// `user_channel_id`s are strings as they don't fit a JSON number.
+	pub fn data(&self) -> Value {
+		match self {
+			Notification::PaymentClaimable { payment_hash, amount_msat }
+			| Notification::PaymentReceived { payment_hash, amount_msat } => json!({
+				"payment_hash": payment_hash.to_string(),
+				"amount_msat": amount_msat,
+			}),
+			Notification::PaymentSent {
+				payment_id,
+				payment_hash,
+				payment_preimage,
+				fee_paid_msat,
+			} => json!({
+				"payment_id": payment_id.map(|id| id.to_string()),
+				"payment_hash": payment_hash.to_string(),
+				"payment_preimage": payment_preimage.to_string(),
+				"fee_paid_msat": fee_paid_msat,
+			}),
+			Notification::PaymentFailed { payment_id, payment_hash, reason } => json!({
+				"payment_id": payment_id.to_string(),
+				"payment_hash": payment_hash.map(|hash| hash.to_string()),
+				"reason": reason,
+			}),
+			Notification::PaymentForwarded {
+				prev_channel_id,
+				next_channel_id,
+				prev_node_id,
+				next_node_id,
+				outbound_amount_msat,
+				fee_earned_msat,
+				claimed_onchain,
+			} => json!({
+				"prev_channel_id": prev_channel_id.map(|id| id.to_string()),
+				"next_channel_id": next_channel_id.map(|id| id.to_string()),
+				"prev_node_id": prev_node_id.map(|id| id.to_string()),
+				"next_node_id": next_node_id.map(|id| id.to_string()),
+				"outbound_amount_msat": outbound_amount_msat,
+				"fee_earned_msat": fee_earned_msat,
+				"claimed_onchain": claimed_onchain,
+			}),
+			Notification::ChannelPending {
+				channel_id,
+				user_channel_id,
+				counterparty_node_id,
+				funding_txo,
+			} => json!({
+				"channel_id": channel_id.to_string(),
+				"user_channel_id": user_channel_id.to_string(),
+				"counterparty_node_id": counterparty_node_id.to_string(),
+				"funding_txo": funding_txo.to_string(),
+			}),
+			Notification::ChannelReady { channel_id, user_channel_id, counterparty_node_id } => {
+				json!({
+					"channel_id": channel_id.to_string(),
+					"user_channel_id": user_channel_id.to_string(),
+					"counterparty_node_id": counterparty_node_id.to_string(),
+				})
+			},
+			Notification::ChannelClosed {
+				channel_id,
+				user_channel_id,
+				counterparty_node_id,
+				reason,
+				capacity_sats,
+				final_balance_msat,
+			} => json!({
+				"channel_id": channel_id.to_string(),
+				"user_channel_id": user_channel_id.to_string(),
+				"counterparty_node_id": counterparty_node_id.map(|id| id.to_string()),
+				"reason": reason,
+				"capacity_sats": capacity_sats,
+				"final_balance_msat": final_balance_msat,
+			}),
+			Notification::PeerConnected { node_id, inbound } => json!({
+				"node_id": node_id.to_string(),
+				"inbound": inbound,
+			}),
+			Notification::PeerDisconnected { node_id } => json!({
+				"node_id": node_id.to_string(),
+			}),
+			Notification::BlockConnected { height, block_hash } => json!({
+				"height": height,
+				"block_hash": block_hash.to_string(),
+			}),
+		}
+	}
+}
@@ -0,0 +1,217 @@
+use crate::common::{NetworkGraph, RapidGossipSync};
+use crate::data_store::DataStore;
+use crate::disk::FilesystemLogger;
+use lightning::util::logger::Logger;
+use lightning::util::persist::{
+	KVStore, NETWORK_GRAPH_PERSISTENCE_KEY, NETWORK_GRAPH_PERSISTENCE_PRIMARY_NAMESPACE,
+	NETWORK_GRAPH_PERSISTENCE_SECONDARY_NAMESPACE,
+};
+use lightning::util::ser::Writeable;
+use lightning::{log_error, log_info};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::runtime;
+
+/// How often a new snapshot is applied after the one at startup.
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const SERVER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Where Rapid Gossip Sync snapshots are loaded from.
+pub enum SnapshotSource {
+	/// A Rapid Gossip Sync server, e.g. `https://rapidsync.lightningdevkit.org/snapshot`, which
+	/// is asked for the changes since the last sync at `<url>/<timestamp>`.
+	Server(String),
+	/// A snapshot file, which is applied again whenever it is replaced with a newer one.
+	File(PathBuf),
+}
+
+impl SnapshotSource {
+	/// Treats `source` as a server URL if it is one, and as the path to a snapshot file otherwise.
+	pub fn parse(source: &str) -> Self {
+		if source.starts_with("http://") || source.starts_with("https://") {
+			SnapshotSource::Server(source.trim_end_matches('/').to_string())
+		} else {
+			SnapshotSource::File(PathBuf::from(source))
+		}
+	}
+}
+
+/// Fills the [`NetworkGraph`] from Rapid Gossip Sync snapshots, rather than waiting for our peers
+/// to gossip the whole graph to us.
+///
+/// The timestamp of the last snapshot applied is kept in the graph, which is persisted right after
+/// every sync, so it never claims to hold data that didn't make it to disk. The next sync only
+/// fetches what changed since.
+pub struct RapidGossip {
+	gossip_sync: Arc<RapidGossipSync>,
+	source: SnapshotSource,
+	http_client: reqwest::Client,
+	kv_store: Arc<DataStore>,
+	/// When the snapshot file was last modified, as of the last time it was applied.
+	file_modified: Mutex<Option<SystemTime>>,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl RapidGossip {
+	/// Applies a snapshot from `source` and keeps doing so every [`SYNC_INTERVAL`]. The node can run
+	/// without the initial snapshot, so failing to apply it is only logged.
+	pub async fn new(
+		source: SnapshotSource, network_graph: Arc<NetworkGraph>, kv_store: Arc<DataStore>,
+		handle: runtime::Handle, logger: Arc<FilesystemLogger>,
+	) -> io::Result<Arc<Self>> {
+		let http_client =
+			reqwest::Client::builder().timeout(SERVER_TIMEOUT).build().map_err(io::Error::other)?;
+		let rapid_gossip = Arc::new(Self {
+			gossip_sync: Arc::new(RapidGossipSync::new(network_graph, Arc::clone(&logger))),
+			source,
+			http_client,
+			kv_store,
+			file_modified: Mutex::new(None),
+			logger,
+		});
+		rapid_gossip.sync_and_log().await;
+
+		let periodic = Arc::clone(&rapid_gossip);
+		handle.spawn(async move {
+			let mut interval = tokio::time::interval(SYNC_INTERVAL);
+			interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+			// The first tick completes immediately, and we just synced.
+			interval.tick().await;
+			loop {
+				interval.tick().await;
+				periodic.sync_and_log().await;
+			}
+		});
+		Ok(rapid_gossip)
+	}
+
+	/// The [`RapidGossipSync`] to hand to the background processor, which holds off on pruning the
+	/// graph until the first snapshot was applied.
+	pub fn gossip_sync(&self) -> Arc<RapidGossipSync> {
+		Arc::clone(&self.gossip_sync)
+	}
+
+	async fn sync_and_log(&self) {
+		match self.sync().await {
+			Ok(Some(timestamp)) => {
+				let graph = self.gossip_sync.network_graph().read_only();
+				log_info!(
+					self.logger,
+					"Applied rapid gossip sync snapshot from {}, the network graph now has {} channels and {} nodes",
+					timestamp,
+					graph.channels().len(),
+					graph.nodes().len()
+				);
+			},
+			Ok(None) => {},
+			Err(e) => log_error!(self.logger, "Rapid gossip sync failed: {}", e),
+		}
+	}
+
+	/// Applies the latest snapshot, returning its timestamp, or `None` if there was nothing new.
+	async fn sync(&self) -> io::Result<Option<u32>> {
+		let network_graph = self.gossip_sync.network_graph();
+		let snapshot = match &self.source {
+			SnapshotSource::Server(url) => {
+				let last_sync = network_graph.get_last_rapid_gossip_sync_timestamp().unwrap_or(0);
+				self.fetch_snapshot(&format!("{}/{}", url, last_sync)).await?
+			},
+			SnapshotSource::File(path) => {
+				let modified = tokio::fs::metadata(path).await?.modified()?;
+				if *self.file_modified.lock().unwrap() == Some(modified) {
+					return Ok(None);
+				}
+				let snapshot = tokio::fs::read(path).await?;
+				*self.file_modified.lock().unwrap() = Some(modified);
+				snapshot
+			},
+		};
+
+		// Applying a full snapshot takes a while, so keep it off the async workers.
+		let gossip_sync = Arc::clone(&self.gossip_sync);
+		let timestamp =
+			tokio::task::spawn_blocking(move || gossip_sync.update_network_graph(&snapshot))
+				.await
+				.unwrap()
+				.map_err(|e| {
+					io::Error::new(io::ErrorKind::InvalidData, format!("Invalid snapshot: {:?}", e))
+				})?;
+		// Snapshots without any channel updates aren't recorded as synced by LDK, but there is no
+		// need to fetch the changes before them again either.
+		network_graph.set_last_rapid_gossip_sync_timestamp(timestamp);
+		self.kv_store.write(
+			NETWORK_GRAPH_PERSISTENCE_PRIMARY_NAMESPACE,
+			NETWORK_GRAPH_PERSISTENCE_SECONDARY_NAMESPACE,
+			NETWORK_GRAPH_PERSISTENCE_KEY,
+			&network_graph.encode(),
+		)?;
+		Ok(Some(timestamp))
+	}
+
+	async fn fetch_snapshot(&self, url: &str) -> io::Result<Vec<u8>> {
+		let to_io_error =
+			|e: reqwest::Error| io::Error::other(format!("Failed to fetch {}: {}", url, e));
+		let response = self.http_client.get(url).send().await.map_err(to_io_error)?;
+		let response = response.error_for_status().map_err(to_io_error)?;
+		Ok(response.bytes().await.map_err(to_io_error)?.to_vec())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::{test_data_dir, test_store};
+	use bitcoin::constants::ChainHash;
+	use bitcoin::Network;
+	use std::fs;
+
+	/// A snapshot without any nodes, announcements or updates, seen at `timestamp`.
+	fn empty_snapshot(timestamp: u32) -> Vec<u8> {
+		let mut snapshot = b"LDK\x01".to_vec();
+		snapshot.extend_from_slice(ChainHash::using_genesis_block(Network::Regtest).as_bytes());
+		snapshot.extend_from_slice(&timestamp.to_be_bytes());
+		snapshot.extend_from_slice(&[0; 12]);
+		snapshot
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_snapshot_file_is_applied_and_persisted() {
+		let data_dir = test_data_dir("rapid-gossip");
+		fs::create_dir_all(&data_dir).unwrap();
+		let (kv_store, logger) = test_store(&data_dir);
+		let network_graph = Arc::new(NetworkGraph::new(Network::Regtest, Arc::clone(&logger)));
+		let snapshot_path = PathBuf::from(&data_dir).join("snapshot.bin");
+		let now =
+			SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as u32;
+		fs::write(&snapshot_path, empty_snapshot(now)).unwrap();
+
+		let rapid_gossip = RapidGossip::new(
+			SnapshotSource::parse(snapshot_path.to_str().unwrap()),
+			network_graph,
+			Arc::clone(&kv_store),
+			runtime::Handle::current(),
+			Arc::clone(&logger),
+		)
+		.await
+		.unwrap();
+		let persisted = crate::disk::read_network(&kv_store, Network::Regtest, logger);
+		assert_eq!(persisted.get_last_rapid_gossip_sync_timestamp(), Some(now));
+
+		// An unchanged file isn't applied again.
+		assert_eq!(rapid_gossip.sync().await.unwrap(), None);
+	}
+
+	#[test]
+	fn test_parse_snapshot_source() {
+		match SnapshotSource::parse("https://rapidsync.lightningdevkit.org/snapshot/") {
+			SnapshotSource::Server(url) => {
+				assert_eq!(url, "https://rapidsync.lightningdevkit.org/snapshot")
+			},
+			SnapshotSource::File(_) => panic!("Expected a server"),
+		}
+		assert!(matches!(SnapshotSource::parse("snapshot.bin"), SnapshotSource::File(_)));
+	}
+}
@@ -0,0 +1,711 @@
+use crate::broadcast_queue::TrackedBroadcaster;
+use crate::chain_source::ChainSource;
+use crate::common::ChainMonitor;
+use crate::data_store::DataStore;
+use crate::disk::FilesystemLogger;
+use crate::encrypted_store;
+use crate::http_backup::HttpBackupClient;
+use bitcoin::BlockHash;
+use lightning::chain::chainmonitor::Persist;
+use lightning::chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate};
+use lightning::chain::transaction::OutPoint;
+use lightning::chain::ChannelMonitorUpdateStatus;
+use lightning::io;
+use lightning::sign::{InMemorySigner, KeysManager};
+use lightning::util::logger::Logger;
+use lightning::util::persist::{
+	KVStore, MigratableKVStore, MonitorUpdatingPersister,
+	ARCHIVED_CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE,
+	CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE,
+	CHANNEL_MONITOR_UPDATE_PERSISTENCE_PRIMARY_NAMESPACE,
+};
+use lightning::{log_error, log_info, log_warn};
+use lightning_persister::fs_store::FilesystemStore;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, RwLock, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Where a [`ReplicatingStore`] mirrors the node's data to.
+pub enum BackupTarget {
+	/// Another directory, ideally on a different disk.
+	Filesystem(FilesystemStore),
+	/// The backup server shipped as the `backup-server` binary.
+	Http(HttpBackupClient),
+}
+
+impl KVStore for BackupTarget {
+	fn read(
+		&self, primary_namespace: &str, secondary_namespace: &str, key: &str,
+	) -> io::Result<Vec<u8>> {
+		match self {
+			BackupTarget::Filesystem(store) => {
+				store.read(primary_namespace, secondary_namespace, key)
+			},
+			BackupTarget::Http(client) => client.read(primary_namespace, secondary_namespace, key),
+		}
+	}
+
+	fn write(
+		&self, primary_namespace: &str, secondary_namespace: &str, key: &str, buf: &[u8],
+	) -> io::Result<()> {
+		match self {
+			BackupTarget::Filesystem(store) => {
+				store.write(primary_namespace, secondary_namespace, key, buf)
+			},
+			BackupTarget::Http(client) => {
+				client.write(primary_namespace, secondary_namespace, key, buf)
+			},
+		}
+	}
+
+	fn remove(
+		&self, primary_namespace: &str, secondary_namespace: &str, key: &str, lazy: bool,
+	) -> io::Result<()> {
+		match self {
+			BackupTarget::Filesystem(store) => {
+				store.remove(primary_namespace, secondary_namespace, key, lazy)
+			},
+			BackupTarget::Http(client) => {
+				client.remove(primary_namespace, secondary_namespace, key, lazy)
+			},
+		}
+	}
+
+	fn list(&self, primary_namespace: &str, secondary_namespace: &str) -> io::Result<Vec<String>> {
+		match self {
+			BackupTarget::Filesystem(store) => store.list(primary_namespace, secondary_namespace),
+			BackupTarget::Http(client) => client.list(primary_namespace, secondary_namespace),
+		}
+	}
+}
+
+enum BackupOp {
+	Write {
+		primary_namespace: String,
+		secondary_namespace: String,
+		key: String,
+		value: Vec<u8>,
+		/// Skip the write if the backup already holds `value`, saving it a new version.
+		skip_if_unchanged: bool,
+	},
+	Remove {
+		primary_namespace: String,
+		secondary_namespace: String,
+		key: String,
+		lazy: bool,
+	},
+	/// Run once everything queued before has been replicated.
+	Notify(Box<dyn FnOnce() + Send>),
+}
+
+struct Queue {
+	ops: VecDeque<(u64, BackupOp)>,
+	next_seq: u64,
+	/// Every op up to and including this one has been applied to the backup.
+	replicated_seq: u64,
+	/// The error the last attempt to apply an op to the backup failed with, if it did.
+	backup_error: Option<String>,
+	/// Set once the backup rejected an op because it holds newer data, e.g. written by a second
+	/// node restored from the same backup. Replication stops for good then, so that we never
+	/// overwrite that data.
+	conflict: Option<String>,
+}
+
+struct Shared {
+	queue: Mutex<Queue>,
+	ops_available: Condvar,
+	op_replicated: Condvar,
+}
+
+/// A [`KVStore`] mirroring every write and removal to a [`BackupTarget`], so that losing the disk
+/// the node's data is on doesn't mean losing its channels.
+///
+/// Reads are served by the primary store. A background thread applies writes to the backup in the
+/// order they were made, retrying until the backup accepts them. Writes return once the backup
+/// has them, except for those of `ChannelMonitor`s and their updates: [`MonitorPersister`] tells
+/// the `ChainMonitor` once those are replicated instead, so they don't block the caller.
+///
+/// If the backup rejects a write because it holds newer data, replication stops and every later
+/// write fails after being committed to the primary store, so the node stops instead of running
+/// ahead of its backup. Monitor updates are never completed then.
+pub struct ReplicatingStore {
+	primary: DataStore,
+	shared: Arc<Shared>,
+	max_replication_wait: Option<Duration>,
+}
+
+impl ReplicatingStore {
+	/// Creates a store mirroring `primary` to `backup`.
+	///
+	/// If `max_replication_wait` is set, writes other than those of `ChannelMonitor`s wait for
+	/// the backup for at most that long, and not at all while it is failing, so an outage doesn't
+	/// stall the node. Such writes stay queued until the backup accepts them.
+	pub fn new(
+		primary: DataStore, backup: BackupTarget, max_replication_wait: Option<Duration>,
+		logger: Arc<FilesystemLogger>,
+	) -> Self {
+		let shared = Arc::new(Shared {
+			queue: Mutex::new(Queue {
+				ops: VecDeque::new(),
+				next_seq: 1,
+				replicated_seq: 0,
+				backup_error: None,
+				conflict: None,
+			}),
+			ops_available: Condvar::new(),
+			op_replicated: Condvar::new(),
+		});
+		let worker_shared = Arc::clone(&shared);
+		thread::Builder::new()
+			.name("backup-replication".to_string())
+			.spawn(move || replicate(backup, worker_shared, logger))
+			.expect("Failed to spawn the backup replication thread");
+		Self { primary, shared, max_replication_wait }
+	}
+
+	/// The store reads are served by.
+	pub fn primary(&self) -> &DataStore {
+		&self.primary
+	}
+
+	/// Calls `callback` from the replication thread once every write made so far has been
+	/// replicated. It is never called if replication stopped.
+	pub fn on_replicated(&self, callback: Box<dyn FnOnce() + Send>) {
+		self.enqueue(BackupOp::Notify(callback));
+	}
+
+	/// Copies every entry the primary store holds to the backup, skipping those it already holds,
+	/// and returns once all are replicated. Used at startup, so a backup which was just set up, or
+	/// missed writes while the node ran without it, holds everything needed to restore the node.
+	///
+	/// Fails as soon as the backup does, rather than waiting for it to come back.
+	pub fn replicate_existing_data(&self, data_dir: &Path) -> io::Result<usize> {
+		let keys = encrypted_store::list_keys(data_dir, &self.primary)?;
+		let mut last_seq = 0;
+		for (primary_namespace, secondary_namespace, key) in keys.iter() {
+			let value = self.primary.read(primary_namespace, secondary_namespace, key)?;
+			last_seq = self.enqueue(BackupOp::Write {
+				primary_namespace: primary_namespace.clone(),
+				secondary_namespace: secondary_namespace.clone(),
+				key: key.clone(),
+				value,
+				skip_if_unchanged: true,
+			});
+		}
+		if !self.wait_for_replication_while_healthy(last_seq, None)? {
+			let queue = self.shared.queue.lock().unwrap();
+			let error = queue.backup_error.clone().unwrap_or_default();
+			return Err(io::Error::new(
+				io::ErrorKind::Other,
+				format!("The backup is failing: {}", error),
+			));
+		}
+		Ok(keys.len())
+	}
+
+	/// Returns the number of writes and removals not replicated yet.
+	pub fn pending_ops(&self) -> usize {
+		let queue = self.shared.queue.lock().unwrap();
+		queue.ops.iter().filter(|(_, op)| !matches!(op, BackupOp::Notify(_))).count()
+	}
+
+	/// Queues `op` for the backup, returning its sequence number. Once replication stopped, ops
+	/// are dropped instead.
+	fn enqueue(&self, op: BackupOp) -> u64 {
+		let mut queue = self.shared.queue.lock().unwrap();
+		let seq = queue.next_seq;
+		queue.next_seq += 1;
+		if queue.conflict.is_none() {
+			queue.ops.push_back((seq, op));
+			self.shared.ops_available.notify_one();
+		}
+		seq
+	}
+
+	/// Waits for the op `seq` to be replicated, however long the backup takes.
+	fn wait_for_replication(&self, seq: u64) -> io::Result<()> {
+		let mut queue = self.shared.queue.lock().unwrap();
+		loop {
+			if let Some(conflict) = &queue.conflict {
+				return Err(replication_stopped(conflict));
+			}
+			if queue.replicated_seq >= seq {
+				return Ok(());
+			}
+			queue = self.shared.op_replicated.wait(queue).unwrap();
+		}
+	}
+
+	/// Waits for the op `seq` to be replicated, unless the backup is failing or takes longer than
+	/// `max_wait`. Returns whether it was replicated.
+	fn wait_for_replication_while_healthy(
+		&self, seq: u64, max_wait: Option<Duration>,
+	) -> io::Result<bool> {
+		let deadline = max_wait.map(|max_wait| Instant::now() + max_wait);
+		let mut queue = self.shared.queue.lock().unwrap();
+		loop {
+			if let Some(conflict) = &queue.conflict {
+				return Err(replication_stopped(conflict));
+			}
+			if queue.replicated_seq >= seq {
+				return Ok(true);
+			}
+			if queue.backup_error.is_some() {
+				return Ok(false);
+			}
+			queue = match deadline {
+				Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+					Some(timeout) => {
+						self.shared.op_replicated.wait_timeout(queue, timeout).unwrap().0
+					},
+					None => return Ok(false),
+				},
+				None => self.shared.op_replicated.wait(queue).unwrap(),
+			};
+		}
+	}
+
+	/// Waits for the write or removal `seq` as configured, unless it is of a `ChannelMonitor`.
+	fn wait_for_write(&self, primary_namespace: &str, seq: u64) -> io::Result<()> {
+		if is_monitor_namespace(primary_namespace) {
+			return Ok(());
+		}
+		match self.max_replication_wait {
+			Some(max_wait) => {
+				self.wait_for_replication_while_healthy(seq, Some(max_wait)).map(|_| ())
+			},
+			None => self.wait_for_replication(seq),
+		}
+	}
+}
+
+fn replication_stopped(conflict: &str) -> io::Error {
+	io::Error::new(
+		io::ErrorKind::AlreadyExists,
+		format!("Replication to the backup stopped: {}", conflict),
+	)
+}
+
+/// Writes of `ChannelMonitor`s and their updates, which are replicated asynchronously.
+fn is_monitor_namespace(primary_namespace: &str) -> bool {
+	primary_namespace == CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE
+		|| primary_namespace == CHANNEL_MONITOR_UPDATE_PERSISTENCE_PRIMARY_NAMESPACE
+		|| primary_namespace == ARCHIVED_CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE
+}
+
+fn replicate(backup: BackupTarget, shared: Arc<Shared>, logger: Arc<FilesystemLogger>) {
+	loop {
+		let (seq, op) = {
+			let mut queue = shared.queue.lock().unwrap();
+			loop {
+				match queue.ops.pop_front() {
+					Some(entry) => break entry,
+					None => queue = shared.ops_available.wait(queue).unwrap(),
+				}
+			}
+		};
+
+		let mut retry_delay = MIN_RETRY_DELAY;
+		let mut failed = false;
+		loop {
+			let (res, description) = match &op {
+				BackupOp::Write {
+					primary_namespace,
+					secondary_namespace,
+					key,
+					value,
+					skip_if_unchanged,
+				} => (
+					if *skip_if_unchanged
+						&& backup.read(primary_namespace, secondary_namespace, key).ok().as_ref()
+							== Some(value)
+					{
+						Ok(())
+					} else {
+						backup.write(primary_namespace, secondary_namespace, key, value)
+					},
+					format!("write {}/{}/{}", primary_namespace, secondary_namespace, key),
+				),
+				BackupOp::Remove { primary_namespace, secondary_namespace, key, lazy } => (
+					match backup.remove(primary_namespace, secondary_namespace, key, *lazy) {
+						Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+						res => res,
+					},
+					format!("remove {}/{}/{}", primary_namespace, secondary_namespace, key),
+				),
+				BackupOp::Notify(_) => break,
+			};
+			match res {
+				Ok(()) => {
+					if failed {
+						log_info!(logger, "Backup reachable again, replicated {}", description);
+					}
+					break;
+				},
+				Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+					let conflict = format!("the backup rejected {}: {}", description, e);
+					log_error!(
+						logger,
+						"Stopped replicating to the backup, as it holds newer data than ours. Is \
+						another node writing to it? {}",
+						conflict
+					);
+					shared.queue.lock().unwrap().conflict = Some(conflict);
+					shared.op_replicated.notify_all();
+					return;
+				},
+				Err(e) => {
+					log_warn!(
+						logger,
+						"Failed to {} in the backup, retrying in {}s: {}",
+						description,
+						retry_delay.as_secs(),
+						e
+					);
+					if !failed {
+						failed = true;
+						// Let writes waiting for the backup return.
+						shared.queue.lock().unwrap().backup_error = Some(e.to_string());
+						shared.op_replicated.notify_all();
+					}
+					thread::sleep(retry_delay);
+					retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+				},
+			}
+		}
+		if let BackupOp::Notify(callback) = op {
+			callback();
+		}
+
+		let mut queue = shared.queue.lock().unwrap();
+		queue.replicated_seq = seq;
+		queue.backup_error = None;
+		shared.op_replicated.notify_all();
+	}
+}
+
+impl KVStore for ReplicatingStore {
+	fn read(
+		&self, primary_namespace: &str, secondary_namespace: &str, key: &str,
+	) -> io::Result<Vec<u8>> {
+		self.primary.read(primary_namespace, secondary_namespace, key)
+	}
+
+	fn write(
+		&self, primary_namespace: &str, secondary_namespace: &str, key: &str, buf: &[u8],
+	) -> io::Result<()> {
+		self.primary.write(primary_namespace, secondary_namespace, key, buf)?;
+		let seq = self.enqueue(BackupOp::Write {
+			primary_namespace: primary_namespace.to_string(),
+			secondary_namespace: secondary_namespace.to_string(),
+			key: key.to_string(),
+			value: buf.to_vec(),
+			skip_if_unchanged: false,
+		});
+		self.wait_for_write(primary_namespace, seq)
+	}
+
+	fn remove(
+		&self, primary_namespace: &str, secondary_namespace: &str, key: &str, lazy: bool,
+	) -> io::Result<()> {
+		self.primary.remove(primary_namespace, secondary_namespace, key, lazy)?;
+		let seq = self.enqueue(BackupOp::Remove {
+			primary_namespace: primary_namespace.to_string(),
+			secondary_namespace: secondary_namespace.to_string(),
+			key: key.to_string(),
+			lazy,
+		});
+		self.wait_for_write(primary_namespace, seq)
+	}
+
+	fn list(&self, primary_namespace: &str, secondary_namespace: &str) -> io::Result<Vec<String>> {
+		self.primary.list(primary_namespace, secondary_namespace)
+	}
+}
+
+impl MigratableKVStore for ReplicatingStore {
+	fn list_all_keys(&self) -> io::Result<Vec<(String, String, String)>> {
+		self.primary.list_all_keys()
+	}
+}
+
+type UpdatingPersister = MonitorUpdatingPersister<
+	Arc<DataStore>,
+	Arc<FilesystemLogger>,
+	Arc<KeysManager>,
+	Arc<KeysManager>,
+	Arc<TrackedBroadcaster>,
+	Arc<ChainSource>,
+>;
+
+/// Persists `ChannelMonitor`s through a [`MonitorUpdatingPersister`] and, if the node's data is
+/// replicated, reports every update as [`ChannelMonitorUpdateStatus::InProgress`] until the
+/// [`ReplicatingStore`] has mirrored it, at which point
+/// [`ChainMonitor::channel_monitor_updated`] is called.
+///
+/// Until then, LDK holds off on sending our counterparty the messages which depend on the update, so
+/// the backup never falls behind what our peers may rely on.
+pub struct MonitorPersister {
+	inner: UpdatingPersister,
+	replicating_store: Option<Arc<ReplicatingStore>>,
+	chain_monitor: RwLock<Weak<ChainMonitor>>,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl MonitorPersister {
+	pub fn new(
+		inner: UpdatingPersister, replicating_store: Option<Arc<ReplicatingStore>>,
+		logger: Arc<FilesystemLogger>,
+	) -> Self {
+		Self { inner, replicating_store, chain_monitor: RwLock::new(Weak::new()), logger }
+	}
+
+	/// Sets the [`ChainMonitor`] to notify of replicated updates. Must be called before it is
+	/// handed any `ChannelMonitor`.
+	pub fn set_chain_monitor(&self, chain_monitor: &Arc<ChainMonitor>) {
+		*self.chain_monitor.write().unwrap() = Arc::downgrade(chain_monitor);
+	}
+
+	pub fn read_all_channel_monitors_with_updates(
+		&self,
+	) -> Result<Vec<(BlockHash, ChannelMonitor<InMemorySigner>)>, io::Error> {
+		self.inner.read_all_channel_monitors_with_updates()
+	}
+
+	fn complete_once_replicated(
+		&self, status: ChannelMonitorUpdateStatus, funding_txo: OutPoint, update_id: Option<u64>,
+	) -> ChannelMonitorUpdateStatus {
+		let replicating_store = match (&status, &self.replicating_store) {
+			(ChannelMonitorUpdateStatus::Completed, Some(store)) => store,
+			_ => return status,
+		};
+		// Updates from chain sync aren't tracked by the `ChainMonitor`, so there is nothing to
+		// notify it of.
+		if let Some(update_id) = update_id {
+			let chain_monitor = self.chain_monitor.read().unwrap().clone();
+			let logger = Arc::clone(&self.logger);
+			replicating_store.on_replicated(Box::new(move || {
+				let res = match chain_monitor.upgrade() {
+					Some(chain_monitor) => chain_monitor
+						.channel_monitor_updated(funding_txo, update_id)
+						.map_err(|e| format!("{:?}", e)),
+					None => Err("the ChainMonitor is gone".to_string()),
+				};
+				if let Err(e) = res {
+					log_error!(
+						logger,
+						"Failed to complete monitor update {} for {}: {}",
+						update_id,
+						funding_txo,
+						e
+					);
+				}
+			}));
+		}
+		ChannelMonitorUpdateStatus::InProgress
+	}
+}
+
+impl Persist<InMemorySigner> for MonitorPersister {
+	fn persist_new_channel(
+		&self, funding_txo: OutPoint, monitor: &ChannelMonitor<InMemorySigner>,
+	) -> ChannelMonitorUpdateStatus {
+		let status = self.inner.persist_new_channel(funding_txo, monitor);
+		self.complete_once_replicated(status, funding_txo, Some(monitor.get_latest_update_id()))
+	}
+
+	fn update_persisted_channel(
+		&self, funding_txo: OutPoint, update: Option<&ChannelMonitorUpdate>,
+		monitor: &ChannelMonitor<InMemorySigner>,
+	) -> ChannelMonitorUpdateStatus {
+		let status = self.inner.update_persisted_channel(funding_txo, update, monitor);
+		self.complete_once_replicated(status, funding_txo, update.map(|update| update.update_id))
+	}
+
+	fn archive_persisted_channel(&self, funding_txo: OutPoint) {
+		Persist::<InMemorySigner>::archive_persisted_channel(&self.inner, funding_txo)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::http_backup::VERSION_PATH;
+	use crate::test_utils::{test_data_dir, MockHttpServer};
+	use std::path::PathBuf;
+	use std::sync::mpsc;
+
+	#[test]
+	fn test_writes_are_mirrored_in_order() {
+		let primary_dir = PathBuf::from(test_data_dir("replicating-store-primary"));
+		let backup_dir = PathBuf::from(test_data_dir("replicating-store-backup"));
+		let logger = Arc::new(FilesystemLogger::new(test_data_dir("replicating-store-logs")));
+		let store = ReplicatingStore::new(
+			DataStore::Filesystem(FilesystemStore::new(primary_dir)),
+			BackupTarget::Filesystem(FilesystemStore::new(backup_dir.clone())),
+			None,
+			logger,
+		);
+		let backup = FilesystemStore::new(backup_dir);
+
+		// Other writes are only done once they were replicated.
+		store.write("", "", "manager", &[1]).unwrap();
+		assert_eq!(backup.read("", "", "manager").unwrap(), vec![1]);
+
+		// Monitor writes return right away, and are replicated before anything queued after them.
+		store.write("monitors", "", "chan_a", &[2]).unwrap();
+		store.write("monitor_updates", "chan_a", "1", &[3]).unwrap();
+		store.remove("monitor_updates", "chan_a", "1", true).unwrap();
+		let (sender, receiver) = mpsc::channel();
+		store.on_replicated(Box::new(move || sender.send(()).unwrap()));
+		receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+		assert_eq!(store.pending_ops(), 0);
+		assert_eq!(backup.read("monitors", "", "chan_a").unwrap(), vec![2]);
+		assert_eq!(
+			backup.read("monitor_updates", "chan_a", "1").unwrap_err().kind(),
+			io::ErrorKind::NotFound
+		);
+
+		// Reads are served by the primary store.
+		backup.write("", "", "manager", &[4]).unwrap();
+		assert_eq!(store.read("", "", "manager").unwrap(), vec![1]);
+	}
+
+	#[test]
+	fn test_existing_data_is_replicated() {
+		let primary_dir = PathBuf::from(test_data_dir("replicating-store-existing-primary"));
+		let backup_dir = PathBuf::from(test_data_dir("replicating-store-existing-backup"));
+		let logger = Arc::new(FilesystemLogger::new(test_data_dir("replicating-store-logs")));
+		let primary = FilesystemStore::new(primary_dir.clone());
+		primary.write("", "", "manager", &[1]).unwrap();
+		primary.write("monitors", "", "chan_a", &[2]).unwrap();
+		let store = ReplicatingStore::new(
+			DataStore::Filesystem(primary),
+			BackupTarget::Filesystem(FilesystemStore::new(backup_dir.clone())),
+			None,
+			logger,
+		);
+
+		assert_eq!(store.replicate_existing_data(&primary_dir).unwrap(), 2);
+		let backup = FilesystemStore::new(backup_dir);
+		assert_eq!(backup.read("", "", "manager").unwrap(), vec![1]);
+		assert_eq!(backup.read("monitors", "", "chan_a").unwrap(), vec![2]);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_existing_data_already_in_the_backup_is_skipped() {
+		let server = MockHttpServer::start(|request| match request.method.as_str() {
+			"GET" if request.path == VERSION_PATH => (200, "0".to_string()),
+			"GET" if request.path.ends_with("key=manager") => (200, "manager".to_string()),
+			"GET" => (404, String::new()),
+			_ => (200, String::new()),
+		})
+		.await;
+		let primary_dir = PathBuf::from(test_data_dir("replicating-store-skip"));
+		let logger = Arc::new(FilesystemLogger::new(test_data_dir("replicating-store-logs")));
+		let primary = FilesystemStore::new(primary_dir.clone());
+		primary.write("", "", "manager", b"manager").unwrap();
+		primary.write("monitors", "", "chan_a", b"monitor").unwrap();
+		let store = ReplicatingStore::new(
+			DataStore::Filesystem(primary),
+			BackupTarget::Http(HttpBackupClient::new(&server.url(), None).unwrap()),
+			None,
+			logger,
+		);
+
+		let res = tokio::task::spawn_blocking(move || store.replicate_existing_data(&primary_dir))
+			.await
+			.unwrap();
+		assert_eq!(res.unwrap(), 2);
+		let puts = server.requests().into_iter().filter(|request| request.method == "PUT");
+		let puts = puts.map(|request| request.path).collect::<Vec<_>>();
+		assert_eq!(puts.len(), 1);
+		assert!(puts[0].ends_with("key=chan_a"));
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_replicating_existing_data_fails_with_the_backup() {
+		let server = MockHttpServer::start(|_| (500, "Disk full".to_string())).await;
+		let primary_dir = PathBuf::from(test_data_dir("replicating-store-existing-failing"));
+		let logger = Arc::new(FilesystemLogger::new(test_data_dir("replicating-store-logs")));
+		let primary = FilesystemStore::new(primary_dir.clone());
+		primary.write("", "", "manager", &[1]).unwrap();
+		let store = ReplicatingStore::new(
+			DataStore::Filesystem(primary),
+			BackupTarget::Http(HttpBackupClient::new(&server.url(), None).unwrap()),
+			None,
+			logger,
+		);
+
+		let res = tokio::task::spawn_blocking(move || store.replicate_existing_data(&primary_dir))
+			.await
+			.unwrap();
+		assert!(res.unwrap_err().to_string().contains("Disk full"));
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_conflicts_stop_replication() {
+		// A backup server holding newer data than ours, e.g. from a second node.
+		let server = MockHttpServer::start(|request| match request.method.as_str() {
+			"GET" if request.path == VERSION_PATH => (200, "0".to_string()),
+			"PUT" => (409, "Version is not newer".to_string()),
+			_ => (400, "unexpected request".to_string()),
+		})
+		.await;
+		let primary_dir = PathBuf::from(test_data_dir("replicating-store-conflict"));
+		let logger = Arc::new(FilesystemLogger::new(test_data_dir("replicating-store-logs")));
+		let store = ReplicatingStore::new(
+			DataStore::Filesystem(FilesystemStore::new(primary_dir)),
+			BackupTarget::Http(HttpBackupClient::new(&server.url(), None).unwrap()),
+			None,
+			logger,
+		);
+
+		tokio::task::spawn_blocking(move || {
+			let err = store.write("", "", "manager", &[1]).unwrap_err();
+			assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+			// Later writes fail right away, once committed locally, and aren't queued.
+			let err = store.write("", "", "manager", &[2]).unwrap_err();
+			assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+			store.write("monitors", "", "chan_a", &[3]).unwrap();
+			assert_eq!(store.read("", "", "manager").unwrap(), vec![2]);
+			assert_eq!(store.pending_ops(), 0);
+		})
+		.await
+		.unwrap();
+
+		// The rejected write isn't retried with ever higher versions.
+		let puts = server.requests().into_iter().filter(|request| request.method == "PUT");
+		assert_eq!(puts.count(), 1);
+	}
+
+	#[test]
+	fn test_writes_dont_wait_for_an_unresponsive_backup() {
+		// A backup server which accepts connections but never answers.
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}", listener.local_addr().unwrap());
+		let primary_dir = PathBuf::from(test_data_dir("replicating-store-unresponsive"));
+		let logger = Arc::new(FilesystemLogger::new(test_data_dir("replicating-store-logs")));
+		let store = ReplicatingStore::new(
+			DataStore::Filesystem(FilesystemStore::new(primary_dir)),
+			BackupTarget::Http(HttpBackupClient::new(&url, None).unwrap()),
+			Some(Duration::from_millis(100)),
+			logger,
+		);
+
+		let started = Instant::now();
+		store.write("", "", "manager", &[1]).unwrap();
+		store.write("", "", "fee_estimates", &[2]).unwrap();
+		store.remove("", "", "fee_estimates", false).unwrap();
+		assert!(started.elapsed() < Duration::from_secs(5));
+
+		// The writes are committed locally and stay queued for the backup.
+		assert_eq!(store.read("", "", "manager").unwrap(), vec![1]);
+		assert_eq!(store.pending_ops(), 2);
+	}
+}
@@ -0,0 +1,395 @@
+use crate::common::{NetworkGraph, Scorer};
+use crate::data_store::DataStore;
+use crate::disk::FilesystemLogger;
+use lightning::io::{self, Read};
+use lightning::ln::msgs::DecodeError;
+use lightning::log_info;
+use lightning::routing::scoring::{
+	ProbabilisticScoringDecayParameters, ProbabilisticScoringFeeParameters,
+};
+use lightning::util::hash_tables::{new_hash_map, HashMap};
+use lightning::util::logger::Logger;
+use lightning::util::persist::{
+	KVStore, SCORER_PERSISTENCE_KEY, SCORER_PERSISTENCE_PRIMARY_NAMESPACE,
+	SCORER_PERSISTENCE_SECONDARY_NAMESPACE,
+};
+use lightning::util::ser::{BigSize, Readable, ReadableArgs, Writeable, Writer};
+use lightning::{read_tlv_fields, write_tlv_fields};
+use serde_json::Value;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// How the scorer learns from payments and probes, and how the router weighs what it learned.
+#[derive(Clone, Default)]
+pub struct ScoringParams {
+	pub fee: ProbabilisticScoringFeeParameters,
+	pub decay: ProbabilisticScoringDecayParameters,
+}
+
+impl ScoringParams {
+	/// Parses scoring parameters from JSON. Anything not given keeps LDK's default, e.g.:
+	///
+	/// ```json
+	/// {
+	///   "fee": {
+	///     "base_penalty_msat": 1000,
+	///     "liquidity_penalty_multiplier_msat": 60000,
+	///     "linear_success_probability": true
+	///   },
+	///   "decay": { "liquidity_offset_half_life_secs": 3600 }
+	/// }
+	/// ```
+	///
+	/// Fee parameters are named as in [`ProbabilisticScoringFeeParameters`], and decay parameters
+	/// as in [`ProbabilisticScoringDecayParameters`] with a `_secs` suffix.
+	pub fn from_json(json: &str) -> Result<Self, String> {
+		let value: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+		let mut params = ScoringParams::default();
+		let object = value.as_object().ok_or("scoring parameters must be an object")?;
+		for (section, fields) in object {
+			if section != "fee" && section != "decay" {
+				return Err(format!("unknown section {}", section));
+			}
+			let fields =
+				fields.as_object().ok_or_else(|| format!("{} must be an object", section))?;
+			for (name, value) in fields {
+				let apply = if section == "fee" {
+					apply_fee_param(&mut params.fee, name, value)
+				} else {
+					apply_decay_param(&mut params.decay, name, value)
+				};
+				apply.map_err(|e| format!("{}.{}: {}", section, name, e))?;
+			}
+		}
+		Ok(params)
+	}
+}
+
+fn apply_fee_param(
+	params: &mut ProbabilisticScoringFeeParameters, name: &str, value: &Value,
+) -> Result<(), String> {
+	if name == "linear_success_probability" {
+		params.linear_success_probability = value.as_bool().ok_or("must be a boolean")?;
+		return Ok(());
+	}
+	let field = match name {
+		"base_penalty_msat" => &mut params.base_penalty_msat,
+		"base_penalty_amount_multiplier_msat" => &mut params.base_penalty_amount_multiplier_msat,
+		"liquidity_penalty_multiplier_msat" => &mut params.liquidity_penalty_multiplier_msat,
+		"liquidity_penalty_amount_multiplier_msat" => {
+			&mut params.liquidity_penalty_amount_multiplier_msat
+		},
+		"historical_liquidity_penalty_multiplier_msat" => {
+			&mut params.historical_liquidity_penalty_multiplier_msat
+		},
+		"historical_liquidity_penalty_amount_multiplier_msat" => {
+			&mut params.historical_liquidity_penalty_amount_multiplier_msat
+		},
+		"anti_probing_penalty_msat" => &mut params.anti_probing_penalty_msat,
+		"considered_impossible_penalty_msat" => &mut params.considered_impossible_penalty_msat,
+		_ => return Err("unknown parameter".to_string()),
+	};
+	*field = value.as_u64().ok_or("must be a non-negative integer")?;
+	Ok(())
+}
+
+fn apply_decay_param(
+	params: &mut ProbabilisticScoringDecayParameters, name: &str, value: &Value,
+) -> Result<(), String> {
+	let field = match name {
+		"historical_no_updates_half_life_secs" => &mut params.historical_no_updates_half_life,
+		"liquidity_offset_half_life_secs" => &mut params.liquidity_offset_half_life,
+		_ => return Err("unknown parameter".to_string()),
+	};
+	let secs = value.as_u64().filter(|secs| *secs > 0).ok_or("must be a positive integer")?;
+	*field = Duration::from_secs(secs);
+	Ok(())
+}
+
+/// The longest a single channel's serialized liquidity estimates may be. They take up less than
+/// two hundred bytes, so anything much longer means the scorer is corrupt.
+const MAX_SERIALIZED_LIQUIDITY_LEN: u64 = 4096;
+
+/// What the scorer learned about one channel, left serialized as LDK wrote it.
+struct SerializedLiquidity(Vec<u8>);
+
+impl Writeable for SerializedLiquidity {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		BigSize(self.0.len() as u64).write(w)?;
+		w.write_all(&self.0)
+	}
+}
+
+impl Readable for SerializedLiquidity {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let len: BigSize = Readable::read(r)?;
+		if len.0 > MAX_SERIALIZED_LIQUIDITY_LEN {
+			return Err(DecodeError::InvalidValue);
+		}
+		let mut tlvs = vec![0; len.0 as usize];
+		r.read_exact(&mut tlvs)?;
+		Ok(Self(tlvs))
+	}
+}
+
+/// A serialized [`Scorer`], which is nothing but its liquidity estimates keyed by short channel
+/// id. LDK doesn't let us remove the estimates of a single channel, so we do it on this form.
+struct ChannelLiquidities(HashMap<u64, SerializedLiquidity>);
+
+impl Writeable for ChannelLiquidities {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		write_tlv_fields!(w, {
+			(0, self.0, required),
+		});
+		Ok(())
+	}
+}
+
+impl Readable for ChannelLiquidities {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let mut channel_liquidities = new_hash_map();
+		read_tlv_fields!(r, {
+			(0, channel_liquidities, required),
+		});
+		Ok(Self(channel_liquidities))
+	}
+}
+
+fn invalid_scorer(e: DecodeError) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, format!("Invalid scorer: {:?}", e))
+}
+
+/// Owns the [`Scorer`] shared with the router and the background processor, and lets it be
+/// inspected, reset or replaced while the node runs.
+///
+/// The background processor only persists the scorer every so often, so every change made here is
+/// persisted right away instead.
+pub struct ScorerManager {
+	scorer: Arc<RwLock<Scorer>>,
+	params: ScoringParams,
+	network_graph: Arc<NetworkGraph>,
+	kv_store: Arc<DataStore>,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl ScorerManager {
+	/// Loads the persisted scorer, or starts a new one if there is none.
+	pub fn new(
+		params: ScoringParams, network_graph: Arc<NetworkGraph>, kv_store: Arc<DataStore>,
+		logger: Arc<FilesystemLogger>,
+	) -> Self {
+		let scorer = crate::disk::read_scorer(
+			&kv_store,
+			params.decay,
+			Arc::clone(&network_graph),
+			Arc::clone(&logger),
+		);
+		Self { scorer: Arc::new(RwLock::new(scorer)), params, network_graph, kv_store, logger }
+	}
+
+	pub fn scorer(&self) -> Arc<RwLock<Scorer>> {
+		Arc::clone(&self.scorer)
+	}
+
+	pub fn params(&self) -> &ScoringParams {
+		&self.params
+	}
+
+	/// The scorer serialized the same way it is persisted.
+	pub fn export(&self) -> Vec<u8> {
+		self.scorer.read().unwrap().encode()
+	}
+
+	/// Replaces the scorer with a serialized one, e.g. from [`Self::export`] on another node.
+	pub fn import(&self, serialized: &[u8]) -> io::Result<()> {
+		let scorer = self.read(serialized).map_err(invalid_scorer)?;
+		let mut current = self.scorer.write().unwrap();
+		*current = scorer;
+		self.persist(&current)?;
+		log_info!(self.logger, "Imported a scorer of {} bytes", serialized.len());
+		Ok(())
+	}
+
+	/// Forgets everything learned about every channel.
+	pub fn reset(&self) -> io::Result<()> {
+		let mut scorer = self.scorer.write().unwrap();
+		*scorer = Scorer::new(
+			self.params.decay,
+			Arc::clone(&self.network_graph),
+			Arc::clone(&self.logger),
+		);
+		self.persist(&scorer)?;
+		log_info!(self.logger, "Reset the scorer");
+		Ok(())
+	}
+
+	/// Forgets everything learned about the channel `scid`, returning whether there was anything.
+	pub fn reset_channel(&self, scid: u64) -> io::Result<bool> {
+		let mut scorer = self.scorer.write().unwrap();
+		let mut liquidities =
+			ChannelLiquidities::read(&mut &scorer.encode()[..]).map_err(invalid_scorer)?;
+		if liquidities.0.remove(&scid).is_none() {
+			return Ok(false);
+		}
+		*scorer = self.read(&liquidities.encode()).map_err(invalid_scorer)?;
+		self.persist(&scorer)?;
+		log_info!(self.logger, "Reset the scorer's liquidity estimates for channel {}", scid);
+		Ok(true)
+	}
+
+	fn read(&self, serialized: &[u8]) -> Result<Scorer, DecodeError> {
+		let args = (self.params.decay, Arc::clone(&self.network_graph), Arc::clone(&self.logger));
+		Scorer::read(&mut &serialized[..], args)
+	}
+
+	fn persist(&self, scorer: &Scorer) -> io::Result<()> {
+		self.kv_store.write(
+			SCORER_PERSISTENCE_PRIMARY_NAMESPACE,
+			SCORER_PERSISTENCE_SECONDARY_NAMESPACE,
+			SCORER_PERSISTENCE_KEY,
+			&scorer.encode(),
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::{test_data_dir, test_store};
+	use bitcoin::constants::ChainHash;
+	use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+	use bitcoin::Network;
+	use lightning::ln::msgs::UnsignedChannelUpdate;
+	use lightning::routing::gossip::NodeId;
+	use lightning::routing::router::{Path, RouteHop};
+	use lightning::routing::scoring::ScoreUpdate;
+	use lightning::types::features::{ChannelFeatures, NodeFeatures};
+	use std::time::{SystemTime, UNIX_EPOCH};
+
+	fn pubkey(byte: u8) -> PublicKey {
+		PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[byte; 32]).unwrap())
+	}
+
+	/// Builds the line a - b - c, with both directions of each channel updated.
+	fn line_graph(logger: Arc<FilesystemLogger>, now: u64) -> NetworkGraph {
+		let graph = NetworkGraph::new(Network::Regtest, logger);
+		for (scid, (one, two)) in [(1, 2), (2, 3)].iter().enumerate() {
+			let scid = scid as u64 + 1;
+			graph
+				.add_channel_from_partial_announcement(
+					scid,
+					now,
+					ChannelFeatures::empty(),
+					pubkey(*one),
+					pubkey(*two),
+				)
+				.unwrap();
+			for channel_flags in [0, 1].iter() {
+				graph
+					.update_channel_unsigned(&UnsignedChannelUpdate {
+						chain_hash: ChainHash::using_genesis_block(Network::Regtest),
+						short_channel_id: scid,
+						timestamp: now as u32,
+						message_flags: 1,
+						channel_flags: *channel_flags,
+						cltv_expiry_delta: 40,
+						htlc_minimum_msat: 1,
+						htlc_maximum_msat: 100_000_000,
+						fee_base_msat: 1000,
+						fee_proportional_millionths: 100,
+						excess_data: Vec::new(),
+					})
+					.unwrap();
+			}
+		}
+		graph
+	}
+
+	fn hop(node: u8, scid: u64) -> RouteHop {
+		RouteHop {
+			pubkey: pubkey(node),
+			node_features: NodeFeatures::empty(),
+			short_channel_id: scid,
+			channel_features: ChannelFeatures::empty(),
+			fee_msat: 10_000,
+			cltv_expiry_delta: 40,
+			maybe_announced_channel: true,
+		}
+	}
+
+	#[test]
+	fn test_reset_channel() {
+		let (kv_store, logger) = test_store(&test_data_dir("scoring"));
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+		let network_graph = Arc::new(line_graph(Arc::clone(&logger), now.as_secs()));
+		let manager = ScorerManager::new(
+			ScoringParams::default(),
+			network_graph,
+			Arc::clone(&kv_store),
+			Arc::clone(&logger),
+		);
+		let path = Path { hops: vec![hop(2, 1), hop(3, 2)], blinded_tail: None };
+		manager.scorer().write().unwrap().payment_path_successful(&path, now);
+		let (b, c) = (NodeId::from_pubkey(&pubkey(2)), NodeId::from_pubkey(&pubkey(3)));
+		let estimate = |scid, target| {
+			manager.scorer().read().unwrap().estimated_channel_liquidity_range(scid, target)
+		};
+		assert!(estimate(1, &b).is_some());
+		assert!(estimate(2, &c).is_some());
+
+		let exported = manager.export();
+		assert!(manager.reset_channel(1).unwrap());
+		assert!(estimate(1, &b).is_none());
+		assert!(estimate(2, &c).is_some());
+		assert!(!manager.reset_channel(1).unwrap());
+
+		// The change was persisted, and the export still holds what was forgotten.
+		let persisted = ScorerManager::new(
+			ScoringParams::default(),
+			Arc::clone(&manager.network_graph),
+			Arc::clone(&kv_store),
+			logger,
+		);
+		assert!(persisted
+			.scorer()
+			.read()
+			.unwrap()
+			.estimated_channel_liquidity_range(1, &b)
+			.is_none());
+		manager.import(&exported).unwrap();
+		assert!(estimate(1, &b).is_some());
+		assert!(manager.import(b"not a scorer").is_err());
+
+		manager.reset().unwrap();
+		assert!(estimate(2, &c).is_none());
+	}
+
+	#[test]
+	fn test_params_from_json() {
+		let params = ScoringParams::from_json(
+			r#"{
+				"fee": { "base_penalty_msat": 42, "linear_success_probability": true },
+				"decay": { "liquidity_offset_half_life_secs": 3600 }
+			}"#,
+		)
+		.unwrap();
+		assert_eq!(params.fee.base_penalty_msat, 42);
+		assert!(params.fee.linear_success_probability);
+		assert_eq!(params.decay.liquidity_offset_half_life, Duration::from_secs(3600));
+		let defaults = ScoringParams::default();
+		assert_eq!(
+			params.fee.liquidity_penalty_multiplier_msat,
+			defaults.fee.liquidity_penalty_multiplier_msat
+		);
+		assert_eq!(
+			params.decay.historical_no_updates_half_life,
+			defaults.decay.historical_no_updates_half_life
+		);
+
+		assert!(ScoringParams::from_json(r#"{"fee": {"base_penalty": 1}}"#).is_err());
+		assert!(ScoringParams::from_json(r#"{"fee": {"base_penalty_msat": -1}}"#).is_err());
+		assert!(ScoringParams::from_json(r#"{"decay": {"liquidity_offset_half_life_secs": 0}}"#)
+			.is_err());
+		assert!(ScoringParams::from_json(r#"{"routing": {}}"#).is_err());
+	}
+}
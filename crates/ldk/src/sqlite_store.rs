@@ -0,0 +1,351 @@
+use crate::disk::{ENCRYPTION_PARAMS_FNAME, KEYS_SEED_FNAME, LOCK_FNAME};
+use lightning::io;
+use lightning::util::persist::{
+	KVStore, MigratableKVStore, KVSTORE_NAMESPACE_KEY_ALPHABET, KVSTORE_NAMESPACE_KEY_MAX_LEN,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// The name of the database file within the LDK data directory.
+pub const SQLITE_DB_FILE_NAME: &str = "ldk_data.sqlite";
+
+const KV_TABLE_NAME: &str = "ldk_data";
+
+/// Files in the root of the data directory which aren't [`KVStore`] entries and thus stay files
+/// when the SQLite store is used.
+const NON_KV_FILES: [&str; 4] =
+	[KEYS_SEED_FNAME, "channel_peer_data", ENCRYPTION_PARAMS_FNAME, LOCK_FNAME];
+
+/// Directories in the root of the data directory which don't hold [`KVStore`] entries.
+const NON_KV_DIRS: [&str; 1] = ["logs"];
+
+/// An entry as `(primary_namespace, secondary_namespace, key, value)`.
+pub(crate) type KVEntry = (String, String, String, Vec<u8>);
+
+/// A [`KVStore`] keeping all entries in a single SQLite database rather than one file each, as
+/// [`lightning_persister::fs_store::FilesystemStore`] does.
+///
+/// Every write is made in its own transaction, and [`SqliteStore::write_batch`] allows writing
+/// several entries atomically.
+pub struct SqliteStore {
+	connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+	/// Opens the database at `db_path`, creating it and its parent directories if needed.
+	pub fn new(db_path: &Path) -> io::Result<Self> {
+		if let Some(parent) = db_path.parent() {
+			fs::create_dir_all(parent)?;
+		}
+		let connection = Connection::open(db_path).map_err(to_io_error)?;
+		// Write-ahead logging lets readers proceed while a write is in progress, while
+		// `synchronous=FULL` still makes every commit durable before it returns, as LDK expects of
+		// a `KVStore`.
+		connection
+			.pragma_update(None, "journal_mode", "WAL")
+			.and_then(|_| connection.pragma_update(None, "synchronous", "FULL"))
+			.map_err(to_io_error)?;
+		let create_table = format!(
+			"CREATE TABLE IF NOT EXISTS {} (
+				primary_namespace TEXT NOT NULL,
+				secondary_namespace TEXT NOT NULL DEFAULT '',
+				key TEXT NOT NULL CHECK (key <> ''),
+				value BLOB,
+				PRIMARY KEY (primary_namespace, secondary_namespace, key)
+			)",
+			KV_TABLE_NAME
+		);
+		connection.execute(&create_table, []).map_err(to_io_error)?;
+		Ok(Self { connection: Mutex::new(connection) })
+	}
+
+	/// Writes all `entries`, given as `(primary_namespace, secondary_namespace, key, value)`, in a
+	/// single transaction, so that either all or none of them are persisted.
+	pub fn write_batch(&self, entries: &[(String, String, String, Vec<u8>)]) -> io::Result<()> {
+		for (primary_namespace, secondary_namespace, key, _) in entries {
+			check_namespace_key_validity(
+				primary_namespace,
+				secondary_namespace,
+				Some(key),
+				"write",
+			)?;
+		}
+		let mut connection = self.connection.lock().unwrap();
+		let tx = connection.transaction().map_err(to_io_error)?;
+		for (primary_namespace, secondary_namespace, key, value) in entries {
+			upsert(&tx, primary_namespace, secondary_namespace, key, value)?;
+		}
+		tx.commit().map_err(to_io_error)
+	}
+}
+
+/// Copies every entry a [`lightning_persister::fs_store::FilesystemStore`] wrote to `data_dir`
+/// into `store`, in a single transaction, and returns how many there were. The files are left in
+/// place.
+pub fn import_filesystem_data(data_dir: &Path, store: &SqliteStore) -> io::Result<usize> {
+	let entries = read_filesystem_entries(data_dir)?;
+	store.write_batch(&entries)?;
+	Ok(entries.len())
+}
+
+/// Reads every entry a [`lightning_persister::fs_store::FilesystemStore`] wrote to `data_dir`.
+pub(crate) fn read_filesystem_entries(data_dir: &Path) -> io::Result<Vec<KVEntry>> {
+	let mut entries = Vec::new();
+	collect_filesystem_entries(data_dir, &[], &mut entries)?;
+	Ok(entries)
+}
+
+fn collect_filesystem_entries(
+	dir: &Path, namespaces: &[String], entries: &mut Vec<KVEntry>,
+) -> io::Result<()> {
+	for dir_entry in fs::read_dir(dir)? {
+		let path = dir_entry?.path();
+		// Anything not named like a key isn't one, e.g. temporary files of interrupted writes or
+		// the SQLite database itself.
+		let name = match path.file_name().and_then(|name| name.to_str()) {
+			Some(name) if is_valid_kvstore_str(name) => name.to_string(),
+			_ => continue,
+		};
+		let in_root = namespaces.is_empty();
+		if path.is_dir() {
+			// Only two levels of namespaces are supported.
+			if namespaces.len() < 2 && !(in_root && NON_KV_DIRS.contains(&name.as_str())) {
+				let mut namespaces = namespaces.to_vec();
+				namespaces.push(name);
+				collect_filesystem_entries(&path, &namespaces, entries)?;
+			}
+			continue;
+		}
+		if in_root && NON_KV_FILES.contains(&name.as_str()) {
+			continue;
+		}
+		let primary_namespace = namespaces.first().cloned().unwrap_or_default();
+		let secondary_namespace = namespaces.get(1).cloned().unwrap_or_default();
+		entries.push((primary_namespace, secondary_namespace, name, fs::read(&path)?));
+	}
+	Ok(())
+}
+
+fn upsert(
+	connection: &Connection, primary_namespace: &str, secondary_namespace: &str, key: &str,
+	value: &[u8],
+) -> io::Result<()> {
+	let sql = format!(
+		"INSERT OR REPLACE INTO {} (primary_namespace, secondary_namespace, key, value)
+		VALUES (?1, ?2, ?3, ?4)",
+		KV_TABLE_NAME
+	);
+	connection
+		.execute(&sql, params![primary_namespace, secondary_namespace, key, value])
+		.map(|_| ())
+		.map_err(to_io_error)
+}
+
+impl KVStore for SqliteStore {
+	fn read(
+		&self, primary_namespace: &str, secondary_namespace: &str, key: &str,
+	) -> io::Result<Vec<u8>> {
+		check_namespace_key_validity(primary_namespace, secondary_namespace, Some(key), "read")?;
+		let sql = format!(
+			"SELECT value FROM {} WHERE primary_namespace = ?1 AND secondary_namespace = ?2 AND key = ?3",
+			KV_TABLE_NAME
+		);
+		let connection = self.connection.lock().unwrap();
+		let value = connection
+			.query_row(&sql, params![primary_namespace, secondary_namespace, key], |row| {
+				row.get::<_, Vec<u8>>(0)
+			})
+			.optional()
+			.map_err(to_io_error)?;
+		value.ok_or_else(|| {
+			io::Error::new(
+				io::ErrorKind::NotFound,
+				format!("No entry for {}/{}/{}", primary_namespace, secondary_namespace, key),
+			)
+		})
+	}
+
+	fn write(
+		&self, primary_namespace: &str, secondary_namespace: &str, key: &str, buf: &[u8],
+	) -> io::Result<()> {
+		check_namespace_key_validity(primary_namespace, secondary_namespace, Some(key), "write")?;
+		let mut connection = self.connection.lock().unwrap();
+		let tx = connection.transaction().map_err(to_io_error)?;
+		upsert(&tx, primary_namespace, secondary_namespace, key, buf)?;
+		tx.commit().map_err(to_io_error)
+	}
+
+	fn remove(
+		&self, primary_namespace: &str, secondary_namespace: &str, key: &str, _lazy: bool,
+	) -> io::Result<()> {
+		check_namespace_key_validity(primary_namespace, secondary_namespace, Some(key), "remove")?;
+		let sql = format!(
+			"DELETE FROM {} WHERE primary_namespace = ?1 AND secondary_namespace = ?2 AND key = ?3",
+			KV_TABLE_NAME
+		);
+		let connection = self.connection.lock().unwrap();
+		connection
+			.execute(&sql, params![primary_namespace, secondary_namespace, key])
+			.map(|_| ())
+			.map_err(to_io_error)
+	}
+
+	fn list(&self, primary_namespace: &str, secondary_namespace: &str) -> io::Result<Vec<String>> {
+		check_namespace_key_validity(primary_namespace, secondary_namespace, None, "list")?;
+		let sql = format!(
+			"SELECT key FROM {} WHERE primary_namespace = ?1 AND secondary_namespace = ?2",
+			KV_TABLE_NAME
+		);
+		let connection = self.connection.lock().unwrap();
+		let mut stmt = connection.prepare(&sql).map_err(to_io_error)?;
+		let keys = stmt
+			.query_map(params![primary_namespace, secondary_namespace], |row| row.get(0))
+			.map_err(to_io_error)?
+			.collect::<Result<Vec<String>, _>>()
+			.map_err(to_io_error)?;
+		Ok(keys)
+	}
+}
+
+impl MigratableKVStore for SqliteStore {
+	fn list_all_keys(&self) -> io::Result<Vec<(String, String, String)>> {
+		let sql =
+			format!("SELECT primary_namespace, secondary_namespace, key FROM {}", KV_TABLE_NAME);
+		let connection = self.connection.lock().unwrap();
+		let mut stmt = connection.prepare(&sql).map_err(to_io_error)?;
+		let keys = stmt
+			.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+			.map_err(to_io_error)?
+			.collect::<Result<Vec<_>, _>>()
+			.map_err(to_io_error)?;
+		Ok(keys)
+	}
+}
+
+fn to_io_error(e: rusqlite::Error) -> io::Error {
+	io::Error::new(io::ErrorKind::Other, format!("SQLite error: {}", e))
+}
+
+/// Returns whether `s` may be used as a namespace or key, following the same rules as
+/// [`lightning_persister::fs_store::FilesystemStore`].
+pub fn is_valid_kvstore_str(s: &str) -> bool {
+	s.len() <= KVSTORE_NAMESPACE_KEY_MAX_LEN
+		&& s.chars().all(|c| KVSTORE_NAMESPACE_KEY_ALPHABET.contains(c))
+}
+
+fn check_namespace_key_validity(
+	primary_namespace: &str, secondary_namespace: &str, key: Option<&str>, operation: &str,
+) -> io::Result<()> {
+	let invalid = |reason: &str| {
+		Err(io::Error::new(
+			io::ErrorKind::Other,
+			format!(
+				"Failed to {} {}/{}/{}: {}",
+				operation,
+				primary_namespace,
+				secondary_namespace,
+				key.unwrap_or_default(),
+				reason
+			),
+		))
+	};
+	if key == Some("") {
+		return invalid("key may not be empty.");
+	}
+	if primary_namespace.is_empty() && !secondary_namespace.is_empty() {
+		return invalid(
+			"primary namespace may not be empty if a non-empty secondary namespace is given.",
+		);
+	}
+	let key_valid = match key {
+		Some(key) => is_valid_kvstore_str(key),
+		None => true,
+	};
+	let valid = is_valid_kvstore_str(primary_namespace)
+		&& is_valid_kvstore_str(secondary_namespace)
+		&& key_valid;
+	if !valid {
+		return invalid("primary namespace, secondary namespace and key must be valid.");
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::test_data_dir;
+	use lightning_persister::fs_store::FilesystemStore;
+	use std::path::PathBuf;
+
+	#[test]
+	fn test_read_write_remove_list() {
+		let db_path = PathBuf::from(test_data_dir("sqlite-store")).join(SQLITE_DB_FILE_NAME);
+		let store = SqliteStore::new(&db_path).unwrap();
+
+		store.write("", "", "manager", &[1, 2, 3]).unwrap();
+		store.write("monitors", "", "chan_a", &[4]).unwrap();
+		store.write("monitors", "", "chan_b", &[5]).unwrap();
+		store.write("monitors", "", "chan_b", &[6]).unwrap();
+		assert_eq!(store.read("", "", "manager").unwrap(), vec![1, 2, 3]);
+		assert_eq!(store.read("monitors", "", "chan_b").unwrap(), vec![6]);
+		assert_eq!(
+			store.read("monitors", "", "chan_c").unwrap_err().kind(),
+			io::ErrorKind::NotFound
+		);
+
+		let mut keys = store.list("monitors", "").unwrap();
+		keys.sort();
+		assert_eq!(keys, vec!["chan_a".to_string(), "chan_b".to_string()]);
+
+		store.remove("monitors", "", "chan_a", false).unwrap();
+		assert_eq!(store.list("monitors", "").unwrap(), vec!["chan_b".to_string()]);
+		assert!(store.write("", "", "logs.txt", &[]).is_err());
+
+		// Entries survive reopening the database.
+		drop(store);
+		let store = SqliteStore::new(&db_path).unwrap();
+		assert_eq!(store.list_all_keys().unwrap().len(), 2);
+	}
+
+	#[test]
+	fn test_import_filesystem_data() {
+		let data_dir = test_data_dir("sqlite-import");
+		let fs_store = FilesystemStore::new(data_dir.clone().into());
+		fs_store.write("", "", "manager", &[1]).unwrap();
+		fs_store.write("monitors", "", "chan_a", &[2]).unwrap();
+		fs_store.write("monitor_updates", "chan_a", "1", &[3]).unwrap();
+		fs::write(Path::new(&data_dir).join("keys_seed"), [4; 32]).unwrap();
+		fs::create_dir_all(Path::new(&data_dir).join("logs")).unwrap();
+		fs::write(Path::new(&data_dir).join("logs").join("logs"), "log line").unwrap();
+
+		let store = SqliteStore::new(&Path::new(&data_dir).join(SQLITE_DB_FILE_NAME)).unwrap();
+		assert_eq!(import_filesystem_data(Path::new(&data_dir), &store).unwrap(), 3);
+		assert_eq!(store.read("", "", "manager").unwrap(), vec![1]);
+		assert_eq!(store.read("monitors", "", "chan_a").unwrap(), vec![2]);
+		assert_eq!(store.read("monitor_updates", "chan_a", "1").unwrap(), vec![3]);
+		assert!(store.read("", "", "keys_seed").is_err());
+	}
+
+	#[test]
+	fn test_write_batch_is_atomic() {
+		let db_path = PathBuf::from(test_data_dir("sqlite-store")).join(SQLITE_DB_FILE_NAME);
+		let store = SqliteStore::new(&db_path).unwrap();
+
+		let entries = vec![
+			("".to_string(), "".to_string(), "manager".to_string(), vec![1]),
+			("monitors".to_string(), "".to_string(), "chan_a".to_string(), vec![2]),
+		];
+		store.write_batch(&entries).unwrap();
+		assert_eq!(store.list_all_keys().unwrap().len(), 2);
+
+		// An invalid key fails the whole batch.
+		let entries = vec![
+			("".to_string(), "".to_string(), "scorer".to_string(), vec![3]),
+			("".to_string(), "".to_string(), "not/a key".to_string(), vec![4]),
+		];
+		assert!(store.write_batch(&entries).is_err());
+		assert!(store.read("", "", "scorer").is_err());
+	}
+}
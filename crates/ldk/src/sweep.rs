@@ -1,25 +1,148 @@
 use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
 use std::{fs, io};
 
-use lightning::sign::{EntropySource, KeysManager, SpendableOutputDescriptor};
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::{Address, Network, ScriptBuf};
+use lightning::sign::{
+	ChangeDestinationSource, EntropySource, KeysManager, SignerProvider, SpendableOutputDescriptor,
+};
 use lightning::util::logger::Logger;
 use lightning::util::persist::KVStore;
 use lightning::util::ser::{Readable, WithoutLength, Writeable};
+use lightning::{log_error, log_info};
 
-use lightning_persister::fs_store::FilesystemStore;
 use crate::common::OutputSweeper;
-use crate::disk::FilesystemLogger;
+use crate::data_store::DataStore;
+use crate::disk::{FilesystemLogger, SWEEP_DESTINATION_FNAME};
 use crate::hex_utils;
 
 const DEPRECATED_PENDING_SPENDABLE_OUTPUT_DIR: &'static str = "pending_spendable_outputs";
 
+/// Where the `OutputSweeper` sends the outputs it sweeps, and how long it waits to batch them.
+///
+/// Outputs are swept to a fresh address from the on-chain wallet unless a destination was set,
+/// e.g. to send swept funds straight to cold storage. The destination is persisted, so it
+/// survives restarts until it is cleared again.
+pub struct SweepPolicy {
+	wallet: Arc<dyn ChangeDestinationSource + Send + Sync>,
+	network: Network,
+	destination: RwLock<Option<Address>>,
+	batch_blocks: u32,
+	kv_store: Arc<DataStore>,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl SweepPolicy {
+	/// Loads the destination persisted in `kv_store`, if any. With `batch_blocks` above 1, new
+	/// outputs are held until the next height which is a multiple of it, so outputs arriving
+	/// within the same window are swept by a single transaction.
+	pub fn new(
+		wallet: Arc<dyn ChangeDestinationSource + Send + Sync>, network: Network,
+		batch_blocks: u32, kv_store: Arc<DataStore>, logger: Arc<FilesystemLogger>,
+	) -> Self {
+		let mut destination = None;
+		if let Ok(bytes) = kv_store.read("", "", SWEEP_DESTINATION_FNAME) {
+			let address = String::from_utf8(bytes)
+				.map_err(|e| e.to_string())
+				.and_then(|address| parse_address(&address, network));
+			match address {
+				Ok(address) => {
+					log_info!(logger, "Sweeping outputs to {}", address);
+					destination = Some(address);
+				},
+				Err(e) => log_error!(logger, "Failed to read the sweep destination: {}", e),
+			}
+		}
+		Self {
+			wallet,
+			network,
+			destination: RwLock::new(destination),
+			batch_blocks,
+			kv_store,
+			logger,
+		}
+	}
+
+	/// The address outputs are swept to, or `None` if they are swept to the on-chain wallet.
+	pub fn destination(&self) -> Option<Address> {
+		self.destination.read().unwrap().clone()
+	}
+
+	/// Sweeps outputs to `address`, which must be on our network, from now on. `None` goes back
+	/// to sweeping to the on-chain wallet.
+	pub fn set_destination(&self, address: Option<&str>) -> Result<(), String> {
+		let address = address.map(|address| parse_address(address, self.network)).transpose()?;
+		let result = match &address {
+			Some(address) => {
+				self.kv_store.write("", "", SWEEP_DESTINATION_FNAME, address.to_string().as_bytes())
+			},
+			None => self.kv_store.remove("", "", SWEEP_DESTINATION_FNAME, false),
+		};
+		result.map_err(|e| format!("failed to persist the sweep destination: {}", e))?;
+		match &address {
+			Some(address) => log_info!(self.logger, "Sweeping outputs to {}", address),
+			None => log_info!(self.logger, "Sweeping outputs to the on-chain wallet"),
+		}
+		*self.destination.write().unwrap() = address;
+		Ok(())
+	}
+
+	pub fn batch_blocks(&self) -> u32 {
+		self.batch_blocks
+	}
+
+	/// The height until which outputs we learn about at `current_height` are held back, so they
+	/// are swept along with the others arriving within the same batching window.
+	pub fn delay_until_height(&self, current_height: u32) -> Option<u32> {
+		if self.batch_blocks <= 1 {
+			return None;
+		}
+		Some((current_height + 1).div_ceil(self.batch_blocks) * self.batch_blocks)
+	}
+}
+
+fn parse_address(address: &str, network: Network) -> Result<Address, String> {
+	Address::<NetworkUnchecked>::from_str(address)
+		.map_err(|e| format!("invalid address {}: {}", address, e))?
+		.require_network(network)
+		.map_err(|e| format!("invalid address {}: {}", address, e))
+}
+
+impl ChangeDestinationSource for SweepPolicy {
+	fn get_change_destination_script(&self) -> Result<ScriptBuf, ()> {
+		match self.destination() {
+			Some(address) => Ok(address.script_pubkey()),
+			None => self.wallet.get_change_destination_script(),
+		}
+	}
+}
+
+/// Sweeps to the destination script of the node's own keys, for nodes without an on-chain wallet.
+/// The swept funds can be spent with the keys seed.
+pub struct KeysDestination {
+	keys_manager: Arc<KeysManager>,
+}
+
+impl KeysDestination {
+	pub fn new(keys_manager: Arc<KeysManager>) -> Self {
+		Self { keys_manager }
+	}
+}
+
+impl ChangeDestinationSource for KeysDestination {
+	fn get_change_destination_script(&self) -> Result<ScriptBuf, ()> {
+		self.keys_manager.get_destination_script([0; 32])
+	}
+}
+
 /// We updated to use LDK's OutputSweeper as part of upgrading to LDK 0.0.123, so migrate away from
 /// the old sweep persistence.
 pub async fn migrate_deprecated_spendable_outputs(
 	ldk_data_dir: String, keys_manager: Arc<KeysManager>, logger: Arc<FilesystemLogger>,
-	persister: Arc<FilesystemStore>, sweeper: Arc<OutputSweeper>,
+	persister: Arc<DataStore>, sweeper: Arc<OutputSweeper>,
 ) {
 	lightning::log_info!(&*logger, "Beginning migration of deprecated spendable outputs");
 	let pending_spendables_dir =
@@ -100,3 +223,53 @@ pub async fn migrate_deprecated_spendable_outputs(
 		outputs.len()
 	);
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::{test_data_dir, test_store};
+
+	struct TestWallet;
+
+	impl ChangeDestinationSource for TestWallet {
+		fn get_change_destination_script(&self) -> Result<ScriptBuf, ()> {
+			Ok(ScriptBuf::new())
+		}
+	}
+
+	#[test]
+	fn test_sweep_destination_is_persisted() {
+		let (kv_store, logger) = test_store(&test_data_dir("sweep-policy"));
+		let new_policy = || {
+			let wallet = Arc::new(TestWallet);
+			SweepPolicy::new(
+				wallet,
+				Network::Regtest,
+				6,
+				Arc::clone(&kv_store),
+				Arc::clone(&logger),
+			)
+		};
+		let policy = new_policy();
+		assert_eq!(policy.get_change_destination_script(), Ok(ScriptBuf::new()));
+
+		let cold_storage = "bcrt1qs758ursh4q9z627kt3pp5yysm78ddny6txaqgw";
+		assert!(policy.set_destination(Some("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2")).is_err());
+		policy.set_destination(Some(cold_storage)).unwrap();
+		let policy = new_policy();
+		let script = parse_address(cold_storage, Network::Regtest).unwrap().script_pubkey();
+		assert_eq!(policy.get_change_destination_script(), Ok(script));
+
+		policy.set_destination(None).unwrap();
+		assert_eq!(new_policy().destination(), None);
+	}
+
+	#[test]
+	fn test_outputs_are_batched() {
+		let (kv_store, logger) = test_store(&test_data_dir("sweep-batching"));
+		let policy = SweepPolicy::new(Arc::new(TestWallet), Network::Regtest, 6, kv_store, logger);
+		assert_eq!(policy.delay_until_height(100), Some(102));
+		assert_eq!(policy.delay_until_height(101), Some(102));
+		assert_eq!(policy.delay_until_height(102), Some(108));
+	}
+}
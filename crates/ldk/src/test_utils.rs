@@ -0,0 +1,246 @@
+//! Helpers shared by the unit tests of the chain and notification backends.
+
+use crate::data_store::DataStore;
+use crate::disk::FilesystemLogger;
+use crate::fee_policy::{FeeEstimates, FeePolicy};
+use lightning_persister::fs_store::FilesystemStore;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Returns a directory for a test to keep its data in, distinct from every other test's.
+pub(crate) fn test_data_dir(name: &str) -> String {
+	static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+	let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+	let dir = std::env::temp_dir().join(format!("ldk-test-{}-{}-{}", name, std::process::id(), id));
+	let _ = std::fs::remove_dir_all(&dir);
+	dir.to_str().unwrap().to_string()
+}
+
+/// Returns a filesystem data store and a logger, both kept in `data_dir`.
+pub(crate) fn test_store(data_dir: &str) -> (Arc<DataStore>, Arc<FilesystemLogger>) {
+	let kv_store = Arc::new(DataStore::Filesystem(FilesystemStore::new(data_dir.into())));
+	(kv_store, Arc::new(FilesystemLogger::new(data_dir.to_string())))
+}
+
+/// Returns [`FeeEstimates`] following the default policy and persisted in `data_dir`.
+pub(crate) fn test_fee_estimates(data_dir: &str) -> Arc<FeeEstimates> {
+	let (kv_store, logger) = test_store(data_dir);
+	Arc::new(FeeEstimates::new(FeePolicy::default(), kv_store, logger))
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct MockHttpRequest {
+	pub(crate) method: String,
+	pub(crate) path: String,
+	pub(crate) headers: Vec<(String, String)>,
+	pub(crate) body: String,
+}
+
+impl MockHttpRequest {
+	/// The value of the header called `name`, ignoring case, if the request had one.
+	pub(crate) fn header(&self, name: &str) -> Option<&str> {
+		let mut headers = self.headers.iter();
+		headers.find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+	}
+}
+
+type Responder = dyn Fn(&MockHttpRequest) -> (u16, String) + Send + Sync;
+
+/// A minimal HTTP/1.1 server answering every request through the given responder and recording
+/// what it was asked, so tests can assert on the requests a client made.
+pub(crate) struct MockHttpServer {
+	port: u16,
+	requests: Arc<Mutex<Vec<MockHttpRequest>>>,
+}
+
+impl MockHttpServer {
+	pub(crate) async fn start<F>(responder: F) -> Self
+	where
+		F: Fn(&MockHttpRequest) -> (u16, String) + Send + Sync + 'static,
+	{
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let requests = Arc::new(Mutex::new(Vec::new()));
+		let responder: Arc<Responder> = Arc::new(responder);
+		let server_requests = Arc::clone(&requests);
+		tokio::spawn(async move {
+			loop {
+				let (stream, _) = match listener.accept().await {
+					Ok(conn) => conn,
+					Err(_) => return,
+				};
+				let requests = Arc::clone(&server_requests);
+				let responder = Arc::clone(&responder);
+				tokio::spawn(async move {
+					let _ = serve_connection(stream, requests, responder).await;
+				});
+			}
+		});
+		Self { port, requests }
+	}
+
+	pub(crate) fn url(&self) -> String {
+		format!("http://127.0.0.1:{}", self.port)
+	}
+
+	pub(crate) fn requests(&self) -> Vec<MockHttpRequest> {
+		self.requests.lock().unwrap().clone()
+	}
+
+	/// Waits up to five seconds for a request to `path` to arrive and returns it.
+	pub(crate) async fn wait_for_request(&self, path: &str) -> MockHttpRequest {
+		for _ in 0..500 {
+			if let Some(req) = self.requests().into_iter().find(|r| r.path == path) {
+				return req;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		panic!("Timed out waiting for a request to {}", path);
+	}
+}
+
+async fn serve_connection(
+	stream: TcpStream, requests: Arc<Mutex<Vec<MockHttpRequest>>>, responder: Arc<Responder>,
+) -> std::io::Result<()> {
+	let mut reader = BufReader::new(stream);
+	loop {
+		let mut request_line = String::new();
+		if reader.read_line(&mut request_line).await? == 0 {
+			return Ok(());
+		}
+		let mut parts = request_line.split_whitespace();
+		let method = parts.next().unwrap_or_default().to_string();
+		let path = parts.next().unwrap_or_default().to_string();
+
+		let mut content_length = 0;
+		let mut headers = Vec::new();
+		loop {
+			let mut line = String::new();
+			reader.read_line(&mut line).await?;
+			let line = line.trim_end();
+			if line.is_empty() {
+				break;
+			}
+			if let Some((name, value)) = line.split_once(':') {
+				if name.trim().eq_ignore_ascii_case("content-length") {
+					content_length = value.trim().parse().unwrap_or(0);
+				}
+				headers.push((name.trim().to_string(), value.trim().to_string()));
+			}
+		}
+		let mut body = vec![0; content_length];
+		reader.read_exact(&mut body).await?;
+
+		let body = String::from_utf8_lossy(&body).into();
+		let request = MockHttpRequest { method, path, headers, body };
+		let (status, response_body) = responder(&request);
+		requests.lock().unwrap().push(request);
+
+		let response = format!(
+			"HTTP/1.1 {} MOCK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+			status,
+			response_body.len(),
+			response_body
+		);
+		reader.get_mut().write_all(response.as_bytes()).await?;
+	}
+}
+
+type ElectrumResponder = dyn Fn(&str, &serde_json::Value) -> serde_json::Value + Send + Sync;
+
+/// A minimal Electrum server speaking newline-delimited JSON-RPC over plain TCP, answering every
+/// call through the given responder and recording the method and params of each.
+pub(crate) struct MockElectrumServer {
+	port: u16,
+	requests: Arc<Mutex<Vec<(String, serde_json::Value)>>>,
+}
+
+impl MockElectrumServer {
+	pub(crate) async fn start<F>(responder: F) -> Self
+	where
+		F: Fn(&str, &serde_json::Value) -> serde_json::Value + Send + Sync + 'static,
+	{
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let requests = Arc::new(Mutex::new(Vec::new()));
+		let responder: Arc<ElectrumResponder> = Arc::new(responder);
+		let server_requests = Arc::clone(&requests);
+		tokio::spawn(async move {
+			loop {
+				let (stream, _) = match listener.accept().await {
+					Ok(conn) => conn,
+					Err(_) => return,
+				};
+				let requests = Arc::clone(&server_requests);
+				let responder = Arc::clone(&responder);
+				tokio::spawn(async move {
+					let mut reader = BufReader::new(stream);
+					let mut line = String::new();
+					while let Ok(len) = reader.read_line(&mut line).await {
+						if len == 0 {
+							return;
+						}
+						let request: serde_json::Value = match serde_json::from_str(&line) {
+							Ok(request) => request,
+							Err(_) => return,
+						};
+						line.clear();
+						let method = request["method"].as_str().unwrap_or_default().to_string();
+						let params = request["params"].clone();
+						let result = responder(&method, &params);
+						requests.lock().unwrap().push((method, params));
+						let response = serde_json::json!({
+							"jsonrpc": "2.0",
+							"id": request["id"],
+							"result": result,
+						});
+						let response = format!("{}\n", response);
+						if reader.get_mut().write_all(response.as_bytes()).await.is_err() {
+							return;
+						}
+					}
+				});
+			}
+		});
+		Self { port, requests }
+	}
+
+	pub(crate) fn url(&self) -> String {
+		format!("tcp://127.0.0.1:{}", self.port)
+	}
+
+	/// Waits up to five seconds for `count` calls to `method` to arrive and returns their params.
+	pub(crate) async fn wait_for_requests(
+		&self, method: &str, count: usize,
+	) -> Vec<serde_json::Value> {
+		for _ in 0..500 {
+			let requests = self.requests.lock().unwrap().clone();
+			let params = requests
+				.into_iter()
+				.filter(|(m, _)| m == method)
+				.map(|(_, params)| params)
+				.collect::<Vec<_>>();
+			if params.len() >= count {
+				return params;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		panic!("Timed out waiting for {} calls to {}", count, method);
+	}
+
+	/// Waits up to five seconds for a call to `method` to arrive and returns its params.
+	pub(crate) async fn wait_for_request(&self, method: &str) -> serde_json::Value {
+		for _ in 0..500 {
+			let requests = self.requests.lock().unwrap().clone();
+			if let Some((_, params)) = requests.into_iter().find(|(m, _)| m == method) {
+				return params;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		panic!("Timed out waiting for a call to {}", method);
+	}
+}
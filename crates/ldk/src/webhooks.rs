@@ -0,0 +1,532 @@
+use crate::data_store::DataStore;
+use crate::disk::{FilesystemLogger, WEBHOOK_OUTBOX_NAMESPACE};
+use crate::hex_utils;
+use crate::notifications::Notification;
+use bitcoin::hashes::{hmac, sha256, Hash, HashEngine};
+use lightning::util::logger::Logger;
+use lightning::util::persist::KVStore;
+use lightning::util::ser::{Readable, Writeable};
+use lightning::{impl_writeable_tlv_based, log_error, log_info, log_warn};
+use rand::Rng;
+use std::cmp;
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::runtime;
+use tokio::sync::Notify;
+
+/// The header carrying the ID of the notification, which stays the same across retries, and when
+/// the notification is made again for the same event, e.g. after a restart, so receivers can
+/// ignore notifications they already processed.
+pub const ID_HEADER: &str = "X-Webhook-Id";
+/// The header carrying when a delivery was attempted, in seconds since the UNIX epoch.
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+/// The header carrying `sha256=<hex>`, the HMAC-SHA256 of `<timestamp>.<body>` keyed with the
+/// webhook secret. Only sent if a secret is configured.
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where notifications are posted and how failed deliveries are retried.
+#[derive(Clone, Debug)]
+pub struct WebhookConfig {
+	pub urls: Vec<String>,
+	/// The key notifications are signed with, if any.
+	pub secret: Option<String>,
+	/// How long to wait before the first retry. Every further retry waits twice as long as the
+	/// one before, up to `max_backoff`.
+	pub initial_backoff: Duration,
+	pub max_backoff: Duration,
+	/// How many times delivery is attempted before the notification is dropped.
+	pub max_attempts: u32,
+}
+
+impl Default for WebhookConfig {
+	fn default() -> Self {
+		Self {
+			urls: Vec::new(),
+			secret: None,
+			initial_backoff: Duration::from_secs(5),
+			max_backoff: Duration::from_secs(60 * 60),
+			max_attempts: 20,
+		}
+	}
+}
+
+/// A notification waiting to be delivered to one URL.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct PendingDelivery {
+	url: String,
+	notification_id: String,
+	payload: Vec<u8>,
+	attempts: u32,
+	/// When the notification was created, in milliseconds since the UNIX epoch. Deliveries to a
+	/// URL are made in this order, so it's unique among the notifications in the outbox.
+	created_at_ms: u64,
+	next_attempt_ms: u64,
+}
+
+impl_writeable_tlv_based!(PendingDelivery, {
+	(0, url, required),
+	(2, notification_id, required),
+	(4, payload, required),
+	(6, attempts, required),
+	(8, created_at_ms, required),
+	(10, next_attempt_ms, required),
+});
+
+/// Posts [`Notification`]s as JSON to the configured webhook URLs.
+///
+/// Every notification is written to an outbox in the data store before [`Webhooks::notify`]
+/// returns and only removed once the receiver acknowledged it with a `2xx` status, so
+/// notifications survive restarts and outages of the receiver. Failed deliveries are retried with
+/// exponential backoff. Each URL is delivered to by its own task, strictly in the order the
+/// notifications were made: a delivery waiting to be retried holds up the later ones to its URL,
+/// but a slow or unreachable receiver doesn't hold up the others.
+pub struct Webhooks {
+	config: WebhookConfig,
+	outbox: Mutex<HashMap<String, PendingDelivery>>,
+	/// Wakes up the task delivering to each URL.
+	wakeups: HashMap<String, Notify>,
+	/// The `created_at_ms` of the latest notification. Only updated with `outbox` locked.
+	last_created_at_ms: AtomicU64,
+	http_client: reqwest::Client,
+	kv_store: Arc<DataStore>,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl Webhooks {
+	/// Loads the deliveries left in the outbox and starts delivering them on `handle`. They are
+	/// attempted again right away, as the receiver may well have come back while we were down.
+	/// Deliveries to URLs which are no longer configured are dropped.
+	pub fn new(
+		config: WebhookConfig, kv_store: Arc<DataStore>, handle: &runtime::Handle,
+		logger: Arc<FilesystemLogger>,
+	) -> io::Result<Arc<Self>> {
+		let http_client = reqwest::Client::builder()
+			.timeout(REQUEST_TIMEOUT)
+			.build()
+			.map_err(io::Error::other)?;
+		let mut outbox = HashMap::new();
+		for key in kv_store.list(WEBHOOK_OUTBOX_NAMESPACE, "")? {
+			let bytes = kv_store.read(WEBHOOK_OUTBOX_NAMESPACE, "", &key)?;
+			match PendingDelivery::read(&mut &bytes[..]) {
+				Ok(mut delivery) if config.urls.contains(&delivery.url) => {
+					delivery.next_attempt_ms = 0;
+					outbox.insert(key, delivery);
+				},
+				Ok(delivery) => {
+					log_warn!(
+						logger,
+						"Dropping notification {} to {}, which is no longer configured",
+						delivery.notification_id,
+						delivery.url
+					);
+					kv_store.remove(WEBHOOK_OUTBOX_NAMESPACE, "", &key, false)?;
+				},
+				Err(e) => log_error!(logger, "Failed to read webhook delivery {}: {:?}", key, e),
+			}
+		}
+		if !outbox.is_empty() {
+			log_info!(logger, "Loaded {} pending webhook deliveries", outbox.len());
+		}
+
+		let wakeups = config.urls.iter().map(|url| (url.clone(), Notify::new())).collect();
+		let last_created_at_ms = outbox.values().map(|delivery| delivery.created_at_ms).max();
+		let webhooks = Arc::new(Self {
+			config,
+			outbox: Mutex::new(outbox),
+			wakeups,
+			last_created_at_ms: AtomicU64::new(last_created_at_ms.unwrap_or(0)),
+			http_client,
+			kv_store,
+			logger,
+		});
+		for url in webhooks.wakeups.keys() {
+			let deliverer = Arc::clone(&webhooks);
+			let url = url.clone();
+			handle.spawn(async move { deliverer.deliver_forever(url).await });
+		}
+		Ok(webhooks)
+	}
+
+	/// Queues `notification` for delivery to every configured URL. It is persisted before this
+	/// returns, delivery happens in the background.
+	pub fn notify(&self, notification: &Notification) {
+		if self.config.urls.is_empty() {
+			return;
+		}
+		let now_ms = unix_time_ms();
+		let notification_id = notification_id(notification);
+		let payload = serde_json::json!({
+			"id": notification_id,
+			"type": notification.event_type(),
+			"created_at": now_ms / 1000,
+			"data": notification.data(),
+		})
+		.to_string()
+		.into_bytes();
+
+		let mut outbox = self.outbox.lock().unwrap();
+		let last_created_at_ms = self.last_created_at_ms.load(Ordering::Acquire);
+		let created_at_ms = cmp::max(now_ms, last_created_at_ms + 1);
+		self.last_created_at_ms.store(created_at_ms, Ordering::Release);
+		for (idx, url) in self.config.urls.iter().enumerate() {
+			let key = format!("{}-{}", notification_id, idx);
+			if outbox.contains_key(&key) {
+				// Made again for the same event, e.g. replayed by LDK, before it was delivered.
+				continue;
+			}
+			let delivery = PendingDelivery {
+				url: url.clone(),
+				notification_id: notification_id.clone(),
+				payload: payload.clone(),
+				attempts: 0,
+				created_at_ms,
+				next_attempt_ms: now_ms,
+			};
+			if let Err(e) =
+				self.kv_store.write(WEBHOOK_OUTBOX_NAMESPACE, "", &key, &delivery.encode())
+			{
+				log_error!(self.logger, "Failed to persist webhook delivery {}: {}", key, e);
+			}
+			outbox.insert(key, delivery);
+		}
+		for wakeup in self.wakeups.values() {
+			wakeup.notify_one();
+		}
+	}
+
+	/// The number of deliveries which haven't succeeded yet.
+	pub fn pending_deliveries(&self) -> usize {
+		self.outbox.lock().unwrap().len()
+	}
+
+	/// Delivers the notifications to `url` one at a time, oldest first, each once it is due.
+	async fn deliver_forever(&self, url: String) {
+		let wakeup = &self.wakeups[&url];
+		loop {
+			let oldest = self
+				.outbox
+				.lock()
+				.unwrap()
+				.iter()
+				.filter(|(_, delivery)| delivery.url == url)
+				.min_by_key(|(_, delivery)| delivery.created_at_ms)
+				.map(|(key, delivery)| (key.clone(), delivery.clone()));
+			let wait = match oldest {
+				Some((key, delivery)) if delivery.next_attempt_ms <= unix_time_ms() => {
+					self.attempt(key, delivery).await;
+					continue;
+				},
+				Some((_, delivery)) => {
+					Duration::from_millis(delivery.next_attempt_ms.saturating_sub(unix_time_ms()))
+				},
+				None => self.config.max_backoff,
+			};
+			tokio::select! {
+				_ = tokio::time::sleep(wait) => {},
+				_ = wakeup.notified() => {},
+			}
+		}
+	}
+
+	async fn attempt(&self, key: String, mut delivery: PendingDelivery) {
+		let timestamp = (unix_time_ms() / 1000).to_string();
+		let mut request = self
+			.http_client
+			.post(&delivery.url)
+			.header("Content-Type", "application/json")
+			.header(ID_HEADER, &delivery.notification_id)
+			.header(TIMESTAMP_HEADER, &timestamp);
+		if let Some(secret) = &self.config.secret {
+			request = request.header(SIGNATURE_HEADER, sign(secret, &timestamp, &delivery.payload));
+		}
+		let result = match request.body(delivery.payload.clone()).send().await {
+			Ok(response) if response.status().is_success() => Ok(()),
+			Ok(response) => Err(format!("status {}", response.status())),
+			Err(e) => Err(e.to_string()),
+		};
+
+		let mut outbox = self.outbox.lock().unwrap();
+		delivery.attempts += 1;
+		let give_up = delivery.attempts >= self.config.max_attempts;
+		match result {
+			Ok(()) => {},
+			Err(e) if give_up => {
+				log_error!(
+					self.logger,
+					"Giving up on delivering notification {} to {} after {} attempts: {}",
+					delivery.notification_id,
+					delivery.url,
+					delivery.attempts,
+					e
+				);
+			},
+			Err(e) => {
+				let backoff = self.backoff(delivery.attempts);
+				log_warn!(
+					self.logger,
+					"Failed to deliver notification {} to {}, retrying in {}s: {}",
+					delivery.notification_id,
+					delivery.url,
+					backoff.as_secs(),
+					e
+				);
+				delivery.next_attempt_ms = unix_time_ms() + backoff.as_millis() as u64;
+				if let Err(e) =
+					self.kv_store.write(WEBHOOK_OUTBOX_NAMESPACE, "", &key, &delivery.encode())
+				{
+					log_error!(self.logger, "Failed to persist webhook delivery {}: {}", key, e);
+				}
+				outbox.insert(key, delivery);
+				return;
+			},
+		}
+		if let Err(e) = self.kv_store.remove(WEBHOOK_OUTBOX_NAMESPACE, "", &key, false) {
+			log_error!(self.logger, "Failed to remove webhook delivery {}: {}", key, e);
+		}
+		outbox.remove(&key);
+	}
+
+	/// How long to wait after the given number of failed attempts.
+	fn backoff(&self, attempts: u32) -> Duration {
+		let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+		cmp::min(self.config.initial_backoff.saturating_mul(factor), self.config.max_backoff)
+	}
+}
+
+/// Derives the ID of `notification` from the event it is about, so it stays the same when LDK
+/// replays the event after a restart. Peers connect and disconnect again and again, so their
+/// notifications get a random ID instead.
+fn notification_id(notification: &Notification) -> String {
+	match notification {
+		Notification::PeerConnected { .. } | Notification::PeerDisconnected { .. } => {
+			hex_utils::hex_str(&rand::thread_rng().gen::<[u8; 16]>())
+		},
+		_ => {
+			let mut engine = sha256::Hash::engine();
+			engine.input(notification.event_type().as_bytes());
+			engine.input(notification.data().to_string().as_bytes());
+			hex_utils::hex_str(&sha256::Hash::from_engine(engine).as_byte_array()[..16])
+		},
+	}
+}
+
+/// Returns the value of the [`SIGNATURE_HEADER`] for a delivery of `payload` at `timestamp`.
+pub fn sign(secret: &str, timestamp: &str, payload: &[u8]) -> String {
+	let mut engine = hmac::HmacEngine::<sha256::Hash>::new(secret.as_bytes());
+	engine.input(timestamp.as_bytes());
+	engine.input(b".");
+	engine.input(payload);
+	let hmac = hmac::Hmac::<sha256::Hash>::from_engine(engine);
+	format!("sha256={}", hex_utils::hex_str(hmac.as_byte_array()))
+}
+
+fn unix_time_ms() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::{test_data_dir, test_store, MockHttpServer};
+	use lightning::ln::types::ChannelId;
+	use lightning::types::payment::PaymentHash;
+	use std::sync::atomic::{AtomicBool, AtomicUsize};
+
+	fn test_config(url: String) -> WebhookConfig {
+		WebhookConfig {
+			urls: vec![url],
+			secret: Some("hunter2".to_string()),
+			initial_backoff: Duration::from_millis(10),
+			max_backoff: Duration::from_millis(100),
+			max_attempts: 5,
+		}
+	}
+
+	async fn wait_until_delivered(webhooks: &Webhooks) {
+		for _ in 0..500 {
+			if webhooks.pending_deliveries() == 0 {
+				return;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		panic!("Timed out waiting for the outbox to drain");
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_notifications_are_signed_and_retried() {
+		let calls = Arc::new(AtomicUsize::new(0));
+		let server_calls = Arc::clone(&calls);
+		let server = MockHttpServer::start(move |_| {
+			if server_calls.fetch_add(1, Ordering::AcqRel) < 2 {
+				(500, "try again".to_string())
+			} else {
+				(200, String::new())
+			}
+		})
+		.await;
+		let (kv_store, logger) = test_store(&test_data_dir("webhooks"));
+		let url = format!("{}/hooks/lightning", server.url());
+		let webhooks =
+			Webhooks::new(test_config(url), kv_store, &runtime::Handle::current(), logger).unwrap();
+
+		webhooks.notify(&Notification::PaymentReceived {
+			payment_hash: PaymentHash([7; 32]),
+			amount_msat: 42_000,
+		});
+		wait_until_delivered(&webhooks).await;
+
+		let requests = server.requests();
+		assert_eq!(requests.len(), 3);
+		let request = requests.last().unwrap();
+		assert_eq!((request.method.as_str(), request.path.as_str()), ("POST", "/hooks/lightning"));
+		let payload: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+		assert_eq!(payload["type"], "payment_received");
+		assert_eq!(payload["data"]["amount_msat"], 42_000);
+		assert_eq!(payload["data"]["payment_hash"], PaymentHash([7; 32]).to_string());
+		// Retries carry the same ID, so the receiver can deduplicate them.
+		assert!(requests.iter().all(|r| r.header(ID_HEADER) == payload["id"].as_str()));
+		let timestamp = request.header(TIMESTAMP_HEADER).unwrap();
+		let signature = sign("hunter2", timestamp, request.body.as_bytes());
+		assert_eq!(request.header(SIGNATURE_HEADER), Some(signature.as_str()));
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_replayed_notifications_keep_their_id() {
+		let server = MockHttpServer::start(|_| (200, String::new())).await;
+		let (kv_store, logger) = test_store(&test_data_dir("webhooks-replayed"));
+		let webhooks =
+			Webhooks::new(test_config(server.url()), kv_store, &runtime::Handle::current(), logger)
+				.unwrap();
+
+		let received = |amount_msat| Notification::PaymentReceived {
+			payment_hash: PaymentHash([7; 32]),
+			amount_msat,
+		};
+		for notification in [received(1_000), received(1_000), received(2_000)] {
+			webhooks.notify(&notification);
+			wait_until_delivered(&webhooks).await;
+		}
+
+		let ids = server
+			.requests()
+			.iter()
+			.map(|request| request.header(ID_HEADER).unwrap().to_string())
+			.collect::<Vec<_>>();
+		assert_eq!(ids.len(), 3);
+		assert_eq!(ids[0], ids[1]);
+		assert_ne!(ids[0], ids[2]);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_retries_hold_up_later_deliveries() {
+		let calls = Arc::new(AtomicUsize::new(0));
+		let server_calls = Arc::clone(&calls);
+		let server = MockHttpServer::start(move |_| {
+			if server_calls.fetch_add(1, Ordering::AcqRel) == 0 {
+				(500, "try again".to_string())
+			} else {
+				(200, String::new())
+			}
+		})
+		.await;
+		let (kv_store, logger) = test_store(&test_data_dir("webhooks-ordered"));
+		let mut config = test_config(server.url());
+		config.initial_backoff = Duration::from_millis(200);
+		let webhooks =
+			Webhooks::new(config, kv_store, &runtime::Handle::current(), logger).unwrap();
+
+		for amount_msat in [1_000, 2_000] {
+			let payment_hash = PaymentHash([7; 32]);
+			webhooks.notify(&Notification::PaymentReceived { payment_hash, amount_msat });
+		}
+		wait_until_delivered(&webhooks).await;
+
+		// The second notification waits for the first one's retry.
+		let amounts = server
+			.requests()
+			.iter()
+			.map(|request| serde_json::from_str::<serde_json::Value>(&request.body).unwrap())
+			.map(|payload| payload["data"]["amount_msat"].as_u64().unwrap())
+			.collect::<Vec<_>>();
+		assert_eq!(amounts, vec![1_000, 1_000, 2_000]);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_outbox_survives_restarts() {
+		let receiving = Arc::new(AtomicBool::new(false));
+		let server_receiving = Arc::clone(&receiving);
+		let server =
+			MockHttpServer::start(move |_| match server_receiving.load(Ordering::Acquire) {
+				true => (204, String::new()),
+				false => (503, String::new()),
+			})
+			.await;
+		let (kv_store, logger) = test_store(&test_data_dir("webhooks-outbox"));
+		let mut config = test_config(server.url());
+		// Long enough not to be retried before the restart.
+		config.initial_backoff = Duration::from_secs(60);
+		let webhooks = Webhooks::new(
+			config.clone(),
+			Arc::clone(&kv_store),
+			&runtime::Handle::current(),
+			Arc::clone(&logger),
+		)
+		.unwrap();
+		webhooks.notify(&Notification::ChannelReady {
+			channel_id: ChannelId([1; 32]),
+			user_channel_id: 1,
+			counterparty_node_id: bitcoin::secp256k1::PublicKey::from_slice(&[2; 33]).unwrap(),
+		});
+		server.wait_for_request("/").await;
+		assert_eq!(webhooks.pending_deliveries(), 1);
+
+		// Loading the outbox again, e.g. after a restart, retries it right away. The retry may
+		// already be done by the time `new` returns, so the outbox is checked before.
+		assert_eq!(kv_store.list(WEBHOOK_OUTBOX_NAMESPACE, "").unwrap().len(), 1);
+		receiving.store(true, Ordering::Release);
+		let webhooks =
+			Webhooks::new(config, Arc::clone(&kv_store), &runtime::Handle::current(), logger)
+				.unwrap();
+		wait_until_delivered(&webhooks).await;
+		assert!(kv_store.list(WEBHOOK_OUTBOX_NAMESPACE, "").unwrap().is_empty());
+		assert_eq!(server.requests().len(), 2);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_unresponsive_url_does_not_delay_others() {
+		// A receiver which accepts connections but never answers.
+		let unresponsive = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let unresponsive_url = format!("http://{}", unresponsive.local_addr().unwrap());
+		let server = MockHttpServer::start(|_| (200, String::new())).await;
+		let (kv_store, logger) = test_store(&test_data_dir("webhooks-concurrent"));
+		let mut config = test_config(unresponsive_url);
+		config.urls.push(server.url());
+		let webhooks =
+			Webhooks::new(config, kv_store, &runtime::Handle::current(), logger).unwrap();
+
+		for amount_msat in [1_000, 2_000] {
+			let payment_hash = PaymentHash([7; 32]);
+			webhooks.notify(&Notification::PaymentReceived { payment_hash, amount_msat });
+		}
+		// Both arrive well before the unresponsive receiver's request times out, in order.
+		for _ in 0..500 {
+			if server.requests().len() == 2 {
+				break;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		let amounts = server
+			.requests()
+			.iter()
+			.map(|request| serde_json::from_str::<serde_json::Value>(&request.body).unwrap())
+			.map(|payload| payload["data"]["amount_msat"].as_u64().unwrap())
+			.collect::<Vec<_>>();
+		assert_eq!(amounts, vec![1_000, 2_000]);
+		assert_eq!(webhooks.pending_deliveries(), 2);
+	}
+}